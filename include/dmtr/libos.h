@@ -20,6 +20,8 @@ DMTR_EXPORT int dmtr_queue(int *qd_out);
 
 DMTR_EXPORT int dmtr_socket(int *qd_out, int domain, int type, int protocol);
 DMTR_EXPORT int dmtr_getsockname(int qd, struct sockaddr *saddr, socklen_t *size);
+DMTR_EXPORT int dmtr_setsockopt(int qd, int level, int optname, const void *optval, socklen_t optlen);
+DMTR_EXPORT int dmtr_getsockopt(int qd, int level, int optname, void *optval, socklen_t *optlen);
 DMTR_EXPORT int dmtr_listen(int fd, int backlog);
 DMTR_EXPORT int dmtr_bind(int qd, const struct sockaddr *saddr, socklen_t size);
 DMTR_EXPORT int dmtr_accept(dmtr_qtoken_t *qtok_out, int sockqd);
@@ -32,6 +34,7 @@ DMTR_EXPORT int dmtr_open2(int *qd_out, const char *pathname, int flags, mode_t
 
 DMTR_EXPORT int dmtr_creat(int *qd_out, const char *pathname, mode_t mode);
 DMTR_EXPORT int dmtr_close(int qd);
+DMTR_EXPORT int dmtr_async_close(dmtr_qtoken_t *qtok_out, int qd);
 DMTR_EXPORT int dmtr_is_qd_valid(int *flag_out, int qd);
 
 DMTR_EXPORT int dmtr_push(
@@ -43,6 +46,17 @@ DMTR_EXPORT int dmtr_pop(dmtr_qtoken_t *qt_out, int qd);
 DMTR_EXPORT int dmtr_pop2(dmtr_qtoken_t *qt_out, int qd, size_t count);
 DMTR_EXPORT int dmtr_lseek(int qd, off_t offset, int whence);
 
+DMTR_EXPORT int dmtr_ping(dmtr_qtoken_t *qtok_out, const struct in_addr *dst, size_t payload_size, int timeout_ms);
+DMTR_EXPORT int dmtr_traceroute(dmtr_qtoken_t *qtok_out, const struct in_addr *dst, int max_hops, int timeout_ms, dmtr_traceroute_hop_t *hops, size_t capacity);
+DMTR_EXPORT int dmtr_resolve(dmtr_qtoken_t *qtok_out, const char *name, int family, int timeout_ms, dmtr_resolve_addr_t *addrs, size_t capacity);
+
+DMTR_EXPORT int dmtr_arp_list(dmtr_arp_entry_t *entries, size_t capacity, size_t *count_out);
+DMTR_EXPORT int dmtr_arp_add(const struct in_addr *addr, const uint8_t link_addr[6]);
+DMTR_EXPORT int dmtr_arp_delete(const struct in_addr *addr);
+DMTR_EXPORT int dmtr_arp_flush(void);
+
+DMTR_EXPORT int dmtr_ipv4_conflict(struct in_addr *addr_out, uint8_t link_addr_out[6]);
+
 DMTR_EXPORT int dmtr_poll(dmtr_qresult_t *qr_out, dmtr_qtoken_t qt);
 DMTR_EXPORT int dmtr_drop(dmtr_qtoken_t qt);
 
@@ -41,6 +41,11 @@ typedef enum dmtr_opcode {
     DMTR_OPC_POP,
     DMTR_OPC_ACCEPT,
     DMTR_OPC_CONNECT,
+    DMTR_OPC_CLOSE,
+    DMTR_OPC_PING,
+    DMTR_OPC_TRACEROUTE,
+    DMTR_OPC_RESOLVE,
+    DMTR_OPC_FAILED,
 } dmtr_opcode_t;
 
 typedef struct dmtr_accept_result {
@@ -48,6 +53,51 @@ typedef struct dmtr_accept_result {
     struct sockaddr_in addr;
 } dmtr_accept_result_t;
 
+// the operation on qr_qd that failed doesn't have a result of its own.
+typedef struct dmtr_failed_result {
+    int error;
+} dmtr_failed_result_t;
+
+typedef struct dmtr_ping_result {
+    int error;
+    uint64_t rtt_ns;
+} dmtr_ping_result_t;
+
+// hops that didn't answer have a zero address.
+typedef struct dmtr_traceroute_hop {
+    struct in_addr addr;
+    uint64_t rtt_ns;
+} dmtr_traceroute_hop_t;
+
+// the hops themselves go to the buffer passed to dmtr_traceroute().
+typedef struct dmtr_traceroute_result {
+    int error;
+    uint32_t num_hops;
+} dmtr_traceroute_result_t;
+
+// family is AF_INET or AF_INET6 and picks the member of addr.
+typedef struct dmtr_resolve_addr {
+    int family;
+    union {
+        struct in_addr v4;
+        struct in6_addr v6;
+    } addr;
+} dmtr_resolve_addr_t;
+
+// the addresses themselves go to the buffer passed to dmtr_resolve(), which may
+// have been too small for all num_addrs of them.
+typedef struct dmtr_resolve_result {
+    int error;
+    uint32_t num_addrs;
+} dmtr_resolve_result_t;
+
+// static entries never expire.
+typedef struct dmtr_arp_entry {
+    struct in_addr addr;
+    uint8_t link_addr[6];
+    int is_static;
+} dmtr_arp_entry_t;
+
 typedef struct dmtr_qresult {
     enum dmtr_opcode qr_opcode;
     int qr_qd;
@@ -55,6 +105,10 @@ typedef struct dmtr_qresult {
     union {
         dmtr_sgarray_t sga;
         dmtr_accept_result_t ares;
+        dmtr_ping_result_t ping;
+        dmtr_traceroute_result_t trace;
+        dmtr_resolve_result_t resolve;
+        dmtr_failed_result_t failed;
     } qr_value;
 } dmtr_qresult_t;
 
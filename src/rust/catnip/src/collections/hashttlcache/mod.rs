@@ -165,15 +165,14 @@ where
     }
 
     pub fn remove(&mut self, key: &K) -> Option<V> {
-        if let Some(ref record) = self.map.remove(key) {
-            if let Some(ref expiry) = record.expiry {
-                if !expiry.has_expired(self.clock) {
-                    return Some(record.value.clone());
-                }
-            }
+        match self.map.remove(key) {
+            Some(Record {
+                expiry: Some(ref e),
+                ..
+            }) if e.has_expired(self.clock) => None,
+            Some(record) => Some(record.value),
+            None => None,
         }
-
-        None
     }
 
     pub fn get(&self, key: &K) -> Option<&V>
@@ -205,6 +204,17 @@ where
         }
     }
 
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let clock = self.clock;
+        match self.map.get_mut(key) {
+            Some(r) => match r.expiry {
+                Some(ref e) if e.has_expired(clock) => None,
+                _ => Some(&mut r.value),
+            },
+            None => None,
+        }
+    }
+
     pub fn advance_clock(&mut self, now: Instant) {
         assert!(now >= self.clock);
         self.clock = now;
@@ -250,8 +260,11 @@ where
                 HashMapEntry::Occupied(e) => {
                     let (record_expiry, value) = {
                         let record = e.get();
-                        let expiry = record.expiry.as_ref().unwrap();
-                        (expiry, record.value.clone())
+                        match record.expiry.as_ref() {
+                            Some(expiry) => (expiry, record.value.clone()),
+                            // the entry was replaced with one that doesn't expire.
+                            None => continue,
+                        }
                     };
 
                     if &graveyard_expiry == record_expiry {
@@ -75,6 +75,24 @@ fn replace_entry() {
     assert!(cache.get(&"a").is_none());
 }
 
+#[test]
+fn replace_with_permanent_entry() {
+    // tests to ensure that an entry replaced with one without a TTL outlives
+    // the old entry's expiry and can still be removed.
+    let now = Instant::now();
+    let later = now + Duration::from_secs(1);
+
+    let mut cache = HashTtlCache::new(now, Some(Duration::from_secs(1)));
+    cache.insert("a", 'a');
+    cache.insert_with_ttl("a", 'b', None);
+    cache.advance_clock(later);
+    let evicted = cache.try_evict(usize::max_value());
+    assert_eq!(evicted.len(), 0);
+    assert!(cache.get(&"a") == Some(&'b'));
+    assert_eq!(cache.remove(&"a"), Some('b'));
+    assert!(cache.get(&"a").is_none());
+}
+
 #[test]
 fn limited_evictions() {
     // tests to ensure that an entry without an explicit TTL gets evicted at
@@ -97,3 +115,17 @@ fn limited_evictions() {
     assert!(evicted.contains_key(&"b"));
     assert!(cache.get(&"b").is_none());
 }
+
+#[test]
+fn get_mut() {
+    // tests to ensure that entries can be modified in place until they expire.
+    let now = Instant::now();
+    let later = now + Duration::from_secs(1);
+
+    let mut cache = HashTtlCache::new(now, Some(Duration::from_secs(1)));
+    cache.insert("a", 'a');
+    *cache.get_mut(&"a").unwrap() = 'b';
+    assert!(cache.get(&"a") == Some(&'b'));
+    cache.advance_clock(later);
+    assert!(cache.get_mut(&"a").is_none());
+}
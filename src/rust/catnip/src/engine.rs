@@ -11,42 +11,71 @@ use crate::{
     operations::ResultFuture,
     protocols::{
         arp,
-        ethernet2::frame::{
-            EtherType2,
-            Ethernet2Header,
+        dhcp,
+        dns,
+        ethernet2::{
+            frame::{
+                EtherType2,
+                Ethernet2Header,
+            },
+            MacAddress,
         },
+        icmpv4,
+        icmpv6,
+        igmp,
+        ip,
         ipv4,
-        tcp::operations::{
-            AcceptFuture,
-            ConnectFuture,
-            PopFuture,
-            PushFuture,
+        ipv6,
+        tcp::{
+            operations::{
+                AcceptFuture,
+                CloseFuture,
+                ConnectFuture,
+                PopFuture,
+                PushFuture,
+            },
+            ListenerStats,
         },
-        udp::peer::{
-            PopFuture as UdpPopFuture,
-            UdpOperation,
+        udp::{
+            self,
+            peer::{
+                PopFuture as UdpPopFuture,
+                PushFuture as UdpPushFuture,
+                UdpOperation,
+            },
         },
     },
     runtime::Runtime,
     scheduler::Operation,
+    socket_options::{
+        SocketOption,
+        SocketOptionName,
+    },
     sync::Bytes,
 };
 use std::{
+    cell::RefCell,
     future::Future,
-    net::Ipv4Addr,
+    net::{
+        IpAddr,
+        Ipv4Addr,
+    },
+    rc::Rc,
     time::Duration,
 };
 use tracy_client::static_span;
 
-#[cfg(test)]
-use crate::protocols::ethernet2::MacAddress;
 #[cfg(test)]
 use hashbrown::HashMap;
+#[cfg(test)]
+use std::net::Ipv6Addr;
 
 pub struct Engine<RT: Runtime> {
     rt: RT,
     arp: arp::Peer<RT>,
     ipv4: ipv4::Peer<RT>,
+    ipv6: ipv6::Peer<RT>,
+    dns: dns::Resolver<RT>,
 
     file_table: FileTable,
 }
@@ -60,12 +89,34 @@ impl<RT: Runtime> Engine<RT> {
     pub fn new(rt: RT) -> Result<Self, Fail> {
         let now = rt.now();
         let file_table = FileTable::new();
-        let arp = arp::Peer::new(now, rt.clone())?;
-        let ipv4 = ipv4::Peer::new(rt.clone(), arp.clone(), file_table.clone());
+        let routes = Rc::new(RefCell::new(ipv4::RoutingTable::new(
+            rt.local_ipv4_addr(),
+            &rt.ipv4_options(),
+        )));
+        let ipv6_routes = Rc::new(ipv6::RoutingTable::new(
+            rt.local_link_addr(),
+            &rt.ipv6_options(),
+        ));
+        let arp = arp::Peer::new(now, rt.clone(), routes.clone())?;
+        let icmpv6 = icmpv6::Peer::new(rt.clone(), ipv6_routes.clone());
+        let igmp = igmp::Peer::new(rt.clone(), routes.clone());
+        let network = ip::Network::new(
+            rt.clone(),
+            arp.clone(),
+            icmpv6.clone(),
+            igmp,
+            routes,
+            ipv6_routes.clone(),
+        );
+        let ipv4 = ipv4::Peer::new(rt.clone(), network, file_table.clone());
+        let ipv6 = ipv6::Peer::new(ipv6_routes, icmpv6, ipv4.tcp.clone(), ipv4.udp.clone());
+        let dns = dns::Resolver::new(rt.clone(), ipv4.udp.clone(), ipv4.dhcp.clone());
         Ok(Engine {
             rt,
             arp,
             ipv4,
+            ipv6,
+            dns,
             file_table,
         })
     }
@@ -77,23 +128,77 @@ impl<RT: Runtime> Engine<RT> {
     pub fn receive(&mut self, bytes: Bytes) -> Result<(), Fail> {
         let _s = static_span!();
         let (header, payload) = Ethernet2Header::parse(bytes)?;
-        if self.rt.local_link_addr() != header.dst_addr && !header.dst_addr.is_broadcast() {
+        // Multicast covers broadcast, and IPv4 and IPv6 check which groups they're listening to.
+        if self.rt.local_link_addr() != header.dst_addr && !header.dst_addr.is_multicast() {
             return Err(Fail::Ignored {
                 details: "Physical dst_addr mismatch",
             });
         }
+        if !self.rt.ethernet2_options().accepts(&header.vlan_tags) {
+            return Err(Fail::Ignored {
+                details: "Frame for another VLAN",
+            });
+        }
         match header.ether_type {
             EtherType2::Arp => self.arp.receive(payload),
             EtherType2::Ipv4 => self.ipv4.receive(payload),
+            EtherType2::Ipv6 => self.ipv6.receive(payload),
         }
     }
 
+    pub fn dhcp_lease(&self) -> Option<dhcp::Lease> {
+        self.ipv4.dhcp_lease()
+    }
+
+    pub fn is_probing_ipv4_addr(&self) -> bool {
+        self.arp.is_probing()
+    }
+
+    pub fn ipv4_addr_conflict(&self) -> Option<arp::Conflict> {
+        self.arp.conflict()
+    }
+
+    pub fn arp_entries(&self) -> Vec<arp::Entry> {
+        self.arp.entries()
+    }
+
+    pub fn add_arp_entry(&self, ipv4_addr: Ipv4Addr, link_addr: MacAddress) -> Result<(), Fail> {
+        self.arp.insert_static(ipv4_addr, link_addr)
+    }
+
+    pub fn remove_arp_entry(&self, ipv4_addr: Ipv4Addr) -> Result<(), Fail> {
+        self.arp.remove(ipv4_addr)
+    }
+
+    pub fn flush_arp_cache(&self) {
+        self.arp.flush_cache()
+    }
+
+    pub fn resolve(
+        &self,
+        name: &str,
+        record_types: &[dns::RecordType],
+        timeout: Option<Duration>,
+    ) -> impl Future<Output = Result<Vec<IpAddr>, Fail>> {
+        self.dns.resolve(name, record_types, timeout)
+    }
+
     pub fn ping(
         &self,
         dest_ipv4_addr: Ipv4Addr,
+        payload_size: usize,
         timeout: Option<Duration>,
     ) -> impl Future<Output = Result<Duration, Fail>> {
-        self.ipv4.ping(dest_ipv4_addr, timeout)
+        self.ipv4.ping(dest_ipv4_addr, payload_size, timeout)
+    }
+
+    pub fn traceroute(
+        &self,
+        dest_ipv4_addr: Ipv4Addr,
+        max_hops: u8,
+        timeout: Option<Duration>,
+    ) -> impl Future<Output = Result<Vec<Option<icmpv4::TracerouteHop>>, Fail>> {
+        self.ipv4.traceroute(dest_ipv4_addr, max_hops, timeout)
     }
 
     pub fn socket(&mut self, protocol: Protocol) -> FileDescriptor {
@@ -103,11 +208,7 @@ impl<RT: Runtime> Engine<RT> {
         }
     }
 
-    pub fn connect(
-        &mut self,
-        fd: FileDescriptor,
-        remote_endpoint: ipv4::Endpoint,
-    ) -> Operation<RT> {
+    pub fn connect(&mut self, fd: FileDescriptor, remote_endpoint: ip::Endpoint) -> Operation<RT> {
         match self.file_table.get(fd) {
             Some(File::TcpSocket) => Operation::from(self.ipv4.tcp.connect(fd, remote_endpoint)),
             Some(File::UdpSocket) => {
@@ -118,7 +219,7 @@ impl<RT: Runtime> Engine<RT> {
         }
     }
 
-    pub fn bind(&mut self, fd: FileDescriptor, endpoint: ipv4::Endpoint) -> Result<(), Fail> {
+    pub fn bind(&mut self, fd: FileDescriptor, endpoint: ip::Endpoint) -> Result<(), Fail> {
         match self.file_table.get(fd) {
             Some(File::TcpSocket) => self.ipv4.tcp.bind(fd, endpoint),
             Some(File::UdpSocket) => self.ipv4.udp.bind(fd, endpoint),
@@ -151,17 +252,27 @@ impl<RT: Runtime> Engine<RT> {
         match self.file_table.get(fd) {
             Some(File::TcpSocket) => Operation::from(self.ipv4.tcp.push(fd, buf)),
             Some(File::UdpSocket) => {
-                let udp_op = UdpOperation::Push(fd, self.ipv4.udp.push(fd, buf));
+                let future = self
+                    .ipv4
+                    .udp
+                    .push(fd, buf)
+                    .unwrap_or_else(|e| UdpPushFuture::done(fd, Err(e)));
+                let udp_op = UdpOperation::Push(ResultFuture::new(future));
                 Operation::Udp(udp_op)
             },
             _ => panic!("TODO: Invalid fd"),
         }
     }
 
-    pub fn pushto(&mut self, fd: FileDescriptor, buf: Bytes, to: ipv4::Endpoint) -> Operation<RT> {
+    pub fn pushto(&mut self, fd: FileDescriptor, buf: Bytes, to: ip::Endpoint) -> Operation<RT> {
         match self.file_table.get(fd) {
             Some(File::UdpSocket) => {
-                let udp_op = UdpOperation::Push(fd, self.ipv4.udp.pushto(fd, buf, to));
+                let future = self
+                    .ipv4
+                    .udp
+                    .pushto(fd, buf, to)
+                    .unwrap_or_else(|e| UdpPushFuture::done(fd, Err(e)));
+                let udp_op = UdpOperation::Push(ResultFuture::new(future));
                 Operation::Udp(udp_op)
             },
             _ => panic!("TODO: Invalid fd"),
@@ -169,7 +280,7 @@ impl<RT: Runtime> Engine<RT> {
     }
 
     pub fn udp_push(&mut self, fd: FileDescriptor, buf: Bytes) -> Result<(), Fail> {
-        self.ipv4.udp.push(fd, buf)
+        self.ipv4.udp.push(fd, buf).map(|_| ())
     }
 
     pub fn udp_pop(&mut self, fd: FileDescriptor) -> UdpPopFuture {
@@ -195,6 +306,73 @@ impl<RT: Runtime> Engine<RT> {
         }
     }
 
+    pub fn async_close(&mut self, fd: FileDescriptor) -> Operation<RT> {
+        match self.file_table.get(fd) {
+            Some(File::TcpSocket) => Operation::from(self.ipv4.tcp.async_close(fd)),
+            Some(File::UdpSocket) => {
+                let udp_op = UdpOperation::Close(fd, self.ipv4.udp.close(fd));
+                Operation::Udp(udp_op)
+            },
+            _ => panic!("TODO: Invalid fd"),
+        }
+    }
+
+    pub fn setsockopt(&mut self, fd: FileDescriptor, option: SocketOption) -> Result<(), Fail> {
+        match self.file_table.get(fd) {
+            Some(File::TcpSocket) => self.ipv4.tcp.setsockopt(fd, option),
+            Some(File::UdpSocket) => self.ipv4.udp.setsockopt(fd, option),
+            _ => Err(Fail::ResourceNotFound {
+                details: "Invalid file descriptor",
+            }),
+        }
+    }
+
+    pub fn getsockopt(
+        &self,
+        fd: FileDescriptor,
+        name: SocketOptionName,
+    ) -> Result<SocketOption, Fail> {
+        match self.file_table.get(fd) {
+            Some(File::TcpSocket) => self.ipv4.tcp.getsockopt(fd, name),
+            Some(File::UdpSocket) => self.ipv4.udp.getsockopt(fd, name),
+            _ => Err(Fail::ResourceNotFound {
+                details: "Invalid file descriptor",
+            }),
+        }
+    }
+
+    pub fn join_multicast_group(
+        &mut self,
+        fd: FileDescriptor,
+        group: Ipv4Addr,
+    ) -> Result<(), Fail> {
+        match self.file_table.get(fd) {
+            Some(File::UdpSocket) => self.ipv4.udp.join_multicast_group(fd, group),
+            Some(File::TcpSocket) => Err(Fail::Unsupported {
+                details: "Multicast on TCP socket",
+            }),
+            _ => Err(Fail::ResourceNotFound {
+                details: "Invalid file descriptor",
+            }),
+        }
+    }
+
+    pub fn leave_multicast_group(
+        &mut self,
+        fd: FileDescriptor,
+        group: Ipv4Addr,
+    ) -> Result<(), Fail> {
+        match self.file_table.get(fd) {
+            Some(File::UdpSocket) => self.ipv4.udp.leave_multicast_group(fd, group),
+            Some(File::TcpSocket) => Err(Fail::Unsupported {
+                details: "Multicast on TCP socket",
+            }),
+            _ => Err(Fail::ResourceNotFound {
+                details: "Invalid file descriptor",
+            }),
+        }
+    }
+
     pub fn tcp_socket(&mut self) -> FileDescriptor {
         self.ipv4.tcp.socket()
     }
@@ -202,7 +380,7 @@ impl<RT: Runtime> Engine<RT> {
     pub fn tcp_connect(
         &mut self,
         socket_fd: FileDescriptor,
-        remote_endpoint: ipv4::Endpoint,
+        remote_endpoint: ip::Endpoint,
     ) -> ConnectFuture<RT> {
         self.ipv4.tcp.connect(socket_fd, remote_endpoint)
     }
@@ -210,7 +388,7 @@ impl<RT: Runtime> Engine<RT> {
     pub fn tcp_bind(
         &mut self,
         socket_fd: FileDescriptor,
-        endpoint: ipv4::Endpoint,
+        endpoint: ip::Endpoint,
     ) -> Result<(), Fail> {
         self.ipv4.tcp.bind(socket_fd, endpoint)
     }
@@ -231,10 +409,22 @@ impl<RT: Runtime> Engine<RT> {
         self.ipv4.tcp.close(socket_fd)
     }
 
+    pub fn tcp_async_close(&mut self, socket_fd: FileDescriptor) -> CloseFuture {
+        self.ipv4.tcp.async_close(socket_fd)
+    }
+
     pub fn tcp_listen(&mut self, socket_fd: FileDescriptor, backlog: usize) -> Result<(), Fail> {
         self.ipv4.tcp.listen(socket_fd, backlog)
     }
 
+    pub fn tcp_listener_stats(&self, socket_fd: FileDescriptor) -> Result<ListenerStats, Fail> {
+        self.ipv4.tcp.listener_stats(socket_fd)
+    }
+
+    pub fn udp_socket_stats(&self, fd: FileDescriptor) -> Result<udp::SocketStats, Fail> {
+        self.ipv4.udp.stats(fd)
+    }
+
     #[cfg(test)]
     pub fn arp_query(&self, ipv4_addr: Ipv4Addr) -> impl Future<Output = Result<MacAddress, Fail>> {
         self.arp.query(ipv4_addr)
@@ -255,6 +445,11 @@ impl<RT: Runtime> Engine<RT> {
         self.arp.export_cache()
     }
 
+    #[cfg(test)]
+    pub fn export_neighbor_cache(&self) -> HashMap<Ipv6Addr, MacAddress> {
+        self.ipv6.export_neighbor_cache()
+    }
+
     #[cfg(test)]
     pub fn import_arp_cache(&self, cache: HashMap<Ipv4Addr, MacAddress>) {
         self.arp.import_cache(cache)
@@ -14,13 +14,18 @@ use std::{
 type Str = &'static str;
 
 custom_error! {#[derive(Clone)] pub Fail
+    AddressFamilyNotSupported{} = "address family not supported",
+    AddressInUse{} = "address in use",
+    AddressNotAvailable{} = "address not available",
     ConnectionAborted{} = "connection aborted",
     ConnectionRefused{} = "connection refused",
     IoError {} = "IO Error",
     BorrowMutError {} = "BorrowMut Error",
     Ignored{details: Str} = "operation had no effect ({details})",
     Malformed{details: Str} = "encountered a malformed datagram ({details})",
+    MessageTooLong{details: Str} = "message too long ({details})",
     Misdelivered{} = "misdelivered datagram",
+    NoRoute{} = "no route to destination",
     OutOfRange{details: Str} = "a value is out of range ({details})",
     ResourceBusy{details: Str} = "resource is busy ({details})",
     ResourceExhausted{details: Str} = "resource exhausted ({details})",
@@ -69,11 +74,16 @@ impl From<eui48::ParseError> for Fail {
 impl Fail {
     pub fn errno(&self) -> libc::c_int {
         match self {
+            Fail::AddressFamilyNotSupported {} => libc::EAFNOSUPPORT,
+            Fail::AddressInUse {} => libc::EADDRINUSE,
+            Fail::AddressNotAvailable {} => libc::EADDRNOTAVAIL,
             Fail::ConnectionAborted {} => libc::ECONNABORTED,
             Fail::ConnectionRefused {} => libc::ECONNREFUSED,
             Fail::Ignored { .. } => 0,
             Fail::Malformed { .. } => libc::EILSEQ,
+            Fail::MessageTooLong { .. } => libc::EMSGSIZE,
             Fail::Misdelivered {} => libc::EHOSTUNREACH,
+            Fail::NoRoute {} => libc::ENETUNREACH,
             Fail::OutOfRange { .. } => libc::ERANGE,
             Fail::ResourceBusy { .. } => libc::EBUSY,
             Fail::ResourceExhausted { .. } => libc::ENOMEM,
@@ -1,18 +1,31 @@
 #![allow(non_camel_case_types)]
 
 use crate::{
+    fail::Fail,
     file_table::FileDescriptor,
     operations::OperationResult,
+    protocols::{
+        arp,
+        icmpv4::TracerouteHop,
+        ip::Endpoint as IpEndpoint,
+    },
 };
 use libc::{
     c_int,
     c_void,
+    in6_addr,
+    in_addr,
     sockaddr_in,
 };
 use std::{
     mem,
+    net::{
+        IpAddr,
+        Ipv4Addr,
+    },
     ptr,
     slice,
+    time::Duration,
 };
 
 pub type dmtr_qtoken_t = u64;
@@ -74,6 +87,11 @@ pub enum dmtr_opcode_t {
     DMTR_OPC_POP,
     DMTR_OPC_ACCEPT,
     DMTR_OPC_CONNECT,
+    DMTR_OPC_CLOSE,
+    DMTR_OPC_PING,
+    DMTR_OPC_TRACEROUTE,
+    DMTR_OPC_RESOLVE,
+    DMTR_OPC_FAILED,
 }
 
 #[derive(Copy, Clone)]
@@ -83,10 +101,139 @@ pub struct dmtr_accept_result_t {
     addr: sockaddr_in,
 }
 
+// The operation on `qr_qd` that failed doesn't have a result of its own.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct dmtr_failed_result_t {
+    pub error: c_int,
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct dmtr_ping_result_t {
+    pub error: c_int,
+    pub rtt_ns: u64,
+}
+
+// Hops that didn't answer have a zero address.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct dmtr_traceroute_hop_t {
+    pub addr: in_addr,
+    pub rtt_ns: u64,
+}
+
+impl From<Option<TracerouteHop>> for dmtr_traceroute_hop_t {
+    fn from(hop: Option<TracerouteHop>) -> Self {
+        match hop {
+            Some(hop) => Self {
+                addr: to_in_addr(hop.addr),
+                rtt_ns: duration_ns(hop.rtt),
+            },
+            None => Self {
+                addr: to_in_addr(Ipv4Addr::UNSPECIFIED),
+                rtt_ns: 0,
+            },
+        }
+    }
+}
+
+// The hops themselves go to the buffer passed to `dmtr_traceroute`.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct dmtr_traceroute_result_t {
+    pub error: c_int,
+    pub num_hops: u32,
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub union dmtr_resolve_addr_value_t {
+    pub v4: in_addr,
+    pub v6: in6_addr,
+}
+
+// The family is AF_INET or AF_INET6 and picks the member of the address.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct dmtr_resolve_addr_t {
+    pub family: c_int,
+    pub addr: dmtr_resolve_addr_value_t,
+}
+
+impl From<IpAddr> for dmtr_resolve_addr_t {
+    fn from(addr: IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(addr) => Self {
+                family: libc::AF_INET,
+                addr: dmtr_resolve_addr_value_t {
+                    v4: to_in_addr(addr),
+                },
+            },
+            IpAddr::V6(addr) => Self {
+                family: libc::AF_INET6,
+                addr: dmtr_resolve_addr_value_t {
+                    v6: in6_addr {
+                        s6_addr: addr.octets(),
+                    },
+                },
+            },
+        }
+    }
+}
+
+// The addresses themselves go to the buffer passed to `dmtr_resolve`, which may have been too small
+// for all `num_addrs` of them.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct dmtr_resolve_result_t {
+    pub error: c_int,
+    pub num_addrs: u32,
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct dmtr_arp_entry_t {
+    pub addr: in_addr,
+    pub link_addr: [u8; 6],
+    pub is_static: c_int,
+}
+
+impl From<arp::Entry> for dmtr_arp_entry_t {
+    fn from(entry: arp::Entry) -> Self {
+        Self {
+            addr: to_in_addr(entry.ipv4_addr),
+            link_addr: entry.link_addr.octets(),
+            is_static: entry.is_static as c_int,
+        }
+    }
+}
+
 #[repr(C)]
 pub union dmtr_qr_value_t {
     pub sga: dmtr_sgarray_t,
     pub ares: dmtr_accept_result_t,
+    pub ping: dmtr_ping_result_t,
+    pub trace: dmtr_traceroute_result_t,
+    pub resolve: dmtr_resolve_result_t,
+    pub failed: dmtr_failed_result_t,
+}
+
+fn to_in_addr(addr: Ipv4Addr) -> in_addr {
+    in_addr {
+        s_addr: u32::from_le_bytes(addr.octets()),
+    }
+}
+
+fn to_errno(e: Fail) -> c_int {
+    match e.errno() {
+        0 => libc::EIO,
+        errno => errno,
+    }
+}
+
+fn duration_ns(d: Duration) -> u64 {
+    d.as_nanos() as u64
 }
 
 #[repr(C)]
@@ -121,6 +268,12 @@ impl dmtr_qresult_t {
                     qr_value,
                 }
             },
+            OperationResult::Close => Self {
+                qr_opcode: dmtr_opcode_t::DMTR_OPC_CLOSE,
+                qr_qd: qd as c_int,
+                qr_qt: qt,
+                qr_value: unsafe { mem::zeroed() },
+            },
             OperationResult::Push => Self {
                 qr_opcode: dmtr_opcode_t::DMTR_OPC_PUSH,
                 qr_qd: qd as c_int,
@@ -129,9 +282,14 @@ impl dmtr_qresult_t {
             },
             OperationResult::Pop(addr, bytes) => {
                 let mut sga = dmtr_sgarray_t::from(&bytes[..]);
-                if let Some(addr) = addr {
-                    sga.sga_addr.sin_port = addr.port.into();
-                    sga.sga_addr.sin_addr.s_addr = u32::from_le_bytes(addr.addr.octets());
+                // `sga_addr` only has room for IPv4 addresses, so IPv6 senders are left out.
+                if let Some(IpEndpoint {
+                    addr: IpAddr::V4(ipv4_addr),
+                    port,
+                }) = addr
+                {
+                    sga.sga_addr.sin_port = port.into();
+                    sga.sga_addr.sin_addr.s_addr = u32::from_le_bytes(ipv4_addr.octets());
                 }
                 let qr_value = dmtr_qr_value_t { sga };
                 Self {
@@ -141,8 +299,67 @@ impl dmtr_qresult_t {
                     qr_value,
                 }
             },
-            OperationResult::Failed(e) => {
-                panic!("Unhandled error: {:?}", e);
+            OperationResult::Ping(r) => {
+                let ping = match r {
+                    Ok(rtt) => dmtr_ping_result_t {
+                        error: 0,
+                        rtt_ns: duration_ns(rtt),
+                    },
+                    Err(e) => dmtr_ping_result_t {
+                        error: to_errno(e),
+                        rtt_ns: 0,
+                    },
+                };
+                Self {
+                    qr_opcode: dmtr_opcode_t::DMTR_OPC_PING,
+                    qr_qd: qd as c_int,
+                    qr_qt: qt,
+                    qr_value: dmtr_qr_value_t { ping },
+                }
+            },
+            OperationResult::Traceroute(r) => {
+                let trace = match r {
+                    Ok(num_hops) => dmtr_traceroute_result_t {
+                        error: 0,
+                        num_hops: num_hops as u32,
+                    },
+                    Err(e) => dmtr_traceroute_result_t {
+                        error: to_errno(e),
+                        num_hops: 0,
+                    },
+                };
+                Self {
+                    qr_opcode: dmtr_opcode_t::DMTR_OPC_TRACEROUTE,
+                    qr_qd: qd as c_int,
+                    qr_qt: qt,
+                    qr_value: dmtr_qr_value_t { trace },
+                }
+            },
+            OperationResult::Resolve(r) => {
+                let resolve = match r {
+                    Ok(num_addrs) => dmtr_resolve_result_t {
+                        error: 0,
+                        num_addrs: num_addrs as u32,
+                    },
+                    Err(e) => dmtr_resolve_result_t {
+                        error: to_errno(e),
+                        num_addrs: 0,
+                    },
+                };
+                Self {
+                    qr_opcode: dmtr_opcode_t::DMTR_OPC_RESOLVE,
+                    qr_qd: qd as c_int,
+                    qr_qt: qt,
+                    qr_value: dmtr_qr_value_t { resolve },
+                }
+            },
+            OperationResult::Failed(e) => Self {
+                qr_opcode: dmtr_opcode_t::DMTR_OPC_FAILED,
+                qr_qd: qd as c_int,
+                qr_qt: qt,
+                qr_value: dmtr_qr_value_t {
+                    failed: dmtr_failed_result_t { error: to_errno(e) },
+                },
             },
         }
     }
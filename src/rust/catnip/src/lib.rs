@@ -39,6 +39,7 @@ pub mod options;
 pub mod protocols;
 pub mod runtime;
 pub mod scheduler;
+pub mod socket_options;
 pub mod sync;
 pub mod test_helpers;
 pub mod timer;
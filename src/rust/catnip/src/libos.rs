@@ -9,18 +9,51 @@ use crate::{
         dmtr_qresult_t,
         dmtr_sgarray_t,
     },
-    protocols::ipv4::Endpoint,
+    protocols::{
+        arp,
+        dhcp,
+        dns::{
+            self,
+            operations::{
+                DnsOperation,
+                ResolveFuture,
+            },
+        },
+        ethernet2::MacAddress,
+        icmpv4::{
+            operations::{
+                Icmpv4Operation,
+                PingFuture,
+                TracerouteFuture,
+            },
+            TracerouteHop,
+        },
+        ip::Endpoint,
+        tcp::ListenerStats,
+        udp,
+    },
     runtime::Runtime,
     scheduler::{
         Operation,
         SchedulerHandle,
     },
+    socket_options::{
+        SocketOption,
+        SocketOptionName,
+    },
     sync::BytesMut,
 };
 use libc::c_int;
 use std::{
+    net::{
+        IpAddr,
+        Ipv4Addr,
+    },
     slice,
-    time::Instant,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 use tracy_client::static_span;
 
@@ -56,7 +89,7 @@ impl<RT: Runtime> LibOS<RT> {
         socket_type: c_int,
         protocol: c_int,
     ) -> Result<FileDescriptor, Fail> {
-        if domain != libc::AF_INET {
+        if domain != libc::AF_INET && domain != libc::AF_INET6 {
             return Err(Fail::Invalid {
                 details: "Invalid domain",
             });
@@ -86,6 +119,14 @@ impl<RT: Runtime> LibOS<RT> {
         self.engine.listen(fd, backlog)
     }
 
+    pub fn listener_stats(&self, fd: FileDescriptor) -> Result<ListenerStats, Fail> {
+        self.engine.tcp_listener_stats(fd)
+    }
+
+    pub fn udp_socket_stats(&self, fd: FileDescriptor) -> Result<udp::SocketStats, Fail> {
+        self.engine.udp_socket_stats(fd)
+    }
+
     pub fn accept(&mut self, fd: FileDescriptor) -> u64 {
         let future = self.engine.accept(fd);
         self.rt.scheduler().insert(future).into_raw()
@@ -100,6 +141,39 @@ impl<RT: Runtime> LibOS<RT> {
         self.engine.close(fd)
     }
 
+    pub fn async_close(&mut self, fd: FileDescriptor) -> QToken {
+        let future = self.engine.async_close(fd);
+        self.rt.scheduler().insert(future).into_raw()
+    }
+
+    pub fn setsockopt(&mut self, fd: FileDescriptor, option: SocketOption) -> Result<(), Fail> {
+        self.engine.setsockopt(fd, option)
+    }
+
+    pub fn getsockopt(
+        &self,
+        fd: FileDescriptor,
+        name: SocketOptionName,
+    ) -> Result<SocketOption, Fail> {
+        self.engine.getsockopt(fd, name)
+    }
+
+    pub fn join_multicast_group(
+        &mut self,
+        fd: FileDescriptor,
+        group: Ipv4Addr,
+    ) -> Result<(), Fail> {
+        self.engine.join_multicast_group(fd, group)
+    }
+
+    pub fn leave_multicast_group(
+        &mut self,
+        fd: FileDescriptor,
+        group: Ipv4Addr,
+    ) -> Result<(), Fail> {
+        self.engine.leave_multicast_group(fd, group)
+    }
+
     pub fn push(&mut self, fd: FileDescriptor, sga: &dmtr_sgarray_t) -> QToken {
         let _s = static_span!();
         let mut len = 0;
@@ -142,6 +216,120 @@ impl<RT: Runtime> LibOS<RT> {
         self.rt.scheduler().insert(future).into_raw()
     }
 
+    pub fn dhcp_lease(&self) -> Option<dhcp::Lease> {
+        self.engine.dhcp_lease()
+    }
+
+    /// Runs the background work until the DHCP client has a lease.
+    pub fn wait_for_dhcp_lease(&mut self, timeout: Duration) -> Result<dhcp::Lease, Fail> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.poll_bg_work();
+            if let Some(lease) = self.engine.dhcp_lease() {
+                return Ok(lease);
+            }
+            if Instant::now() >= deadline {
+                return Err(Fail::Timeout {});
+            }
+        }
+    }
+
+    /// The last host we found using our IPv4 address, if there was one.
+    pub fn ipv4_addr_conflict(&self) -> Option<arp::Conflict> {
+        self.engine.ipv4_addr_conflict()
+    }
+
+    pub fn arp_entries(&self) -> Vec<arp::Entry> {
+        self.engine.arp_entries()
+    }
+
+    pub fn add_arp_entry(
+        &mut self,
+        ipv4_addr: Ipv4Addr,
+        link_addr: MacAddress,
+    ) -> Result<(), Fail> {
+        self.engine.add_arp_entry(ipv4_addr, link_addr)
+    }
+
+    pub fn remove_arp_entry(&mut self, ipv4_addr: Ipv4Addr) -> Result<(), Fail> {
+        self.engine.remove_arp_entry(ipv4_addr)
+    }
+
+    pub fn flush_arp_cache(&mut self) {
+        self.engine.flush_arp_cache()
+    }
+
+    /// Runs the background work until we're done probing for other hosts using our IPv4 address.
+    pub fn wait_for_ipv4_addr(&mut self, timeout: Duration) -> Result<(), Fail> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.poll_bg_work();
+            if self.engine.ipv4_addr_conflict().is_some() {
+                return Err(Fail::AddressInUse {});
+            }
+            if !self.engine.is_probing_ipv4_addr() {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(Fail::Timeout {});
+            }
+        }
+    }
+
+    pub fn ping(
+        &mut self,
+        dst: Ipv4Addr,
+        payload_size: usize,
+        timeout: Option<Duration>,
+    ) -> QToken {
+        let future: PingFuture = Box::pin(self.engine.ping(dst, payload_size, timeout));
+        let op = Operation::Icmpv4(Icmpv4Operation::from(future));
+        self.rt.scheduler().insert(op).into_raw()
+    }
+
+    /// Hands the hops to `store` once the traceroute finishes, leaving only their number in the
+    /// result.
+    pub fn traceroute<F>(
+        &mut self,
+        dst: Ipv4Addr,
+        max_hops: u8,
+        timeout: Option<Duration>,
+        store: F,
+    ) -> QToken
+    where
+        F: FnOnce(&[Option<TracerouteHop>]) + 'static,
+    {
+        let traceroute = self.engine.traceroute(dst, max_hops, timeout);
+        let future: TracerouteFuture = Box::pin(async move {
+            let hops = traceroute.await?;
+            store(&hops[..]);
+            Ok(hops.len())
+        });
+        let op = Operation::Icmpv4(Icmpv4Operation::from(future));
+        self.rt.scheduler().insert(op).into_raw()
+    }
+
+    /// Like `traceroute`, hands the addresses to `store` and only leaves their number in the result.
+    pub fn resolve<F>(
+        &mut self,
+        name: &str,
+        record_types: &[dns::RecordType],
+        timeout: Option<Duration>,
+        store: F,
+    ) -> QToken
+    where
+        F: FnOnce(&[IpAddr]) + 'static,
+    {
+        let resolve = self.engine.resolve(name, record_types, timeout);
+        let future: ResolveFuture = Box::pin(async move {
+            let addrs = resolve.await?;
+            store(&addrs[..]);
+            Ok(addrs.len())
+        });
+        let op = Operation::Dns(DnsOperation::from(future));
+        self.rt.scheduler().insert(op).into_raw()
+    }
+
     pub fn drop_qtoken(&mut self, qt: QToken) {
         drop(self.rt.scheduler().from_raw_handle(qt).unwrap());
     }
@@ -190,6 +378,9 @@ impl<RT: Runtime> LibOS<RT> {
         let (qd, r) = match self.rt.scheduler().take(handle) {
             Operation::Tcp(f) => f.expect_result(),
             Operation::Udp(f) => f.expect_result(),
+            // Pings aren't tied to a queue descriptor.
+            Operation::Icmpv4(f) => (0, f.expect_result()),
+            Operation::Dns(f) => (0, f.expect_result()),
             Operation::Background(..) => panic!("Polled background operation"),
         };
         dmtr_qresult_t::pack(r, qd, qt)
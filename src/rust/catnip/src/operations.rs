@@ -1,7 +1,7 @@
 use crate::{
     fail::Fail,
     file_table::FileDescriptor,
-    protocols::ipv4,
+    protocols::ip,
     sync::Bytes,
 };
 use std::{
@@ -11,6 +11,7 @@ use std::{
         Context,
         Poll,
     },
+    time::Duration,
 };
 
 pub struct ResultFuture<F: Future> {
@@ -47,7 +48,13 @@ where
 pub enum OperationResult {
     Connect,
     Accept(FileDescriptor),
+    Close,
     Push,
-    Pop(Option<ipv4::Endpoint>, Bytes),
+    Pop(Option<ip::Endpoint>, Bytes),
+    // Ping and traceroute carry their errors along since they're reported through the result.
+    Ping(Result<Duration, Fail>),
+    // The hops and addresses themselves went to the caller's buffer, so only their number is left.
+    Traceroute(Result<usize, Fail>),
+    Resolve(Result<usize, Fail>),
     Failed(Fail),
 }
@@ -4,6 +4,7 @@
 use crate::protocols::{
     arp,
     ethernet2::MacAddress,
+    ipv4,
     tcp,
 };
 use rand::{
@@ -15,6 +16,7 @@ use std::net::Ipv4Addr;
 #[derive(Clone, Debug)]
 pub struct Options {
     pub arp: arp::Options,
+    pub ipv4: ipv4::Options,
     pub my_ipv4_addr: Ipv4Addr,
     pub my_link_addr: MacAddress,
     pub rng_seed: [u8; 32],
@@ -27,6 +29,7 @@ impl Default for Options {
         thread_rng().fill(rng_seed.as_mut());
         Options {
             arp: arp::Options::default(),
+            ipv4: ipv4::Options::default(),
             my_ipv4_addr: Ipv4Addr::new(0, 0, 0, 0),
             my_link_addr: MacAddress::nil(),
             rng_seed,
@@ -41,6 +44,11 @@ impl Options {
         self
     }
 
+    pub fn ipv4(mut self, value: ipv4::Options) -> Self {
+        self.ipv4 = value;
+        self
+    }
+
     pub fn my_ipv4_addr(mut self, value: Ipv4Addr) -> Self {
         assert!(!value.is_unspecified());
         assert!(!value.is_broadcast());
@@ -9,6 +9,7 @@ mod tests;
 
 use crate::{
     collections::HashTtlCache,
+    fail::Fail,
     protocols::ethernet2::MacAddress,
 };
 use futures::{
@@ -34,15 +35,32 @@ const DUMMY_MAC_ADDRESS: MacAddress = MacAddress::new([0; 6]);
 struct Record {
     link_addr: MacAddress,
     ipv4_addr: Ipv4Addr,
+    is_static: bool,
+    expires_at: Option<Instant>,
+    // Whether we sent to the neighbor, or heard that it's reachable from above, since we learned
+    // the entry.
+    used: bool,
+    confirmed: bool,
+}
+
+/// An entry in the ARP table. Static entries never expire and aren't replaced by what we learn
+/// from the network.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ArpEntry {
+    pub ipv4_addr: Ipv4Addr,
+    pub link_addr: MacAddress,
+    pub is_static: bool,
 }
 
 pub struct ArpCache {
     cache: HashTtlCache<Ipv4Addr, Record>,
+    default_ttl: Option<Duration>,
+    clock: Instant,
     rmap: HashMap<MacAddress, Ipv4Addr>,
+    // Addresses we recently failed to resolve.
+    failures: HashTtlCache<Ipv4Addr, ()>,
 
-    // TODO: Allow multiple waiters for the same address
-    // TODO: Deregister waiters here when the receiver goes away.
-    waiters: HashMap<Ipv4Addr, Sender<MacAddress>>,
+    waiters: HashMap<Ipv4Addr, Vec<Sender<Result<MacAddress, Fail>>>>,
     arp_disabled: bool,
 }
 
@@ -50,7 +68,10 @@ impl ArpCache {
     pub fn new(now: Instant, default_ttl: Option<Duration>, arp_disabled: bool) -> ArpCache {
         ArpCache {
             cache: HashTtlCache::new(now, default_ttl),
+            default_ttl,
+            clock: now,
             rmap: HashMap::default(),
+            failures: HashTtlCache::new(now, None),
             waiters: HashMap::default(),
             arp_disabled,
         }
@@ -62,44 +83,104 @@ impl ArpCache {
         link_addr: MacAddress,
         ttl: Option<Duration>,
     ) -> Option<MacAddress> {
+        // What we learn doesn't override what we were told.
+        if let Some(r) = self.cache.get(&ipv4_addr) {
+            if r.is_static {
+                return Some(r.link_addr);
+            }
+        }
         let record = Record {
             ipv4_addr,
             link_addr,
+            is_static: false,
+            expires_at: None,
+            used: false,
+            confirmed: false,
         };
-
-        let result = self
-            .cache
-            .insert_with_ttl(ipv4_addr, record, ttl)
-            .map(|r| r.link_addr);
-        self.rmap.insert(link_addr, ipv4_addr);
-        if let Some(sender) = self.waiters.remove(&ipv4_addr) {
-            let _ = sender.send(link_addr);
-        }
-        result
+        self.insert_record(record, ttl)
     }
 
     pub fn insert(&mut self, ipv4_addr: Ipv4Addr, link_addr: MacAddress) -> Option<MacAddress> {
+        let ttl = self.default_ttl;
+        self.insert_with_ttl(ipv4_addr, link_addr, ttl)
+    }
+
+    /// Adds an entry that stays until it's removed.
+    pub fn insert_static(
+        &mut self,
+        ipv4_addr: Ipv4Addr,
+        link_addr: MacAddress,
+    ) -> Option<MacAddress> {
         let record = Record {
             ipv4_addr,
             link_addr,
+            is_static: true,
+            expires_at: None,
+            used: false,
+            confirmed: false,
         };
-        if let Some(sender) = self.waiters.remove(&ipv4_addr) {
-            let _ = sender.send(link_addr);
+        self.insert_record(record, None)
+    }
+
+    fn insert_record(&mut self, mut record: Record, ttl: Option<Duration>) -> Option<MacAddress> {
+        record.expires_at = ttl.map(|ttl| self.clock + ttl);
+        let ipv4_addr = record.ipv4_addr;
+        let link_addr = record.link_addr;
+        let result = self
+            .cache
+            .insert_with_ttl(ipv4_addr, record, ttl)
+            .map(|r| r.link_addr);
+        if let Some(old_link_addr) = result {
+            if old_link_addr != link_addr {
+                self.rmap.remove(&old_link_addr);
+            }
         }
-        let result = self.cache.insert(ipv4_addr, record).map(|r| r.link_addr);
         self.rmap.insert(link_addr, ipv4_addr);
+        self.failures.remove(&ipv4_addr);
+        self.wake_waiters(ipv4_addr, Ok(link_addr));
         result
     }
 
-    pub fn remove(&mut self, ipv4_addr: Ipv4Addr) {
-        if let Some(record) = self.cache.remove(&ipv4_addr) {
-            assert!(self.rmap.remove(&record.link_addr).is_some());
-        } else {
-            panic!(
-                "attempt to remove unrecognized engine (`{}`) from ARP cache",
-                ipv4_addr
-            );
+    /// Remembers that `ipv4_addr` didn't answer for `ttl` and fails everyone waiting for it.
+    pub fn insert_failure(&mut self, ipv4_addr: Ipv4Addr, ttl: Option<Duration>) {
+        if ttl.is_some() {
+            self.failures.insert_with_ttl(ipv4_addr, (), ttl);
         }
+        self.wake_waiters(ipv4_addr, Err(Fail::Timeout {}));
+    }
+
+    pub fn has_failed(&self, ipv4_addr: Ipv4Addr) -> bool {
+        self.failures.get(&ipv4_addr).is_some()
+    }
+
+    fn wake_waiters(&mut self, ipv4_addr: Ipv4Addr, result: Result<MacAddress, Fail>) {
+        if let Some(senders) = self.waiters.remove(&ipv4_addr) {
+            for sender in senders {
+                let _ = sender.send(result.clone());
+            }
+        }
+    }
+
+    pub fn remove(&mut self, ipv4_addr: Ipv4Addr) -> Option<MacAddress> {
+        let record = self.cache.remove(&ipv4_addr)?;
+        if self.rmap.get(&record.link_addr) == Some(&ipv4_addr) {
+            self.rmap.remove(&record.link_addr);
+        }
+        Some(record.link_addr)
+    }
+
+    /// Removes everything we learned, keeping the static entries.
+    pub fn flush(&mut self) {
+        let learned: Vec<Ipv4Addr> = self
+            .cache
+            .iter()
+            .filter(|(_, r)| !r.is_static)
+            .map(|(&k, _)| k)
+            .collect();
+        for ipv4_addr in learned {
+            self.remove(ipv4_addr);
+        }
+        self.failures.clear();
     }
 
     pub fn get_link_addr(&self, ipv4_addr: Ipv4Addr) -> Option<&MacAddress> {
@@ -111,14 +192,60 @@ impl ArpCache {
         result
     }
 
-    pub fn wait_link_addr(&mut self, ipv4_addr: Ipv4Addr) -> impl Future<Output = MacAddress> {
+    /// Like `get_link_addr`, but we'll try to keep the entry from expiring since it's in use.
+    pub fn use_link_addr(&mut self, ipv4_addr: Ipv4Addr) -> Option<MacAddress> {
+        if self.arp_disabled {
+            return Some(DUMMY_MAC_ADDRESS);
+        }
+        let record = self.cache.get_mut(&ipv4_addr)?;
+        record.used = true;
+        Some(record.link_addr)
+    }
+
+    /// Notes that the neighbor at `ipv4_addr` is still there, like when it acknowledges our data.
+    pub fn confirm(&mut self, ipv4_addr: Ipv4Addr) {
+        if let Some(record) = self.cache.get_mut(&ipv4_addr) {
+            record.confirmed = true;
+        }
+    }
+
+    /// Looks at the entries that expire within `window`. Confirmed ones get renewed, and the ones
+    /// that were only used are returned, so we can ask the neighbors if they're still there. The
+    /// rest are left to expire.
+    pub fn refresh(&mut self, window: Duration) -> Vec<(Ipv4Addr, MacAddress)> {
+        let mut confirmed = vec![];
+        let mut stale = vec![];
+        for (&ipv4_addr, record) in self.cache.iter() {
+            match record.expires_at {
+                Some(expires_at) if expires_at <= self.clock + window => (),
+                _ => continue,
+            }
+            if record.confirmed {
+                confirmed.push((ipv4_addr, record.link_addr));
+            } else if record.used {
+                stale.push((ipv4_addr, record.link_addr));
+            }
+        }
+        for (ipv4_addr, link_addr) in confirmed {
+            self.insert(ipv4_addr, link_addr);
+        }
+        stale
+    }
+
+    pub fn wait_link_addr(
+        &mut self,
+        ipv4_addr: Ipv4Addr,
+    ) -> impl Future<Output = Result<MacAddress, Fail>> {
         let (tx, rx) = channel();
         if self.arp_disabled {
-            let _ = tx.send(DUMMY_MAC_ADDRESS);
+            let _ = tx.send(Ok(DUMMY_MAC_ADDRESS));
         } else if let Some(r) = self.cache.get(&ipv4_addr) {
-            let _ = tx.send(r.link_addr);
+            let _ = tx.send(Ok(r.link_addr));
         } else {
-            assert!(self.waiters.insert(ipv4_addr, tx).is_none());
+            let senders = self.waiters.entry(ipv4_addr).or_insert_with(Vec::new);
+            // Forget the waiters that went away in the meantime.
+            senders.retain(|s| !s.is_canceled());
+            senders.push(tx);
         }
         rx.map(|r| r.expect("Dropped waiter?"))
     }
@@ -129,10 +256,13 @@ impl ArpCache {
     }
 
     pub fn advance_clock(&mut self, now: Instant) {
-        self.cache.advance_clock(now)
+        self.clock = now;
+        self.cache.advance_clock(now);
+        self.failures.advance_clock(now);
     }
 
     pub fn try_evict(&mut self, count: usize) -> HashMap<Ipv4Addr, MacAddress> {
+        self.failures.try_evict(count);
         let evicted = self.cache.try_evict(count);
         let mut result = HashMap::default();
         for (k, v) in &evicted {
@@ -146,6 +276,18 @@ impl ArpCache {
     pub fn clear(&mut self) {
         self.cache.clear();
         self.rmap.clear();
+        self.failures.clear();
+    }
+
+    pub fn entries(&self) -> Vec<ArpEntry> {
+        self.cache
+            .iter()
+            .map(|(_, r)| ArpEntry {
+                ipv4_addr: r.ipv4_addr,
+                link_addr: r.link_addr,
+                is_static: r.is_static,
+            })
+            .collect()
     }
 
     pub fn export(&self) -> HashMap<Ipv4Addr, MacAddress> {
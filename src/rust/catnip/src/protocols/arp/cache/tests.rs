@@ -21,3 +21,35 @@ fn with_default_ttl() {
     assert!(evicted.contains_key(&test_helpers::ALICE_IPV4));
     assert!(cache.get_link_addr(test_helpers::ALICE_IPV4).is_none());
 }
+
+#[test]
+fn refresh() {
+    // tests to ensure that entries about to expire are renewed if they were
+    // confirmed, reported if they were used and otherwise left to expire.
+    let now = Instant::now();
+    let later = now + Duration::from_secs(8);
+    let even_later = now + Duration::from_secs(10);
+
+    let mut cache = ArpCache::new(now, Some(Duration::from_secs(10)), false);
+    cache.insert(test_helpers::ALICE_IPV4, test_helpers::ALICE_MAC);
+    cache.insert(test_helpers::BOB_IPV4, test_helpers::BOB_MAC);
+    cache.insert(test_helpers::CARRIE_IPV4, test_helpers::CARRIE_MAC);
+    cache.confirm(test_helpers::ALICE_IPV4);
+    assert_eq!(
+        cache.use_link_addr(test_helpers::BOB_IPV4),
+        Some(test_helpers::BOB_MAC)
+    );
+    assert!(cache.refresh(Duration::from_secs(1)).is_empty());
+
+    cache.advance_clock(later);
+    assert!(cache.refresh(Duration::from_secs(1)).is_empty());
+    assert_eq!(
+        cache.refresh(Duration::from_secs(2)),
+        vec![(test_helpers::BOB_IPV4, test_helpers::BOB_MAC)]
+    );
+
+    cache.advance_clock(even_later);
+    assert!(cache.get_link_addr(test_helpers::ALICE_IPV4) == Some(&test_helpers::ALICE_MAC));
+    assert!(cache.get_link_addr(test_helpers::BOB_IPV4).is_none());
+    assert!(cache.get_link_addr(test_helpers::CARRIE_IPV4).is_none());
+}
@@ -9,5 +9,17 @@ mod peer;
 #[cfg(test)]
 mod tests;
 
+pub use cache::ArpEntry as Entry;
 pub use options::ArpOptions as Options;
-pub use peer::ArpPeer as Peer;
+pub use peer::{
+    ArpConflict as Conflict,
+    ArpPeer as Peer,
+    PendingFrame,
+};
+// Lets other protocols' tests play a host that claims the same address.
+#[cfg(test)]
+pub use pdu::{
+    ArpMessage,
+    ArpOperation,
+    ArpPdu,
+};
@@ -11,9 +11,21 @@ use std::{
 #[derive(Clone, Debug)]
 pub struct ArpOptions {
     pub cache_ttl: Duration,
+    // How long before an entry in use expires we start asking the neighbor for it again.
+    pub refresh_window: Duration,
+    // How long to wait for a reply to the first request, doubling for each retry.
     pub request_timeout: Duration,
     pub retry_count: usize,
+    // How long to remember that an address didn't answer.
+    pub negative_cache_ttl: Duration,
+    // How many frames may wait for an address to be resolved.
+    pub max_pending_frames: usize,
+    // RFC 5227: How many probes to send before using an address, and how many announcements
+    // after. Zero skips that step.
+    pub probe_count: usize,
+    pub announce_count: usize,
 
+    // Static entries, which don't expire.
     pub initial_values: HashMap<MacAddress, Ipv4Addr>,
     pub disable_arp: bool,
 }
@@ -22,8 +34,13 @@ impl Default for ArpOptions {
     fn default() -> Self {
         ArpOptions {
             cache_ttl: Duration::from_secs(15),
-            request_timeout: Duration::from_secs(20),
+            refresh_window: Duration::from_secs(3),
+            request_timeout: Duration::from_secs(1),
             retry_count: 5,
+            negative_cache_ttl: Duration::from_secs(5),
+            max_pending_frames: 16,
+            probe_count: 3,
+            announce_count: 2,
             initial_values: HashMap::new(),
             disable_arp: false,
         }
@@ -48,4 +65,32 @@ impl ArpOptions {
         self.retry_count = value;
         self
     }
+
+    pub fn refresh_window(mut self, value: Duration) -> Self {
+        assert!(value > Duration::new(0, 0));
+        self.refresh_window = value;
+        self
+    }
+
+    pub fn negative_cache_ttl(mut self, value: Duration) -> Self {
+        assert!(value > Duration::new(0, 0));
+        self.negative_cache_ttl = value;
+        self
+    }
+
+    pub fn max_pending_frames(mut self, value: usize) -> Self {
+        assert!(value > 0);
+        self.max_pending_frames = value;
+        self
+    }
+
+    pub fn probe_count(mut self, value: usize) -> Self {
+        self.probe_count = value;
+        self
+    }
+
+    pub fn announce_count(mut self, value: usize) -> Self {
+        self.announce_count = value;
+        self
+    }
 }
@@ -2,7 +2,10 @@
 // Licensed under the MIT license.
 
 use super::{
-    cache::ArpCache,
+    cache::{
+        ArpCache,
+        ArpEntry,
+    },
     pdu::{
         ArpMessage,
         ArpOperation,
@@ -10,13 +13,20 @@ use super::{
     },
 };
 use crate::{
+    collections::watched::WatchedValue,
     fail::Fail,
-    protocols::ethernet2::{
-        frame::{
-            EtherType2,
-            Ethernet2Header,
+    protocols::{
+        ethernet2::{
+            frame::{
+                EtherType2,
+                Ethernet2Header,
+            },
+            MacAddress,
+        },
+        ipv4::{
+            self,
+            routing::multicast_link_addr,
         },
-        MacAddress,
     },
     runtime::Runtime,
     scheduler::SchedulerHandle,
@@ -26,6 +36,7 @@ use futures::FutureExt;
 use hashbrown::HashMap;
 use std::{
     cell::RefCell,
+    collections::VecDeque,
     future::Future,
     net::Ipv4Addr,
     rc::Rc,
@@ -35,41 +46,124 @@ use std::{
     },
 };
 
+// RFC 5227 section 1.1: Timing of probes, announcements and defenses.
+const PROBE_WAIT: Duration = Duration::from_secs(1);
+const PROBE_MIN: Duration = Duration::from_secs(1);
+const PROBE_MAX: Duration = Duration::from_secs(2);
+const ANNOUNCE_WAIT: Duration = Duration::from_secs(2);
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(2);
+const DEFEND_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Another host using the address we claimed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArpConflict {
+    pub ipv4_addr: Ipv4Addr,
+    pub link_addr: MacAddress,
+}
+
+/// Sends a frame once it knows the link address to send it to, or learns why it won't.
+pub type PendingFrame = Box<dyn FnOnce(Result<MacAddress, Fail>) -> Result<(), Fail>>;
+
+/// An address we're sending requests for.
+struct Resolution {
+    num_requests: usize,
+    retransmit_at: Instant,
+    timeout: Duration,
+    // Frames waiting for the address, oldest first, and the task resolving it for them.
+    pending: VecDeque<PendingFrame>,
+    driver: Option<SchedulerHandle>,
+}
+
+impl Resolution {
+    fn new(now: Instant, timeout: Duration) -> Self {
+        Self {
+            num_requests: 0,
+            retransmit_at: now,
+            timeout,
+            pending: VecDeque::new(),
+            driver: None,
+        }
+    }
+}
+
+struct Claim {
+    ipv4_addr: Ipv4Addr,
+    // Until probing is done, the address isn't ours to answer for.
+    probing: bool,
+    last_defense: Option<Instant>,
+}
+
 #[derive(Clone)]
 pub struct ArpPeer<RT: Runtime> {
     rt: RT,
     // TODO: Move this to a strong owner that gets polled once.
     cache: Rc<RefCell<ArpCache>>,
+    routes: Rc<RefCell<ipv4::RoutingTable>>,
+    resolutions: Rc<RefCell<HashMap<Ipv4Addr, Resolution>>>,
+    claimed: Rc<RefCell<Option<Claim>>>,
+    conflict: Rc<WatchedValue<Option<ArpConflict>>>,
     background: Rc<SchedulerHandle>,
+    startup: Option<Rc<SchedulerHandle>>,
 }
 
 impl<RT: Runtime> ArpPeer<RT> {
-    pub fn new(now: Instant, rt: RT) -> Result<ArpPeer<RT>, Fail> {
+    pub fn new(
+        now: Instant,
+        rt: RT,
+        routes: Rc<RefCell<ipv4::RoutingTable>>,
+    ) -> Result<ArpPeer<RT>, Fail> {
         let options = rt.arp_options();
         let cache = Rc::new(RefCell::new(ArpCache::new(
             now,
             Some(options.cache_ttl),
             options.disable_arp,
         )));
-        let handle = rt.spawn(Self::background(rt.clone(), cache.clone()));
-        let peer = ArpPeer {
-            rt,
+        let handle = rt.spawn(Self::background(rt.clone(), cache.clone(), routes.clone()));
+        let mut peer = ArpPeer {
+            rt: rt.clone(),
             cache,
+            routes,
+            resolutions: Rc::new(RefCell::new(HashMap::new())),
+            claimed: Rc::new(RefCell::new(None)),
+            conflict: Rc::new(WatchedValue::new(None)),
             background: Rc::new(handle),
+            startup: None,
         };
         for (&link_addr, &ipv4_addr) in &options.initial_values {
-            peer.insert(ipv4_addr, link_addr);
+            peer.insert_static(ipv4_addr, link_addr)?;
+        }
+        let local_ipv4_addr = rt.local_ipv4_addr();
+        if !local_ipv4_addr.is_unspecified() {
+            let claim = peer.claim(local_ipv4_addr);
+            let handle = rt.spawn(async move {
+                if let Err(e) = claim.await {
+                    warn!("Failed to claim {}: {:?}", local_ipv4_addr, e);
+                }
+            });
+            peer.startup = Some(Rc::new(handle));
         }
         Ok(peer)
     }
 
-    async fn background(rt: RT, cache: Rc<RefCell<ArpCache>>) {
+    async fn background(
+        rt: RT,
+        cache: Rc<RefCell<ArpCache>>,
+        routes: Rc<RefCell<ipv4::RoutingTable>>,
+    ) {
         loop {
             let current_time = rt.now();
-            {
+            let stale = {
                 let mut cache = cache.borrow_mut();
                 cache.advance_clock(current_time);
+                let stale = cache.refresh(rt.arp_options().refresh_window);
                 cache.try_evict(2);
+                stale
+            };
+            // Ask the neighbors we're still talking to directly, so their entries are renewed
+            // before they expire and nobody has to wait for a broadcast request.
+            for (ipv4_addr, link_addr) in stale {
+                let local_ipv4_addr = routes.borrow().source_addr(ipv4_addr);
+                rt.transmit(Self::request_to(&rt, link_addr, local_ipv4_addr, ipv4_addr));
             }
             // TODO: Make this more precise.
             rt.wait(Duration::from_secs(1)).await;
@@ -83,6 +177,9 @@ impl<RT: Runtime> ArpPeer<RT> {
         // > ?Do I speak the protocol in ar$pro?
         // > [optionally check the protocol length ar$pln]
         let pdu = ArpPdu::parse(buf)?;
+        if self.detect_conflict(&pdu) {
+            return Ok(());
+        }
 
         // from RFC 826:
         // > Merge_flag := false
@@ -90,17 +187,16 @@ impl<RT: Runtime> ArpPeer<RT> {
         // > already in my translation table, update the sender
         // > hardware address field of the entry with the new
         // > information in the packet and set Merge_flag to true.
-        let merge_flag = {
-            let mut cache = self.cache.borrow_mut();
-            if cache.get_link_addr(pdu.sender_protocol_addr).is_some() {
-                cache.insert(pdu.sender_protocol_addr, pdu.sender_hardware_addr);
-                true
-            } else {
-                false
-            }
-        };
+        let merge_flag = self
+            .cache
+            .borrow()
+            .get_link_addr(pdu.sender_protocol_addr)
+            .is_some();
+        if merge_flag {
+            self.learn(pdu.sender_protocol_addr, pdu.sender_hardware_addr);
+        }
         // from RFC 826: ?Am I the target protocol address?
-        if pdu.target_protocol_addr != self.rt.local_ipv4_addr() {
+        if !self.routes.borrow().is_local(pdu.target_protocol_addr) {
             if merge_flag {
                 // we did do something.
                 return Ok(());
@@ -115,10 +211,9 @@ impl<RT: Runtime> ArpPeer<RT> {
         // > If Merge_flag is false, add the triplet <protocol type,
         // > sender protocol address, sender hardware address> to
         // > the translation table.
-        if !merge_flag {
-            self.cache
-                .borrow_mut()
-                .insert(pdu.sender_protocol_addr, pdu.sender_hardware_addr);
+        // RFC 5227 section 2.1.1: Probes don't have a sender address to remember.
+        if !merge_flag && !pdu.sender_protocol_addr.is_unspecified() {
+            self.learn(pdu.sender_protocol_addr, pdu.sender_hardware_addr);
         }
 
         match pdu.operation {
@@ -130,12 +225,13 @@ impl<RT: Runtime> ArpPeer<RT> {
                     ethernet2_hdr: Ethernet2Header {
                         dst_addr: pdu.sender_hardware_addr,
                         src_addr: self.rt.local_link_addr(),
+                        vlan_tags: self.rt.ethernet2_options().vlan_tags(None),
                         ether_type: EtherType2::Arp,
                     },
                     arp_pdu: ArpPdu {
                         operation: ArpOperation::Reply,
                         sender_hardware_addr: self.rt.local_link_addr(),
-                        sender_protocol_addr: self.rt.local_ipv4_addr(),
+                        sender_protocol_addr: pdu.target_protocol_addr,
                         target_hardware_addr: pdu.sender_hardware_addr,
                         target_protocol_addr: pdu.sender_protocol_addr,
                     },
@@ -148,61 +244,354 @@ impl<RT: Runtime> ArpPeer<RT> {
                     "reply from `{}/{}`",
                     pdu.sender_protocol_addr, pdu.sender_hardware_addr
                 );
-                self.cache
-                    .borrow_mut()
-                    .insert(pdu.sender_protocol_addr, pdu.sender_hardware_addr);
+                self.learn(pdu.sender_protocol_addr, pdu.sender_hardware_addr);
                 Ok(())
             },
         }
     }
 
+    /// Remembers `link_addr` for `ipv4_addr` and sends the frames that were waiting for it.
+    fn learn(&self, ipv4_addr: Ipv4Addr, link_addr: MacAddress) {
+        self.cache.borrow_mut().insert(ipv4_addr, link_addr);
+        self.send_pending(ipv4_addr, link_addr);
+    }
+
+    fn send_pending(&self, ipv4_addr: Ipv4Addr, link_addr: MacAddress) {
+        let resolution = self.resolutions.borrow_mut().remove(&ipv4_addr);
+        if let Some(resolution) = resolution {
+            for send in resolution.pending {
+                if let Err(e) = send(Ok(link_addr)) {
+                    warn!("Failed to send frame to {}: {:?}", ipv4_addr, e);
+                }
+            }
+        }
+    }
+
+    /// Looks up the link address for datagrams to `ipv4_addr`. Off-link destinations resolve to
+    /// their gateway's link address.
     pub fn try_query(&self, ipv4_addr: Ipv4Addr) -> Option<MacAddress> {
-        self.cache.borrow().get_link_addr(ipv4_addr).cloned()
+        let next_hop = self.routes.borrow().next_hop(ipv4_addr).ok()?;
+        if next_hop.is_broadcast() {
+            return Some(MacAddress::broadcast());
+        }
+        if next_hop.is_multicast() {
+            return Some(multicast_link_addr(next_hop));
+        }
+        self.cache.borrow_mut().use_link_addr(next_hop)
     }
 
+    /// Like `try_query`, but sends ARP requests on a cache miss. All queries for an address share
+    /// the same requests.
     pub fn query(&self, ipv4_addr: Ipv4Addr) -> impl Future<Output = Result<MacAddress, Fail>> {
-        let rt = self.rt.clone();
-        let cache = self.cache.clone();
+        let peer = self.clone();
+        let next_hop = self.routes.borrow().next_hop(ipv4_addr);
         async move {
-            if let Some(&link_addr) = cache.borrow().get_link_addr(ipv4_addr) {
-                return Ok(link_addr);
-            }
-            let msg = ArpMessage {
-                ethernet2_hdr: Ethernet2Header {
-                    dst_addr: MacAddress::broadcast(),
-                    src_addr: rt.local_link_addr(),
-                    ether_type: EtherType2::Arp,
-                },
-                arp_pdu: ArpPdu {
-                    operation: ArpOperation::Request,
-                    sender_hardware_addr: rt.local_link_addr(),
-                    sender_protocol_addr: rt.local_ipv4_addr(),
-                    target_hardware_addr: MacAddress::broadcast(),
-                    target_protocol_addr: ipv4_addr,
-                },
+            let ipv4_addr = next_hop?;
+            if ipv4_addr.is_broadcast() {
+                return Ok(MacAddress::broadcast());
+            }
+            if ipv4_addr.is_multicast() {
+                return Ok(multicast_link_addr(ipv4_addr));
+            }
+            let arp_response = {
+                let mut cache = peer.cache.borrow_mut();
+                cache.advance_clock(peer.rt.now());
+                if let Some(link_addr) = cache.use_link_addr(ipv4_addr) {
+                    return Ok(link_addr);
+                }
+                if cache.has_failed(ipv4_addr) {
+                    return Err(Fail::Timeout {});
+                }
+                cache.wait_link_addr(ipv4_addr).fuse()
             };
-            let arp_response = cache.borrow_mut().wait_link_addr(ipv4_addr).fuse();
             futures::pin_mut!(arp_response);
+            loop {
+                let retransmit_at = peer.retransmit(ipv4_addr)?;
+                futures::select! {
+                    r = arp_response => return r,
+                    _ = peer.rt.wait_until(retransmit_at).fuse() => (),
+                }
+            }
+        }
+    }
 
-            // from TCP/IP illustrated, chapter 4:
-            // > The frequency of the ARP request is very close to one per
-            // > second, the maximum suggested by [RFC1122].
-            let arp_options = rt.arp_options();
+    /// Calls `send` with the link address for `ipv4_addr`, right away if we know it. Otherwise the
+    /// frame waits for the address to be resolved, and the oldest waiting frame to the same next
+    /// hop gets dropped if there are too many. `send` gets the error instead if the frame won't be
+    /// sent; if that happens right away, so does the error it returns.
+    pub fn transmit_when_resolved(
+        &self,
+        ipv4_addr: Ipv4Addr,
+        send: PendingFrame,
+    ) -> Result<(), Fail> {
+        if let Some(link_addr) = self.try_query(ipv4_addr) {
+            return send(Ok(link_addr));
+        }
+        let next_hop = match self.routes.borrow().next_hop(ipv4_addr) {
+            Ok(next_hop) => next_hop,
+            Err(e) => return send(Err(e)),
+        };
+        if self.cache.borrow().has_failed(next_hop) {
+            return send(Err(Fail::Timeout {}));
+        }
+        let options = self.rt.arp_options();
+        let mut resolutions = self.resolutions.borrow_mut();
+        let resolution = resolutions
+            .entry(next_hop)
+            .or_insert_with(|| Resolution::new(self.rt.now(), options.request_timeout));
+        let dropped = if resolution.pending.len() >= options.max_pending_frames {
+            warn!("Dropped frame waiting for {}", next_hop);
+            resolution.pending.pop_front()
+        } else {
+            None
+        };
+        resolution.pending.push_back(send);
+        // Someone has to keep asking while the frames wait.
+        if resolution.driver.is_none() {
+            let peer = self.clone();
+            let query = self.query(next_hop);
+            resolution.driver = Some(self.rt.spawn(async move {
+                match query.await {
+                    Ok(link_addr) => peer.send_pending(next_hop, link_addr),
+                    Err(e) => warn!("Failed to resolve {}: {:?}", next_hop, e),
+                }
+            }));
+        }
+        drop(resolutions);
+        if let Some(send) = dropped {
+            let _ = send(Err(Fail::ResourceExhausted {
+                details: "Too many frames waiting for ARP",
+            }));
+        }
+        Ok(())
+    }
 
-            for i in 0..arp_options.retry_count + 1 {
-                rt.transmit(msg.clone());
-                futures::select! {
-                    link_addr = arp_response => {
-                        debug!("ARP result available ({})", link_addr);
-                        return Ok(link_addr);
-                    },
-                    _ = rt.wait(arp_options.request_timeout).fuse() => {
-                        warn!("ARP request timeout; attempt {}.", i + 1);
-                    },
+    /// Sends the next request for `ipv4_addr` if it's due, and returns when the one after it is.
+    /// Fails once the last request went unanswered, dropping the frames waiting for the address.
+    fn retransmit(&self, ipv4_addr: Ipv4Addr) -> Result<Instant, Fail> {
+        let options = self.rt.arp_options();
+        let now = self.rt.now();
+        let mut resolutions = self.resolutions.borrow_mut();
+        let resolution = resolutions
+            .entry(ipv4_addr)
+            .or_insert_with(|| Resolution::new(now, options.request_timeout));
+        // Whoever was waiting on these requests gave up a while ago, so start over.
+        if resolution.driver.is_none() && now >= resolution.retransmit_at + resolution.timeout {
+            *resolution = Resolution::new(now, options.request_timeout);
+        }
+        if now < resolution.retransmit_at {
+            return Ok(resolution.retransmit_at);
+        }
+        if resolution.num_requests > options.retry_count {
+            let resolution = resolutions.remove(&ipv4_addr).unwrap();
+            drop(resolutions);
+            if !resolution.pending.is_empty() {
+                warn!(
+                    "Dropped {} frames waiting for {}",
+                    resolution.pending.len(),
+                    ipv4_addr
+                );
+            }
+            {
+                let mut cache = self.cache.borrow_mut();
+                cache.advance_clock(now);
+                cache.insert_failure(ipv4_addr, Some(options.negative_cache_ttl));
+            }
+            for send in resolution.pending {
+                let _ = send(Err(Fail::Timeout {}));
+            }
+            return Err(Fail::Timeout {});
+        }
+        if resolution.num_requests > 0 {
+            warn!("ARP request timeout; attempt {}.", resolution.num_requests);
+        }
+        let local_ipv4_addr = self.routes.borrow().source_addr(ipv4_addr);
+        self.rt.transmit(self.request(local_ipv4_addr, ipv4_addr));
+        resolution.num_requests += 1;
+        resolution.retransmit_at = now + resolution.timeout;
+        resolution.timeout *= 2;
+        Ok(resolution.retransmit_at)
+    }
+
+    /// Probes for other hosts using `ipv4_addr` and then announces that it's ours (RFC 5227
+    /// sections 2.1 and 2.3). Fails with `AddressInUse` if it turns out to be taken. We defend the
+    /// address from then on, until it's released.
+    pub fn claim(&self, ipv4_addr: Ipv4Addr) -> impl Future<Output = Result<(), Fail>> {
+        let options = self.rt.arp_options();
+        let probe_count = if options.disable_arp {
+            0
+        } else {
+            options.probe_count
+        };
+        *self.claimed.borrow_mut() = Some(Claim {
+            ipv4_addr,
+            probing: probe_count > 0,
+            last_defense: None,
+        });
+        self.conflict.set(None);
+        let peer = self.clone();
+        async move {
+            if options.disable_arp {
+                return Ok(());
+            }
+            if probe_count > 0 {
+                peer.probe(ipv4_addr, probe_count).await?;
+                match *peer.claimed.borrow_mut() {
+                    Some(ref mut claim) if claim.ipv4_addr == ipv4_addr => claim.probing = false,
+                    _ => (),
                 }
             }
-            Err(Fail::Timeout {})
+            let mut announce_at = peer.rt.now();
+            for _ in 0..options.announce_count {
+                peer.rt.wait_until(announce_at).await;
+                peer.rt.transmit(peer.request(ipv4_addr, ipv4_addr));
+                announce_at += ANNOUNCE_INTERVAL;
+            }
+            Ok(())
+        }
+    }
+
+    /// Stops defending the address we claimed.
+    pub fn release(&self) {
+        *self.claimed.borrow_mut() = None;
+    }
+
+    /// Whether we're still probing for the address we're claiming.
+    pub fn is_probing(&self) -> bool {
+        self.claimed.borrow().as_ref().map_or(false, |c| c.probing)
+    }
+
+    /// The last conflict over the address we claimed, if there was one.
+    pub fn conflict(&self) -> Option<ArpConflict> {
+        self.conflict.get()
+    }
+
+    /// Resolves once another host turns out to be using the address we claimed.
+    pub async fn lost_claim(&self) -> ArpConflict {
+        loop {
+            let (conflict, conflict_changed) = self.conflict.watch();
+            if let Some(conflict) = conflict {
+                return conflict;
+            }
+            conflict_changed.await;
+        }
+    }
+
+    async fn probe(&self, ipv4_addr: Ipv4Addr, probe_count: usize) -> Result<(), Fail> {
+        let (_, conflict) = self.conflict.watch();
+        futures::pin_mut!(conflict);
+        let mut probe_at = self.rt.now() + self.random_delay(Duration::new(0, 0), PROBE_WAIT);
+        for i in 0..=probe_count {
+            futures::select! {
+                _ = conflict => return Err(Fail::AddressInUse {}),
+                _ = self.rt.wait_until(probe_at).fuse() => (),
+            }
+            if i == probe_count {
+                break;
+            }
+            self.rt
+                .transmit(self.request(Ipv4Addr::UNSPECIFIED, ipv4_addr));
+            probe_at += if i + 1 < probe_count {
+                self.random_delay(PROBE_MIN, PROBE_MAX)
+            } else {
+                ANNOUNCE_WAIT
+            };
+        }
+        Ok(())
+    }
+
+    fn random_delay(&self, min: Duration, max: Duration) -> Duration {
+        let range = (max - min).as_millis() as u64;
+        min + Duration::from_millis(self.rt.rng_gen::<u64>() % (range + 1))
+    }
+
+    /// Builds a broadcast request, which is a probe without a sender address and an announcement
+    /// when the sender is the target.
+    fn request(
+        &self,
+        sender_protocol_addr: Ipv4Addr,
+        target_protocol_addr: Ipv4Addr,
+    ) -> ArpMessage {
+        Self::request_to(
+            &self.rt,
+            MacAddress::broadcast(),
+            sender_protocol_addr,
+            target_protocol_addr,
+        )
+    }
+
+    fn request_to(
+        rt: &RT,
+        dst_addr: MacAddress,
+        sender_protocol_addr: Ipv4Addr,
+        target_protocol_addr: Ipv4Addr,
+    ) -> ArpMessage {
+        ArpMessage {
+            ethernet2_hdr: Ethernet2Header {
+                dst_addr,
+                src_addr: rt.local_link_addr(),
+                vlan_tags: rt.ethernet2_options().vlan_tags(None),
+                ether_type: EtherType2::Arp,
+            },
+            arp_pdu: ArpPdu {
+                operation: ArpOperation::Request,
+                sender_hardware_addr: rt.local_link_addr(),
+                sender_protocol_addr,
+                target_hardware_addr: MacAddress::nil(),
+                target_protocol_addr,
+            },
+        }
+    }
+
+    /// Looks for other hosts using or probing for the address we claimed (RFC 5227 sections 2.1.1
+    /// and 2.4). Returns true if `pdu` was about it and needs no further processing.
+    fn detect_conflict(&self, pdu: &ArpPdu) -> bool {
+        if pdu.sender_hardware_addr == self.rt.local_link_addr() {
+            return false;
+        }
+        let mut claimed = self.claimed.borrow_mut();
+        let claim = match *claimed {
+            Some(ref mut claim) => claim,
+            None => return false,
+        };
+        let conflict = ArpConflict {
+            ipv4_addr: claim.ipv4_addr,
+            link_addr: pdu.sender_hardware_addr,
+        };
+        if claim.probing {
+            let is_probe = pdu.operation == ArpOperation::Request
+                && pdu.sender_protocol_addr.is_unspecified()
+                && pdu.target_protocol_addr == claim.ipv4_addr;
+            if pdu.sender_protocol_addr == claim.ipv4_addr || is_probe {
+                warn!(
+                    "{} is in use by {}",
+                    claim.ipv4_addr, pdu.sender_hardware_addr
+                );
+                self.conflict.set(Some(conflict));
+            }
+            return pdu.sender_protocol_addr == claim.ipv4_addr
+                || pdu.target_protocol_addr == claim.ipv4_addr;
+        }
+        if pdu.sender_protocol_addr != claim.ipv4_addr {
+            return false;
+        }
+        // Defend the address once, and give up on it if that didn't help.
+        let now = self.rt.now();
+        match claim.last_defense {
+            Some(t) if now < t + DEFEND_INTERVAL => {
+                warn!(
+                    "{} is in use by {}",
+                    claim.ipv4_addr, pdu.sender_hardware_addr
+                );
+                self.conflict.set(Some(conflict));
+            },
+            _ => {
+                claim.last_defense = Some(now);
+                self.rt
+                    .transmit(self.request(claim.ipv4_addr, claim.ipv4_addr));
+            },
         }
+        true
     }
 
     pub fn export_cache(&self) -> HashMap<Ipv4Addr, MacAddress> {
@@ -214,6 +603,51 @@ impl<RT: Runtime> ArpPeer<RT> {
     }
 
     pub fn insert(&self, ipv4_addr: Ipv4Addr, link_addr: MacAddress) {
-        self.cache.borrow_mut().insert(ipv4_addr, link_addr);
+        self.learn(ipv4_addr, link_addr);
+    }
+
+    /// Tells us the neighbor we send datagrams for `ipv4_addr` to is still reachable, so its entry
+    /// is renewed without asking it.
+    pub fn confirm(&self, ipv4_addr: Ipv4Addr) {
+        if let Ok(next_hop) = self.routes.borrow().next_hop(ipv4_addr) {
+            self.cache.borrow_mut().confirm(next_hop);
+        }
+    }
+
+    pub fn entries(&self) -> Vec<ArpEntry> {
+        let mut cache = self.cache.borrow_mut();
+        cache.advance_clock(self.rt.now());
+        cache.entries()
+    }
+
+    /// Adds an entry that never expires, replacing whatever we knew about `ipv4_addr`.
+    pub fn insert_static(&self, ipv4_addr: Ipv4Addr, link_addr: MacAddress) -> Result<(), Fail> {
+        if ipv4_addr.is_unspecified() || ipv4_addr.is_broadcast() || ipv4_addr.is_multicast() {
+            return Err(Fail::Invalid {
+                details: "ARP entries need a unicast IPv4 address",
+            });
+        }
+        if link_addr.is_nil() || link_addr.is_broadcast() {
+            return Err(Fail::Invalid {
+                details: "Invalid link address for ARP entry",
+            });
+        }
+        self.cache.borrow_mut().insert_static(ipv4_addr, link_addr);
+        self.send_pending(ipv4_addr, link_addr);
+        Ok(())
+    }
+
+    pub fn remove(&self, ipv4_addr: Ipv4Addr) -> Result<(), Fail> {
+        match self.cache.borrow_mut().remove(ipv4_addr) {
+            Some(..) => Ok(()),
+            None => Err(Fail::ResourceNotFound {
+                details: "No ARP entry for address",
+            }),
+        }
+    }
+
+    /// Forgets the addresses we learned from the network, but not the static entries.
+    pub fn flush_cache(&self) {
+        self.cache.borrow_mut().flush();
     }
 }
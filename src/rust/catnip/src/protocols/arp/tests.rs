@@ -1,18 +1,49 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
-use super::pdu::{
-    ArpOperation,
-    ArpPdu,
+use super::{
+    pdu::{
+        ArpMessage,
+        ArpOperation,
+        ArpPdu,
+    },
+    Conflict,
+    Entry,
+    Options,
 };
 use crate::{
+    engine::{
+        Engine,
+        Protocol,
+    },
     fail::Fail,
-    protocols::ethernet2::frame::{
-        Ethernet2Header,
-        MIN_PAYLOAD_SIZE,
+    operations::OperationResult,
+    protocols::{
+        ethernet2::{
+            frame::{
+                EtherType2,
+                Ethernet2Header,
+                VlanTags,
+                MIN_PAYLOAD_SIZE,
+            },
+            MacAddress,
+        },
+        ip,
+    },
+    runtime::{
+        PacketBuf,
+        Runtime,
+    },
+    scheduler::Operation,
+    socket_options::SocketOption,
+    sync::{
+        Bytes,
+        BytesMut,
+    },
+    test_helpers::{
+        self,
+        TestRuntime,
     },
-    runtime::Runtime,
-    test_helpers,
 };
 use futures::{
     task::{
@@ -24,7 +55,9 @@ use futures::{
 use hashbrown::HashMap;
 use must_let::must_let;
 use std::{
+    convert::TryFrom,
     future::Future,
+    pin::Pin,
     task::Poll,
     time::{
         Duration,
@@ -152,8 +185,10 @@ fn no_reply() {
     let arp = ArpPdu::parse(payload).unwrap();
     assert_eq!(arp.operation, ArpOperation::Request);
 
+    // the wait doubles with each retry.
+    let mut timeout = options.request_timeout;
     for i in 0..options.retry_count {
-        now += options.request_timeout;
+        now += timeout;
         alice.rt().advance_clock(now);
         assert!(Future::poll(fut.as_mut(), &mut ctx).is_pending());
         info!("no_reply(): retry #{}", i + 1);
@@ -161,11 +196,395 @@ fn no_reply() {
         let (_, payload) = Ethernet2Header::parse(bytes).unwrap();
         let arp = ArpPdu::parse(payload).unwrap();
         assert_eq!(arp.operation, ArpOperation::Request);
+        timeout *= 2;
     }
 
     // timeout
-    now += options.request_timeout;
+    now += timeout;
     alice.rt().advance_clock(now);
 
     must_let!(let Poll::Ready(Err(Fail::Timeout {})) = Future::poll(fut.as_mut(), &mut ctx));
+
+    // the failure is remembered for a little while, so we don't ask again right away.
+    let mut fut = alice.arp_query(test_helpers::CARRIE_IPV4).boxed_local();
+    must_let!(let Poll::Ready(Err(Fail::Timeout {})) = Future::poll(fut.as_mut(), &mut ctx));
+    assert!(alice.rt().try_pop_frame().is_none());
+
+    now += options.negative_cache_ttl;
+    alice.rt().advance_clock(now);
+    let mut fut = alice.arp_query(test_helpers::CARRIE_IPV4).boxed_local();
+    assert!(Future::poll(fut.as_mut(), &mut ctx).is_pending());
+    parse_request(alice.rt().pop_frame());
+}
+
+#[test]
+fn shared_request() {
+    let now = Instant::now();
+    let alice = test_helpers::new_alice(now);
+    alice.import_arp_cache(HashMap::new());
+    let mut carrie = test_helpers::new_carrie(now);
+    carrie.import_arp_cache(HashMap::new());
+
+    // Both queries wait on the same request.
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut first = alice.arp_query(test_helpers::CARRIE_IPV4).boxed_local();
+    let mut second = alice.arp_query(test_helpers::CARRIE_IPV4).boxed_local();
+    assert!(Future::poll(first.as_mut(), &mut ctx).is_pending());
+    assert!(Future::poll(second.as_mut(), &mut ctx).is_pending());
+    let request = alice.rt().pop_frame();
+    assert!(alice.rt().try_pop_frame().is_none());
+
+    carrie.receive(request).unwrap();
+    alice.receive(carrie.rt().pop_frame()).unwrap();
+    must_let!(let Poll::Ready(Ok(link_addr)) = Future::poll(first.as_mut(), &mut ctx));
+    assert_eq!(link_addr, test_helpers::CARRIE_MAC);
+    must_let!(let Poll::Ready(Ok(link_addr)) = Future::poll(second.as_mut(), &mut ctx));
+    assert_eq!(link_addr, test_helpers::CARRIE_MAC);
+}
+
+#[test]
+fn pending_frames() {
+    let now = Instant::now();
+    let options = Options::default()
+        .probe_count(0)
+        .announce_count(0)
+        .max_pending_frames(2);
+    let mut alice = test_helpers::new_alice_with_arp_options(now, options);
+    let mut carrie = test_helpers::new_carrie(now);
+    carrie.import_arp_cache(HashMap::new());
+
+    // Datagrams to Carrie wait for her address, and the oldest one is dropped to make room.
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let fd = alice.socket(Protocol::Udp);
+    let port = ip::Port::try_from(53).unwrap();
+    let remote = ip::Endpoint::new(test_helpers::CARRIE_IPV4, port);
+    let mut pushes = vec![];
+    for i in 1..=3 {
+        let buf = BytesMut::from(&vec![i; 64][..]).freeze();
+        must_let!(let Operation::Udp(push) = alice.pushto(fd, buf, remote));
+        pushes.push(push);
+    }
+    alice.rt().poll_scheduler();
+    for push in &mut pushes {
+        let _ = Future::poll(Pin::new(push), &mut ctx);
+    }
+    let mut pushes = pushes.into_iter();
+    must_let!(let (_, OperationResult::Failed(Fail::ResourceExhausted { .. })) = pushes.next().unwrap().expect_result());
+    let request = alice.rt().pop_frame();
+    assert_eq!(
+        parse_request(request.clone()).target_protocol_addr,
+        test_helpers::CARRIE_IPV4
+    );
+    assert!(alice.rt().try_pop_frame().is_none());
+
+    carrie.receive(request).unwrap();
+    alice.receive(carrie.rt().pop_frame()).unwrap();
+    for i in 2..=3 {
+        let frame = alice.rt().pop_frame();
+        let (ethernet2_hdr, _) = Ethernet2Header::parse(frame.clone()).unwrap();
+        assert_eq!(ethernet2_hdr.dst_addr, test_helpers::CARRIE_MAC);
+        assert_eq!(frame[frame.len() - 1], i);
+    }
+    assert!(alice.rt().try_pop_frame().is_none());
+
+    // Their pushes complete once they're sent.
+    for mut push in pushes {
+        assert!(Future::poll(Pin::new(&mut push), &mut ctx).is_ready());
+        must_let!(let (_, OperationResult::Push) = push.expect_result());
+    }
+}
+
+#[test]
+fn pending_frames_limits() {
+    let mut now = Instant::now();
+    let options = Options::default().probe_count(0).announce_count(0);
+    let mut alice = test_helpers::new_alice_with_arp_options(now, options);
+    alice.import_arp_cache(HashMap::new());
+    let options = alice.rt().arp_options();
+    let mut ctx = Context::from_waker(noop_waker_ref());
+
+    // Datagrams waiting for Carrie's address count against the socket's send buffer.
+    let fd = alice.socket(Protocol::Udp);
+    alice
+        .setsockopt(fd, SocketOption::SendBufferSize(Some(1000)))
+        .unwrap();
+    let port = ip::Port::try_from(53).unwrap();
+    let remote = ip::Endpoint::new(test_helpers::CARRIE_IPV4, port);
+    let buf = BytesMut::from(&[0; 400][..]).freeze();
+    must_let!(let Operation::Udp(mut first) = alice.pushto(fd, buf.clone(), remote));
+    must_let!(let Operation::Udp(mut second) = alice.pushto(fd, buf.clone(), remote));
+    must_let!(let Operation::Udp(mut third) = alice.pushto(fd, buf, remote));
+    assert!(Future::poll(Pin::new(&mut third), &mut ctx).is_ready());
+    must_let!(let (_, OperationResult::Failed(Fail::ResourceExhausted { .. })) = third.expect_result());
+
+    // Their pushes fail when the address can't be resolved.
+    let mut timeout = options.request_timeout;
+    for _ in 0..=options.retry_count {
+        alice.rt().poll_scheduler();
+        assert!(Future::poll(Pin::new(&mut first), &mut ctx).is_pending());
+        assert!(alice.rt().try_pop_frame().is_some());
+        now += timeout;
+        alice.rt().advance_clock(now);
+        timeout *= 2;
+    }
+    alice.rt().poll_scheduler();
+    for mut push in vec![first, second] {
+        assert!(Future::poll(Pin::new(&mut push), &mut ctx).is_ready());
+        must_let!(let (_, OperationResult::Failed(Fail::Timeout {})) = push.expect_result());
+    }
+    assert!(alice.rt().try_pop_frame().is_none());
+}
+
+fn parse_request(frame: Bytes) -> ArpPdu {
+    let (_, payload) = Ethernet2Header::parse(frame).unwrap();
+    let pdu = ArpPdu::parse(payload).unwrap();
+    assert_eq!(pdu.operation, ArpOperation::Request);
+    pdu
+}
+
+#[test]
+fn probe_and_announce() {
+    let mut now = Instant::now();
+    let alice = test_helpers::new_alice_with_arp_options(now, Options::default());
+    let options = alice.rt().arp_options();
+    assert_eq!(options.probe_count, 3);
+    assert_eq!(options.announce_count, 2);
+    assert!(alice.is_probing_ipv4_addr());
+
+    // The probes and announcements are randomly spread over at most 9 seconds.
+    let mut frames = vec![];
+    for _ in 0..100 {
+        now += Duration::from_millis(100);
+        alice.rt().advance_clock(now);
+        alice.rt().poll_scheduler();
+        while let Some(frame) = alice.rt().try_pop_frame() {
+            frames.push(frame);
+        }
+    }
+    assert_eq!(frames.len(), 5);
+    assert!(!alice.is_probing_ipv4_addr());
+    assert_eq!(alice.ipv4_addr_conflict(), None);
+
+    for frame in &frames[..3] {
+        let probe = parse_request(frame.clone());
+        assert!(probe.sender_protocol_addr.is_unspecified());
+        assert_eq!(probe.target_hardware_addr, MacAddress::nil());
+        assert_eq!(probe.target_protocol_addr, test_helpers::ALICE_IPV4);
+    }
+    for frame in &frames[3..] {
+        let announcement = parse_request(frame.clone());
+        assert_eq!(announcement.sender_protocol_addr, test_helpers::ALICE_IPV4);
+        assert_eq!(announcement.target_protocol_addr, test_helpers::ALICE_IPV4);
+    }
+
+    // Bob had a stale link address for Alice, which the announcement replaces.
+    let mut bob = test_helpers::new_bob(now);
+    let mut cache = HashMap::new();
+    cache.insert(test_helpers::ALICE_IPV4, test_helpers::CARRIE_MAC);
+    bob.import_arp_cache(cache);
+    bob.receive(frames[4].clone()).unwrap();
+    assert_eq!(
+        bob.export_arp_cache().get(&test_helpers::ALICE_IPV4),
+        Some(&test_helpers::ALICE_MAC)
+    );
+}
+
+#[test]
+fn probe_conflict() {
+    let mut now = Instant::now();
+    let mut alice = test_helpers::new_alice_with_arp_options(now, Options::default());
+    let impostor = TestRuntime::new(
+        "impostor",
+        now,
+        test_helpers::BOB_MAC,
+        test_helpers::ALICE_IPV4,
+    );
+    let mut impostor = Engine::new(impostor).unwrap();
+
+    let probe = loop {
+        now += Duration::from_millis(100);
+        alice.rt().advance_clock(now);
+        alice.rt().poll_scheduler();
+        if let Some(frame) = alice.rt().try_pop_frame() {
+            break frame;
+        }
+    };
+
+    // The host that already has the address answers the probe.
+    impostor.receive(probe).unwrap();
+    alice.receive(impostor.rt().pop_frame()).unwrap();
+    alice.rt().poll_scheduler();
+    assert_eq!(
+        alice.ipv4_addr_conflict(),
+        Some(Conflict {
+            ipv4_addr: test_helpers::ALICE_IPV4,
+            link_addr: test_helpers::BOB_MAC,
+        })
+    );
+
+    // Alice gives up on the address without announcing it.
+    alice.rt().advance_clock(now + Duration::from_secs(10));
+    alice.rt().poll_scheduler();
+    assert!(alice.rt().try_pop_frame().is_none());
+}
+
+#[test]
+fn defend_address() {
+    let now = Instant::now();
+    let mut alice = test_helpers::new_alice(now);
+    assert!(!alice.is_probing_ipv4_addr());
+
+    let msg = ArpMessage {
+        ethernet2_hdr: Ethernet2Header {
+            dst_addr: MacAddress::broadcast(),
+            src_addr: test_helpers::BOB_MAC,
+            vlan_tags: VlanTags::new(),
+            ether_type: EtherType2::Arp,
+        },
+        arp_pdu: ArpPdu {
+            operation: ArpOperation::Request,
+            sender_hardware_addr: test_helpers::BOB_MAC,
+            sender_protocol_addr: test_helpers::ALICE_IPV4,
+            target_hardware_addr: MacAddress::nil(),
+            target_protocol_addr: test_helpers::ALICE_IPV4,
+        },
+    };
+    let mut buf = BytesMut::zeroed(msg.compute_size());
+    msg.serialize(&mut buf[..]);
+    let announcement = buf.freeze();
+
+    // Alice answers the first announcement of her address with one of her own.
+    alice.receive(announcement.clone()).unwrap();
+    let defense = parse_request(alice.rt().pop_frame());
+    assert_eq!(defense.sender_hardware_addr, test_helpers::ALICE_MAC);
+    assert_eq!(defense.sender_protocol_addr, test_helpers::ALICE_IPV4);
+    assert_eq!(alice.ipv4_addr_conflict(), None);
+    assert_eq!(
+        alice.export_arp_cache().get(&test_helpers::ALICE_IPV4),
+        Some(&test_helpers::ALICE_MAC)
+    );
+
+    // Another one soon after means the conflict is real.
+    alice.rt().advance_clock(now + Duration::from_secs(5));
+    alice.receive(announcement).unwrap();
+    assert!(alice.rt().try_pop_frame().is_none());
+    assert_eq!(
+        alice.ipv4_addr_conflict(),
+        Some(Conflict {
+            ipv4_addr: test_helpers::ALICE_IPV4,
+            link_addr: test_helpers::BOB_MAC,
+        })
+    );
+
+    // Alice can't send from the address she lost anymore.
+    let fd = alice.socket(Protocol::Udp);
+    let remote = ip::Endpoint::new(test_helpers::BOB_IPV4, ip::Port::try_from(80).unwrap());
+    must_let!(let Operation::Udp(..) = alice.connect(fd, remote));
+    must_let!(let Err(Fail::AddressInUse {}) = alice.udp_push(fd, BytesMut::from(&b"hello"[..]).freeze()));
+}
+
+#[test]
+fn static_entries() {
+    let mut now = Instant::now();
+    let options = Options::default().probe_count(0).announce_count(0);
+    let mut alice = test_helpers::new_alice_with_arp_options(now, options.clone());
+    let carrie = test_helpers::new_carrie(now);
+    carrie.import_arp_cache(HashMap::new());
+    assert!(alice.arp_entries().is_empty());
+
+    alice
+        .add_arp_entry(test_helpers::BOB_IPV4, test_helpers::BOB_MAC)
+        .unwrap();
+    must_let!(let Err(Fail::Invalid { .. }) = alice.add_arp_entry(test_helpers::CARRIE_IPV4, MacAddress::broadcast()));
+
+    // Alice learns Carrie's address from her request.
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut fut = carrie.arp_query(test_helpers::ALICE_IPV4).boxed_local();
+    assert!(Future::poll(fut.as_mut(), &mut ctx).is_pending());
+    alice.receive(carrie.rt().pop_frame()).unwrap();
+    let mut entries = alice.arp_entries();
+    entries.sort_by_key(|e| e.ipv4_addr);
+    assert_eq!(
+        entries,
+        vec![
+            Entry {
+                ipv4_addr: test_helpers::BOB_IPV4,
+                link_addr: test_helpers::BOB_MAC,
+                is_static: true,
+            },
+            Entry {
+                ipv4_addr: test_helpers::CARRIE_IPV4,
+                link_addr: test_helpers::CARRIE_MAC,
+                is_static: false,
+            },
+        ]
+    );
+    alice.flush_arp_cache();
+    assert_eq!(alice.arp_entries().len(), 1);
+
+    // Learned entries expire, but static ones don't.
+    let mut fut = carrie.arp_query(test_helpers::ALICE_IPV4).boxed_local();
+    now += Duration::from_secs(1);
+    carrie.rt().advance_clock(now);
+    assert!(Future::poll(fut.as_mut(), &mut ctx).is_pending());
+    alice.receive(carrie.rt().pop_frame()).unwrap();
+    assert_eq!(alice.arp_entries().len(), 2);
+    now += options.cache_ttl;
+    alice.rt().advance_clock(now);
+    assert_eq!(
+        alice.arp_entries(),
+        vec![Entry {
+            ipv4_addr: test_helpers::BOB_IPV4,
+            link_addr: test_helpers::BOB_MAC,
+            is_static: true,
+        }]
+    );
+
+    alice.remove_arp_entry(test_helpers::BOB_IPV4).unwrap();
+    must_let!(let Err(Fail::ResourceNotFound { .. }) = alice.remove_arp_entry(test_helpers::BOB_IPV4));
+    assert!(alice.arp_entries().is_empty());
+}
+
+#[test]
+fn refresh_before_expiry() {
+    let mut now = Instant::now();
+    let options = Options::default().probe_count(0).announce_count(0);
+    let alice = test_helpers::new_alice_with_arp_options(now, options.clone());
+    let mut bob = test_helpers::new_bob(now);
+    let mut cache = HashMap::new();
+    cache.insert(test_helpers::BOB_IPV4, test_helpers::BOB_MAC);
+    cache.insert(test_helpers::CARRIE_IPV4, test_helpers::CARRIE_MAC);
+    alice.import_arp_cache(cache);
+
+    // Alice only talks to Bob.
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut fut = alice.arp_query(test_helpers::BOB_IPV4).boxed_local();
+    must_let!(let Poll::Ready(Ok(..)) = Future::poll(fut.as_mut(), &mut ctx));
+
+    // Shortly before his entry expires, she asks him directly if he's still there.
+    now += options.cache_ttl - options.refresh_window;
+    alice.rt().advance_clock(now);
+    alice.rt().poll_scheduler();
+    let request = alice.rt().pop_frame();
+    let (ethernet2_hdr, _) = Ethernet2Header::parse(request.clone()).unwrap();
+    assert_eq!(ethernet2_hdr.dst_addr, test_helpers::BOB_MAC);
+    assert_eq!(
+        parse_request(request.clone()).target_protocol_addr,
+        test_helpers::BOB_IPV4
+    );
+    assert!(alice.rt().try_pop_frame().is_none());
+    bob.receive(request).unwrap();
+    alice.receive(bob.rt().pop_frame()).unwrap();
+
+    // Bob's entry was renewed, and Carrie's expired.
+    now += options.refresh_window;
+    alice.rt().advance_clock(now);
+    assert_eq!(
+        alice.arp_entries(),
+        vec![Entry {
+            ipv4_addr: test_helpers::BOB_IPV4,
+            link_addr: test_helpers::BOB_MAC,
+            is_static: false,
+        }]
+    );
 }
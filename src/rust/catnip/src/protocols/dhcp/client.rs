@@ -0,0 +1,369 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::datagram::{
+    DhcpMessage,
+    DhcpMessageType,
+    DHCP_CLIENT_PORT,
+    DHCP_SERVER_PORT,
+};
+use crate::{
+    fail::Fail,
+    protocols::{
+        ethernet2::frame::Ethernet2Header,
+        ip,
+        ipv4::{
+            self,
+            routing::netmask_prefix_len,
+        },
+        udp::datagram::{
+            UdpDatagram,
+            UdpHeader,
+        },
+    },
+    runtime::Runtime,
+    scheduler::SchedulerHandle,
+    sync::{
+        Bytes,
+        BytesMut,
+    },
+};
+use futures::{
+    channel::mpsc,
+    FutureExt,
+    StreamExt,
+};
+use std::{
+    cell::RefCell,
+    cmp,
+    convert::TryFrom,
+    net::Ipv4Addr,
+    rc::Rc,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+// RFC 2131 section 4.1: Retransmissions start after 4 seconds and back off up to 64 seconds.
+const INITIAL_RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(4);
+const MAX_RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(64);
+// How long we keep requesting an offered address before starting over.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+// RFC 2131 section 3.1: How long to wait after declining an address before starting over.
+const DECLINE_BACKOFF: Duration = Duration::from_secs(10);
+
+/// The configuration a DHCP server handed us. The lease's times count from when we asked for it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DhcpLease {
+    pub addr: Ipv4Addr,
+    pub subnet_mask: Option<Ipv4Addr>,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr>,
+    pub server_id: Ipv4Addr,
+    pub acquired: Instant,
+    pub lease_time: Duration,
+    pub renewal_time: Duration,
+    pub rebinding_time: Duration,
+}
+
+impl DhcpLease {
+    fn from_ack(ack: &DhcpMessage, server_id: Ipv4Addr, acquired: Instant) -> Option<Self> {
+        if ack.message_type != DhcpMessageType::Ack {
+            return None;
+        }
+        // RFC 2131 section 4.4.5: T1 and T2 default to half and seven eighths of the lease.
+        let lease_time = ack.lease_time?;
+        Some(Self {
+            addr: ack.your_addr,
+            subnet_mask: ack.subnet_mask,
+            gateway: ack.routers.first().cloned(),
+            dns_servers: ack.dns_servers.clone(),
+            server_id: ack.server_id.unwrap_or(server_id),
+            acquired,
+            lease_time,
+            renewal_time: ack.renewal_time.unwrap_or(lease_time / 2),
+            rebinding_time: ack.rebinding_time.unwrap_or(lease_time * 7 / 8),
+        })
+    }
+}
+
+/// A DHCP client (RFC 2131) that configures the primary IPv4 address, its subnet and the
+/// default gateway, and keeps renewing the lease in the background.
+#[derive(Clone)]
+pub struct DhcpClient<RT: Runtime> {
+    rt: RT,
+    lease: Rc<RefCell<Option<DhcpLease>>>,
+    tx: mpsc::UnboundedSender<DhcpMessage>,
+    #[allow(unused)]
+    handle: Rc<SchedulerHandle>,
+}
+
+impl<RT: Runtime> DhcpClient<RT> {
+    pub fn new(rt: RT, network: ip::Network<RT>) -> Self {
+        let lease = Rc::new(RefCell::new(None));
+        let (tx, rx) = mpsc::unbounded();
+        let negotiator = Negotiator {
+            rt: rt.clone(),
+            network,
+            lease: lease.clone(),
+        };
+        // Whatever address we started with isn't ours until a server says so.
+        negotiator.unbind();
+        let handle = rt.spawn(negotiator.run(rx));
+        Self {
+            rt,
+            lease,
+            tx,
+            handle: Rc::new(handle),
+        }
+    }
+
+    pub fn lease(&self) -> Option<DhcpLease> {
+        self.lease.borrow().clone()
+    }
+
+    pub fn receive(&self, ip_hdr: &ip::Header, buf: Bytes) -> Result<(), Fail> {
+        let (_, data) = UdpHeader::parse(ip_hdr, buf)?;
+        let msg = DhcpMessage::parse(&data[..])?;
+        if msg.client_link_addr != self.rt.local_link_addr() {
+            return Err(Fail::Ignored {
+                details: "DHCP message for another client",
+            });
+        }
+        self.tx.unbounded_send(msg).unwrap();
+        Ok(())
+    }
+}
+
+/// Whether a UDP datagram is headed for the DHCP client port.
+pub fn is_client_datagram(buf: &[u8]) -> bool {
+    buf.len() >= 4 && u16::from_be_bytes([buf[2], buf[3]]) == DHCP_CLIENT_PORT
+}
+
+/// The background task that walks through the client states of RFC 2131 section 4.4.
+#[derive(Clone)]
+struct Negotiator<RT: Runtime> {
+    rt: RT,
+    network: ip::Network<RT>,
+    lease: Rc<RefCell<Option<DhcpLease>>>,
+}
+
+impl<RT: Runtime> Negotiator<RT> {
+    async fn run(self, mut rx: mpsc::UnboundedReceiver<DhcpMessage>) {
+        loop {
+            let mut lease = match self.acquire(&mut rx).await {
+                Some(lease) => lease,
+                None => continue,
+            };
+            // RFC 2131 section 3.1: Make sure nobody else is using the address before taking it.
+            if let Err(e) = self.network.arp.claim(lease.addr).await {
+                warn!("Declining DHCP lease for {}: {:?}", lease.addr, e);
+                self.network.arp.release();
+                self.decline(&lease).await;
+                continue;
+            }
+            loop {
+                self.bind(lease.clone());
+                let outcome = {
+                    let extend = self.extend(&mut rx, &lease).fuse();
+                    let conflict = self.network.arp.lost_claim().fuse();
+                    futures::pin_mut!(extend, conflict);
+                    futures::select! {
+                        extended = extend => Ok(extended),
+                        conflict = conflict => Err(conflict),
+                    }
+                };
+                match outcome {
+                    Ok(Some(extended)) => lease = extended,
+                    Ok(None) => {
+                        warn!("Lost DHCP lease for {}", lease.addr);
+                        self.unbind();
+                        break;
+                    },
+                    // RFC 5227 section 2.4: Someone else kept using the address after we defended
+                    // it, so we have to give it up.
+                    Err(conflict) => {
+                        warn!(
+                            "Declining DHCP lease for {}: in use by {}",
+                            lease.addr, conflict.link_addr
+                        );
+                        self.unbind();
+                        self.decline(&lease).await;
+                        break;
+                    },
+                }
+            }
+        }
+    }
+
+    /// INIT, SELECTING and REQUESTING: Takes the first offer we get.
+    async fn acquire(&self, rx: &mut mpsc::UnboundedReceiver<DhcpMessage>) -> Option<DhcpLease> {
+        let link_addr = self.rt.local_link_addr();
+        let xid = self.rt.rng_gen();
+        let mut discover = DhcpMessage::new(DhcpMessageType::Discover, xid, link_addr);
+        discover.broadcast = true;
+        let offer = self
+            .exchange(
+                rx,
+                &discover,
+                Ipv4Addr::BROADCAST,
+                &[DhcpMessageType::Offer],
+                None,
+            )
+            .await?;
+        let server_id = offer.server_id?;
+
+        let mut request = DhcpMessage::new(DhcpMessageType::Request, xid, link_addr);
+        request.broadcast = true;
+        request.server_id = Some(server_id);
+        request.requested_addr = Some(offer.your_addr);
+        let requested = self.rt.now();
+        let ack = self
+            .exchange(
+                rx,
+                &request,
+                Ipv4Addr::BROADCAST,
+                &[DhcpMessageType::Ack, DhcpMessageType::Nak],
+                Some(requested + REQUEST_TIMEOUT),
+            )
+            .await?;
+        DhcpLease::from_ack(&ack, server_id, requested)
+    }
+
+    /// RENEWING and REBINDING: Asks the server that gave us the lease to extend it, and then any
+    /// server, until the lease runs out.
+    async fn extend(
+        &self,
+        rx: &mut mpsc::UnboundedReceiver<DhcpMessage>,
+        lease: &DhcpLease,
+    ) -> Option<DhcpLease> {
+        self.rt
+            .wait_until(lease.acquired + lease.renewal_time)
+            .await;
+        let expected = [DhcpMessageType::Ack, DhcpMessageType::Nak];
+        let xid = self.rt.rng_gen();
+        let mut request =
+            DhcpMessage::new(DhcpMessageType::Request, xid, self.rt.local_link_addr());
+        request.client_addr = lease.addr;
+
+        let requested = self.rt.now();
+        let rebind_at = lease.acquired + lease.rebinding_time;
+        if let Some(ack) = self
+            .exchange(rx, &request, lease.server_id, &expected, Some(rebind_at))
+            .await
+        {
+            return DhcpLease::from_ack(&ack, lease.server_id, requested);
+        }
+        let requested = self.rt.now();
+        let expiry = lease.acquired + lease.lease_time;
+        let ack = self
+            .exchange(rx, &request, Ipv4Addr::BROADCAST, &expected, Some(expiry))
+            .await?;
+        DhcpLease::from_ack(&ack, lease.server_id, requested)
+    }
+
+    /// Sends `msg` until we get a reply of an `expected` type, backing off between attempts.
+    /// Gives up at `deadline`, if there is one.
+    async fn exchange(
+        &self,
+        rx: &mut mpsc::UnboundedReceiver<DhcpMessage>,
+        msg: &DhcpMessage,
+        dst_addr: Ipv4Addr,
+        expected: &[DhcpMessageType],
+        deadline: Option<Instant>,
+    ) -> Option<DhcpMessage> {
+        let mut timeout = INITIAL_RETRANSMIT_TIMEOUT;
+        loop {
+            let now = self.rt.now();
+            if let Some(deadline) = deadline {
+                if now >= deadline {
+                    return None;
+                }
+            }
+            if let Err(e) = self.send(msg, dst_addr).await {
+                warn!("Failed to send DHCP message: {:?}", e);
+            }
+            let mut retransmit_at = now + timeout;
+            if let Some(deadline) = deadline {
+                retransmit_at = cmp::min(retransmit_at, deadline);
+            }
+            loop {
+                futures::select! {
+                    reply = rx.next().fuse() => match reply {
+                        Some(reply) if reply.xid == msg.xid && expected.contains(&reply.message_type) => {
+                            return Some(reply);
+                        },
+                        Some(..) => (),
+                        None => return None,
+                    },
+                    _ = self.rt.wait_until(retransmit_at).fuse() => break,
+                }
+            }
+            timeout = cmp::min(timeout * 2, MAX_RETRANSMIT_TIMEOUT);
+        }
+    }
+
+    async fn decline(&self, lease: &DhcpLease) {
+        let xid = self.rt.rng_gen();
+        let mut decline =
+            DhcpMessage::new(DhcpMessageType::Decline, xid, self.rt.local_link_addr());
+        decline.server_id = Some(lease.server_id);
+        decline.requested_addr = Some(lease.addr);
+        if let Err(e) = self.send(&decline, Ipv4Addr::BROADCAST).await {
+            warn!("Failed to send DHCP message: {:?}", e);
+        }
+        self.rt.wait(DECLINE_BACKOFF).await;
+    }
+
+    async fn send(&self, msg: &DhcpMessage, dst_addr: Ipv4Addr) -> Result<(), Fail> {
+        let link_addr = self.network.arp.query(dst_addr).await?;
+        let ip_hdr =
+            self.network
+                .new_header(msg.client_addr.into(), dst_addr.into(), ip::Protocol::Udp)?;
+        let mut data = BytesMut::zeroed(msg.compute_size());
+        msg.serialize(&mut data[..]);
+        let datagram = UdpDatagram {
+            ethernet2_hdr: Ethernet2Header {
+                dst_addr: link_addr,
+                src_addr: self.rt.local_link_addr(),
+                vlan_tags: self.rt.ethernet2_options().vlan_tags(None),
+                ether_type: ip_hdr.ether_type(),
+            },
+            ip_hdr,
+            udp_hdr: UdpHeader {
+                src_port: Some(ip::Port::try_from(DHCP_CLIENT_PORT)?),
+                dst_port: ip::Port::try_from(DHCP_SERVER_PORT)?,
+            },
+            data: data.freeze(),
+        };
+        self.rt.transmit(datagram);
+        Ok(())
+    }
+
+    fn bind(&self, lease: DhcpLease) {
+        info!("DHCP lease for {} from {}", lease.addr, lease.server_id);
+        let mut options = self.rt.ipv4_options();
+        if let Some(subnet_mask) = lease.subnet_mask {
+            if netmask_prefix_len(subnet_mask).is_some() {
+                options = options.netmask(subnet_mask);
+            }
+        }
+        if let Some(gateway) = lease.gateway {
+            options = options.default_gateway(gateway);
+        }
+        self.configure(lease.addr, &options);
+        *self.lease.borrow_mut() = Some(lease);
+    }
+
+    fn unbind(&self) {
+        self.network.arp.release();
+        self.configure(Ipv4Addr::UNSPECIFIED, &self.rt.ipv4_options());
+        *self.lease.borrow_mut() = None;
+    }
+
+    fn configure(&self, addr: Ipv4Addr, options: &ipv4::Options) {
+        self.rt.set_local_ipv4_addr(addr);
+        *self.network.ipv4_routes.borrow_mut() = ipv4::RoutingTable::new(addr, options);
+    }
+}
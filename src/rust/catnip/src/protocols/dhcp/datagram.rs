@@ -0,0 +1,313 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use crate::{
+    fail::Fail,
+    protocols::ethernet2::MacAddress,
+};
+use byteorder::{
+    ByteOrder,
+    NetworkEndian,
+};
+use num_traits::FromPrimitive;
+use std::{
+    cmp,
+    net::Ipv4Addr,
+    time::Duration,
+};
+
+pub const DHCP_SERVER_PORT: u16 = 67;
+pub const DHCP_CLIENT_PORT: u16 = 68;
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+const FLAG_BROADCAST: u16 = 0x8000;
+const MAGIC_COOKIE: u32 = 0x6382_5363;
+
+// The fixed BOOTP fields, then the magic cookie.
+const DHCP_HEADER_SIZE: usize = 240;
+// RFC 1542 section 3.2.1: Some relay agents drop BOOTP messages shorter than this.
+const MIN_MESSAGE_SIZE: usize = 300;
+
+// RFC 2132 option codes.
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS_SERVER: u8 = 6;
+const OPTION_REQUESTED_ADDR: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_PARAMETER_REQUEST_LIST: u8 = 55;
+const OPTION_RENEWAL_TIME: u8 = 58;
+const OPTION_REBINDING_TIME: u8 = 59;
+const OPTION_END: u8 = 255;
+
+const REQUESTED_PARAMETERS: [u8; 6] = [
+    OPTION_SUBNET_MASK,
+    OPTION_ROUTER,
+    OPTION_DNS_SERVER,
+    OPTION_LEASE_TIME,
+    OPTION_RENEWAL_TIME,
+    OPTION_REBINDING_TIME,
+];
+
+#[repr(u8)]
+#[derive(FromPrimitive, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DhcpMessageType {
+    Discover = 1,
+    Offer = 2,
+    Request = 3,
+    Decline = 4,
+    Ack = 5,
+    Nak = 6,
+    Release = 7,
+    Inform = 8,
+}
+
+impl DhcpMessageType {
+    fn is_reply(self) -> bool {
+        match self {
+            DhcpMessageType::Offer | DhcpMessageType::Ack | DhcpMessageType::Nak => true,
+            _ => false,
+        }
+    }
+}
+
+/// A DHCP message (RFC 2131 section 2) with the options we understand pulled out of it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DhcpMessage {
+    pub message_type: DhcpMessageType,
+    pub xid: u32,
+    // Asks the server to broadcast its replies, since we can't receive unicast ones without an
+    // address.
+    pub broadcast: bool,
+    pub client_addr: Ipv4Addr,
+    pub your_addr: Ipv4Addr,
+    pub client_link_addr: MacAddress,
+
+    pub server_id: Option<Ipv4Addr>,
+    pub requested_addr: Option<Ipv4Addr>,
+    pub lease_time: Option<Duration>,
+    pub renewal_time: Option<Duration>,
+    pub rebinding_time: Option<Duration>,
+    pub subnet_mask: Option<Ipv4Addr>,
+    pub routers: Vec<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr>,
+}
+
+impl DhcpMessage {
+    pub fn new(message_type: DhcpMessageType, xid: u32, client_link_addr: MacAddress) -> Self {
+        Self {
+            message_type,
+            xid,
+            broadcast: false,
+            client_addr: Ipv4Addr::UNSPECIFIED,
+            your_addr: Ipv4Addr::UNSPECIFIED,
+            client_link_addr,
+            server_id: None,
+            requested_addr: None,
+            lease_time: None,
+            renewal_time: None,
+            rebinding_time: None,
+            subnet_mask: None,
+            routers: vec![],
+            dns_servers: vec![],
+        }
+    }
+
+    fn options_size(&self) -> usize {
+        let addr_option_size = |addr: &Option<Ipv4Addr>| addr.map(|_| 6).unwrap_or(0);
+        let time_option_size = |time: &Option<Duration>| time.map(|_| 6).unwrap_or(0);
+        let list_option_size = |addrs: &Vec<Ipv4Addr>| match addrs.len() {
+            0 => 0,
+            n => 2 + 4 * n,
+        };
+        let parameter_request_list_size = if self.message_type.is_reply() {
+            0
+        } else {
+            2 + REQUESTED_PARAMETERS.len()
+        };
+        // The message type and the end option.
+        3 + addr_option_size(&self.server_id)
+            + addr_option_size(&self.requested_addr)
+            + time_option_size(&self.lease_time)
+            + time_option_size(&self.renewal_time)
+            + time_option_size(&self.rebinding_time)
+            + addr_option_size(&self.subnet_mask)
+            + list_option_size(&self.routers)
+            + list_option_size(&self.dns_servers)
+            + parameter_request_list_size
+            + 1
+    }
+
+    pub fn compute_size(&self) -> usize {
+        cmp::max(DHCP_HEADER_SIZE + self.options_size(), MIN_MESSAGE_SIZE)
+    }
+
+    pub fn parse(buf: &[u8]) -> Result<Self, Fail> {
+        if buf.len() < DHCP_HEADER_SIZE {
+            return Err(Fail::Malformed {
+                details: "DHCP message too small",
+            });
+        }
+        if buf[1] != HTYPE_ETHERNET || buf[2] != 6 {
+            return Err(Fail::Unsupported {
+                details: "DHCP message for another link type",
+            });
+        }
+        if NetworkEndian::read_u32(&buf[236..240]) != MAGIC_COOKIE {
+            return Err(Fail::Malformed {
+                details: "Missing DHCP magic cookie",
+            });
+        }
+        let read_addr = |b: &[u8]| Ipv4Addr::from(NetworkEndian::read_u32(b));
+        let mut message_type = None;
+        let mut msg = Self::new(
+            DhcpMessageType::Discover,
+            NetworkEndian::read_u32(&buf[4..8]),
+            MacAddress::from_bytes(&buf[28..34]),
+        );
+        msg.broadcast = NetworkEndian::read_u16(&buf[10..12]) & FLAG_BROADCAST != 0;
+        msg.client_addr = read_addr(&buf[12..16]);
+        msg.your_addr = read_addr(&buf[16..20]);
+
+        let mut options = &buf[DHCP_HEADER_SIZE..];
+        while let Some(&code) = options.get(0) {
+            match code {
+                OPTION_PAD => {
+                    options = &options[1..];
+                    continue;
+                },
+                OPTION_END => break,
+                _ => (),
+            }
+            if options.len() < 2 || options.len() < 2 + options[1] as usize {
+                return Err(Fail::Malformed {
+                    details: "DHCP option truncated",
+                });
+            }
+            let (value, rest) = options[2..].split_at(options[1] as usize);
+            options = rest;
+            let addr = || -> Result<Ipv4Addr, Fail> {
+                if value.len() != 4 {
+                    return Err(Fail::Malformed {
+                        details: "Invalid DHCP address option",
+                    });
+                }
+                Ok(read_addr(value))
+            };
+            let time = || addr().map(|a| Duration::from_secs(u32::from(a) as u64));
+            match code {
+                OPTION_MESSAGE_TYPE if value.len() == 1 => {
+                    message_type = FromPrimitive::from_u8(value[0]);
+                },
+                OPTION_SERVER_ID => msg.server_id = Some(addr()?),
+                OPTION_REQUESTED_ADDR => msg.requested_addr = Some(addr()?),
+                OPTION_LEASE_TIME => msg.lease_time = Some(time()?),
+                OPTION_RENEWAL_TIME => msg.renewal_time = Some(time()?),
+                OPTION_REBINDING_TIME => msg.rebinding_time = Some(time()?),
+                OPTION_SUBNET_MASK => msg.subnet_mask = Some(addr()?),
+                OPTION_ROUTER => msg.routers = value.chunks_exact(4).map(read_addr).collect(),
+                OPTION_DNS_SERVER => {
+                    msg.dns_servers = value.chunks_exact(4).map(read_addr).collect()
+                },
+                _ => (),
+            }
+        }
+        msg.message_type = message_type.ok_or(Fail::Malformed {
+            details: "Missing DHCP message type",
+        })?;
+        let op = if msg.message_type.is_reply() {
+            BOOTREPLY
+        } else {
+            BOOTREQUEST
+        };
+        if buf[0] != op {
+            return Err(Fail::Malformed {
+                details: "DHCP message type doesn't match BOOTP op",
+            });
+        }
+        Ok(msg)
+    }
+
+    pub fn serialize(&self, buf: &mut [u8]) {
+        let buf = &mut buf[..self.compute_size()];
+        for byte in buf.iter_mut() {
+            *byte = 0;
+        }
+        buf[0] = if self.message_type.is_reply() {
+            BOOTREPLY
+        } else {
+            BOOTREQUEST
+        };
+        buf[1] = HTYPE_ETHERNET;
+        buf[2] = 6;
+        NetworkEndian::write_u32(&mut buf[4..8], self.xid);
+        if self.broadcast {
+            NetworkEndian::write_u16(&mut buf[10..12], FLAG_BROADCAST);
+        }
+        NetworkEndian::write_u32(&mut buf[12..16], u32::from(self.client_addr));
+        NetworkEndian::write_u32(&mut buf[16..20], u32::from(self.your_addr));
+        buf[28..34].copy_from_slice(&self.client_link_addr.octets());
+        NetworkEndian::write_u32(&mut buf[236..240], MAGIC_COOKIE);
+
+        let mut options = OptionWriter {
+            buf: &mut buf[DHCP_HEADER_SIZE..],
+            pos: 0,
+        };
+        options.write(OPTION_MESSAGE_TYPE, &[self.message_type as u8]);
+        let addr_options = [
+            (OPTION_SERVER_ID, self.server_id),
+            (OPTION_REQUESTED_ADDR, self.requested_addr),
+        ];
+        for &(code, addr) in addr_options.iter() {
+            if let Some(addr) = addr {
+                options.write(code, &addr.octets());
+            }
+        }
+        let time_options = [
+            (OPTION_LEASE_TIME, self.lease_time),
+            (OPTION_RENEWAL_TIME, self.renewal_time),
+            (OPTION_REBINDING_TIME, self.rebinding_time),
+        ];
+        for &(code, time) in time_options.iter() {
+            if let Some(time) = time {
+                let secs = cmp::min(time.as_secs(), u32::MAX as u64) as u32;
+                options.write(code, &secs.to_be_bytes());
+            }
+        }
+        if let Some(subnet_mask) = self.subnet_mask {
+            options.write(OPTION_SUBNET_MASK, &subnet_mask.octets());
+        }
+        let list_options = [
+            (OPTION_ROUTER, &self.routers),
+            (OPTION_DNS_SERVER, &self.dns_servers),
+        ];
+        for &(code, addrs) in list_options.iter() {
+            if !addrs.is_empty() {
+                let value: Vec<u8> = addrs.iter().flat_map(|a| a.octets().to_vec()).collect();
+                options.write(code, &value);
+            }
+        }
+        if !self.message_type.is_reply() {
+            options.write(OPTION_PARAMETER_REQUEST_LIST, &REQUESTED_PARAMETERS);
+        }
+        options.buf[options.pos] = OPTION_END;
+    }
+}
+
+struct OptionWriter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> OptionWriter<'a> {
+    fn write(&mut self, code: u8, value: &[u8]) {
+        self.buf[self.pos] = code;
+        self.buf[self.pos + 1] = value.len() as u8;
+        self.buf[(self.pos + 2)..(self.pos + 2 + value.len())].copy_from_slice(value);
+        self.pos += 2 + value.len();
+    }
+}
@@ -0,0 +1,14 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+mod client;
+pub mod datagram;
+
+#[cfg(test)]
+mod tests;
+
+pub use client::{
+    is_client_datagram,
+    DhcpClient as Client,
+    DhcpLease as Lease,
+};
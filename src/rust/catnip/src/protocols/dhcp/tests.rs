@@ -0,0 +1,255 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::datagram::{
+    DhcpMessage,
+    DhcpMessageType,
+    DHCP_CLIENT_PORT,
+    DHCP_SERVER_PORT,
+};
+use crate::{
+    engine::Protocol,
+    file_table::FileDescriptor,
+    protocols::{
+        arp::{
+            ArpMessage,
+            ArpOperation,
+            ArpPdu,
+        },
+        ethernet2::{
+            frame::{
+                EtherType2,
+                Ethernet2Header,
+                VlanTags,
+            },
+            MacAddress,
+        },
+        ip,
+        ipv4::{
+            self,
+            datagram::Ipv4Header,
+        },
+    },
+    runtime::{
+        PacketBuf,
+        Runtime,
+    },
+    sync::{
+        Bytes,
+        BytesMut,
+    },
+    test_helpers,
+};
+use futures::task::noop_waker_ref;
+use must_let::must_let;
+use std::{
+    convert::TryFrom,
+    future::Future,
+    net::Ipv4Addr,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+const LEASED_ADDR: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 100);
+const GATEWAY_ADDR: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 254);
+const DNS_SERVER_ADDR: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 53);
+const LEASE_TIME: Duration = Duration::from_secs(400);
+
+/// Bob plays the DHCP server, listening on the server port of every address.
+fn new_server(now: Instant) -> (test_helpers::TestEngine, FileDescriptor) {
+    let mut bob = test_helpers::new_bob(now);
+    let fd = bob.socket(Protocol::Udp);
+    let port = ip::Port::try_from(DHCP_SERVER_PORT).unwrap();
+    bob.bind(fd, ip::Endpoint::new(Ipv4Addr::UNSPECIFIED, port))
+        .unwrap();
+    (bob, fd)
+}
+
+/// Hands a frame from Alice to the server and returns the IPv4 header and the message in it.
+fn serve(
+    bob: &mut test_helpers::TestEngine,
+    fd: FileDescriptor,
+    frame: Bytes,
+) -> (Ipv4Header, DhcpMessage) {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let (_, ipv4_datagram) = Ethernet2Header::parse(frame.clone()).unwrap();
+    let (ipv4_hdr, _) = Ipv4Header::parse(ipv4_datagram).unwrap();
+    bob.receive(frame).unwrap();
+    let mut pop_future = bob.udp_pop(fd);
+    must_let!(let Poll::Ready(Ok((Some(remote), buf))) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    assert_eq!(remote.port, ip::Port::try_from(DHCP_CLIENT_PORT).unwrap());
+    (ipv4_hdr, DhcpMessage::parse(&buf[..]).unwrap())
+}
+
+fn reply(
+    bob: &mut test_helpers::TestEngine,
+    fd: FileDescriptor,
+    request: &DhcpMessage,
+    message_type: DhcpMessageType,
+) -> Bytes {
+    let mut msg = DhcpMessage::new(message_type, request.xid, request.client_link_addr);
+    msg.your_addr = LEASED_ADDR;
+    msg.server_id = Some(test_helpers::BOB_IPV4);
+    msg.lease_time = Some(LEASE_TIME);
+    msg.subnet_mask = Some(Ipv4Addr::new(255, 255, 255, 0));
+    msg.routers = vec![GATEWAY_ADDR];
+    msg.dns_servers = vec![DNS_SERVER_ADDR];
+    let mut buf = BytesMut::zeroed(msg.compute_size());
+    msg.serialize(&mut buf[..]);
+
+    let port = ip::Port::try_from(DHCP_CLIENT_PORT).unwrap();
+    let to = ip::Endpoint::new(Ipv4Addr::BROADCAST, port);
+    let _push = bob.pushto(fd, buf.freeze(), to);
+    bob.rt().pop_frame()
+}
+
+#[test]
+fn datagram_round_trip() {
+    let mut msg = DhcpMessage::new(DhcpMessageType::Ack, 0x1234, test_helpers::ALICE_MAC);
+    msg.broadcast = true;
+    msg.your_addr = LEASED_ADDR;
+    msg.server_id = Some(test_helpers::BOB_IPV4);
+    msg.lease_time = Some(LEASE_TIME);
+    msg.renewal_time = Some(LEASE_TIME / 4);
+    msg.routers = vec![GATEWAY_ADDR, Ipv4Addr::new(192, 168, 1, 253)];
+    msg.dns_servers = vec![DNS_SERVER_ADDR];
+    let mut buf = BytesMut::zeroed(msg.compute_size());
+    msg.serialize(&mut buf[..]);
+    assert_eq!(DhcpMessage::parse(&buf[..]).unwrap(), msg);
+
+    // Replies need their BOOTP op to match.
+    buf[0] = 1;
+    assert!(DhcpMessage::parse(&buf[..]).is_err());
+}
+
+#[test]
+fn acquire_renew_and_expire() {
+    let now = Instant::now();
+    let options = ipv4::Options::default().dhcp(true);
+    let mut alice = test_helpers::new_alice_with_ipv4_options(now, options);
+    let (mut bob, bob_fd) = new_server(now);
+
+    // Alice gives up her configured address and broadcasts for one.
+    assert_eq!(alice.rt().local_ipv4_addr(), Ipv4Addr::UNSPECIFIED);
+    alice.rt().poll_scheduler();
+    let (ipv4_hdr, discover) = serve(&mut bob, bob_fd, alice.rt().pop_frame());
+    assert_eq!(ipv4_hdr.src_addr, Ipv4Addr::UNSPECIFIED);
+    assert_eq!(ipv4_hdr.dst_addr, Ipv4Addr::BROADCAST);
+    assert_eq!(discover.message_type, DhcpMessageType::Discover);
+    assert_eq!(discover.client_link_addr, test_helpers::ALICE_MAC);
+    assert!(discover.broadcast);
+
+    // She requests the address we offer...
+    let offer = reply(&mut bob, bob_fd, &discover, DhcpMessageType::Offer);
+    alice.receive(offer).unwrap();
+    alice.rt().poll_scheduler();
+    let (_, request) = serve(&mut bob, bob_fd, alice.rt().pop_frame());
+    assert_eq!(request.message_type, DhcpMessageType::Request);
+    assert_eq!(request.xid, discover.xid);
+    assert_eq!(request.requested_addr, Some(LEASED_ADDR));
+    assert_eq!(request.server_id, Some(test_helpers::BOB_IPV4));
+
+    // ...and configures herself once we acknowledge it.
+    let ack = reply(&mut bob, bob_fd, &request, DhcpMessageType::Ack);
+    alice.receive(ack).unwrap();
+    alice.rt().poll_scheduler();
+    let lease = alice.dhcp_lease().unwrap();
+    assert_eq!(lease.addr, LEASED_ADDR);
+    assert_eq!(lease.gateway, Some(GATEWAY_ADDR));
+    assert_eq!(lease.dns_servers, vec![DNS_SERVER_ADDR]);
+    assert_eq!(lease.acquired, now);
+    assert_eq!(lease.renewal_time, LEASE_TIME / 2);
+    assert_eq!(alice.rt().local_ipv4_addr(), LEASED_ADDR);
+
+    // Halfway through the lease, she asks us to extend it directly.
+    let now = now + LEASE_TIME / 2;
+    alice.rt().advance_clock(now);
+    alice.rt().poll_scheduler();
+    let (ipv4_hdr, renew) = serve(&mut bob, bob_fd, alice.rt().pop_frame());
+    assert_eq!(ipv4_hdr.src_addr, LEASED_ADDR);
+    assert_eq!(ipv4_hdr.dst_addr, test_helpers::BOB_IPV4);
+    assert_eq!(renew.message_type, DhcpMessageType::Request);
+    assert_eq!(renew.client_addr, LEASED_ADDR);
+    assert!(!renew.broadcast);
+
+    let ack = reply(&mut bob, bob_fd, &renew, DhcpMessageType::Ack);
+    alice.receive(ack).unwrap();
+    alice.rt().poll_scheduler();
+    assert_eq!(alice.dhcp_lease().unwrap().acquired, now);
+
+    // Without any more answers, the lease runs out and she starts over.
+    let now = now + LEASE_TIME;
+    alice.rt().advance_clock(now);
+    alice.rt().poll_scheduler();
+    assert!(alice.dhcp_lease().is_none());
+    assert_eq!(alice.rt().local_ipv4_addr(), Ipv4Addr::UNSPECIFIED);
+    let (_, discover) = serve(&mut bob, bob_fd, alice.rt().pop_frame());
+    assert_eq!(discover.message_type, DhcpMessageType::Discover);
+}
+
+#[test]
+fn decline_address_in_use() {
+    let now = Instant::now();
+    let options = ipv4::Options::default().dhcp(true);
+    let mut alice = test_helpers::new_alice_with_ipv4_options(now, options);
+    let (mut bob, bob_fd) = new_server(now);
+
+    alice.rt().poll_scheduler();
+    let (_, discover) = serve(&mut bob, bob_fd, alice.rt().pop_frame());
+    alice
+        .receive(reply(&mut bob, bob_fd, &discover, DhcpMessageType::Offer))
+        .unwrap();
+    alice.rt().poll_scheduler();
+    let (_, request) = serve(&mut bob, bob_fd, alice.rt().pop_frame());
+    alice
+        .receive(reply(&mut bob, bob_fd, &request, DhcpMessageType::Ack))
+        .unwrap();
+    alice.rt().poll_scheduler();
+    assert_eq!(alice.rt().local_ipv4_addr(), LEASED_ADDR);
+
+    // Carrie keeps announcing the leased address after Alice defends it...
+    let msg = ArpMessage {
+        ethernet2_hdr: Ethernet2Header {
+            dst_addr: MacAddress::broadcast(),
+            src_addr: test_helpers::CARRIE_MAC,
+            vlan_tags: VlanTags::new(),
+            ether_type: EtherType2::Arp,
+        },
+        arp_pdu: ArpPdu {
+            operation: ArpOperation::Request,
+            sender_hardware_addr: test_helpers::CARRIE_MAC,
+            sender_protocol_addr: LEASED_ADDR,
+            target_hardware_addr: MacAddress::nil(),
+            target_protocol_addr: LEASED_ADDR,
+        },
+    };
+    let mut buf = BytesMut::zeroed(msg.compute_size());
+    msg.serialize(&mut buf[..]);
+    let announcement = buf.freeze();
+    alice.receive(announcement.clone()).unwrap();
+    alice.rt().pop_frame();
+    alice.rt().advance_clock(now + Duration::from_secs(5));
+    alice.receive(announcement).unwrap();
+
+    // ...so Alice gives it up and tells the server.
+    alice.rt().poll_scheduler();
+    assert!(alice.dhcp_lease().is_none());
+    assert_eq!(alice.rt().local_ipv4_addr(), Ipv4Addr::UNSPECIFIED);
+    let (_, decline) = serve(&mut bob, bob_fd, alice.rt().pop_frame());
+    assert_eq!(decline.message_type, DhcpMessageType::Decline);
+    assert_eq!(decline.requested_addr, Some(LEASED_ADDR));
+    assert_eq!(decline.server_id, Some(test_helpers::BOB_IPV4));
+
+    // She starts over after backing off.
+    alice.rt().advance_clock(now + Duration::from_secs(15));
+    alice.rt().poll_scheduler();
+    let (_, discover) = serve(&mut bob, bob_fd, alice.rt().pop_frame());
+    assert_eq!(discover.message_type, DhcpMessageType::Discover);
+}
@@ -0,0 +1,339 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use crate::fail::Fail;
+use byteorder::{
+    ByteOrder,
+    NetworkEndian,
+};
+use num_traits::FromPrimitive;
+use std::{
+    net::{
+        Ipv4Addr,
+        Ipv6Addr,
+    },
+    time::Duration,
+};
+
+pub const DNS_PORT: u16 = 53;
+
+pub const RCODE_NO_ERROR: u8 = 0;
+pub const RCODE_NAME_ERROR: u8 = 3;
+pub const RCODE_REFUSED: u8 = 5;
+
+const DNS_HEADER_SIZE: usize = 12;
+// The type and class after a question's name, plus the TTL and data length after a record's.
+const QUESTION_FIELDS_SIZE: usize = 4;
+const RECORD_FIELDS_SIZE: usize = 10;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const FLAG_RECURSION_AVAILABLE: u16 = 0x0080;
+const RCODE_MASK: u16 = 0x000f;
+
+const CLASS_IN: u16 = 1;
+
+// RFC 1035 section 2.3.4: Size limits of names on the wire.
+const MAX_LABEL_SIZE: usize = 63;
+const MAX_NAME_SIZE: usize = 255;
+// More compression pointers than this in a single name means they loop.
+const MAX_NAME_POINTERS: usize = 16;
+
+#[repr(u16)]
+#[derive(FromPrimitive, Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum RecordType {
+    A = 1,
+    Cname = 5,
+    Aaaa = 28,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsQuestion {
+    pub name: String,
+    pub record_type: RecordType,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsRecord {
+    pub name: String,
+    pub ttl: Duration,
+    pub data: RecordData,
+}
+
+impl DnsRecord {
+    fn record_type(&self) -> RecordType {
+        match self.data {
+            RecordData::A(..) => RecordType::A,
+            RecordData::Aaaa(..) => RecordType::Aaaa,
+            RecordData::Cname(..) => RecordType::Cname,
+        }
+    }
+
+    fn data_size(&self) -> usize {
+        match self.data {
+            RecordData::A(..) => 4,
+            RecordData::Aaaa(..) => 16,
+            RecordData::Cname(ref name) => encoded_name_size(name),
+        }
+    }
+
+    fn compute_size(&self) -> usize {
+        encoded_name_size(&self.name) + RECORD_FIELDS_SIZE + self.data_size()
+    }
+}
+
+/// A DNS message (RFC 1035 section 4.1) with at most one question, which is all stub resolvers
+/// ask. Parsing keeps the answers we understand and skips the authority and additional sections.
+/// We never compress names we write, but follow compression in the ones we read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsMessage {
+    pub id: u16,
+    pub is_response: bool,
+    pub truncated: bool,
+    pub rcode: u8,
+    pub question: Option<DnsQuestion>,
+    pub answers: Vec<DnsRecord>,
+}
+
+impl DnsMessage {
+    pub fn query(id: u16, name: &str, record_type: RecordType) -> Self {
+        Self {
+            id,
+            is_response: false,
+            truncated: false,
+            rcode: RCODE_NO_ERROR,
+            question: Some(DnsQuestion {
+                name: name.to_string(),
+                record_type,
+            }),
+            answers: vec![],
+        }
+    }
+
+    pub fn compute_size(&self) -> usize {
+        let question_size = self
+            .question
+            .as_ref()
+            .map(|q| encoded_name_size(&q.name) + QUESTION_FIELDS_SIZE)
+            .unwrap_or(0);
+        DNS_HEADER_SIZE
+            + question_size
+            + self.answers.iter().map(|r| r.compute_size()).sum::<usize>()
+    }
+
+    pub fn parse(buf: &[u8]) -> Result<Self, Fail> {
+        if buf.len() < DNS_HEADER_SIZE {
+            return Err(Fail::Malformed {
+                details: "DNS message too small",
+            });
+        }
+        let flags = NetworkEndian::read_u16(&buf[2..4]);
+        let num_questions = NetworkEndian::read_u16(&buf[4..6]);
+        let num_answers = NetworkEndian::read_u16(&buf[6..8]);
+        if num_questions > 1 {
+            return Err(Fail::Unsupported {
+                details: "DNS message with several questions",
+            });
+        }
+        let mut msg = Self {
+            id: NetworkEndian::read_u16(&buf[0..2]),
+            is_response: flags & FLAG_RESPONSE != 0,
+            truncated: flags & FLAG_TRUNCATED != 0,
+            rcode: (flags & RCODE_MASK) as u8,
+            question: None,
+            answers: vec![],
+        };
+
+        let mut pos = DNS_HEADER_SIZE;
+        if num_questions == 1 {
+            let (name, next) = read_name(buf, pos)?;
+            let fields = read_fields(buf, next, QUESTION_FIELDS_SIZE)?;
+            let record_type = FromPrimitive::from_u16(NetworkEndian::read_u16(&fields[0..2]))
+                .ok_or(Fail::Unsupported {
+                    details: "Unsupported DNS question type",
+                })?;
+            msg.question = Some(DnsQuestion { name, record_type });
+            pos = next + QUESTION_FIELDS_SIZE;
+        }
+        for _ in 0..num_answers {
+            let (name, next) = read_name(buf, pos)?;
+            let fields = read_fields(buf, next, RECORD_FIELDS_SIZE)?;
+            let class = NetworkEndian::read_u16(&fields[2..4]);
+            // RFC 2181 section 8: TTLs with the top bit set count as zero.
+            let ttl = match NetworkEndian::read_u32(&fields[4..8]) {
+                ttl if ttl & 0x8000_0000 != 0 => 0,
+                ttl => ttl,
+            };
+            let data_pos = next + RECORD_FIELDS_SIZE;
+            let data_size = NetworkEndian::read_u16(&fields[8..10]) as usize;
+            let data = read_fields(buf, data_pos, data_size)?;
+            pos = data_pos + data_size;
+
+            let data = match FromPrimitive::from_u16(NetworkEndian::read_u16(&fields[0..2])) {
+                _ if class != CLASS_IN => continue,
+                Some(RecordType::A) if data_size == 4 => {
+                    RecordData::A(Ipv4Addr::from(NetworkEndian::read_u32(data)))
+                },
+                Some(RecordType::Aaaa) if data_size == 16 => {
+                    let mut octets = [0; 16];
+                    octets.copy_from_slice(data);
+                    RecordData::Aaaa(Ipv6Addr::from(octets))
+                },
+                Some(RecordType::Cname) => RecordData::Cname(read_name(buf, data_pos)?.0),
+                Some(..) => {
+                    return Err(Fail::Malformed {
+                        details: "Invalid DNS record data size",
+                    })
+                },
+                None => continue,
+            };
+            msg.answers.push(DnsRecord {
+                name,
+                ttl: Duration::from_secs(ttl as u64),
+                data,
+            });
+        }
+        Ok(msg)
+    }
+
+    pub fn serialize(&self, buf: &mut [u8]) {
+        let buf = &mut buf[..self.compute_size()];
+        let mut flags = FLAG_RECURSION_DESIRED;
+        if self.is_response {
+            flags |= FLAG_RESPONSE | FLAG_RECURSION_AVAILABLE | (self.rcode as u16 & RCODE_MASK);
+        }
+        if self.truncated {
+            flags |= FLAG_TRUNCATED;
+        }
+        NetworkEndian::write_u16(&mut buf[0..2], self.id);
+        NetworkEndian::write_u16(&mut buf[2..4], flags);
+        NetworkEndian::write_u16(&mut buf[4..6], self.question.is_some() as u16);
+        NetworkEndian::write_u16(&mut buf[6..8], self.answers.len() as u16);
+        NetworkEndian::write_u16(&mut buf[8..10], 0);
+        NetworkEndian::write_u16(&mut buf[10..12], 0);
+
+        let mut pos = DNS_HEADER_SIZE;
+        if let Some(ref question) = self.question {
+            pos += write_name(&mut buf[pos..], &question.name);
+            NetworkEndian::write_u16(&mut buf[pos..(pos + 2)], question.record_type as u16);
+            NetworkEndian::write_u16(&mut buf[(pos + 2)..(pos + 4)], CLASS_IN);
+            pos += QUESTION_FIELDS_SIZE;
+        }
+        for record in &self.answers {
+            pos += write_name(&mut buf[pos..], &record.name);
+            let fields = &mut buf[pos..(pos + RECORD_FIELDS_SIZE)];
+            NetworkEndian::write_u16(&mut fields[0..2], record.record_type() as u16);
+            NetworkEndian::write_u16(&mut fields[2..4], CLASS_IN);
+            NetworkEndian::write_u32(&mut fields[4..8], record.ttl.as_secs() as u32);
+            NetworkEndian::write_u16(&mut fields[8..10], record.data_size() as u16);
+            pos += RECORD_FIELDS_SIZE;
+            match record.data {
+                RecordData::A(addr) => buf[pos..(pos + 4)].copy_from_slice(&addr.octets()),
+                RecordData::Aaaa(addr) => buf[pos..(pos + 16)].copy_from_slice(&addr.octets()),
+                RecordData::Cname(ref name) => {
+                    write_name(&mut buf[pos..], name);
+                },
+            }
+            pos += record.data_size();
+        }
+    }
+}
+
+/// Checks that `name` fits in a query and puts it in the lowercase, dotted form without a
+/// trailing dot that we compare names in.
+pub fn normalize_name(name: &str) -> Result<String, Fail> {
+    let name = if name.ends_with('.') {
+        &name[..(name.len() - 1)]
+    } else {
+        name
+    };
+    if name.is_empty()
+        || encoded_name_size(name) > MAX_NAME_SIZE
+        || name
+            .split('.')
+            .any(|label| label.is_empty() || label.len() > MAX_LABEL_SIZE)
+    {
+        return Err(Fail::Invalid {
+            details: "Invalid domain name",
+        });
+    }
+    Ok(name.to_ascii_lowercase())
+}
+
+fn encoded_name_size(name: &str) -> usize {
+    // Each label gets a length byte in place of its dot, and the root label ends the name.
+    if name.is_empty() {
+        1
+    } else {
+        name.len() + 2
+    }
+}
+
+fn write_name(buf: &mut [u8], name: &str) -> usize {
+    let mut pos = 0;
+    if !name.is_empty() {
+        for label in name.split('.') {
+            buf[pos] = label.len() as u8;
+            buf[(pos + 1)..(pos + 1 + label.len())].copy_from_slice(label.as_bytes());
+            pos += 1 + label.len();
+        }
+    }
+    buf[pos] = 0;
+    pos + 1
+}
+
+fn read_fields(buf: &[u8], pos: usize, size: usize) -> Result<&[u8], Fail> {
+    buf.get(pos..(pos + size)).ok_or(Fail::Malformed {
+        details: "DNS message truncated",
+    })
+}
+
+/// Reads the name at `pos`, returning it normalized along with where the data after it starts.
+fn read_name(buf: &[u8], mut pos: usize) -> Result<(String, usize), Fail> {
+    let mut labels = vec![];
+    let mut size = 1;
+    let mut end = None;
+    let mut num_pointers = 0;
+    loop {
+        let len = read_fields(buf, pos, 1)?[0] as usize;
+        match len & 0xc0 {
+            0xc0 => {
+                let offset = read_fields(buf, pos, 2)?;
+                end = end.or(Some(pos + 2));
+                num_pointers += 1;
+                if num_pointers > MAX_NAME_POINTERS {
+                    return Err(Fail::Malformed {
+                        details: "DNS name compression loop",
+                    });
+                }
+                pos = NetworkEndian::read_u16(offset) as usize & 0x3fff;
+            },
+            0 if len == 0 => break,
+            0 => {
+                let label = read_fields(buf, pos + 1, len)?;
+                size += 1 + len;
+                if size > MAX_NAME_SIZE {
+                    return Err(Fail::Malformed {
+                        details: "DNS name too long",
+                    });
+                }
+                labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+                pos += 1 + len;
+            },
+            _ => {
+                return Err(Fail::Unsupported {
+                    details: "Unsupported DNS label type",
+                })
+            },
+        }
+    }
+    Ok((labels.join("."), end.unwrap_or(pos + 1)))
+}
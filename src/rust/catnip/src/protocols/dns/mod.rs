@@ -0,0 +1,14 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+pub mod datagram;
+pub mod operations;
+mod options;
+mod resolver;
+
+#[cfg(test)]
+mod tests;
+
+pub use datagram::RecordType;
+pub use options::DnsOptions as Options;
+pub use resolver::DnsResolver as Resolver;
@@ -0,0 +1,45 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use crate::{
+    fail::Fail,
+    operations::{
+        OperationResult,
+        ResultFuture,
+    },
+};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+};
+
+pub type ResolveFuture = Pin<Box<dyn Future<Output = Result<usize, Fail>>>>;
+
+pub struct DnsOperation(ResultFuture<ResolveFuture>);
+
+impl From<ResolveFuture> for DnsOperation {
+    fn from(f: ResolveFuture) -> Self {
+        DnsOperation(ResultFuture::new(f))
+    }
+}
+
+impl Future for DnsOperation {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<()> {
+        Future::poll(Pin::new(&mut self.get_mut().0), ctx)
+    }
+}
+
+impl DnsOperation {
+    pub fn expect_result(self) -> OperationResult {
+        match self.0 {
+            ResultFuture { done: Some(r), .. } => OperationResult::Resolve(r),
+            _ => panic!("Future not ready"),
+        }
+    }
+}
@@ -0,0 +1,48 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use std::{
+    net::IpAddr,
+    time::Duration,
+};
+
+#[derive(Clone, Debug)]
+pub struct DnsOptions {
+    // Asked in order, before any a DHCP lease names.
+    pub servers: Vec<IpAddr>,
+    // How long to wait for each server to answer.
+    pub timeout: Duration,
+    // How many times to go through the servers.
+    pub attempts: usize,
+}
+
+impl Default for DnsOptions {
+    fn default() -> Self {
+        // The same as resolv.conf(5).
+        DnsOptions {
+            servers: vec![],
+            timeout: Duration::from_secs(5),
+            attempts: 2,
+        }
+    }
+}
+
+impl DnsOptions {
+    pub fn server(mut self, addr: IpAddr) -> Self {
+        assert!(!addr.is_unspecified() && !addr.is_multicast());
+        self.servers.push(addr);
+        self
+    }
+
+    pub fn timeout(mut self, value: Duration) -> Self {
+        assert!(value > Duration::new(0, 0));
+        self.timeout = value;
+        self
+    }
+
+    pub fn attempts(mut self, value: usize) -> Self {
+        assert!(value > 0);
+        self.attempts = value;
+        self
+    }
+}
@@ -0,0 +1,321 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::{
+    datagram::{
+        normalize_name,
+        DnsMessage,
+        DnsRecord,
+        RecordData,
+        RecordType,
+        DNS_PORT,
+        RCODE_NAME_ERROR,
+        RCODE_NO_ERROR,
+        RCODE_REFUSED,
+    },
+    options::DnsOptions,
+};
+use crate::{
+    collections::HashTtlCache,
+    fail::Fail,
+    file_table::FileDescriptor,
+    protocols::{
+        dhcp,
+        ip,
+        udp,
+    },
+    runtime::Runtime,
+    sync::BytesMut,
+};
+use futures::FutureExt;
+use std::{
+    cell::{
+        RefCell,
+        RefMut,
+    },
+    cmp,
+    convert::TryFrom,
+    future::Future,
+    net::{
+        IpAddr,
+        Ipv4Addr,
+        Ipv6Addr,
+    },
+    rc::Rc,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+// How many random ports we try before giving up on binding a query socket.
+const MAX_BIND_ATTEMPTS: usize = 8;
+
+/// A stub resolver (RFC 1123 section 6.1.3.1) that asks recursive servers for A and AAAA
+/// records and caches the answers for as long as their TTLs allow. We don't fall back to TCP, so
+/// truncated answers are used as they are.
+#[derive(Clone)]
+pub struct DnsResolver<RT: Runtime> {
+    rt: RT,
+    udp: udp::Peer<RT>,
+    dhcp: Option<dhcp::Client<RT>>,
+    cache: Rc<RefCell<HashTtlCache<(String, RecordType), Vec<IpAddr>>>>,
+}
+
+impl<RT: Runtime> DnsResolver<RT> {
+    pub fn new(rt: RT, udp: udp::Peer<RT>, dhcp: Option<dhcp::Client<RT>>) -> Self {
+        let cache = HashTtlCache::new(rt.now(), None);
+        Self {
+            rt,
+            udp,
+            dhcp,
+            cache: Rc::new(RefCell::new(cache)),
+        }
+    }
+
+    /// Looks up the addresses of `name` with a query for each of `record_types`. Names that are
+    /// already addresses come back as they are.
+    pub fn resolve(
+        &self,
+        name: &str,
+        record_types: &[RecordType],
+        timeout: Option<Duration>,
+    ) -> impl Future<Output = Result<Vec<IpAddr>, Fail>> {
+        let resolver = self.clone();
+        let literal = name.parse::<IpAddr>().ok();
+        let name = normalize_name(name);
+        let record_types = record_types.to_vec();
+        let deadline = timeout.map(|t| self.rt.now() + t);
+        async move {
+            if let Some(addr) = literal {
+                return Ok(vec![addr]);
+            }
+            let name = name?;
+            let mut addrs = vec![];
+            let mut error = None;
+            for record_type in record_types {
+                match resolver.lookup(&name, record_type, deadline).await {
+                    Ok(a) => addrs.extend(a),
+                    // A name without AAAA records may still have A records.
+                    Err(e) => error = Some(e),
+                }
+            }
+            match error {
+                Some(e) if addrs.is_empty() => Err(e),
+                _ if addrs.is_empty() => Err(Fail::ResourceNotFound {
+                    details: "Name has no addresses",
+                }),
+                _ => Ok(addrs),
+            }
+        }
+    }
+
+    async fn lookup(
+        &self,
+        name: &str,
+        record_type: RecordType,
+        deadline: Option<Instant>,
+    ) -> Result<Vec<IpAddr>, Fail> {
+        let key = (name.to_string(), record_type);
+        if let Some(addrs) = self.cache().get(&key) {
+            return Ok(addrs.clone());
+        }
+        let (addrs, ttl) = self.query(name, record_type, deadline).await?;
+        match ttl {
+            Some(ttl) if ttl > Duration::new(0, 0) => {
+                self.cache().insert_with_ttl(key, addrs.clone(), Some(ttl));
+            },
+            _ => (),
+        }
+        Ok(addrs)
+    }
+
+    fn cache(&self) -> RefMut<HashTtlCache<(String, RecordType), Vec<IpAddr>>> {
+        let mut cache = self.cache.borrow_mut();
+        cache.advance_clock(self.rt.now());
+        cache.try_evict(usize::max_value());
+        cache
+    }
+
+    /// Asks each server in turn until one of them answers, going through them
+    /// `options.attempts` times. Returns the addresses and how long they may be cached.
+    async fn query(
+        &self,
+        name: &str,
+        record_type: RecordType,
+        deadline: Option<Instant>,
+    ) -> Result<(Vec<IpAddr>, Option<Duration>), Fail> {
+        let options = self.rt.dns_options();
+        let servers = self.servers(&options);
+        if servers.is_empty() {
+            return Err(Fail::ResourceNotFound {
+                details: "No DNS servers",
+            });
+        }
+        let mut error = Fail::Timeout {};
+        for _ in 0..options.attempts {
+            for &server in &servers {
+                let now = self.rt.now();
+                let mut timeout_at = now + options.timeout;
+                if let Some(deadline) = deadline {
+                    if now >= deadline {
+                        return Err(Fail::Timeout {});
+                    }
+                    timeout_at = cmp::min(timeout_at, deadline);
+                }
+                let id = self.rt.rng_gen();
+                let query = DnsMessage::query(id, name, record_type);
+                let response = match self.exchange(server, &query, timeout_at).await {
+                    Ok(response) => response,
+                    Err(e) => {
+                        error = e;
+                        continue;
+                    },
+                };
+                match response.rcode {
+                    RCODE_NO_ERROR => {
+                        return Ok(answer_addrs(&response.answers, name, record_type))
+                    },
+                    RCODE_NAME_ERROR => {
+                        return Err(Fail::ResourceNotFound {
+                            details: "No such domain",
+                        })
+                    },
+                    RCODE_REFUSED => error = Fail::ConnectionRefused {},
+                    _ => {
+                        error = Fail::ResourceBusy {
+                            details: "DNS server failure",
+                        }
+                    },
+                }
+            }
+        }
+        Err(error)
+    }
+
+    fn servers(&self, options: &DnsOptions) -> Vec<IpAddr> {
+        let mut servers = options.servers.clone();
+        if let Some(lease) = self.dhcp.as_ref().and_then(|dhcp| dhcp.lease()) {
+            for addr in lease.dns_servers {
+                let addr = IpAddr::V4(addr);
+                if !servers.contains(&addr) {
+                    servers.push(addr);
+                }
+            }
+        }
+        servers
+    }
+
+    /// Sends `query` to `server` and waits for the response to it until `timeout_at`.
+    async fn exchange(
+        &self,
+        server: IpAddr,
+        query: &DnsMessage,
+        timeout_at: Instant,
+    ) -> Result<DnsMessage, Fail> {
+        let socket = QuerySocket::open(&self.rt, &self.udp, server)?;
+        let server = ip::Endpoint::new(server, ip::Port::try_from(DNS_PORT)?);
+        let mut buf = BytesMut::zeroed(query.compute_size());
+        query.serialize(&mut buf[..]);
+        // Don't wait for a response to a query that never went out.
+        let push = self.udp.pushto(socket.fd, buf.freeze(), server)?.fuse();
+        futures::pin_mut!(push);
+        loop {
+            let r = futures::select! {
+                r = push => {
+                    r?;
+                    continue;
+                },
+                r = self.udp.pop(socket.fd).fuse() => r,
+                _ = self.rt.wait_until(timeout_at).fuse() => return Err(Fail::Timeout {}),
+            };
+            let (remote, buf) = r?;
+            if remote != Some(server) {
+                continue;
+            }
+            // RFC 5452 section 9.1: Only the response to our question counts.
+            match DnsMessage::parse(&buf[..]) {
+                Ok(response)
+                    if response.is_response
+                        && response.id == query.id
+                        && response.question == query.question =>
+                {
+                    return Ok(response)
+                },
+                Ok(..) => (),
+                Err(e) => warn!("Dropped DNS response: {:?}", e),
+            }
+        }
+    }
+}
+
+/// Follows CNAMEs from `name` to its addresses of `record_type`. Returns them with the shortest
+/// TTL along the way.
+fn answer_addrs(
+    answers: &[DnsRecord],
+    name: &str,
+    record_type: RecordType,
+) -> (Vec<IpAddr>, Option<Duration>) {
+    let mut name = name.to_string();
+    let mut addrs = vec![];
+    let mut ttl: Option<Duration> = None;
+    // A chain can't be longer than the answers, which keeps loops out.
+    for _ in 0..=answers.len() {
+        let mut alias = None;
+        for record in answers.iter().filter(|r| r.name == name) {
+            match record.data {
+                RecordData::A(addr) if record_type == RecordType::A => addrs.push(addr.into()),
+                RecordData::Aaaa(addr) if record_type == RecordType::Aaaa => {
+                    addrs.push(addr.into())
+                },
+                RecordData::Cname(ref target) => alias = Some(target.clone()),
+                _ => continue,
+            }
+            ttl = Some(ttl.map_or(record.ttl, |t| cmp::min(t, record.ttl)));
+        }
+        match alias {
+            Some(alias) if addrs.is_empty() => name = alias,
+            _ => break,
+        }
+    }
+    (addrs, ttl)
+}
+
+/// A UDP socket for a single exchange, bound to a random port and closed when dropped.
+struct QuerySocket<RT: Runtime> {
+    udp: udp::Peer<RT>,
+    fd: FileDescriptor,
+}
+
+impl<RT: Runtime> QuerySocket<RT> {
+    fn open(rt: &RT, udp: &udp::Peer<RT>, server: IpAddr) -> Result<Self, Fail> {
+        let socket = Self {
+            udp: udp.clone(),
+            fd: udp.socket(),
+        };
+        let local_addr: IpAddr = match server {
+            IpAddr::V4(..) => Ipv4Addr::UNSPECIFIED.into(),
+            IpAddr::V6(..) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        // RFC 5452 section 9.2: Random ports make forged responses much harder to slip in.
+        let first_port: u16 = ip::Port::first_private_port().into();
+        for _ in 0..MAX_BIND_ATTEMPTS {
+            let port = first_port + rt.rng_gen::<u16>() % (u16::MAX - first_port + 1);
+            let local = ip::Endpoint::new(local_addr, ip::Port::try_from(port)?);
+            if udp.bind(socket.fd, local).is_ok() {
+                return Ok(socket);
+            }
+        }
+        Err(Fail::ResourceExhausted {
+            details: "Out of private ports",
+        })
+    }
+}
+
+impl<RT: Runtime> Drop for QuerySocket<RT> {
+    fn drop(&mut self) {
+        if let Err(e) = self.udp.close(self.fd) {
+            warn!("Failed to close DNS socket: {:?}", e);
+        }
+    }
+}
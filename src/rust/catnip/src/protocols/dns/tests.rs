@@ -0,0 +1,265 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::datagram::{
+    DnsMessage,
+    DnsQuestion,
+    DnsRecord,
+    RecordData,
+    RecordType,
+    DNS_PORT,
+    RCODE_NAME_ERROR,
+};
+use crate::{
+    engine::Protocol,
+    fail::Fail,
+    file_table::FileDescriptor,
+    protocols::{
+        dns,
+        ethernet2::frame::Ethernet2Header,
+        ip,
+        ipv4::datagram::Ipv4Header,
+    },
+    runtime::Runtime,
+    sync::{
+        Bytes,
+        BytesMut,
+    },
+    test_helpers,
+};
+use futures::{
+    task::noop_waker_ref,
+    FutureExt,
+};
+use must_let::must_let;
+use std::{
+    convert::TryFrom,
+    future::Future,
+    net::{
+        IpAddr,
+        Ipv4Addr,
+    },
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+const WEB_ADDR: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 7);
+
+/// Listens on the DNS port of the engine's address.
+fn listen(engine: &mut test_helpers::TestEngine, addr: Ipv4Addr) -> FileDescriptor {
+    let fd = engine.socket(Protocol::Udp);
+    let port = ip::Port::try_from(DNS_PORT).unwrap();
+    engine.bind(fd, ip::Endpoint::new(addr, port)).unwrap();
+    fd
+}
+
+/// Hands a query from Alice to a server and returns where it came from along with the query.
+fn serve(
+    server: &mut test_helpers::TestEngine,
+    fd: FileDescriptor,
+    frame: Bytes,
+) -> (ip::Endpoint, DnsMessage) {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    server.receive(frame).unwrap();
+    let mut pop_future = server.udp_pop(fd);
+    must_let!(let Poll::Ready(Ok((Some(remote), buf))) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    (remote, DnsMessage::parse(&buf[..]).unwrap())
+}
+
+fn answer(
+    server: &mut test_helpers::TestEngine,
+    fd: FileDescriptor,
+    to: ip::Endpoint,
+    response: &DnsMessage,
+) -> Bytes {
+    let mut buf = BytesMut::zeroed(response.compute_size());
+    response.serialize(&mut buf[..]);
+    let _push = server.pushto(fd, buf.freeze(), to);
+    server.rt().pop_frame()
+}
+
+fn dst_addr(frame: &Bytes) -> Ipv4Addr {
+    let (_, ipv4_datagram) = Ethernet2Header::parse(frame.clone()).unwrap();
+    let (ipv4_hdr, _) = Ipv4Header::parse(ipv4_datagram).unwrap();
+    ipv4_hdr.dst_addr
+}
+
+#[test]
+fn parse_compressed_response() {
+    let mut buf = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 2, 0, 0, 0, 0];
+    // The question for example.com at offset 12.
+    buf.extend_from_slice(b"\x07example\x03com\x00\x00\x01\x00\x01");
+    // www.example.com is an alias for example.com, which has a single address.
+    buf.extend_from_slice(b"\x03WWW\xc0\x0c\x00\x05\x00\x01\x00\x00\x01\x2c\x00\x02\xc0\x0c");
+    buf.extend_from_slice(b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x00\x3c\x00\x04\xcb\x00\x71\x07");
+    let msg = DnsMessage::parse(&buf[..]).unwrap();
+    assert_eq!(msg.id, 0x1234);
+    assert!(msg.is_response);
+    assert_eq!(
+        msg.question,
+        Some(DnsQuestion {
+            name: "example.com".to_string(),
+            record_type: RecordType::A,
+        })
+    );
+    assert_eq!(
+        msg.answers,
+        vec![
+            DnsRecord {
+                name: "www.example.com".to_string(),
+                ttl: Duration::from_secs(300),
+                data: RecordData::Cname("example.com".to_string()),
+            },
+            DnsRecord {
+                name: "example.com".to_string(),
+                ttl: Duration::from_secs(60),
+                data: RecordData::A(WEB_ADDR),
+            },
+        ]
+    );
+
+    // A pointer to itself never ends.
+    buf[12] = 0xc0;
+    buf[13] = 0x0c;
+    must_let!(let Err(Fail::Malformed { .. }) = DnsMessage::parse(&buf[..]));
+}
+
+#[test]
+fn resolve_and_cache() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+    let options = dns::Options::default().server(test_helpers::BOB_IPV4.into());
+    let mut alice = test_helpers::new_alice_with_dns_options(now, options);
+    let mut bob = test_helpers::new_bob(now);
+    let bob_fd = listen(&mut bob, test_helpers::BOB_IPV4);
+
+    // Addresses don't need a server.
+    let mut resolve = alice
+        .resolve("192.168.1.2", &[RecordType::A], None)
+        .boxed_local();
+    must_let!(let Poll::Ready(Ok(addrs)) = Future::poll(Pin::new(&mut resolve), &mut ctx));
+    assert_eq!(addrs, vec![IpAddr::V4(test_helpers::BOB_IPV4)]);
+
+    let mut resolve = alice
+        .resolve("WWW.Example.com.", &[RecordType::A], None)
+        .boxed_local();
+    assert!(Future::poll(Pin::new(&mut resolve), &mut ctx).is_pending());
+    let (remote, query) = serve(&mut bob, bob_fd, alice.rt().pop_frame());
+    assert_eq!(
+        query.question,
+        Some(DnsQuestion {
+            name: "www.example.com".to_string(),
+            record_type: RecordType::A,
+        })
+    );
+
+    // The server answers through an alias.
+    let mut response = query.clone();
+    response.is_response = true;
+    response.answers = vec![
+        DnsRecord {
+            name: "www.example.com".to_string(),
+            ttl: Duration::from_secs(300),
+            data: RecordData::Cname("web.example.net".to_string()),
+        },
+        DnsRecord {
+            name: "web.example.net".to_string(),
+            ttl: Duration::from_secs(60),
+            data: RecordData::A(WEB_ADDR),
+        },
+    ];
+    let reply = answer(&mut bob, bob_fd, remote, &response);
+
+    // Answers to a different question don't count.
+    let mut wrong = response.clone();
+    wrong.id = wrong.id.wrapping_add(1);
+    alice
+        .receive(answer(&mut bob, bob_fd, remote, &wrong))
+        .unwrap();
+    assert!(Future::poll(Pin::new(&mut resolve), &mut ctx).is_pending());
+
+    alice.receive(reply).unwrap();
+    must_let!(let Poll::Ready(Ok(addrs)) = Future::poll(Pin::new(&mut resolve), &mut ctx));
+    assert_eq!(addrs, vec![IpAddr::V4(WEB_ADDR)]);
+
+    // The answer is cached for as long as the shortest TTL allows.
+    let mut resolve = alice
+        .resolve("www.example.com", &[RecordType::A], None)
+        .boxed_local();
+    must_let!(let Poll::Ready(Ok(addrs)) = Future::poll(Pin::new(&mut resolve), &mut ctx));
+    assert_eq!(addrs, vec![IpAddr::V4(WEB_ADDR)]);
+
+    alice.rt().advance_clock(now + Duration::from_secs(60));
+    let mut resolve = alice
+        .resolve("www.example.com", &[RecordType::A], None)
+        .boxed_local();
+    assert!(Future::poll(Pin::new(&mut resolve), &mut ctx).is_pending());
+    let (_, query) = serve(&mut bob, bob_fd, alice.rt().pop_frame());
+    assert_eq!(query.question.unwrap().name, "www.example.com");
+}
+
+#[test]
+fn retries_and_errors() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+    let options = dns::Options::default()
+        .server(test_helpers::BOB_IPV4.into())
+        .server(test_helpers::CARRIE_IPV4.into())
+        .timeout(Duration::from_secs(1))
+        .attempts(1);
+    let mut alice = test_helpers::new_alice_with_dns_options(now, options);
+    let mut carrie = test_helpers::new_carrie(now);
+    let carrie_fd = listen(&mut carrie, test_helpers::CARRIE_IPV4);
+
+    // Bob doesn't answer, so Alice moves on to Carrie, who doesn't know the name.
+    let mut resolve = alice
+        .resolve("missing.example.com", &[RecordType::Aaaa], None)
+        .boxed_local();
+    assert!(Future::poll(Pin::new(&mut resolve), &mut ctx).is_pending());
+    assert_eq!(dst_addr(&alice.rt().pop_frame()), test_helpers::BOB_IPV4);
+
+    let now = now + Duration::from_secs(1);
+    alice.rt().advance_clock(now);
+    assert!(Future::poll(Pin::new(&mut resolve), &mut ctx).is_pending());
+    let (remote, query) = serve(&mut carrie, carrie_fd, alice.rt().pop_frame());
+    assert_eq!(
+        query.question.as_ref().unwrap().record_type,
+        RecordType::Aaaa
+    );
+    let mut response = query.clone();
+    response.is_response = true;
+    response.rcode = RCODE_NAME_ERROR;
+    alice
+        .receive(answer(&mut carrie, carrie_fd, remote, &response))
+        .unwrap();
+    must_let!(let Poll::Ready(Err(Fail::ResourceNotFound { .. })) = Future::poll(Pin::new(&mut resolve), &mut ctx));
+
+    // The overall timeout cuts the retries short.
+    let mut resolve = alice
+        .resolve(
+            "slow.example.com",
+            &[RecordType::A],
+            Some(Duration::from_millis(1500)),
+        )
+        .boxed_local();
+    assert!(Future::poll(Pin::new(&mut resolve), &mut ctx).is_pending());
+    assert_eq!(dst_addr(&alice.rt().pop_frame()), test_helpers::BOB_IPV4);
+    let now = now + Duration::from_secs(1);
+    alice.rt().advance_clock(now);
+    assert!(Future::poll(Pin::new(&mut resolve), &mut ctx).is_pending());
+    assert_eq!(dst_addr(&alice.rt().pop_frame()), test_helpers::CARRIE_IPV4);
+    alice.rt().advance_clock(now + Duration::from_millis(500));
+    must_let!(let Poll::Ready(Err(Fail::Timeout {})) = Future::poll(Pin::new(&mut resolve), &mut ctx));
+
+    let mut resolve = alice
+        .resolve("bad..name", &[RecordType::A], None)
+        .boxed_local();
+    must_let!(let Poll::Ready(Err(Fail::Invalid { .. })) = Future::poll(Pin::new(&mut resolve), &mut ctx));
+}
@@ -8,9 +8,10 @@ use byteorder::{
     NetworkEndian,
 };
 use num_traits::FromPrimitive;
-use std::convert::{
-    TryFrom,
-    TryInto,
+use std::{
+    convert::TryFrom,
+    fmt,
+    ops::Deref,
 };
 
 pub const MIN_PAYLOAD_SIZE: usize = 46;
@@ -21,6 +22,7 @@ pub const ETHERNET2_HEADER2_SIZE: usize = 14;
 pub enum EtherType2 {
     Arp = 0x806,
     Ipv4 = 0x800,
+    Ipv6 = 0x86dd,
 }
 
 impl TryFrom<u16> for EtherType2 {
@@ -36,19 +38,100 @@ impl TryFrom<u16> for EtherType2 {
     }
 }
 
+// Tag protocol identifiers for 802.1Q customer tags and 802.1ad (QinQ) service tags, plus the
+// pre-standard one some switches still use for the outer tag.
+pub const TPID_8021Q: u16 = 0x8100;
+pub const TPID_8021AD: u16 = 0x88a8;
+const TPID_QINQ_LEGACY: u16 = 0x9100;
+
+pub const VLAN_TAG_SIZE: usize = 4;
+const MAX_VLAN_TAGS: usize = 2;
+
+/// An 802.1Q tag: the TPID followed by the priority code point, drop eligible indicator and VLAN
+/// ID.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct VlanTag {
+    pub tpid: u16,
+    pub pcp: u8,
+    pub dei: bool,
+    pub vid: u16,
+}
+
+impl VlanTag {
+    fn parse(buf: &[u8]) -> Self {
+        let tci = NetworkEndian::read_u16(&buf[2..4]);
+        Self {
+            tpid: NetworkEndian::read_u16(&buf[0..2]),
+            pcp: (tci >> 13) as u8,
+            dei: tci & 0x1000 != 0,
+            vid: tci & 0xfff,
+        }
+    }
+
+    fn serialize(&self, buf: &mut [u8]) {
+        let tci = (self.pcp as u16) << 13 | (self.dei as u16) << 12 | self.vid & 0xfff;
+        NetworkEndian::write_u16(&mut buf[0..2], self.tpid);
+        NetworkEndian::write_u16(&mut buf[2..4], tci);
+    }
+}
+
+/// The VLAN tags of a frame, outermost first. We never deal with more than QinQ's two, so they're
+/// kept inline instead of allocating for every frame.
+#[derive(Copy, Clone, Default)]
+pub struct VlanTags {
+    tags: [VlanTag; MAX_VLAN_TAGS],
+    len: usize,
+}
+
+impl VlanTags {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, tag: VlanTag) {
+        assert!(self.len < MAX_VLAN_TAGS, "Too many VLAN tags");
+        self.tags[self.len] = tag;
+        self.len += 1;
+    }
+}
+
+impl Deref for VlanTags {
+    type Target = [VlanTag];
+
+    fn deref(&self) -> &[VlanTag] {
+        &self.tags[..self.len]
+    }
+}
+
+impl PartialEq for VlanTags {
+    fn eq(&self, other: &Self) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl Eq for VlanTags {}
+
+impl fmt::Debug for VlanTags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Ethernet2Header {
     // Bytes 0..6
     pub dst_addr: MacAddress,
     // Bytes 6..12
     pub src_addr: MacAddress,
-    // Bytes 12..14
+    // 4 bytes each, outermost first.
+    pub vlan_tags: VlanTags,
+    // The last 2 bytes
     pub ether_type: EtherType2,
 }
 
 impl Ethernet2Header {
     pub fn compute_size(&self) -> usize {
-        ETHERNET2_HEADER2_SIZE
+        ETHERNET2_HEADER2_SIZE + self.vlan_tags.len() * VLAN_TAG_SIZE
     }
 
     pub fn parse(buf: Bytes) -> Result<(Self, Bytes), Fail> {
@@ -57,23 +140,49 @@ impl Ethernet2Header {
                 details: "Frame too small",
             });
         }
-        let (hdr_buf, payload_buf) = buf.split(ETHERNET2_HEADER2_SIZE);
-
-        let dst_addr = MacAddress::from_bytes(&hdr_buf[0..6]);
-        let src_addr = MacAddress::from_bytes(&hdr_buf[6..12]);
-        let ether_type = EtherType2::try_from(NetworkEndian::read_u16(&hdr_buf[12..14]))?;
+        let dst_addr = MacAddress::from_bytes(&buf[0..6]);
+        let src_addr = MacAddress::from_bytes(&buf[6..12]);
+        let mut vlan_tags = VlanTags::new();
+        let mut pos = 12;
+        loop {
+            let ether_type = NetworkEndian::read_u16(&buf[pos..(pos + 2)]);
+            match ether_type {
+                TPID_8021Q | TPID_8021AD | TPID_QINQ_LEGACY => (),
+                _ => break,
+            }
+            if vlan_tags.len() == MAX_VLAN_TAGS {
+                return Err(Fail::Unsupported {
+                    details: "Too many VLAN tags",
+                });
+            }
+            if buf.len() < ETHERNET2_HEADER2_SIZE + (vlan_tags.len() + 1) * VLAN_TAG_SIZE {
+                return Err(Fail::Malformed {
+                    details: "Frame too small for VLAN tag",
+                });
+            }
+            vlan_tags.push(VlanTag::parse(&buf[pos..(pos + VLAN_TAG_SIZE)]));
+            pos += VLAN_TAG_SIZE;
+        }
+        let ether_type = EtherType2::try_from(NetworkEndian::read_u16(&buf[pos..(pos + 2)]))?;
+        let (_, payload_buf) = buf.split(pos + 2);
         let hdr = Self {
             dst_addr,
             src_addr,
+            vlan_tags,
             ether_type,
         };
         Ok((hdr, payload_buf))
     }
 
     pub fn serialize(&self, buf: &mut [u8]) {
-        let buf: &mut [u8; ETHERNET2_HEADER2_SIZE] = buf.try_into().unwrap();
+        assert_eq!(buf.len(), self.compute_size());
         buf[0..6].copy_from_slice(&self.dst_addr.octets());
         buf[6..12].copy_from_slice(&self.src_addr.octets());
-        NetworkEndian::write_u16(&mut buf[12..14], self.ether_type as u16);
+        let mut pos = 12;
+        for tag in self.vlan_tags.iter() {
+            tag.serialize(&mut buf[pos..(pos + VLAN_TAG_SIZE)]);
+            pos += VLAN_TAG_SIZE;
+        }
+        NetworkEndian::write_u16(&mut buf[pos..(pos + 2)], self.ether_type as u16);
     }
 }
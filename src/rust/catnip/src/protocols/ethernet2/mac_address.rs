@@ -41,6 +41,10 @@ impl MacAddress {
         self.0.is_unicast()
     }
 
+    pub fn is_multicast(self) -> bool {
+        self.0.is_multicast()
+    }
+
     pub fn to_canonical(self) -> String {
         self.0.to_canonical()
     }
@@ -3,8 +3,13 @@
 
 pub mod frame;
 mod mac_address;
+mod options;
+
+#[cfg(test)]
+mod tests;
 
 pub use mac_address::MacAddress;
+pub use options::Ethernet2Options as Options;
 
 #[cfg(test)]
 pub use frame::MIN_PAYLOAD_SIZE;
@@ -0,0 +1,89 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::frame::{
+    VlanTag,
+    VlanTags,
+    ETHERNET2_HEADER2_SIZE,
+    TPID_8021AD,
+    TPID_8021Q,
+    VLAN_TAG_SIZE,
+};
+
+// VLAN IDs 0 and 4095 are reserved.
+const MAX_VLAN_ID: u16 = 4094;
+const MAX_PCP: u8 = 7;
+
+#[derive(Clone, Debug, Default)]
+pub struct Ethernet2Options {
+    // The 802.1Q VLAN we're on. Frames are sent untagged and only untagged ones are accepted
+    // when it's `None`.
+    pub vlan_id: Option<u16>,
+    // With QinQ, the 802.1ad service VLAN that carries `vlan_id`. Ignored without `vlan_id`.
+    pub outer_vlan_id: Option<u16>,
+    // The priority code point for tagged frames whose socket doesn't pick one.
+    pub pcp: u8,
+}
+
+impl Ethernet2Options {
+    pub fn vlan_id(mut self, value: u16) -> Self {
+        assert!(value > 0 && value <= MAX_VLAN_ID);
+        self.vlan_id = Some(value);
+        self
+    }
+
+    pub fn outer_vlan_id(mut self, value: u16) -> Self {
+        assert!(value > 0 && value <= MAX_VLAN_ID);
+        self.outer_vlan_id = Some(value);
+        self
+    }
+
+    pub fn pcp(mut self, value: u8) -> Self {
+        assert!(value <= MAX_PCP);
+        self.pcp = value;
+        self
+    }
+
+    fn vlan_ids(&self) -> impl Iterator<Item = u16> {
+        let outer_vlan_id = match self.vlan_id {
+            Some(..) => self.outer_vlan_id,
+            None => None,
+        };
+        outer_vlan_id.into_iter().chain(self.vlan_id)
+    }
+
+    /// The tags to stamp on outgoing frames, outermost first, with `pcp` overriding the default
+    /// priority.
+    pub fn vlan_tags(&self, pcp: Option<u8>) -> VlanTags {
+        let pcp = pcp.unwrap_or(self.pcp);
+        let qinq = self.vlan_ids().count() > 1;
+        let mut tags = VlanTags::new();
+        for (i, vid) in self.vlan_ids().enumerate() {
+            tags.push(VlanTag {
+                tpid: if qinq && i == 0 {
+                    TPID_8021AD
+                } else {
+                    TPID_8021Q
+                },
+                pcp,
+                dei: false,
+                vid,
+            });
+        }
+        tags
+    }
+
+    /// Whether an incoming frame with `vlan_tags` is on our VLAN. Priority tagged frames (VLAN
+    /// ID 0) count as untagged.
+    pub fn accepts(&self, vlan_tags: &[VlanTag]) -> bool {
+        vlan_tags
+            .iter()
+            .map(|tag| tag.vid)
+            .filter(|&vid| vid != 0)
+            .eq(self.vlan_ids())
+    }
+
+    pub fn header_size(&self) -> usize {
+        ETHERNET2_HEADER2_SIZE + self.vlan_ids().count() * VLAN_TAG_SIZE
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::{
+    frame::{
+        EtherType2,
+        Ethernet2Header,
+        VlanTag,
+        TPID_8021AD,
+        TPID_8021Q,
+    },
+    Options,
+};
+use crate::{
+    engine::Protocol,
+    fail::Fail,
+    protocols::ip,
+    socket_options::SocketOption,
+    sync::{
+        Bytes,
+        BytesMut,
+    },
+    test_helpers,
+};
+use futures::task::noop_waker_ref;
+use must_let::must_let;
+use std::{
+    convert::TryFrom,
+    future::Future,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+    time::Instant,
+};
+
+#[test]
+fn qinq_tags() {
+    let mut frame = vec![];
+    frame.extend_from_slice(&test_helpers::ALICE_MAC.octets());
+    frame.extend_from_slice(&test_helpers::BOB_MAC.octets());
+    // A service tag for VLAN 200 around a customer tag for VLAN 100 with priority 5 and DEI set.
+    frame.extend_from_slice(&[0x88, 0xa8, 0x00, 0xc8]);
+    frame.extend_from_slice(&[0x81, 0x00, 0xb0, 0x64]);
+    frame.extend_from_slice(&[0x08, 0x00]);
+    frame.extend_from_slice(b"payload");
+    let frame = BytesMut::from(&frame[..]).freeze();
+
+    let (header, payload) = Ethernet2Header::parse(frame.clone()).unwrap();
+    assert_eq!(header.ether_type, EtherType2::Ipv4);
+    assert_eq!(&payload[..], b"payload");
+    let outer = VlanTag {
+        tpid: TPID_8021AD,
+        pcp: 0,
+        dei: false,
+        vid: 200,
+    };
+    let inner = VlanTag {
+        tpid: TPID_8021Q,
+        pcp: 5,
+        dei: true,
+        vid: 100,
+    };
+    assert_eq!(&header.vlan_tags[..], &[outer, inner][..]);
+    assert_eq!(header.compute_size(), 22);
+
+    let mut buf = BytesMut::zeroed(header.compute_size());
+    header.serialize(&mut buf[..]);
+    assert_eq!(&buf[..], &frame[..22]);
+
+    // We don't go deeper than QinQ.
+    let mut frame = frame[..12].to_vec();
+    for _ in 0..3 {
+        frame.extend_from_slice(&[0x81, 0x00, 0x00, 0x64]);
+    }
+    frame.extend_from_slice(&[0x08, 0x00]);
+    must_let!(let Err(Fail::Unsupported { .. }) = Ethernet2Header::parse(BytesMut::from(&frame[..]).freeze()));
+}
+
+#[test]
+fn vlan_options() {
+    let options = Options::default();
+    assert!(options.vlan_tags(None).is_empty());
+    assert_eq!(options.header_size(), 14);
+
+    let options = Options::default().vlan_id(100).outer_vlan_id(200).pcp(3);
+    let tags = options.vlan_tags(None);
+    assert_eq!(tags.len(), 2);
+    assert_eq!(
+        (tags[0].tpid, tags[0].vid, tags[0].pcp),
+        (TPID_8021AD, 200, 3)
+    );
+    assert_eq!(
+        (tags[1].tpid, tags[1].vid, tags[1].pcp),
+        (TPID_8021Q, 100, 3)
+    );
+    assert_eq!(options.vlan_tags(Some(6))[1].pcp, 6);
+    assert_eq!(options.header_size(), 22);
+    assert!(options.accepts(&tags));
+    assert!(!options.accepts(&tags[1..]));
+
+    // Priority tagged frames count as untagged.
+    let priority_tag = VlanTag {
+        tpid: TPID_8021Q,
+        pcp: 4,
+        dei: false,
+        vid: 0,
+    };
+    assert!(Options::default().accepts(&[priority_tag]));
+    assert!(!Options::default().vlan_id(100).accepts(&[priority_tag]));
+}
+
+#[test]
+fn vlan_udp() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+    let options = Options::default().vlan_id(100);
+    let mut alice = test_helpers::new_alice_with_ethernet2_options(now, options.clone());
+    let mut bob = test_helpers::new_bob_with_ethernet2_options(now, options);
+    let mut carrie = test_helpers::new_carrie(now);
+
+    let port = ip::Port::try_from(80).unwrap();
+    let alice_addr = ip::Endpoint::new(test_helpers::ALICE_IPV4, port);
+    let alice_fd = alice.socket(Protocol::Udp);
+    alice.bind(alice_fd, alice_addr).unwrap();
+
+    let send = |engine: &mut test_helpers::TestEngine, fd| -> Bytes {
+        let buf = BytesMut::from(&b"hello"[..]).freeze();
+        let _push = engine.pushto(fd, buf, alice_addr);
+        engine.rt().pop_frame()
+    };
+
+    // Bob is on Alice's VLAN, so his frames are tagged and get through.
+    let bob_fd = bob.socket(Protocol::Udp);
+    bob.bind(bob_fd, ip::Endpoint::new(test_helpers::BOB_IPV4, port))
+        .unwrap();
+    let frame = send(&mut bob, bob_fd);
+    let (header, _) = Ethernet2Header::parse(frame.clone()).unwrap();
+    assert_eq!(header.vlan_tags.len(), 1);
+    assert_eq!((header.vlan_tags[0].vid, header.vlan_tags[0].pcp), (100, 0));
+    alice.receive(frame).unwrap();
+    let mut pop_future = alice.udp_pop(alice_fd);
+    must_let!(let Poll::Ready(Ok((_, buf))) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    assert_eq!(&buf[..], b"hello");
+
+    // Sockets pick their own priority.
+    must_let!(let Err(Fail::OutOfRange { .. }) = bob.setsockopt(bob_fd, SocketOption::VlanPriority(Some(8))));
+    bob.setsockopt(bob_fd, SocketOption::VlanPriority(Some(5)))
+        .unwrap();
+    let (header, _) = Ethernet2Header::parse(send(&mut bob, bob_fd)).unwrap();
+    assert_eq!(header.vlan_tags[0].pcp, 5);
+
+    // Carrie isn't on the VLAN, so Alice drops her untagged frames.
+    let carrie_fd = carrie.socket(Protocol::Udp);
+    carrie
+        .bind(
+            carrie_fd,
+            ip::Endpoint::new(test_helpers::CARRIE_IPV4, port),
+        )
+        .unwrap();
+    let frame = send(&mut carrie, carrie_fd);
+    assert!(Ethernet2Header::parse(frame.clone())
+        .unwrap()
+        .0
+        .vlan_tags
+        .is_empty());
+    must_let!(let Err(Fail::Ignored { .. }) = alice.receive(frame));
+}
@@ -7,7 +7,10 @@ use crate::{
             Ethernet2Header,
             MIN_PAYLOAD_SIZE,
         },
-        ipv4::datagram::Ipv4Header,
+        ipv4::datagram::{
+            Ipv4Header,
+            Ipv4Protocol2,
+        },
     },
     runtime::PacketBuf,
     sync::Bytes,
@@ -21,8 +24,19 @@ use std::{
     convert::TryInto,
 };
 
-#[allow(unused)]
-const MAX_ICMPV4_DATAGRAM_SIZE: usize = 576;
+// RFC 1812 section 4.3.2.3: Error messages quote as much of the original datagram as fits in a
+// datagram of this size.
+pub const MAX_ICMPV4_DATAGRAM_SIZE: usize = 576;
+
+// Codes for `Icmpv4Type2::DestinationUnreachable` (RFC 792).
+pub const ICMPV4_NET_UNREACHABLE: u8 = 0;
+pub const ICMPV4_HOST_UNREACHABLE: u8 = 1;
+pub const ICMPV4_PROTOCOL_UNREACHABLE: u8 = 2;
+pub const ICMPV4_PORT_UNREACHABLE: u8 = 3;
+
+// Error messages quote at least the original IPv4 header and the first 8 bytes of its payload,
+// which covers the ports of both TCP and UDP.
+const ICMPV4_ERROR_MIN_QUOTE_SIZE: usize = 8;
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum Icmpv4Type2 {
@@ -71,11 +85,11 @@ impl Icmpv4Type2 {
     fn serialize(&self) -> (u8, [u8; 4]) {
         use Icmpv4Type2::*;
         match self {
-            EchoReply { .. } => (0, [0u8; 4]),
+            EchoReply { id, seq_num } => (0, echo_rest_of_header(*id, *seq_num)),
             DestinationUnreachable => (3, [0u8; 4]),
             SourceQuench => (4, [0u8; 4]),
             RedirectMessage => (5, [0u8; 4]),
-            EchoRequest { .. } => (8, [0u8; 4]),
+            EchoRequest { id, seq_num } => (8, echo_rest_of_header(*id, *seq_num)),
             RouterAdvertisement => (9, [0u8; 4]),
             RouterSolicitation => (10, [0u8; 4]),
             TimeExceeded => (11, [0u8; 4]),
@@ -86,18 +100,106 @@ impl Icmpv4Type2 {
     }
 }
 
+fn echo_rest_of_header(id: u16, seq_num: u16) -> [u8; 4] {
+    let mut rest_of_header = [0u8; 4];
+    NetworkEndian::write_u16(&mut rest_of_header[0..2], id);
+    NetworkEndian::write_u16(&mut rest_of_header[2..4], seq_num);
+    rest_of_header
+}
+
+/// Splits the start of the original datagram quoted by an error message into its header and
+/// payload.
+fn parse_quote(buf: Bytes) -> Result<(Ipv4Header, Bytes), Fail> {
+    let (ipv4_hdr, data) = Ipv4Header::parse_truncated(buf)?;
+    if data.len() < ICMPV4_ERROR_MIN_QUOTE_SIZE {
+        return Err(Fail::Malformed {
+            details: "ICMPv4 error quotes too little of the original datagram",
+        });
+    }
+    Ok((ipv4_hdr, data))
+}
+
+/// The body of a Destination Unreachable message: the start of the datagram that couldn't be
+/// delivered.
+#[derive(Clone, Debug)]
+pub struct DestinationUnreachable {
+    pub code: u8,
+    pub ipv4_hdr: Ipv4Header,
+    pub data: Bytes,
+}
+
+impl DestinationUnreachable {
+    pub fn parse(code: u8, buf: Bytes) -> Result<Self, Fail> {
+        let (ipv4_hdr, data) = parse_quote(buf)?;
+        Ok(Self {
+            code,
+            ipv4_hdr,
+            data,
+        })
+    }
+
+    /// Whether the destination won't ever accept the datagram, as opposed to a condition that
+    /// may be transient (RFC 1122 section 4.2.3.9).
+    pub fn is_hard_error(&self) -> bool {
+        match self.code {
+            ICMPV4_PROTOCOL_UNREACHABLE | ICMPV4_PORT_UNREACHABLE => true,
+            _ => false,
+        }
+    }
+
+    /// The error to report to the socket that sent the original datagram.
+    pub fn to_fail(&self) -> Fail {
+        match self.code {
+            ICMPV4_NET_UNREACHABLE | ICMPV4_HOST_UNREACHABLE => Fail::NoRoute {},
+            _ => Fail::ConnectionRefused {},
+        }
+    }
+}
+
+/// The body of a Time Exceeded message, which a router sends when a datagram's TTL runs out.
+#[derive(Clone, Debug)]
+pub struct TimeExceeded {
+    pub code: u8,
+    pub ipv4_hdr: Ipv4Header,
+    pub data: Bytes,
+}
+
+impl TimeExceeded {
+    pub fn parse(code: u8, buf: Bytes) -> Result<Self, Fail> {
+        let (ipv4_hdr, data) = parse_quote(buf)?;
+        Ok(Self {
+            code,
+            ipv4_hdr,
+            data,
+        })
+    }
+
+    /// The identifier and sequence number of the Echo Request that expired, if that's what the
+    /// original datagram was. Its checksum isn't verified since the quote may be truncated.
+    pub fn echo_request(&self) -> Option<(u16, u16)> {
+        if self.ipv4_hdr.protocol != Ipv4Protocol2::Icmpv4 || self.data[0] != 8 {
+            return None;
+        }
+        let id = NetworkEndian::read_u16(&self.data[4..6]);
+        let seq_num = NetworkEndian::read_u16(&self.data[6..8]);
+        Some((id, seq_num))
+    }
+}
+
 pub struct Icmpv4Message {
     pub ethernet2_hdr: Ethernet2Header,
     pub ipv4_hdr: Ipv4Header,
     pub icmpv4_hdr: Icmpv4Header,
     // TODO: Add a body enum when we need it.
+    pub data: Bytes,
 }
 
 impl PacketBuf for Icmpv4Message {
     fn compute_size(&self) -> usize {
         let size = self.ethernet2_hdr.compute_size()
             + self.ipv4_hdr.compute_size()
-            + self.icmpv4_hdr.compute_size();
+            + self.icmpv4_hdr.compute_size()
+            + self.data.len();
 
         // Pad the end of the buffer with zeros if needed.
         cmp::max(size, MIN_PAYLOAD_SIZE)
@@ -113,17 +215,22 @@ impl PacketBuf for Icmpv4Message {
             .serialize(&mut buf[cur_pos..(cur_pos + eth_hdr_size)]);
         cur_pos += eth_hdr_size;
 
-        let ipv4_payload_len = icmpv4_hdr_size;
+        let ipv4_payload_len = icmpv4_hdr_size + self.data.len();
         self.ipv4_hdr.serialize(
             &mut buf[cur_pos..(cur_pos + ipv4_hdr_size)],
             ipv4_payload_len,
         );
         cur_pos += ipv4_hdr_size;
 
-        self.icmpv4_hdr
-            .serialize(&mut buf[cur_pos..(cur_pos + icmpv4_hdr_size)]);
+        self.icmpv4_hdr.serialize(
+            &mut buf[cur_pos..(cur_pos + icmpv4_hdr_size)],
+            &self.data[..],
+        );
         cur_pos += icmpv4_hdr_size;
 
+        buf[cur_pos..(cur_pos + self.data.len())].copy_from_slice(&self.data[..]);
+        cur_pos += self.data.len();
+
         // Add Ethernet padding if needed.
         for byte in &mut buf[cur_pos..] {
             *byte = 0;
@@ -167,7 +274,7 @@ impl Icmpv4Header {
         Ok((Self { icmpv4_type, code }, data_buf))
     }
 
-    pub fn serialize(&self, buf: &mut [u8]) {
+    pub fn serialize(&self, buf: &mut [u8], data: &[u8]) {
         let buf: &mut [u8; ICMPV4_HEADER2_SIZE] =
             (&mut buf[..ICMPV4_HEADER2_SIZE]).try_into().unwrap();
         let (type_byte, rest_of_header) = self.icmpv4_type.serialize();
@@ -175,7 +282,7 @@ impl Icmpv4Header {
         buf[1] = self.code;
         // Skip the checksum for now.
         buf[4..8].copy_from_slice(&rest_of_header[..]);
-        let checksum = icmpv4_checksum(buf, &[]);
+        let checksum = icmpv4_checksum(buf, data);
         NetworkEndian::write_u16(&mut buf[2..4], checksum);
     }
 }
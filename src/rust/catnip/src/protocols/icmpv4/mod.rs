@@ -1,7 +1,12 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
-mod datagram;
+pub mod datagram;
+pub mod operations;
 mod peer;
 
-pub use peer::Icmpv4Peer as Peer;
+pub use peer::{
+    Icmpv4Peer as Peer,
+    TracerouteHop,
+    MAX_PING_PAYLOAD_SIZE,
+};
@@ -0,0 +1,62 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use crate::{
+    fail::Fail,
+    operations::{
+        OperationResult,
+        ResultFuture,
+    },
+};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+    time::Duration,
+};
+
+pub type PingFuture = Pin<Box<dyn Future<Output = Result<Duration, Fail>>>>;
+pub type TracerouteFuture = Pin<Box<dyn Future<Output = Result<usize, Fail>>>>;
+
+pub enum Icmpv4Operation {
+    Ping(ResultFuture<PingFuture>),
+    Traceroute(ResultFuture<TracerouteFuture>),
+}
+
+impl From<PingFuture> for Icmpv4Operation {
+    fn from(f: PingFuture) -> Self {
+        Icmpv4Operation::Ping(ResultFuture::new(f))
+    }
+}
+
+impl From<TracerouteFuture> for Icmpv4Operation {
+    fn from(f: TracerouteFuture) -> Self {
+        Icmpv4Operation::Traceroute(ResultFuture::new(f))
+    }
+}
+
+impl Future for Icmpv4Operation {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<()> {
+        match self.get_mut() {
+            Icmpv4Operation::Ping(ref mut f) => Future::poll(Pin::new(f), ctx),
+            Icmpv4Operation::Traceroute(ref mut f) => Future::poll(Pin::new(f), ctx),
+        }
+    }
+}
+
+impl Icmpv4Operation {
+    pub fn expect_result(self) -> OperationResult {
+        match self {
+            Icmpv4Operation::Ping(ResultFuture { done: Some(r), .. }) => OperationResult::Ping(r),
+            Icmpv4Operation::Traceroute(ResultFuture { done: Some(r), .. }) => {
+                OperationResult::Traceroute(r)
+            },
+            _ => panic!("Future not ready"),
+        }
+    }
+}
@@ -2,8 +2,12 @@
 // Licensed under the MIT license.
 
 use super::datagram::{
+    DestinationUnreachable,
     Icmpv4Header,
     Icmpv4Type2,
+    TimeExceeded,
+    ICMPV4_HEADER2_SIZE,
+    MAX_ICMPV4_DATAGRAM_SIZE,
 };
 use crate::{
     fail::Fail,
@@ -14,61 +18,110 @@ use crate::{
             Ethernet2Header,
         },
         icmpv4::datagram::Icmpv4Message,
+        ip,
+        ipv4,
         ipv4::datagram::{
             Ipv4Header,
             Ipv4Protocol2,
+            IPV4_HEADER2_SIZE,
         },
     },
     runtime::Runtime,
-    scheduler::SchedulerHandle,
-    sync::Bytes,
+    sync::{
+        Bytes,
+        BytesMut,
+    },
 };
 use byteorder::{
     ByteOrder,
     NetworkEndian,
 };
 use futures::{
+    future::{
+        self,
+        Either,
+    },
     FutureExt,
-    StreamExt,
 };
 use hashbrown::HashMap;
 use std::{
     cell::RefCell,
+    cmp,
     future::Future,
     net::Ipv4Addr,
     num::Wrapping,
     process,
     rc::Rc,
-    time::Duration,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 // TODO: Use unsync channel
-use futures::channel::{
-    mpsc,
-    oneshot::{
-        channel,
-        Sender,
-    },
+use futures::channel::oneshot::{
+    channel,
+    Sender,
 };
 
+// Echo Requests can't carry more data than fits in the largest IPv4 datagram.
+pub const MAX_PING_PAYLOAD_SIZE: usize = 65535 - IPV4_HEADER2_SIZE - ICMPV4_HEADER2_SIZE;
+
+const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+// RFC 1812 section 4.3.2.8: Errors are rate limited, here with a token bucket that allows a burst of
+// this many...
+const ICMP_ERROR_BURST: u32 = 50;
+// ...and then one more per interval, like Linux's defaults.
+const ICMP_ERROR_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A router or destination that answered a traceroute probe.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TracerouteHop {
+    pub addr: Ipv4Addr,
+    pub rtt: Duration,
+}
+
+#[derive(Clone)]
 pub struct Icmpv4Peer<RT: Runtime> {
     rt: RT,
-    arp: arp::Peer<RT>,
+    network: ip::Network<RT>,
+    fragmenter: ipv4::Fragmenter<RT>,
+
+    inner: Rc<RefCell<Inner>>,
+}
 
-    #[allow(unused)]
-    handle: SchedulerHandle,
-    tx: mpsc::UnboundedSender<(Ipv4Addr, u16, u16)>,
+enum EchoResponse {
+    Reply(Bytes),
+    // A router on the way dropped the request, which happens on purpose for traceroute.
+    TimeExceeded(Ipv4Addr),
+}
 
+/// Forgets an Echo Request once nobody's waiting for the response anymore, even if the qtoken
+/// was dropped before it arrived.
+struct PendingEcho {
     inner: Rc<RefCell<Inner>>,
+    key: (u16, u16),
+}
+
+impl Drop for PendingEcho {
+    fn drop(&mut self) {
+        self.inner.borrow_mut().requests.remove(&self.key);
+    }
 }
 
 struct Inner {
-    requests: HashMap<(u16, u16), Sender<()>>,
+    requests: HashMap<(u16, u16), Sender<EchoResponse>>,
     ping_seq_num_counter: Wrapping<u16>,
+    error_tokens: u32,
+    error_tokens_refilled: Instant,
 }
 
 impl<RT: Runtime> Icmpv4Peer<RT> {
-    pub fn new(rt: RT, arp: arp::Peer<RT>) -> Icmpv4Peer<RT> {
-        let (tx, rx) = mpsc::unbounded();
+    pub fn new(
+        rt: RT,
+        network: ip::Network<RT>,
+        fragmenter: ipv4::Fragmenter<RT>,
+    ) -> Icmpv4Peer<RT> {
         let inner = Inner {
             requests: HashMap::new(),
             // from [TCP/IP Illustrated]():
@@ -76,155 +129,390 @@ impl<RT: Runtime> Icmpv4Peer<RT> {
             // > Number field starts with the value 0 and is increased by 1 every
             // > time a new Echo Request message is sent.
             ping_seq_num_counter: Wrapping(0),
+            error_tokens: ICMP_ERROR_BURST,
+            error_tokens_refilled: rt.now(),
         };
         let inner = Rc::new(RefCell::new(inner));
-        let future = Self::background(rt.clone(), arp.clone(), rx);
-        let handle = rt.spawn(future);
         Icmpv4Peer {
             rt,
-            arp,
-            tx,
-            handle,
+            network,
+            fragmenter,
             inner,
         }
     }
 
-    async fn background(
-        rt: RT,
-        arp: arp::Peer<RT>,
-        mut rx: mpsc::UnboundedReceiver<(Ipv4Addr, u16, u16)>,
-    ) {
-        while let Some((dst_ipv4_addr, id, seq_num)) = rx.next().await {
-            let r: Result<_, Fail> = try {
-                debug!("initiating ARP query");
-                let dst_link_addr = arp.query(dst_ipv4_addr).await?;
-                debug!(
-                    "ARP query complete ({} -> {})",
-                    dst_ipv4_addr, dst_link_addr
-                );
-                let msg = Icmpv4Message {
-                    ethernet2_hdr: Ethernet2Header {
-                        dst_addr: dst_link_addr,
-                        src_addr: rt.local_link_addr(),
-                        ether_type: EtherType2::Ipv4,
-                    },
-                    ipv4_hdr: Ipv4Header::new(
-                        rt.local_ipv4_addr(),
-                        dst_ipv4_addr,
-                        Ipv4Protocol2::Icmpv4,
-                    ),
-                    icmpv4_hdr: Icmpv4Header {
-                        icmpv4_type: Icmpv4Type2::EchoReply { id, seq_num },
-                        code: 0,
-                    },
-                };
-                rt.transmit(msg);
+    /// Sends a message once its destination's link address is known, without holding up the
+    /// messages to other destinations.
+    fn transmit(
+        &self,
+        ipv4_hdr: Ipv4Header,
+        icmpv4_hdr: Icmpv4Header,
+        data: Bytes,
+    ) -> Result<(), Fail> {
+        let dst_ipv4_addr = ipv4_hdr.dst_addr;
+        let rt = self.rt.clone();
+        let fragmenter = self.fragmenter.clone();
+        let send: arp::PendingFrame = Box::new(move |link_addr| {
+            let msg = Icmpv4Message {
+                ethernet2_hdr: Ethernet2Header {
+                    dst_addr: link_addr?,
+                    src_addr: rt.local_link_addr(),
+                    vlan_tags: rt.ethernet2_options().vlan_tags(None),
+                    ether_type: EtherType2::Ipv4,
+                },
+                ipv4_hdr,
+                icmpv4_hdr,
+                data,
             };
-            if let Err(e) = r {
-                warn!(
-                    "reply_to_ping({}, {}, {}) failed: {:?}",
-                    dst_ipv4_addr, id, seq_num, e
-                )
-            }
-        }
+            fragmenter.transmit(msg)
+        });
+        self.network.arp.transmit_when_resolved(dst_ipv4_addr, send)
     }
 
-    pub fn receive(&mut self, ipv4_header: &Ipv4Header, buf: Bytes) -> Result<(), Fail> {
-        let (icmpv4_hdr, _) = Icmpv4Header::parse(buf)?;
+    /// Handles an incoming message, returning Destination Unreachable errors for the transport
+    /// that sent the original datagram.
+    pub fn receive(
+        &mut self,
+        ipv4_header: &Ipv4Header,
+        buf: Bytes,
+    ) -> Result<Option<DestinationUnreachable>, Fail> {
+        let (icmpv4_hdr, data) = Icmpv4Header::parse(buf)?;
         match icmpv4_hdr.icmpv4_type {
             Icmpv4Type2::EchoRequest { id, seq_num } => {
-                self.reply_to_ping(ipv4_header.src_addr, id, seq_num);
+                // Reply from the address that was pinged unless it was a broadcast.
+                let src_addr = if self
+                    .network
+                    .ipv4_routes
+                    .borrow()
+                    .is_local(ipv4_header.dst_addr)
+                {
+                    ipv4_header.dst_addr
+                } else {
+                    self.network
+                        .ipv4_routes
+                        .borrow()
+                        .source_addr(ipv4_header.src_addr)
+                };
+                let mut reply_hdr = self.new_header(src_addr, ipv4_header.src_addr);
+                // Like Linux, answer with the same priority the request asked for.
+                reply_hdr.dscp = ipv4_header.dscp;
+                self.reply_to_ping(reply_hdr, id, seq_num, data)?;
             },
             Icmpv4Type2::EchoReply { id, seq_num } => {
                 let mut inner = self.inner.borrow_mut();
                 if let Some(tx) = inner.requests.remove(&(id, seq_num)) {
-                    let _ = tx.send(());
+                    let _ = tx.send(EchoResponse::Reply(data));
+                }
+            },
+            Icmpv4Type2::TimeExceeded => {
+                let error = TimeExceeded::parse(icmpv4_hdr.code, data)?;
+                if !self
+                    .network
+                    .ipv4_routes
+                    .borrow()
+                    .is_local(error.ipv4_hdr.src_addr)
+                {
+                    return Err(Fail::Ignored {
+                        details: "ICMPv4 error about a datagram we didn't send",
+                    });
+                }
+                let key = error.echo_request().ok_or(Fail::Ignored {
+                    details: "Time Exceeded for something other than a ping",
+                })?;
+                let mut inner = self.inner.borrow_mut();
+                if let Some(tx) = inner.requests.remove(&key) {
+                    let _ = tx.send(EchoResponse::TimeExceeded(ipv4_header.src_addr));
+                }
+            },
+            Icmpv4Type2::DestinationUnreachable => {
+                let error = DestinationUnreachable::parse(icmpv4_hdr.code, data)?;
+                if !self
+                    .network
+                    .ipv4_routes
+                    .borrow()
+                    .is_local(error.ipv4_hdr.src_addr)
+                {
+                    return Err(Fail::Ignored {
+                        details: "ICMPv4 error about a datagram we didn't send",
+                    });
                 }
+                return Ok(Some(error));
             },
             _ => {
                 warn!("Unsupported ICMPv4 message: {:?}", icmpv4_hdr);
             },
         }
-        Ok(())
+        Ok(None)
+    }
+
+    /// Tells the sender of a datagram we received that it couldn't be delivered.
+    pub fn send_destination_unreachable(
+        &mut self,
+        code: u8,
+        ipv4_hdr: &Ipv4Header,
+        payload: Bytes,
+    ) -> Result<(), Fail> {
+        // RFC 1122 section 3.2.2: Errors are only sent about datagrams to and from a single host.
+        let src_addr = ipv4_hdr.src_addr;
+        if !self
+            .network
+            .ipv4_routes
+            .borrow()
+            .is_local(ipv4_hdr.dst_addr)
+            || src_addr.is_broadcast()
+            || src_addr.is_multicast()
+            || src_addr.is_unspecified()
+        {
+            return Err(Fail::Ignored {
+                details: "No ICMPv4 errors about broadcast or multicast datagrams",
+            });
+        }
+        // Errors only go to hosts we can reach right away, so spoofed sources can't pile them up
+        // waiting for ARP.
+        if self.network.arp.try_query(src_addr).is_none() {
+            return Err(Fail::Ignored {
+                details: "No ICMPv4 errors to unresolved hosts",
+            });
+        }
+        if !self.take_error_token() {
+            return Err(Fail::Ignored {
+                details: "ICMPv4 error rate limit exceeded",
+            });
+        }
+        // Quote the original header and as much of its payload as fits.
+        let ipv4_hdr_size = ipv4_hdr.compute_size();
+        let max_quote_size = MAX_ICMPV4_DATAGRAM_SIZE - IPV4_HEADER2_SIZE - ICMPV4_HEADER2_SIZE;
+        let quote_size = cmp::min(ipv4_hdr_size + payload.len(), max_quote_size);
+        let mut quote = BytesMut::zeroed(quote_size);
+        ipv4_hdr.serialize(&mut quote[..ipv4_hdr_size], payload.len());
+        quote[ipv4_hdr_size..].copy_from_slice(&payload[..(quote_size - ipv4_hdr_size)]);
+
+        let icmpv4_hdr = Icmpv4Header {
+            icmpv4_type: Icmpv4Type2::DestinationUnreachable,
+            code,
+        };
+        let error_hdr = self.new_header(ipv4_hdr.dst_addr, src_addr);
+        self.transmit(error_hdr, icmpv4_hdr, quote.freeze())
+    }
+
+    fn take_error_token(&self) -> bool {
+        let now = self.rt.now();
+        let mut inner = self.inner.borrow_mut();
+        let elapsed = now - inner.error_tokens_refilled;
+        let refill = cmp::min(
+            elapsed.as_nanos() / ICMP_ERROR_INTERVAL.as_nanos(),
+            ICMP_ERROR_BURST as u128,
+        ) as u32;
+        if inner.error_tokens + refill >= ICMP_ERROR_BURST {
+            inner.error_tokens = ICMP_ERROR_BURST;
+            inner.error_tokens_refilled = now;
+        } else {
+            inner.error_tokens += refill;
+            inner.error_tokens_refilled += ICMP_ERROR_INTERVAL * refill;
+        }
+        if inner.error_tokens == 0 {
+            return false;
+        }
+        inner.error_tokens -= 1;
+        true
     }
 
+    fn new_header(&self, src_addr: Ipv4Addr, dst_addr: Ipv4Addr) -> Ipv4Header {
+        let mut ipv4_hdr = Ipv4Header::new(src_addr, dst_addr, Ipv4Protocol2::Icmpv4);
+        ipv4_hdr.identification = self.network.next_ipv4_identification(dst_addr);
+        ipv4_hdr
+    }
+
+    /// Sends an Echo Request carrying `payload_size` bytes and returns the round trip time once
+    /// the same bytes come back.
     pub fn ping(
         &self,
         dst_ipv4_addr: Ipv4Addr,
+        payload_size: usize,
         timeout: Option<Duration>,
     ) -> impl Future<Output = Result<Duration, Fail>> {
-        let timeout = timeout.unwrap_or_else(|| Duration::from_millis(5000));
-        let id = {
-            let mut state = 0xFFFF as u32;
-            let addr_octets = self.rt.local_ipv4_addr().octets();
-            state += NetworkEndian::read_u16(&addr_octets[0..2]) as u32;
-            state += NetworkEndian::read_u16(&addr_octets[3..4]) as u32;
-
-            let mut pid_buf = [0u8; 4];
-            NetworkEndian::write_u32(&mut pid_buf[..], process::id());
-            state += NetworkEndian::read_u16(&pid_buf[0..2]) as u32;
-            state += NetworkEndian::read_u16(&pid_buf[2..4]) as u32;
-
-            let nonce: [u8; 2] = self.rt.rng_gen();
-            state += NetworkEndian::read_u16(&nonce[..]) as u32;
-
-            while state > 0xFFFF {
-                state -= 0xFFFF;
+        if payload_size > MAX_PING_PAYLOAD_SIZE {
+            return Either::Left(future::err(Fail::OutOfRange {
+                details: "Ping payload too large",
+            }));
+        }
+        let timeout = timeout.unwrap_or(DEFAULT_PING_TIMEOUT);
+        let id = self.make_id();
+        let seq_num = self.next_seq_num();
+        let payload: Bytes = {
+            let mut buf = BytesMut::zeroed(payload_size);
+            for (i, byte) in buf.iter_mut().enumerate() {
+                *byte = i as u8;
             }
-            !state as u16
+            buf.freeze()
         };
-        let seq_num = {
-            let mut inner = self.inner.borrow_mut();
-            let Wrapping(seq_num) = inner.ping_seq_num_counter;
-            inner.ping_seq_num_counter += Wrapping(1);
-            seq_num
-        };
-        let arp = self.arp.clone();
+        let echo = self.echo(dst_ipv4_addr, id, seq_num, None, payload.clone(), timeout);
+        Either::Right(async move {
+            match echo.await? {
+                (EchoResponse::Reply(data), rtt) => {
+                    if data != payload {
+                        return Err(Fail::Malformed {
+                            details: "Echo Reply payload doesn't match the request",
+                        });
+                    }
+                    Ok(rtt)
+                },
+                (EchoResponse::TimeExceeded(..), _) => Err(Fail::NoRoute {}),
+            }
+        })
+    }
+
+    /// Sends Echo Requests with increasing TTLs until one reaches `dst_ipv4_addr`, collecting the
+    /// routers that answer along the way. Hops that don't answer within `timeout` are `None`.
+    pub fn traceroute(
+        &self,
+        dst_ipv4_addr: Ipv4Addr,
+        max_hops: u8,
+        timeout: Option<Duration>,
+    ) -> impl Future<Output = Result<Vec<Option<TracerouteHop>>, Fail>> {
+        let timeout = timeout.unwrap_or(DEFAULT_PING_TIMEOUT);
+        let id = self.make_id();
+        let self_ = self.clone();
+        async move {
+            let mut hops = vec![];
+            for ttl in 1..=max_hops {
+                let seq_num = self_.next_seq_num();
+                let probe = self_.echo(
+                    dst_ipv4_addr,
+                    id,
+                    seq_num,
+                    Some(ttl),
+                    Bytes::empty(),
+                    timeout,
+                );
+                match probe.await {
+                    Ok((EchoResponse::Reply(..), rtt)) => {
+                        hops.push(Some(TracerouteHop {
+                            addr: dst_ipv4_addr,
+                            rtt,
+                        }));
+                        break;
+                    },
+                    Ok((EchoResponse::TimeExceeded(addr), rtt)) => {
+                        hops.push(Some(TracerouteHop { addr, rtt }))
+                    },
+                    Err(Fail::Timeout {}) => hops.push(None),
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(hops)
+        }
+    }
+
+    fn make_id(&self) -> u16 {
+        let mut state = 0xFFFF as u32;
+        let addr_octets = self.rt.local_ipv4_addr().octets();
+        state += NetworkEndian::read_u16(&addr_octets[0..2]) as u32;
+        state += NetworkEndian::read_u16(&addr_octets[2..4]) as u32;
+
+        let mut pid_buf = [0u8; 4];
+        NetworkEndian::write_u32(&mut pid_buf[..], process::id());
+        state += NetworkEndian::read_u16(&pid_buf[0..2]) as u32;
+        state += NetworkEndian::read_u16(&pid_buf[2..4]) as u32;
+
+        let nonce: [u8; 2] = self.rt.rng_gen();
+        state += NetworkEndian::read_u16(&nonce[..]) as u32;
+
+        while state > 0xFFFF {
+            state -= 0xFFFF;
+        }
+        !state as u16
+    }
+
+    fn next_seq_num(&self) -> u16 {
+        let mut inner = self.inner.borrow_mut();
+        let Wrapping(seq_num) = inner.ping_seq_num_counter;
+        inner.ping_seq_num_counter += Wrapping(1);
+        seq_num
+    }
+
+    /// Sends a single Echo Request and waits for the response to it.
+    fn echo(
+        &self,
+        dst_ipv4_addr: Ipv4Addr,
+        id: u16,
+        seq_num: u16,
+        ttl: Option<u8>,
+        data: Bytes,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<(EchoResponse, Duration), Fail>> {
+        let network = self.network.clone();
         let rt = self.rt.clone();
+        let src_ipv4_addr = self.network.ipv4_routes.borrow().source_addr(dst_ipv4_addr);
+        let fragmenter = self.fragmenter.clone();
         let inner = self.inner.clone();
         async move {
             let t0 = rt.now();
             debug!("initiating ARP query");
-            let dst_link_addr = arp.query(dst_ipv4_addr).await?;
+            let dst_link_addr = network.arp.query(dst_ipv4_addr).await?;
             debug!(
                 "ARP query complete ({} -> {})",
                 dst_ipv4_addr, dst_link_addr
             );
 
+            let key = (id, seq_num);
+            let rx = {
+                let mut inner = inner.borrow_mut();
+                if inner.requests.contains_key(&key) {
+                    return Err(Fail::ResourceBusy {
+                        details: "Echo Request with this sequence number still outstanding",
+                    });
+                }
+                let (tx, rx) = channel();
+                inner.requests.insert(key, tx);
+                rx
+            };
+            let _pending = PendingEcho {
+                inner: inner.clone(),
+                key,
+            };
+
+            let mut ipv4_hdr = Ipv4Header::new(src_ipv4_addr, dst_ipv4_addr, Ipv4Protocol2::Icmpv4);
+            ipv4_hdr.identification = network.next_ipv4_identification(dst_ipv4_addr);
+            if let Some(ttl) = ttl {
+                ipv4_hdr.time_to_live = ttl;
+            }
             let msg = Icmpv4Message {
                 ethernet2_hdr: Ethernet2Header {
                     dst_addr: dst_link_addr,
                     src_addr: rt.local_link_addr(),
+                    vlan_tags: rt.ethernet2_options().vlan_tags(None),
                     ether_type: EtherType2::Ipv4,
                 },
-                ipv4_hdr: Ipv4Header::new(
-                    rt.local_ipv4_addr(),
-                    dst_ipv4_addr,
-                    Ipv4Protocol2::Icmpv4,
-                ),
+                ipv4_hdr,
                 icmpv4_hdr: Icmpv4Header {
                     icmpv4_type: Icmpv4Type2::EchoRequest { id, seq_num },
                     code: 0,
                 },
+                data,
             };
-            rt.transmit(msg);
-            let rx = {
-                let (tx, rx) = channel();
-                let mut inner = inner.borrow_mut();
-                assert!(inner.requests.insert((id, seq_num), tx).is_none());
-                rx
-            };
-            // TODO: Handle cancellation here and unregister the completion in `requests`.
+            fragmenter.transmit(msg)?;
             futures::select! {
-                _ = rx.fuse() => Ok(rt.now() - t0),
+                r = rx.fuse() => match r {
+                    Ok(response) => Ok((response, rt.now() - t0)),
+                    Err(..) => Err(Fail::ConnectionAborted {}),
+                },
                 _ = rt.wait(timeout).fuse() => Err(Fail::Timeout {}),
             }
         }
     }
 
-    pub fn reply_to_ping(&mut self, dest_ipv4_addr: Ipv4Addr, id: u16, seq_num: u16) {
-        self.tx
-            .unbounded_send((dest_ipv4_addr, id, seq_num))
-            .unwrap();
+    pub fn reply_to_ping(
+        &mut self,
+        ipv4_hdr: Ipv4Header,
+        id: u16,
+        seq_num: u16,
+        data: Bytes,
+    ) -> Result<(), Fail> {
+        let icmpv4_hdr = Icmpv4Header {
+            icmpv4_type: Icmpv4Type2::EchoReply { id, seq_num },
+            code: 0,
+        };
+        self.transmit(ipv4_hdr, icmpv4_hdr, data)
     }
 }
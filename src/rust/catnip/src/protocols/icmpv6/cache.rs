@@ -0,0 +1,82 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use crate::{
+    collections::HashTtlCache,
+    protocols::ethernet2::MacAddress,
+};
+use futures::{
+    channel::oneshot::{
+        channel,
+        Sender,
+    },
+    FutureExt,
+};
+use hashbrown::HashMap;
+use std::{
+    future::Future,
+    net::Ipv6Addr,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+const DUMMY_MAC_ADDRESS: MacAddress = MacAddress::new([0; 6]);
+
+/// Maps IPv6 neighbors to their link addresses; the Neighbor Discovery counterpart of the ARP
+/// cache.
+pub struct NeighborCache {
+    cache: HashTtlCache<Ipv6Addr, MacAddress>,
+    waiters: HashMap<Ipv6Addr, Vec<Sender<MacAddress>>>,
+    disabled: bool,
+}
+
+impl NeighborCache {
+    pub fn new(now: Instant, default_ttl: Option<Duration>, disabled: bool) -> NeighborCache {
+        NeighborCache {
+            cache: HashTtlCache::new(now, default_ttl),
+            waiters: HashMap::default(),
+            disabled,
+        }
+    }
+
+    pub fn insert(&mut self, ipv6_addr: Ipv6Addr, link_addr: MacAddress) -> Option<MacAddress> {
+        if let Some(senders) = self.waiters.remove(&ipv6_addr) {
+            for sender in senders {
+                let _ = sender.send(link_addr);
+            }
+        }
+        self.cache.insert(ipv6_addr, link_addr)
+    }
+
+    pub fn get_link_addr(&self, ipv6_addr: Ipv6Addr) -> Option<MacAddress> {
+        if self.disabled {
+            return Some(DUMMY_MAC_ADDRESS);
+        }
+        self.cache.get(&ipv6_addr).cloned()
+    }
+
+    pub fn wait_link_addr(&mut self, ipv6_addr: Ipv6Addr) -> impl Future<Output = MacAddress> {
+        let (tx, rx) = channel();
+        match self.get_link_addr(ipv6_addr) {
+            Some(link_addr) => {
+                let _ = tx.send(link_addr);
+            },
+            None => self.waiters.entry(ipv6_addr).or_default().push(tx),
+        }
+        rx.map(|r| r.expect("Dropped waiter?"))
+    }
+
+    pub fn advance_clock(&mut self, now: Instant) {
+        self.cache.advance_clock(now)
+    }
+
+    pub fn try_evict(&mut self, count: usize) -> HashMap<Ipv6Addr, MacAddress> {
+        self.cache.try_evict(count)
+    }
+
+    pub fn export(&self) -> HashMap<Ipv6Addr, MacAddress> {
+        self.cache.iter().map(|(k, v)| (*k, *v)).collect()
+    }
+}
@@ -0,0 +1,331 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use crate::{
+    fail::Fail,
+    protocols::{
+        ethernet2::{
+            frame::{
+                Ethernet2Header,
+                MIN_PAYLOAD_SIZE,
+            },
+            MacAddress,
+        },
+        ipv6::datagram::Ipv6Header,
+    },
+    runtime::PacketBuf,
+    sync::Bytes,
+};
+use byteorder::{
+    ByteOrder,
+    NetworkEndian,
+};
+use std::{
+    cmp,
+    convert::TryInto,
+    net::Ipv6Addr,
+};
+
+const ICMPV6_ECHO_HEADER_SIZE: usize = 8;
+// Type, code, checksum, flags/reserved and the target address.
+const ICMPV6_ND_HEADER_SIZE: usize = 24;
+// A link-layer address option for Ethernet takes up a single 8 byte unit.
+const ND_LINK_ADDR_OPTION_SIZE: usize = 8;
+
+const ND_OPTION_SOURCE_LINK_ADDR: u8 = 1;
+const ND_OPTION_TARGET_LINK_ADDR: u8 = 2;
+
+const ND_FLAG_ROUTER: u8 = 0x80;
+const ND_FLAG_SOLICITED: u8 = 0x40;
+const ND_FLAG_OVERRIDE: u8 = 0x20;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Icmpv6Type {
+    EchoRequest {
+        id: u16,
+        seq_num: u16,
+    },
+    EchoReply {
+        id: u16,
+        seq_num: u16,
+    },
+    // RFC 4861 section 4.3
+    NeighborSolicitation {
+        target_addr: Ipv6Addr,
+        source_link_addr: Option<MacAddress>,
+    },
+    // RFC 4861 section 4.4
+    NeighborAdvertisement {
+        router: bool,
+        solicited: bool,
+        override_flag: bool,
+        target_addr: Ipv6Addr,
+        target_link_addr: Option<MacAddress>,
+    },
+}
+
+impl Icmpv6Type {
+    fn type_byte(&self) -> u8 {
+        match self {
+            Icmpv6Type::EchoRequest { .. } => 128,
+            Icmpv6Type::EchoReply { .. } => 129,
+            Icmpv6Type::NeighborSolicitation { .. } => 135,
+            Icmpv6Type::NeighborAdvertisement { .. } => 136,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Icmpv6Header {
+    pub icmpv6_type: Icmpv6Type,
+    pub code: u8,
+}
+
+impl Icmpv6Header {
+    pub fn compute_size(&self) -> usize {
+        match self.icmpv6_type {
+            Icmpv6Type::EchoRequest { .. } | Icmpv6Type::EchoReply { .. } => {
+                ICMPV6_ECHO_HEADER_SIZE
+            },
+            Icmpv6Type::NeighborSolicitation {
+                source_link_addr: link_addr,
+                ..
+            }
+            | Icmpv6Type::NeighborAdvertisement {
+                target_link_addr: link_addr,
+                ..
+            } => match link_addr {
+                Some(..) => ICMPV6_ND_HEADER_SIZE + ND_LINK_ADDR_OPTION_SIZE,
+                None => ICMPV6_ND_HEADER_SIZE,
+            },
+        }
+    }
+
+    pub fn parse(ipv6_hdr: &Ipv6Header, buf: Bytes) -> Result<(Self, Bytes), Fail> {
+        if buf.len() < 4 {
+            return Err(Fail::Malformed {
+                details: "ICMPv6 datagram too small for header",
+            });
+        }
+        let checksum = NetworkEndian::read_u16(&buf[2..4]);
+        if checksum != icmpv6_checksum(ipv6_hdr, &buf[..]) {
+            return Err(Fail::Malformed {
+                details: "ICMPv6 checksum mismatch",
+            });
+        }
+        let type_byte = buf[0];
+        let code = buf[1];
+        let (icmpv6_type, data) = match type_byte {
+            128 | 129 => {
+                if buf.len() < ICMPV6_ECHO_HEADER_SIZE {
+                    return Err(Fail::Malformed {
+                        details: "ICMPv6 echo message too small",
+                    });
+                }
+                let (hdr_buf, data) = buf.split(ICMPV6_ECHO_HEADER_SIZE);
+                let id = NetworkEndian::read_u16(&hdr_buf[4..6]);
+                let seq_num = NetworkEndian::read_u16(&hdr_buf[6..8]);
+                let icmpv6_type = if type_byte == 128 {
+                    Icmpv6Type::EchoRequest { id, seq_num }
+                } else {
+                    Icmpv6Type::EchoReply { id, seq_num }
+                };
+                (icmpv6_type, data)
+            },
+            135 | 136 => {
+                if buf.len() < ICMPV6_ND_HEADER_SIZE {
+                    return Err(Fail::Malformed {
+                        details: "Neighbor Discovery message too small",
+                    });
+                }
+                let flags = buf[4];
+                let target_octets: [u8; 16] = buf[8..24].try_into().unwrap();
+                let target_addr = Ipv6Addr::from(target_octets);
+                let option_kind = if type_byte == 135 {
+                    ND_OPTION_SOURCE_LINK_ADDR
+                } else {
+                    ND_OPTION_TARGET_LINK_ADDR
+                };
+                let link_addr = parse_link_addr_option(&buf[ICMPV6_ND_HEADER_SIZE..], option_kind)?;
+                let icmpv6_type = if type_byte == 135 {
+                    Icmpv6Type::NeighborSolicitation {
+                        target_addr,
+                        source_link_addr: link_addr,
+                    }
+                } else {
+                    Icmpv6Type::NeighborAdvertisement {
+                        router: flags & ND_FLAG_ROUTER != 0,
+                        solicited: flags & ND_FLAG_SOLICITED != 0,
+                        override_flag: flags & ND_FLAG_OVERRIDE != 0,
+                        target_addr,
+                        target_link_addr: link_addr,
+                    }
+                };
+                (icmpv6_type, Bytes::empty())
+            },
+            _ => {
+                return Err(Fail::Unsupported {
+                    details: "Unsupported ICMPv6 message type",
+                })
+            },
+        };
+        Ok((Self { icmpv6_type, code }, data))
+    }
+
+    fn serialize(&self, buf: &mut [u8]) {
+        let size = self.compute_size();
+        let buf = &mut buf[..size];
+        buf[0] = self.icmpv6_type.type_byte();
+        buf[1] = self.code;
+        // The checksum is filled in once the rest of the message is written.
+        for byte in &mut buf[2..] {
+            *byte = 0;
+        }
+        match self.icmpv6_type {
+            Icmpv6Type::EchoRequest { id, seq_num } | Icmpv6Type::EchoReply { id, seq_num } => {
+                NetworkEndian::write_u16(&mut buf[4..6], id);
+                NetworkEndian::write_u16(&mut buf[6..8], seq_num);
+            },
+            Icmpv6Type::NeighborSolicitation {
+                target_addr,
+                source_link_addr,
+            } => {
+                buf[8..24].copy_from_slice(&target_addr.octets());
+                if let Some(link_addr) = source_link_addr {
+                    serialize_link_addr_option(
+                        &mut buf[24..],
+                        ND_OPTION_SOURCE_LINK_ADDR,
+                        link_addr,
+                    );
+                }
+            },
+            Icmpv6Type::NeighborAdvertisement {
+                router,
+                solicited,
+                override_flag,
+                target_addr,
+                target_link_addr,
+            } => {
+                let mut flags = 0;
+                if router {
+                    flags |= ND_FLAG_ROUTER;
+                }
+                if solicited {
+                    flags |= ND_FLAG_SOLICITED;
+                }
+                if override_flag {
+                    flags |= ND_FLAG_OVERRIDE;
+                }
+                buf[4] = flags;
+                buf[8..24].copy_from_slice(&target_addr.octets());
+                if let Some(link_addr) = target_link_addr {
+                    serialize_link_addr_option(
+                        &mut buf[24..],
+                        ND_OPTION_TARGET_LINK_ADDR,
+                        link_addr,
+                    );
+                }
+            },
+        }
+    }
+}
+
+/// Looks for a link-layer address option of type `kind`, skipping any other options.
+fn parse_link_addr_option(mut buf: &[u8], kind: u8) -> Result<Option<MacAddress>, Fail> {
+    let mut link_addr = None;
+    while !buf.is_empty() {
+        if buf.len() < 2 {
+            return Err(Fail::Malformed {
+                details: "Neighbor Discovery option truncated",
+            });
+        }
+        // The length is in units of 8 bytes, and options can't be empty.
+        let len = buf[1] as usize * 8;
+        if len == 0 || len > buf.len() {
+            return Err(Fail::Malformed {
+                details: "Invalid Neighbor Discovery option length",
+            });
+        }
+        if buf[0] == kind && len == ND_LINK_ADDR_OPTION_SIZE {
+            link_addr = Some(MacAddress::from_bytes(&buf[2..8]));
+        }
+        buf = &buf[len..];
+    }
+    Ok(link_addr)
+}
+
+fn serialize_link_addr_option(buf: &mut [u8], kind: u8, link_addr: MacAddress) {
+    buf[0] = kind;
+    buf[1] = (ND_LINK_ADDR_OPTION_SIZE / 8) as u8;
+    buf[2..8].copy_from_slice(&link_addr.octets());
+}
+
+/// The ICMPv6 checksum covers an IPv6 pseudo header and the whole message (RFC 4443 section
+/// 2.3), with the checksum field read as zero.
+fn icmpv6_checksum(ipv6_hdr: &Ipv6Header, buf: &[u8]) -> u16 {
+    let mut state = 0xffffu32;
+    state += ipv6_hdr.pseudo_header_sum(buf.len());
+
+    state += NetworkEndian::read_u16(&buf[0..2]) as u32;
+    // Skip the checksum.
+    let mut chunks_iter = buf[4..].chunks_exact(2);
+    while let Some(chunk) = chunks_iter.next() {
+        state += NetworkEndian::read_u16(chunk) as u32;
+    }
+    if let Some(&b) = chunks_iter.remainder().get(0) {
+        state += NetworkEndian::read_u16(&[b, 0]) as u32;
+    }
+    while state > 0xffff {
+        state -= 0xffff;
+    }
+    !state as u16
+}
+
+#[derive(Clone)]
+pub struct Icmpv6Message {
+    pub ethernet2_hdr: Ethernet2Header,
+    pub ipv6_hdr: Ipv6Header,
+    pub icmpv6_hdr: Icmpv6Header,
+    pub data: Bytes,
+}
+
+impl PacketBuf for Icmpv6Message {
+    fn compute_size(&self) -> usize {
+        let size = self.ethernet2_hdr.compute_size()
+            + self.ipv6_hdr.compute_size()
+            + self.icmpv6_hdr.compute_size()
+            + self.data.len();
+
+        // Pad the end of the buffer with zeros if needed.
+        cmp::max(size, MIN_PAYLOAD_SIZE)
+    }
+
+    fn serialize(&self, buf: &mut [u8]) {
+        let eth_hdr_size = self.ethernet2_hdr.compute_size();
+        let ipv6_hdr_size = self.ipv6_hdr.compute_size();
+        let icmpv6_size = self.icmpv6_hdr.compute_size() + self.data.len();
+        let mut cur_pos = 0;
+
+        self.ethernet2_hdr
+            .serialize(&mut buf[cur_pos..(cur_pos + eth_hdr_size)]);
+        cur_pos += eth_hdr_size;
+
+        self.ipv6_hdr
+            .serialize(&mut buf[cur_pos..(cur_pos + ipv6_hdr_size)], icmpv6_size);
+        cur_pos += ipv6_hdr_size;
+
+        let icmpv6_buf = &mut buf[cur_pos..(cur_pos + icmpv6_size)];
+        let icmpv6_hdr_size = self.icmpv6_hdr.compute_size();
+        self.icmpv6_hdr
+            .serialize(&mut icmpv6_buf[..icmpv6_hdr_size]);
+        icmpv6_buf[icmpv6_hdr_size..].copy_from_slice(&self.data[..]);
+        let checksum = icmpv6_checksum(&self.ipv6_hdr, icmpv6_buf);
+        NetworkEndian::write_u16(&mut icmpv6_buf[2..4], checksum);
+        cur_pos += icmpv6_size;
+
+        // Add Ethernet padding if needed.
+        for byte in &mut buf[cur_pos..] {
+            *byte = 0;
+        }
+    }
+}
@@ -0,0 +1,8 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+mod cache;
+pub mod datagram;
+mod peer;
+
+pub use peer::Icmpv6Peer as Peer;
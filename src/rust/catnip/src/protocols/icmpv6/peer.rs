@@ -0,0 +1,315 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::{
+    cache::NeighborCache,
+    datagram::{
+        Icmpv6Header,
+        Icmpv6Message,
+        Icmpv6Type,
+    },
+};
+use crate::{
+    fail::Fail,
+    protocols::{
+        ethernet2::{
+            frame::{
+                EtherType2,
+                Ethernet2Header,
+            },
+            MacAddress,
+        },
+        ipv6::{
+            datagram::{
+                Ipv6Header,
+                Ipv6NextHeader,
+            },
+            routing::{
+                multicast_link_addr,
+                solicited_node_addr,
+                ALL_NODES_ADDR,
+            },
+            RoutingTable,
+        },
+    },
+    runtime::Runtime,
+    scheduler::SchedulerHandle,
+    sync::Bytes,
+};
+use futures::{
+    channel::mpsc,
+    FutureExt,
+    StreamExt,
+};
+use hashbrown::HashMap;
+use std::{
+    cell::RefCell,
+    future::Future,
+    net::Ipv6Addr,
+    rc::Rc,
+    time::Duration,
+};
+
+// RFC 4861 section 7.1.1: Neighbor Discovery messages must arrive with the maximum hop limit,
+// which proves they weren't forwarded by a router.
+const ND_HOP_LIMIT: u8 = 255;
+
+/// ICMPv6 echo and Neighbor Discovery, which resolves IPv6 neighbors to link addresses the way
+/// ARP does for IPv4.
+#[derive(Clone)]
+pub struct Icmpv6Peer<RT: Runtime> {
+    rt: RT,
+    cache: Rc<RefCell<NeighborCache>>,
+    routes: Rc<RoutingTable>,
+    tx: mpsc::UnboundedSender<(Ipv6Addr, Ipv6Addr, u16, u16, Bytes)>,
+    background: Rc<SchedulerHandle>,
+    echo_handle: Rc<SchedulerHandle>,
+}
+
+impl<RT: Runtime> Icmpv6Peer<RT> {
+    pub fn new(rt: RT, routes: Rc<RoutingTable>) -> Icmpv6Peer<RT> {
+        let options = rt.ipv6_options();
+        let cache = Rc::new(RefCell::new(NeighborCache::new(
+            rt.now(),
+            Some(options.neighbor_cache_ttl),
+            rt.arp_options().disable_arp,
+        )));
+        let handle = rt.spawn(Self::background(rt.clone(), cache.clone()));
+        let (tx, rx) = mpsc::unbounded();
+        let echo_handle = rt.spawn(Self::reply_to_pings(
+            rt.clone(),
+            cache.clone(),
+            routes.clone(),
+            rx,
+        ));
+        Icmpv6Peer {
+            rt,
+            cache,
+            routes,
+            tx,
+            background: Rc::new(handle),
+            echo_handle: Rc::new(echo_handle),
+        }
+    }
+
+    async fn background(rt: RT, cache: Rc<RefCell<NeighborCache>>) {
+        loop {
+            let current_time = rt.now();
+            {
+                let mut cache = cache.borrow_mut();
+                cache.advance_clock(current_time);
+                cache.try_evict(2);
+            }
+            rt.wait(Duration::from_secs(1)).await;
+        }
+    }
+
+    async fn reply_to_pings(
+        rt: RT,
+        cache: Rc<RefCell<NeighborCache>>,
+        routes: Rc<RoutingTable>,
+        mut rx: mpsc::UnboundedReceiver<(Ipv6Addr, Ipv6Addr, u16, u16, Bytes)>,
+    ) {
+        while let Some((src_addr, dst_addr, id, seq_num, data)) = rx.next().await {
+            let r: Result<_, Fail> = try {
+                let dst_link_addr =
+                    Self::resolve(rt.clone(), cache.clone(), &routes, dst_addr).await?;
+                let msg = Icmpv6Message {
+                    ethernet2_hdr: Ethernet2Header {
+                        dst_addr: dst_link_addr,
+                        src_addr: rt.local_link_addr(),
+                        vlan_tags: rt.ethernet2_options().vlan_tags(None),
+                        ether_type: EtherType2::Ipv6,
+                    },
+                    ipv6_hdr: Ipv6Header::new(src_addr, dst_addr, Ipv6NextHeader::Icmpv6),
+                    icmpv6_hdr: Icmpv6Header {
+                        icmpv6_type: Icmpv6Type::EchoReply { id, seq_num },
+                        code: 0,
+                    },
+                    data,
+                };
+                rt.transmit(msg);
+            };
+            if let Err(e) = r {
+                warn!("ICMPv6 echo reply to {} failed: {:?}", dst_addr, e);
+            }
+        }
+    }
+
+    pub fn receive(&mut self, ipv6_hdr: &Ipv6Header, buf: Bytes) -> Result<(), Fail> {
+        let (icmpv6_hdr, data) = Icmpv6Header::parse(ipv6_hdr, buf)?;
+        match icmpv6_hdr.icmpv6_type {
+            Icmpv6Type::EchoRequest { id, seq_num } => {
+                // Reply from the address that was pinged unless it was a multicast group.
+                let src_addr = if self.routes.is_local(ipv6_hdr.dst_addr) {
+                    ipv6_hdr.dst_addr
+                } else {
+                    self.routes.source_addr(ipv6_hdr.src_addr)
+                };
+                self.tx
+                    .unbounded_send((src_addr, ipv6_hdr.src_addr, id, seq_num, data))
+                    .unwrap();
+                Ok(())
+            },
+            Icmpv6Type::EchoReply { .. } => Ok(()),
+            Icmpv6Type::NeighborSolicitation {
+                target_addr,
+                source_link_addr,
+            } => {
+                Self::check_nd_message(ipv6_hdr, icmpv6_hdr.code)?;
+                if !self.routes.is_local(target_addr) {
+                    return Err(Fail::Ignored {
+                        details: "Neighbor Solicitation for another host",
+                    });
+                }
+                // A solicitation from the unspecified address comes from a host checking that
+                // the target address isn't taken, so the answer goes to everybody.
+                let (dst_addr, dst_link_addr, solicited) = if ipv6_hdr.src_addr.is_unspecified() {
+                    (ALL_NODES_ADDR, multicast_link_addr(ALL_NODES_ADDR), false)
+                } else {
+                    if let Some(link_addr) = source_link_addr {
+                        self.cache.borrow_mut().insert(ipv6_hdr.src_addr, link_addr);
+                    }
+                    let link_addr = match source_link_addr {
+                        Some(link_addr) => link_addr,
+                        None => self.cache.borrow().get_link_addr(ipv6_hdr.src_addr).ok_or(
+                            Fail::Ignored {
+                                details: "Neighbor Solicitation from unknown link address",
+                            },
+                        )?,
+                    };
+                    (ipv6_hdr.src_addr, link_addr, true)
+                };
+                let mut reply_hdr = Ipv6Header::new(target_addr, dst_addr, Ipv6NextHeader::Icmpv6);
+                reply_hdr.hop_limit = ND_HOP_LIMIT;
+                let reply = Icmpv6Message {
+                    ethernet2_hdr: Ethernet2Header {
+                        dst_addr: dst_link_addr,
+                        src_addr: self.rt.local_link_addr(),
+                        vlan_tags: self.rt.ethernet2_options().vlan_tags(None),
+                        ether_type: EtherType2::Ipv6,
+                    },
+                    ipv6_hdr: reply_hdr,
+                    icmpv6_hdr: Icmpv6Header {
+                        icmpv6_type: Icmpv6Type::NeighborAdvertisement {
+                            router: false,
+                            solicited,
+                            override_flag: true,
+                            target_addr,
+                            target_link_addr: Some(self.rt.local_link_addr()),
+                        },
+                        code: 0,
+                    },
+                    data: Bytes::empty(),
+                };
+                self.rt.transmit(reply);
+                Ok(())
+            },
+            Icmpv6Type::NeighborAdvertisement {
+                target_addr,
+                target_link_addr,
+                ..
+            } => {
+                Self::check_nd_message(ipv6_hdr, icmpv6_hdr.code)?;
+                match target_link_addr {
+                    Some(link_addr) => {
+                        debug!("advertisement from `{}/{}`", target_addr, link_addr);
+                        self.cache.borrow_mut().insert(target_addr, link_addr);
+                        Ok(())
+                    },
+                    None => Err(Fail::Ignored {
+                        details: "Neighbor Advertisement without link address",
+                    }),
+                }
+            },
+        }
+    }
+
+    fn check_nd_message(ipv6_hdr: &Ipv6Header, code: u8) -> Result<(), Fail> {
+        if ipv6_hdr.hop_limit != ND_HOP_LIMIT || code != 0 {
+            return Err(Fail::Malformed {
+                details: "Invalid Neighbor Discovery message",
+            });
+        }
+        Ok(())
+    }
+
+    /// Looks up the link address for datagrams to `ipv6_addr`. Off-link destinations resolve to
+    /// their gateway's link address.
+    pub fn try_query(&self, ipv6_addr: Ipv6Addr) -> Option<MacAddress> {
+        let next_hop = self.routes.next_hop(ipv6_addr);
+        if next_hop.is_multicast() {
+            return Some(multicast_link_addr(next_hop));
+        }
+        self.cache.borrow().get_link_addr(next_hop)
+    }
+
+    /// Like `try_query`, but sends Neighbor Solicitations on a cache miss.
+    pub fn query(&self, ipv6_addr: Ipv6Addr) -> impl Future<Output = Result<MacAddress, Fail>> {
+        Self::resolve(self.rt.clone(), self.cache.clone(), &self.routes, ipv6_addr)
+    }
+
+    fn resolve(
+        rt: RT,
+        cache: Rc<RefCell<NeighborCache>>,
+        routes: &RoutingTable,
+        ipv6_addr: Ipv6Addr,
+    ) -> impl Future<Output = Result<MacAddress, Fail>> {
+        let next_hop = routes.next_hop(ipv6_addr);
+        let local_ipv6_addr = routes.source_addr(next_hop);
+        async move {
+            if next_hop.is_multicast() {
+                return Ok(multicast_link_addr(next_hop));
+            }
+            if let Some(link_addr) = cache.borrow().get_link_addr(next_hop) {
+                return Ok(link_addr);
+            }
+            // RFC 4861 section 7.2.2: Solicitations go to the target's solicited-node group.
+            let group_addr = solicited_node_addr(next_hop);
+            let mut ipv6_hdr = Ipv6Header::new(local_ipv6_addr, group_addr, Ipv6NextHeader::Icmpv6);
+            ipv6_hdr.hop_limit = ND_HOP_LIMIT;
+            let msg = Icmpv6Message {
+                ethernet2_hdr: Ethernet2Header {
+                    dst_addr: multicast_link_addr(group_addr),
+                    src_addr: rt.local_link_addr(),
+                    vlan_tags: rt.ethernet2_options().vlan_tags(None),
+                    ether_type: EtherType2::Ipv6,
+                },
+                ipv6_hdr,
+                icmpv6_hdr: Icmpv6Header {
+                    icmpv6_type: Icmpv6Type::NeighborSolicitation {
+                        target_addr: next_hop,
+                        source_link_addr: Some(rt.local_link_addr()),
+                    },
+                    code: 0,
+                },
+                data: Bytes::empty(),
+            };
+            let response = cache.borrow_mut().wait_link_addr(next_hop).fuse();
+            futures::pin_mut!(response);
+
+            let options = rt.ipv6_options();
+            for i in 0..options.solicitation_retries {
+                rt.transmit(msg.clone());
+                futures::select! {
+                    link_addr = response => {
+                        debug!("Neighbor Discovery result available ({})", link_addr);
+                        return Ok(link_addr);
+                    },
+                    _ = rt.wait(options.solicitation_timeout).fuse() => {
+                        warn!("Neighbor Solicitation timeout; attempt {}.", i + 1);
+                    },
+                }
+            }
+            Err(Fail::Timeout {})
+        }
+    }
+
+    pub fn export_cache(&self) -> HashMap<Ipv6Addr, MacAddress> {
+        self.cache.borrow().export()
+    }
+
+    pub fn insert(&self, ipv6_addr: Ipv6Addr, link_addr: MacAddress) {
+        self.cache.borrow_mut().insert(ipv6_addr, link_addr);
+    }
+}
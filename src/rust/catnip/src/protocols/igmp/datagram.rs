@@ -0,0 +1,305 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use crate::{
+    fail::Fail,
+    protocols::{
+        ethernet2::frame::{
+            Ethernet2Header,
+            MIN_PAYLOAD_SIZE,
+        },
+        ipv4::datagram::Ipv4Header,
+    },
+    runtime::PacketBuf,
+    sync::Bytes,
+};
+use byteorder::{
+    ByteOrder,
+    NetworkEndian,
+};
+use num_traits::FromPrimitive;
+use std::{
+    cmp,
+    net::Ipv4Addr,
+    time::Duration,
+};
+
+const IGMP_MEMBERSHIP_QUERY: u8 = 0x11;
+const IGMPV2_MEMBERSHIP_REPORT: u8 = 0x16;
+const IGMPV2_LEAVE_GROUP: u8 = 0x17;
+const IGMPV3_MEMBERSHIP_REPORT: u8 = 0x22;
+
+const IGMPV2_MESSAGE_SIZE: usize = 8;
+const IGMPV3_QUERY_MIN_SIZE: usize = 12;
+const IGMPV3_REPORT_HEADER_SIZE: usize = 8;
+// Record type, auxiliary data length, number of sources and the group.
+const IGMPV3_GROUP_RECORD_SIZE: usize = 8;
+
+// IGMPv1 queries leave the maximum response time out, and hosts assume 10 seconds.
+const IGMPV1_MAX_RESP_TIME: Duration = Duration::from_secs(10);
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum IgmpVersion {
+    V2,
+    V3,
+}
+
+/// What an IGMPv3 group record says about our membership (RFC 3376 section 4.2.12).
+#[repr(u8)]
+#[derive(FromPrimitive, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum GroupRecordType {
+    ModeIsInclude = 1,
+    ModeIsExclude = 2,
+    ChangeToIncludeMode = 3,
+    ChangeToExcludeMode = 4,
+    AllowNewSources = 5,
+    BlockOldSources = 6,
+}
+
+/// We don't filter sources, so records never list any and the ones we parse are skipped.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct GroupRecord {
+    pub record_type: GroupRecordType,
+    pub group_addr: Ipv4Addr,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum IgmpPdu {
+    // An unspecified group makes it a general query. IGMPv1 queries count as IGMPv2 ones.
+    Query {
+        version: IgmpVersion,
+        max_resp_time: Duration,
+        group_addr: Ipv4Addr,
+    },
+    V2Report {
+        group_addr: Ipv4Addr,
+    },
+    V2Leave {
+        group_addr: Ipv4Addr,
+    },
+    V3Report {
+        records: Vec<GroupRecord>,
+    },
+}
+
+impl IgmpPdu {
+    pub fn compute_size(&self) -> usize {
+        match self {
+            IgmpPdu::Query {
+                version: IgmpVersion::V2,
+                ..
+            }
+            | IgmpPdu::V2Report { .. }
+            | IgmpPdu::V2Leave { .. } => IGMPV2_MESSAGE_SIZE,
+            IgmpPdu::Query {
+                version: IgmpVersion::V3,
+                ..
+            } => IGMPV3_QUERY_MIN_SIZE,
+            IgmpPdu::V3Report { records } => {
+                IGMPV3_REPORT_HEADER_SIZE + records.len() * IGMPV3_GROUP_RECORD_SIZE
+            },
+        }
+    }
+
+    pub fn parse(buf: Bytes) -> Result<Self, Fail> {
+        if buf.len() < IGMPV2_MESSAGE_SIZE {
+            return Err(Fail::Malformed {
+                details: "IGMP message too small",
+            });
+        }
+        if igmp_checksum(&buf[..]) != NetworkEndian::read_u16(&buf[2..4]) {
+            return Err(Fail::Malformed {
+                details: "IGMP checksum mismatch",
+            });
+        }
+        let group_addr = Ipv4Addr::from(NetworkEndian::read_u32(&buf[4..8]));
+        let pdu = match buf[0] {
+            // RFC 3376 section 7.1: The length tells the query versions apart.
+            IGMP_MEMBERSHIP_QUERY if buf.len() == IGMPV2_MESSAGE_SIZE => {
+                let max_resp_time = match buf[1] {
+                    0 => IGMPV1_MAX_RESP_TIME,
+                    n => Duration::from_millis(n as u64 * 100),
+                };
+                IgmpPdu::Query {
+                    version: IgmpVersion::V2,
+                    max_resp_time,
+                    group_addr,
+                }
+            },
+            IGMP_MEMBERSHIP_QUERY if buf.len() >= IGMPV3_QUERY_MIN_SIZE => IgmpPdu::Query {
+                version: IgmpVersion::V3,
+                max_resp_time: Duration::from_millis(decode_max_resp_code(buf[1]) as u64 * 100),
+                group_addr,
+            },
+            IGMP_MEMBERSHIP_QUERY => {
+                return Err(Fail::Malformed {
+                    details: "Invalid IGMP query size",
+                })
+            },
+            IGMPV2_MEMBERSHIP_REPORT => IgmpPdu::V2Report { group_addr },
+            IGMPV2_LEAVE_GROUP => IgmpPdu::V2Leave { group_addr },
+            IGMPV3_MEMBERSHIP_REPORT => IgmpPdu::V3Report {
+                records: parse_group_records(&buf[..])?,
+            },
+            _ => {
+                return Err(Fail::Unsupported {
+                    details: "Unsupported IGMP message type",
+                })
+            },
+        };
+        Ok(pdu)
+    }
+
+    pub fn serialize(&self, buf: &mut [u8]) {
+        let buf = &mut buf[..self.compute_size()];
+        for byte in buf.iter_mut() {
+            *byte = 0;
+        }
+        match self {
+            IgmpPdu::Query {
+                version,
+                max_resp_time,
+                group_addr,
+            } => {
+                buf[0] = IGMP_MEMBERSHIP_QUERY;
+                // Larger IGMPv3 codes switch to the floating point encoding, which we don't need
+                // for the queries we build.
+                let max_code = match version {
+                    IgmpVersion::V2 => u8::MAX,
+                    IgmpVersion::V3 => 127,
+                };
+                let tenths = cmp::min(max_resp_time.as_millis() / 100, max_code as u128);
+                buf[1] = tenths as u8;
+                NetworkEndian::write_u32(&mut buf[4..8], u32::from(*group_addr));
+            },
+            IgmpPdu::V2Report { group_addr } => {
+                buf[0] = IGMPV2_MEMBERSHIP_REPORT;
+                NetworkEndian::write_u32(&mut buf[4..8], u32::from(*group_addr));
+            },
+            IgmpPdu::V2Leave { group_addr } => {
+                buf[0] = IGMPV2_LEAVE_GROUP;
+                NetworkEndian::write_u32(&mut buf[4..8], u32::from(*group_addr));
+            },
+            IgmpPdu::V3Report { records } => {
+                buf[0] = IGMPV3_MEMBERSHIP_REPORT;
+                NetworkEndian::write_u16(&mut buf[6..8], records.len() as u16);
+                let records_buf = &mut buf[IGMPV3_REPORT_HEADER_SIZE..];
+                for (record, record_buf) in records
+                    .iter()
+                    .zip(records_buf.chunks_exact_mut(IGMPV3_GROUP_RECORD_SIZE))
+                {
+                    record_buf[0] = record.record_type as u8;
+                    NetworkEndian::write_u32(&mut record_buf[4..8], u32::from(record.group_addr));
+                }
+            },
+        }
+        let checksum = igmp_checksum(buf);
+        NetworkEndian::write_u16(&mut buf[2..4], checksum);
+    }
+}
+
+/// RFC 3376 section 4.1.1: Codes of 128 and up are a floating point number with a 3 bit
+/// exponent and a 4 bit mantissa.
+fn decode_max_resp_code(code: u8) -> u32 {
+    if code < 128 {
+        return code as u32;
+    }
+    let exp = (code >> 4) & 0x7;
+    let mant = code & 0xf;
+    ((mant as u32) | 0x10) << (exp + 3)
+}
+
+fn parse_group_records(buf: &[u8]) -> Result<Vec<GroupRecord>, Fail> {
+    if buf.len() < IGMPV3_REPORT_HEADER_SIZE {
+        return Err(Fail::Malformed {
+            details: "IGMPv3 report too small",
+        });
+    }
+    let num_records = NetworkEndian::read_u16(&buf[6..8]) as usize;
+    let mut buf = &buf[IGMPV3_REPORT_HEADER_SIZE..];
+    let mut records = Vec::with_capacity(num_records);
+    for _ in 0..num_records {
+        if buf.len() < IGMPV3_GROUP_RECORD_SIZE {
+            return Err(Fail::Malformed {
+                details: "IGMPv3 group record truncated",
+            });
+        }
+        // The auxiliary data length is in 32-bit words.
+        let num_sources = NetworkEndian::read_u16(&buf[2..4]) as usize;
+        let len = IGMPV3_GROUP_RECORD_SIZE + 4 * num_sources + 4 * buf[1] as usize;
+        if buf.len() < len {
+            return Err(Fail::Malformed {
+                details: "IGMPv3 group record truncated",
+            });
+        }
+        let record_type = FromPrimitive::from_u8(buf[0]).ok_or(Fail::Malformed {
+            details: "Invalid IGMPv3 group record type",
+        })?;
+        records.push(GroupRecord {
+            record_type,
+            group_addr: Ipv4Addr::from(NetworkEndian::read_u32(&buf[4..8])),
+        });
+        buf = &buf[len..];
+    }
+    Ok(records)
+}
+
+/// The checksum covers the whole IGMP message, with the checksum field read as zero.
+fn igmp_checksum(buf: &[u8]) -> u16 {
+    let mut state = 0xffffu32;
+    state += NetworkEndian::read_u16(&buf[0..2]) as u32;
+    // Skip the checksum.
+    let mut chunks_iter = buf[4..].chunks_exact(2);
+    while let Some(chunk) = chunks_iter.next() {
+        state += NetworkEndian::read_u16(chunk) as u32;
+    }
+    if let Some(&b) = chunks_iter.remainder().get(0) {
+        state += NetworkEndian::read_u16(&[b, 0]) as u32;
+    }
+    while state > 0xffff {
+        state -= 0xffff;
+    }
+    !state as u16
+}
+
+#[derive(Clone)]
+pub struct IgmpMessage {
+    pub ethernet2_hdr: Ethernet2Header,
+    pub ipv4_hdr: Ipv4Header,
+    pub igmp_pdu: IgmpPdu,
+}
+
+impl PacketBuf for IgmpMessage {
+    fn compute_size(&self) -> usize {
+        let size = self.ethernet2_hdr.compute_size()
+            + self.ipv4_hdr.compute_size()
+            + self.igmp_pdu.compute_size();
+
+        // Pad the end of the buffer with zeros if needed.
+        cmp::max(size, MIN_PAYLOAD_SIZE)
+    }
+
+    fn serialize(&self, buf: &mut [u8]) {
+        let eth_hdr_size = self.ethernet2_hdr.compute_size();
+        let ipv4_hdr_size = self.ipv4_hdr.compute_size();
+        let igmp_size = self.igmp_pdu.compute_size();
+        let mut cur_pos = 0;
+
+        self.ethernet2_hdr
+            .serialize(&mut buf[cur_pos..(cur_pos + eth_hdr_size)]);
+        cur_pos += eth_hdr_size;
+
+        self.ipv4_hdr
+            .serialize(&mut buf[cur_pos..(cur_pos + ipv4_hdr_size)], igmp_size);
+        cur_pos += ipv4_hdr_size;
+
+        self.igmp_pdu
+            .serialize(&mut buf[cur_pos..(cur_pos + igmp_size)]);
+        cur_pos += igmp_size;
+
+        // Add Ethernet padding if needed.
+        for byte in &mut buf[cur_pos..] {
+            *byte = 0;
+        }
+    }
+}
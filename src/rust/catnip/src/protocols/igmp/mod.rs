@@ -0,0 +1,14 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+pub mod datagram;
+mod peer;
+
+#[cfg(test)]
+mod tests;
+
+pub use datagram::IgmpVersion as Version;
+pub use peer::{
+    IgmpPeer as Peer,
+    ALL_SYSTEMS_ADDR,
+};
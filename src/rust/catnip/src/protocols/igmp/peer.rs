@@ -0,0 +1,299 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::datagram::{
+    GroupRecord,
+    GroupRecordType,
+    IgmpMessage,
+    IgmpPdu,
+    IgmpVersion,
+};
+use crate::{
+    fail::Fail,
+    protocols::{
+        ethernet2::frame::{
+            EtherType2,
+            Ethernet2Header,
+        },
+        ipv4::{
+            self,
+            datagram::{
+                Ipv4Header,
+                Ipv4HeaderOption,
+                Ipv4Protocol2,
+                IPV4_FLAG_DONT_FRAGMENT,
+            },
+            routing::multicast_link_addr,
+        },
+    },
+    runtime::Runtime,
+    scheduler::SchedulerHandle,
+    sync::Bytes,
+};
+use hashbrown::HashMap;
+use std::{
+    cell::{
+        Cell,
+        RefCell,
+    },
+    net::Ipv4Addr,
+    rc::Rc,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+pub const ALL_SYSTEMS_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 1);
+const ALL_ROUTERS_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 2);
+const IGMPV3_ROUTERS_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 22);
+
+// RFC 3376 section 8.2 and 8.3: The defaults that go into the Older Version Querier Present
+// Timeout.
+const QUERY_INTERVAL: Duration = Duration::from_secs(125);
+const QUERY_RESPONSE_INTERVAL: Duration = Duration::from_secs(10);
+
+struct Group {
+    // How many sockets joined the group.
+    refcount: usize,
+    // The next report we'll send for the group, if any.
+    report: Option<(Instant, SchedulerHandle)>,
+}
+
+/// IGMPv2 and IGMPv3 host membership, which tells multicast routers which groups to forward to
+/// our link. Membership is per host, so sockets joining the same group share it.
+#[derive(Clone)]
+pub struct IgmpPeer<RT: Runtime> {
+    reporter: Reporter<RT>,
+    groups: Rc<RefCell<HashMap<Ipv4Addr, Group>>>,
+}
+
+impl<RT: Runtime> IgmpPeer<RT> {
+    pub fn new(rt: RT, routes: Rc<RefCell<ipv4::RoutingTable>>) -> Self {
+        let options = rt.ipv4_options();
+        let reporter = Reporter {
+            rt,
+            routes,
+            version: options.igmp_version,
+            v2_querier_until: Rc::new(Cell::new(None)),
+        };
+        Self {
+            reporter,
+            groups: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    /// Whether datagrams to `addr` are for us. Everybody is in the all-systems group.
+    pub fn is_member(&self, addr: Ipv4Addr) -> bool {
+        addr == ALL_SYSTEMS_ADDR || self.groups.borrow().contains_key(&addr)
+    }
+
+    pub fn join(&self, group_addr: Ipv4Addr) -> Result<(), Fail> {
+        if !group_addr.is_multicast() {
+            return Err(Fail::Invalid {
+                details: "Not a multicast group",
+            });
+        }
+        let mut groups = self.groups.borrow_mut();
+        if let Some(group) = groups.get_mut(&group_addr) {
+            group.refcount += 1;
+            return Ok(());
+        }
+        // RFC 3376 section 5.1: Send the report right away and repeat it in case it gets lost.
+        let report = if group_addr != ALL_SYSTEMS_ADDR {
+            self.reporter
+                .send_report(group_addr, GroupRecordType::ChangeToExcludeMode);
+            self.schedule_repeats(group_addr)
+        } else {
+            None
+        };
+        let group = Group {
+            refcount: 1,
+            report,
+        };
+        groups.insert(group_addr, group);
+        Ok(())
+    }
+
+    pub fn leave(&self, group_addr: Ipv4Addr) -> Result<(), Fail> {
+        let mut groups = self.groups.borrow_mut();
+        match groups.get_mut(&group_addr) {
+            Some(group) if group.refcount > 1 => {
+                group.refcount -= 1;
+                return Ok(());
+            },
+            Some(..) => (),
+            None => return Err(Fail::AddressNotAvailable {}),
+        }
+        groups.remove(&group_addr);
+        if group_addr != ALL_SYSTEMS_ADDR {
+            self.reporter.send_leave(group_addr);
+        }
+        Ok(())
+    }
+
+    pub fn receive(&self, buf: Bytes) -> Result<(), Fail> {
+        match IgmpPdu::parse(buf)? {
+            IgmpPdu::Query {
+                version,
+                max_resp_time,
+                group_addr,
+            } => {
+                if version == IgmpVersion::V2 {
+                    self.reporter.heard_v2_querier();
+                }
+                let mut groups = self.groups.borrow_mut();
+                for (&addr, group) in groups.iter_mut() {
+                    if addr == ALL_SYSTEMS_ADDR
+                        || (!group_addr.is_unspecified() && group_addr != addr)
+                    {
+                        continue;
+                    }
+                    self.schedule_response(addr, group, max_resp_time);
+                }
+                Ok(())
+            },
+            // RFC 2236 section 3: An IGMPv2 host that hears somebody else report a group keeps
+            // quiet about it.
+            IgmpPdu::V2Report { group_addr } => {
+                if self.reporter.version() == IgmpVersion::V2 {
+                    if let Some(group) = self.groups.borrow_mut().get_mut(&group_addr) {
+                        group.report = None;
+                    }
+                }
+                Ok(())
+            },
+            _ => Err(Fail::Ignored {
+                details: "IGMP message for routers",
+            }),
+        }
+    }
+
+    /// Answers a query after a random delay of up to `max_resp_time`, unless we'll report the
+    /// group sooner anyway.
+    fn schedule_response(&self, group_addr: Ipv4Addr, group: &mut Group, max_resp_time: Duration) {
+        let rt = &self.reporter.rt;
+        let delay = random_delay(rt, max_resp_time);
+        let when = rt.now() + delay;
+        if let Some((pending, _)) = group.report {
+            if pending > rt.now() && pending <= when {
+                return;
+            }
+        }
+        let reporter = self.reporter.clone();
+        let handle = rt.spawn(async move {
+            reporter.rt.wait_until(when).await;
+            reporter.send_report(group_addr, GroupRecordType::ModeIsExclude);
+        });
+        group.report = Some((when, handle));
+    }
+
+    fn schedule_repeats(&self, group_addr: Ipv4Addr) -> Option<(Instant, SchedulerHandle)> {
+        let rt = &self.reporter.rt;
+        let options = rt.ipv4_options();
+        if options.igmp_robustness < 2 {
+            return None;
+        }
+        let mut when = rt.now();
+        let deadlines: Vec<_> = (1..options.igmp_robustness)
+            .map(|_| {
+                when += random_delay(rt, options.igmp_unsolicited_report_interval);
+                when
+            })
+            .collect();
+        let next = deadlines[0];
+        let reporter = self.reporter.clone();
+        let handle = rt.spawn(async move {
+            for when in deadlines {
+                reporter.rt.wait_until(when).await;
+                reporter.send_report(group_addr, GroupRecordType::ChangeToExcludeMode);
+            }
+        });
+        Some((next, handle))
+    }
+}
+
+fn random_delay<RT: Runtime>(rt: &RT, max: Duration) -> Duration {
+    let max_millis = max.as_millis() as u64;
+    if max_millis == 0 {
+        return Duration::new(0, 0);
+    }
+    Duration::from_millis(rt.rng_gen::<u64>() % max_millis)
+}
+
+/// Builds and sends reports in whichever version the link's routers speak.
+#[derive(Clone)]
+struct Reporter<RT: Runtime> {
+    rt: RT,
+    routes: Rc<RefCell<ipv4::RoutingTable>>,
+    version: IgmpVersion,
+    // RFC 3376 section 7.2.1: We act like an IGMPv2 host until this long after the last IGMPv2
+    // query.
+    v2_querier_until: Rc<Cell<Option<Instant>>>,
+}
+
+impl<RT: Runtime> Reporter<RT> {
+    fn version(&self) -> IgmpVersion {
+        match self.v2_querier_until.get() {
+            Some(until) if until > self.rt.now() => IgmpVersion::V2,
+            _ => self.version,
+        }
+    }
+
+    fn heard_v2_querier(&self) {
+        let robustness = self.rt.ipv4_options().igmp_robustness as u32;
+        let timeout = QUERY_INTERVAL * robustness + QUERY_RESPONSE_INTERVAL;
+        self.v2_querier_until.set(Some(self.rt.now() + timeout));
+    }
+
+    fn send_report(&self, group_addr: Ipv4Addr, record_type: GroupRecordType) {
+        match self.version() {
+            IgmpVersion::V2 => self.send(group_addr, IgmpPdu::V2Report { group_addr }),
+            IgmpVersion::V3 => self.send_v3_report(group_addr, record_type),
+        }
+    }
+
+    fn send_leave(&self, group_addr: Ipv4Addr) {
+        match self.version() {
+            IgmpVersion::V2 => self.send(ALL_ROUTERS_ADDR, IgmpPdu::V2Leave { group_addr }),
+            IgmpVersion::V3 => {
+                self.send_v3_report(group_addr, GroupRecordType::ChangeToIncludeMode)
+            },
+        }
+    }
+
+    fn send_v3_report(&self, group_addr: Ipv4Addr, record_type: GroupRecordType) {
+        let record = GroupRecord {
+            record_type,
+            group_addr,
+        };
+        let pdu = IgmpPdu::V3Report {
+            records: vec![record],
+        };
+        self.send(IGMPV3_ROUTERS_ADDR, pdu);
+    }
+
+    fn send(&self, dst_addr: Ipv4Addr, igmp_pdu: IgmpPdu) {
+        // RFC 3376 section 4: Reports stay on the link and carry the Router Alert option so
+        // routers look at them. They're never fragmented, so the identification doesn't matter.
+        let mut ipv4_hdr = Ipv4Header::new(
+            self.routes.borrow().source_addr(dst_addr),
+            dst_addr,
+            Ipv4Protocol2::Igmp,
+        );
+        ipv4_hdr.time_to_live = 1;
+        ipv4_hdr.flags |= IPV4_FLAG_DONT_FRAGMENT;
+        ipv4_hdr.options.push(Ipv4HeaderOption::RouterAlert(0));
+        let msg = IgmpMessage {
+            ethernet2_hdr: Ethernet2Header {
+                dst_addr: multicast_link_addr(dst_addr),
+                src_addr: self.rt.local_link_addr(),
+                vlan_tags: self.rt.ethernet2_options().vlan_tags(None),
+                ether_type: EtherType2::Ipv4,
+            },
+            ipv4_hdr,
+            igmp_pdu,
+        };
+        self.rt.transmit(msg);
+    }
+}
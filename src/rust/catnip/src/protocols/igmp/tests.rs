@@ -0,0 +1,243 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::{
+    datagram::{
+        GroupRecord,
+        GroupRecordType,
+        IgmpMessage,
+        IgmpPdu,
+        IgmpVersion,
+    },
+    ALL_SYSTEMS_ADDR,
+};
+use crate::{
+    engine::Protocol,
+    fail::Fail,
+    protocols::{
+        ethernet2::{
+            frame::{
+                EtherType2,
+                Ethernet2Header,
+                VlanTags,
+            },
+            MacAddress,
+        },
+        ip,
+        ipv4::{
+            self,
+            datagram::{
+                Ipv4Header,
+                Ipv4HeaderOption,
+                Ipv4Protocol2,
+            },
+            routing::multicast_link_addr,
+        },
+    },
+    runtime::{
+        PacketBuf,
+        Runtime,
+    },
+    socket_options::SocketOption,
+    sync::{
+        Bytes,
+        BytesMut,
+    },
+    test_helpers,
+};
+use futures::task::noop_waker_ref;
+use must_let::must_let;
+use std::{
+    convert::TryFrom,
+    future::Future,
+    net::Ipv4Addr,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+const GROUP_ADDR: Ipv4Addr = Ipv4Addr::new(239, 1, 2, 3);
+
+fn parse_igmp(frame: Bytes) -> (Ethernet2Header, Ipv4Header, IgmpPdu) {
+    let (eth_hdr, ipv4_datagram) = Ethernet2Header::parse(frame).unwrap();
+    let (ipv4_hdr, payload) = Ipv4Header::parse(ipv4_datagram).unwrap();
+    assert_eq!(ipv4_hdr.protocol, Ipv4Protocol2::Igmp);
+    (eth_hdr, ipv4_hdr, IgmpPdu::parse(payload).unwrap())
+}
+
+fn query(version: IgmpVersion, group_addr: Ipv4Addr) -> Bytes {
+    let dst_addr = if group_addr.is_unspecified() {
+        ALL_SYSTEMS_ADDR
+    } else {
+        group_addr
+    };
+    let mut ipv4_hdr = Ipv4Header::new(
+        Ipv4Addr::new(192, 168, 1, 254),
+        dst_addr,
+        Ipv4Protocol2::Igmp,
+    );
+    ipv4_hdr.time_to_live = 1;
+    let msg = IgmpMessage {
+        ethernet2_hdr: Ethernet2Header {
+            dst_addr: multicast_link_addr(dst_addr),
+            src_addr: test_helpers::CARRIE_MAC,
+            vlan_tags: VlanTags::new(),
+            ether_type: EtherType2::Ipv4,
+        },
+        ipv4_hdr,
+        igmp_pdu: IgmpPdu::Query {
+            version,
+            max_resp_time: Duration::from_secs(1),
+            group_addr,
+        },
+    };
+    let mut frame = BytesMut::zeroed(msg.compute_size());
+    msg.serialize(&mut frame[..]);
+    frame.freeze()
+}
+
+#[test]
+fn multicast_link_addrs() {
+    assert_eq!(
+        multicast_link_addr(Ipv4Addr::new(239, 129, 2, 3)),
+        MacAddress::new([0x01, 0x00, 0x5e, 0x01, 0x02, 0x03])
+    );
+    assert_eq!(
+        multicast_link_addr(ALL_SYSTEMS_ADDR),
+        MacAddress::new([0x01, 0x00, 0x5e, 0x00, 0x00, 0x01])
+    );
+}
+
+#[test]
+fn multicast_udp() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+    let options = ipv4::Options::default().igmp_robustness(1);
+    let mut alice = test_helpers::new_alice_with_ipv4_options(now, options);
+    let mut bob = test_helpers::new_bob(now);
+
+    let port = ip::Port::try_from(5000).unwrap();
+    let group = ip::Endpoint::new(GROUP_ADDR, port);
+
+    // Alice listens on the group and tells the routers about it.
+    let alice_fd = alice.socket(Protocol::Udp);
+    alice.bind(alice_fd, group).unwrap();
+    alice.join_multicast_group(alice_fd, GROUP_ADDR).unwrap();
+    let (eth_hdr, ipv4_hdr, pdu) = parse_igmp(alice.rt().pop_frame());
+    assert_eq!(eth_hdr.dst_addr, multicast_link_addr(ipv4_hdr.dst_addr));
+    assert_eq!(ipv4_hdr.dst_addr, Ipv4Addr::new(224, 0, 0, 22));
+    assert_eq!(ipv4_hdr.src_addr, test_helpers::ALICE_IPV4);
+    assert_eq!(ipv4_hdr.time_to_live, 1);
+    assert_eq!(ipv4_hdr.options, vec![Ipv4HeaderOption::RouterAlert(0)]);
+    let record = GroupRecord {
+        record_type: GroupRecordType::ChangeToExcludeMode,
+        group_addr: GROUP_ADDR,
+    };
+    assert_eq!(
+        pdu,
+        IgmpPdu::V3Report {
+            records: vec![record]
+        }
+    );
+
+    // Multicast datagrams go to the group's link address and stay on the link by default.
+    let bob_fd = bob.socket(Protocol::Udp);
+    bob.bind(bob_fd, ip::Endpoint::new(test_helpers::BOB_IPV4, port))
+        .unwrap();
+    let send = |bob: &mut test_helpers::TestEngine| {
+        let buf = BytesMut::from(&b"hello"[..]).freeze();
+        let _push = bob.pushto(bob_fd, buf, group);
+        bob.rt().pop_frame()
+    };
+    let datagram = send(&mut bob);
+    let (eth_hdr, ipv4_datagram) = Ethernet2Header::parse(datagram.clone()).unwrap();
+    let (ipv4_hdr, _) = Ipv4Header::parse(ipv4_datagram).unwrap();
+    assert_eq!(
+        eth_hdr.dst_addr,
+        MacAddress::new([0x01, 0x00, 0x5e, 0x01, 0x02, 0x03])
+    );
+    assert_eq!(ipv4_hdr.src_addr, test_helpers::BOB_IPV4);
+    assert_eq!(ipv4_hdr.time_to_live, 1);
+
+    alice.receive(datagram).unwrap();
+    let mut pop_future = alice.udp_pop(alice_fd);
+    must_let!(let Poll::Ready(Ok((Some(remote), buf))) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    assert_eq!(remote.addr, test_helpers::BOB_IPV4.into());
+    assert_eq!(&buf[..], b"hello");
+
+    bob.setsockopt(bob_fd, SocketOption::MulticastTimeToLive(Some(4)))
+        .unwrap();
+    let datagram = send(&mut bob);
+    let (_, ipv4_datagram) = Ethernet2Header::parse(datagram.clone()).unwrap();
+    let (ipv4_hdr, _) = Ipv4Header::parse(ipv4_datagram).unwrap();
+    assert_eq!(ipv4_hdr.time_to_live, 4);
+
+    // Once Alice leaves, the group's datagrams aren't for her anymore.
+    alice.leave_multicast_group(alice_fd, GROUP_ADDR).unwrap();
+    let (_, _, pdu) = parse_igmp(alice.rt().pop_frame());
+    let record = GroupRecord {
+        record_type: GroupRecordType::ChangeToIncludeMode,
+        group_addr: GROUP_ADDR,
+    };
+    assert_eq!(
+        pdu,
+        IgmpPdu::V3Report {
+            records: vec![record]
+        }
+    );
+    must_let!(let Err(Fail::Misdelivered {}) = alice.receive(datagram));
+    must_let!(let Err(Fail::AddressNotAvailable {}) = alice.leave_multicast_group(alice_fd, GROUP_ADDR));
+}
+
+#[test]
+fn igmpv2_querier() {
+    let now = Instant::now();
+    let options = ipv4::Options::default().igmp_robustness(1);
+    let mut alice = test_helpers::new_alice_with_ipv4_options(now, options);
+
+    // Two sockets share the membership, which is only reported once.
+    let fd1 = alice.socket(Protocol::Udp);
+    let fd2 = alice.socket(Protocol::Udp);
+    alice.join_multicast_group(fd1, GROUP_ADDR).unwrap();
+    let _ = parse_igmp(alice.rt().pop_frame());
+    alice.join_multicast_group(fd2, GROUP_ADDR).unwrap();
+
+    // An IGMPv2 query makes us answer like an IGMPv2 host, after a random delay.
+    alice
+        .receive(query(IgmpVersion::V2, Ipv4Addr::UNSPECIFIED))
+        .unwrap();
+    let now = now + Duration::from_secs(1);
+    alice.rt().advance_clock(now);
+    alice.rt().poll_scheduler();
+    let (_, ipv4_hdr, pdu) = parse_igmp(alice.rt().pop_frame());
+    assert_eq!(ipv4_hdr.dst_addr, GROUP_ADDR);
+    assert_eq!(
+        pdu,
+        IgmpPdu::V2Report {
+            group_addr: GROUP_ADDR
+        }
+    );
+
+    // Queries for other groups don't concern us.
+    alice
+        .receive(query(IgmpVersion::V2, Ipv4Addr::new(239, 9, 9, 9)))
+        .unwrap_err();
+
+    // The group is left when the last socket leaves it.
+    alice.leave_multicast_group(fd1, GROUP_ADDR).unwrap();
+    alice.close(fd2).unwrap();
+    let (_, ipv4_hdr, pdu) = parse_igmp(alice.rt().pop_frame());
+    assert_eq!(ipv4_hdr.dst_addr, Ipv4Addr::new(224, 0, 0, 2));
+    assert_eq!(
+        pdu,
+        IgmpPdu::V2Leave {
+            group_addr: GROUP_ADDR
+        }
+    );
+}
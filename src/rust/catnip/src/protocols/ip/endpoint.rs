@@ -0,0 +1,29 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::Port;
+use std::net::IpAddr;
+
+/// A transport endpoint of either address family.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IpEndpoint {
+    pub addr: IpAddr,
+    pub port: Port,
+}
+
+impl IpEndpoint {
+    pub fn new(addr: impl Into<IpAddr>, port: Port) -> IpEndpoint {
+        IpEndpoint {
+            addr: addr.into(),
+            port,
+        }
+    }
+
+    pub fn address(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn port(&self) -> Port {
+        self.port
+    }
+}
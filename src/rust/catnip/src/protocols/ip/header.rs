@@ -0,0 +1,110 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use crate::{
+    fail::Fail,
+    protocols::{
+        ethernet2::frame::EtherType2,
+        ipv4::datagram::{
+            Ipv4Header,
+            Ipv4Protocol2,
+        },
+        ipv6::datagram::{
+            Ipv6Header,
+            Ipv6NextHeader,
+        },
+    },
+};
+use std::net::IpAddr;
+
+/// The transport protocols that run over both IPv4 and IPv6.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum IpProtocol {
+    Tcp,
+    Udp,
+}
+
+impl From<IpProtocol> for Ipv4Protocol2 {
+    fn from(protocol: IpProtocol) -> Self {
+        match protocol {
+            IpProtocol::Tcp => Ipv4Protocol2::Tcp,
+            IpProtocol::Udp => Ipv4Protocol2::Udp,
+        }
+    }
+}
+
+impl From<IpProtocol> for Ipv6NextHeader {
+    fn from(protocol: IpProtocol) -> Self {
+        match protocol {
+            IpProtocol::Tcp => Ipv6NextHeader::Tcp,
+            IpProtocol::Udp => Ipv6NextHeader::Udp,
+        }
+    }
+}
+
+/// The network header in front of a TCP segment or UDP datagram.
+#[derive(Clone, Debug)]
+pub enum IpHeader {
+    V4(Ipv4Header),
+    V6(Ipv6Header),
+}
+
+impl IpHeader {
+    pub fn new(src_addr: IpAddr, dst_addr: IpAddr, protocol: IpProtocol) -> Result<Self, Fail> {
+        match (src_addr, dst_addr) {
+            (IpAddr::V4(src_addr), IpAddr::V4(dst_addr)) => Ok(IpHeader::V4(Ipv4Header::new(
+                src_addr,
+                dst_addr,
+                protocol.into(),
+            ))),
+            (IpAddr::V6(src_addr), IpAddr::V6(dst_addr)) => Ok(IpHeader::V6(Ipv6Header::new(
+                src_addr,
+                dst_addr,
+                protocol.into(),
+            ))),
+            _ => Err(Fail::AddressFamilyNotSupported {}),
+        }
+    }
+
+    pub fn src_addr(&self) -> IpAddr {
+        match self {
+            IpHeader::V4(hdr) => hdr.src_addr.into(),
+            IpHeader::V6(hdr) => hdr.src_addr.into(),
+        }
+    }
+
+    pub fn dst_addr(&self) -> IpAddr {
+        match self {
+            IpHeader::V4(hdr) => hdr.dst_addr.into(),
+            IpHeader::V6(hdr) => hdr.dst_addr.into(),
+        }
+    }
+
+    pub fn ether_type(&self) -> EtherType2 {
+        match self {
+            IpHeader::V4(..) => EtherType2::Ipv4,
+            IpHeader::V6(..) => EtherType2::Ipv6,
+        }
+    }
+
+    pub fn compute_size(&self) -> usize {
+        match self {
+            IpHeader::V4(hdr) => hdr.compute_size(),
+            IpHeader::V6(hdr) => hdr.compute_size(),
+        }
+    }
+
+    pub fn serialize(&self, buf: &mut [u8], payload_len: usize) {
+        match self {
+            IpHeader::V4(hdr) => hdr.serialize(buf, payload_len),
+            IpHeader::V6(hdr) => hdr.serialize(buf, payload_len),
+        }
+    }
+
+    pub fn pseudo_header_sum(&self, len: usize) -> u32 {
+        match self {
+            IpHeader::V4(hdr) => hdr.pseudo_header_sum(len),
+            IpHeader::V6(hdr) => hdr.pseudo_header_sum(len),
+        }
+    }
+}
@@ -1,6 +1,15 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
+mod endpoint;
+mod header;
+mod network;
 pub mod port;
 
+pub use endpoint::IpEndpoint as Endpoint;
+pub use header::{
+    IpHeader as Header,
+    IpProtocol as Protocol,
+};
+pub use network::Network;
 pub use port::Port;
@@ -0,0 +1,163 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::{
+    Header,
+    Protocol,
+};
+use crate::{
+    collections::HashTtlCache,
+    fail::Fail,
+    protocols::{
+        arp,
+        ethernet2::MacAddress,
+        icmpv6,
+        igmp,
+        ipv4,
+        ipv6,
+    },
+    runtime::Runtime,
+};
+use futures::FutureExt;
+use std::{
+    cell::RefCell,
+    future::Future,
+    net::{
+        IpAddr,
+        Ipv4Addr,
+    },
+    num::Wrapping,
+    rc::Rc,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+/// The network layer as TCP and UDP see it, for either address family: which addresses are
+/// ours, which one to send from, and which link address to send to.
+#[derive(Clone)]
+pub struct Network<RT: Runtime> {
+    rt: RT,
+    pub arp: arp::Peer<RT>,
+    pub icmpv6: icmpv6::Peer<RT>,
+    pub igmp: igmp::Peer<RT>,
+    pub ipv4_routes: Rc<RefCell<ipv4::RoutingTable>>,
+    pub ipv6_routes: Rc<ipv6::RoutingTable>,
+
+    // RFC 6864 section 4.1: The identification of IPv4 datagrams has to be unique per
+    // destination for as long as fragments may be around, so each one gets its own counter. We
+    // forget a counter along with the time it was last used once it's been idle for longer than
+    // fragments live, which we take to be our own reassembly timeout.
+    ipv4_identifications: Rc<RefCell<HashTtlCache<Ipv4Addr, (Wrapping<u16>, Instant)>>>,
+    ipv4_identification_ttl: Duration,
+}
+
+impl<RT: Runtime> Network<RT> {
+    pub fn new(
+        rt: RT,
+        arp: arp::Peer<RT>,
+        icmpv6: icmpv6::Peer<RT>,
+        igmp: igmp::Peer<RT>,
+        ipv4_routes: Rc<RefCell<ipv4::RoutingTable>>,
+        ipv6_routes: Rc<ipv6::RoutingTable>,
+    ) -> Self {
+        let ttl = rt.ipv4_options().reassembly_timeout;
+        let identifications = HashTtlCache::new(rt.now(), Some(ttl));
+        Self {
+            rt,
+            arp,
+            icmpv6,
+            igmp,
+            ipv4_routes,
+            ipv6_routes,
+            ipv4_identifications: Rc::new(RefCell::new(identifications)),
+            ipv4_identification_ttl: ttl,
+        }
+    }
+
+    /// Builds the header for an outgoing datagram.
+    pub fn new_header(
+        &self,
+        src_addr: IpAddr,
+        dst_addr: IpAddr,
+        protocol: Protocol,
+    ) -> Result<Header, Fail> {
+        let mut header = Header::new(src_addr, dst_addr, protocol)?;
+        if let Header::V4(ref mut header) = header {
+            header.identification = self.next_ipv4_identification(header.dst_addr);
+        }
+        Ok(header)
+    }
+
+    pub fn next_ipv4_identification(&self, dst_addr: Ipv4Addr) -> u16 {
+        let now = self.rt.now();
+        let ttl = self.ipv4_identification_ttl;
+        let mut identifications = self.ipv4_identifications.borrow_mut();
+
+        // Using a counter doesn't refresh its expiry, so counters that were used since they were
+        // inserted get another lease based on when that was.
+        identifications.advance_clock(now);
+        for (addr, (counter, last_used)) in identifications.try_evict(usize::max_value()) {
+            if now < last_used + ttl {
+                let remaining = last_used + ttl - now;
+                identifications.insert_with_ttl(addr, (counter, last_used), Some(remaining));
+            }
+        }
+
+        if let Some((counter, last_used)) = identifications.get_mut(&dst_addr) {
+            let Wrapping(identification) = *counter;
+            *counter += Wrapping(1);
+            *last_used = now;
+            return identification;
+        }
+        let identification: u16 = self.rt.rng_gen();
+        identifications.insert(dst_addr, (Wrapping(identification) + Wrapping(1), now));
+        identification
+    }
+
+    pub fn is_local(&self, addr: IpAddr) -> bool {
+        match addr {
+            IpAddr::V4(addr) => self.ipv4_routes.borrow().is_local(addr),
+            IpAddr::V6(addr) => self.ipv6_routes.is_local(addr),
+        }
+    }
+
+    /// Whether we gave up `addr` to another host that's using it too.
+    pub fn is_lost(&self, addr: IpAddr) -> bool {
+        match (addr, self.arp.conflict()) {
+            (IpAddr::V4(addr), Some(conflict)) => conflict.ipv4_addr == addr,
+            _ => false,
+        }
+    }
+
+    pub fn source_addr(&self, dst_addr: IpAddr) -> IpAddr {
+        match dst_addr {
+            IpAddr::V4(addr) => self.ipv4_routes.borrow().source_addr(addr).into(),
+            IpAddr::V6(addr) => self.ipv6_routes.source_addr(addr).into(),
+        }
+    }
+
+    pub fn try_query(&self, addr: IpAddr) -> Option<MacAddress> {
+        match addr {
+            IpAddr::V4(addr) => self.arp.try_query(addr),
+            IpAddr::V6(addr) => self.icmpv6.try_query(addr),
+        }
+    }
+
+    /// Notes that the neighbor we reach `addr` through is still there, like when it acknowledges
+    /// our data. Only ARP keeps track of this for now.
+    pub fn confirm_reachable(&self, addr: IpAddr) {
+        if let IpAddr::V4(addr) = addr {
+            self.arp.confirm(addr);
+        }
+    }
+
+    /// Resolves the link address with ARP or Neighbor Discovery, depending on the family.
+    pub fn query(&self, addr: IpAddr) -> impl Future<Output = Result<MacAddress, Fail>> {
+        match addr {
+            IpAddr::V4(addr) => self.arp.query(addr).left_future(),
+            IpAddr::V6(addr) => self.icmpv6.query(addr).right_future(),
+        }
+    }
+}
@@ -61,6 +61,17 @@ impl EphemeralPorts {
         })
     }
 
+    pub fn alloc_where(&mut self, f: impl Fn(Port) -> bool) -> Result<Port, Fail> {
+        let ix = self
+            .ports
+            .iter()
+            .rposition(|&p| f(p))
+            .ok_or(Fail::ResourceExhausted {
+                details: "Out of private ports",
+            })?;
+        Ok(self.ports.swap_remove(ix))
+    }
+
     pub fn free(&mut self, port: Port) {
         self.ports.push(port);
     }
@@ -8,24 +8,34 @@ use byteorder::{
 };
 use num_traits::FromPrimitive;
 use std::{
-    convert::{
-        TryFrom,
-        TryInto,
-    },
+    convert::TryFrom,
     net::Ipv4Addr,
 };
 
 pub const IPV4_HEADER2_SIZE: usize = 20;
+// The IHL field is 4 bits wide, so the header with options can be at most 60 bytes.
+pub const IPV4_MAX_OPTIONS_SIZE: usize = 40;
 
-// todo: need citation
+// The default TTL recommended by RFC 1700.
 pub const DEFAULT_IPV4_TTL: u8 = 64;
 pub const IPV4_IHL_NO_OPTIONS: u8 = 5;
 pub const IPV4_VERSION: u8 = 4;
 
+pub const IPV4_FLAG_DONT_FRAGMENT: u8 = 0x2;
+pub const IPV4_FLAG_MORE_FRAGMENTS: u8 = 0x1;
+
+const IPV4_OPTION_END_OF_LIST: u8 = 0;
+const IPV4_OPTION_NO_OPERATION: u8 = 1;
+const IPV4_OPTION_RECORD_ROUTE: u8 = 7;
+const IPV4_OPTION_ROUTER_ALERT: u8 = 148;
+// The high bit of the option type says whether fragments must carry a copy of the option.
+const IPV4_OPTION_COPIED: u8 = 0x80;
+
 #[repr(u8)]
-#[derive(FromPrimitive, Copy, Clone, PartialEq, Eq, Debug)]
+#[derive(FromPrimitive, Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum Ipv4Protocol2 {
     Icmpv4 = 0x01,
+    Igmp = 0x02,
     Tcp = 0x06,
     Udp = 0x11,
 }
@@ -43,10 +53,129 @@ impl TryFrom<u8> for Ipv4Protocol2 {
     }
 }
 
+/// An option from the variable-length part of the IPv4 header. End of list and no-op padding
+/// aren't represented; they're regenerated when serializing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Ipv4HeaderOption {
+    // RFC 2113
+    RouterAlert(u16),
+    // RFC 791: `pointer` is the 1-based octet offset of the next free slot within the option.
+    RecordRoute { pointer: u8, route: Vec<Ipv4Addr> },
+    Other { kind: u8, data: Vec<u8> },
+}
+
+impl Ipv4HeaderOption {
+    /// A Record Route option with room for `slots` addresses.
+    pub fn record_route(slots: usize) -> Self {
+        Ipv4HeaderOption::RecordRoute {
+            pointer: 4,
+            route: vec![Ipv4Addr::UNSPECIFIED; slots],
+        }
+    }
+
+    pub fn kind(&self) -> u8 {
+        match self {
+            Ipv4HeaderOption::RouterAlert(..) => IPV4_OPTION_ROUTER_ALERT,
+            Ipv4HeaderOption::RecordRoute { .. } => IPV4_OPTION_RECORD_ROUTE,
+            Ipv4HeaderOption::Other { kind, .. } => *kind,
+        }
+    }
+
+    /// Whether the option must be repeated in every fragment of a datagram.
+    pub fn is_copied(&self) -> bool {
+        self.kind() & IPV4_OPTION_COPIED != 0
+    }
+
+    fn compute_size(&self) -> usize {
+        match self {
+            Ipv4HeaderOption::RouterAlert(..) => 4,
+            Ipv4HeaderOption::RecordRoute { route, .. } => 3 + 4 * route.len(),
+            Ipv4HeaderOption::Other { data, .. } => 2 + data.len(),
+        }
+    }
+
+    fn parse(buf: &[u8]) -> Result<Vec<Self>, Fail> {
+        let mut options = vec![];
+        let mut pos = 0;
+        while pos < buf.len() {
+            let kind = buf[pos];
+            if kind == IPV4_OPTION_END_OF_LIST {
+                break;
+            }
+            if kind == IPV4_OPTION_NO_OPERATION {
+                pos += 1;
+                continue;
+            }
+            if pos + 2 > buf.len() {
+                return Err(Fail::Malformed {
+                    details: "IPv4 option truncated",
+                });
+            }
+            let len = buf[pos + 1] as usize;
+            if len < 2 || pos + len > buf.len() {
+                return Err(Fail::Malformed {
+                    details: "Invalid IPv4 option length",
+                });
+            }
+            let data = &buf[(pos + 2)..(pos + len)];
+            let option = match kind {
+                IPV4_OPTION_ROUTER_ALERT => {
+                    if data.len() != 2 {
+                        return Err(Fail::Malformed {
+                            details: "Invalid IPv4 Router Alert option",
+                        });
+                    }
+                    Ipv4HeaderOption::RouterAlert(NetworkEndian::read_u16(data))
+                },
+                IPV4_OPTION_RECORD_ROUTE => {
+                    if data.is_empty() || (data.len() - 1) % 4 != 0 {
+                        return Err(Fail::Malformed {
+                            details: "Invalid IPv4 Record Route option",
+                        });
+                    }
+                    let route = data[1..]
+                        .chunks_exact(4)
+                        .map(|c| Ipv4Addr::from(NetworkEndian::read_u32(c)))
+                        .collect();
+                    Ipv4HeaderOption::RecordRoute {
+                        pointer: data[0],
+                        route,
+                    }
+                },
+                _ => Ipv4HeaderOption::Other {
+                    kind,
+                    data: data.to_vec(),
+                },
+            };
+            options.push(option);
+            pos += len;
+        }
+        Ok(options)
+    }
+
+    fn serialize(&self, buf: &mut [u8]) {
+        buf[0] = self.kind();
+        buf[1] = self.compute_size() as u8;
+        match self {
+            Ipv4HeaderOption::RouterAlert(value) => {
+                NetworkEndian::write_u16(&mut buf[2..4], *value)
+            },
+            Ipv4HeaderOption::RecordRoute { pointer, route } => {
+                buf[2] = *pointer;
+                for (i, addr) in route.iter().enumerate() {
+                    buf[(3 + 4 * i)..(7 + 4 * i)].copy_from_slice(&addr.octets());
+                }
+            },
+            Ipv4HeaderOption::Other { data, .. } => buf[2..].copy_from_slice(&data[..]),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Ipv4Header {
     // [ version 4 bits ] [ IHL 4 bits ]
     // The user shouldn't be able to mutate the version, so we parse it out but don't include it
-    // here. Since we don't support IPv4 options, the same holds for the ihl field.
+    // here. The IHL is derived from the options when serializing.
     // pub version: u8,
     // pub ihl: u8,
 
@@ -60,6 +189,7 @@ pub struct Ipv4Header {
 
     // [ flags 3 bits ] [ fragment offset 13 bits ]
     pub flags: u8,
+    // In units of 8 bytes.
     pub fragment_offset: u16,
 
     pub time_to_live: u8,
@@ -69,17 +199,22 @@ pub struct Ipv4Header {
     // header_checksum: u16,
     pub src_addr: Ipv4Addr,
     pub dst_addr: Ipv4Addr,
+
+    pub options: Vec<Ipv4HeaderOption>,
 }
 
 fn ipv4_checksum(buf: &[u8]) -> u16 {
-    let buf: &[u8; IPV4_HEADER2_SIZE] = buf.try_into().expect("Invalid header size");
+    assert!(
+        buf.len() >= IPV4_HEADER2_SIZE && buf.len() % 4 == 0,
+        "Invalid header size"
+    );
     let mut state = 0xffffu32;
     for i in 0..5 {
         state += NetworkEndian::read_u16(&buf[(2 * i)..(2 * i + 2)]) as u32;
     }
     // Skip the 5th u16 since octets 10-12 are the header checksum, whose value should be zero when
     // computing a checksum.
-    for i in 6..10 {
+    for i in 6..(buf.len() / 2) {
         state += NetworkEndian::read_u16(&buf[(2 * i)..(2 * i + 2)]) as u32;
     }
     while state > 0xffff {
@@ -96,44 +231,87 @@ impl Ipv4Header {
             identification: 0,
             flags: 0,
             fragment_offset: 0,
-            time_to_live: 0,
+            time_to_live: DEFAULT_IPV4_TTL,
             protocol,
             src_addr,
             dst_addr,
+            options: vec![],
         }
     }
 
     pub fn compute_size(&self) -> usize {
-        // We don't support IPv4 options, so this is always 20.
-        IPV4_HEADER2_SIZE
+        IPV4_HEADER2_SIZE + self.options_size()
+    }
+
+    // Options are padded with zeros (End of Option List) to a multiple of 4 bytes.
+    fn options_size(&self) -> usize {
+        let size: usize = self.options.iter().map(|o| o.compute_size()).sum();
+        (size + 3) & !3
+    }
+
+    pub fn is_fragment(&self) -> bool {
+        self.flags & IPV4_FLAG_MORE_FRAGMENTS != 0 || self.fragment_offset != 0
+    }
+
+    /// Sums the 16-bit words of the pseudo header that the TCP and UDP checksums cover, for `len`
+    /// bytes of upper-layer data.
+    pub fn pseudo_header_sum(&self, len: usize) -> u32 {
+        let mut state = 0u32;
+
+        // 1) Source address (4 bytes)
+        let src_octets = self.src_addr.octets();
+        state += NetworkEndian::read_u16(&src_octets[0..2]) as u32;
+        state += NetworkEndian::read_u16(&src_octets[2..4]) as u32;
+
+        // 2) Destination address (4 bytes)
+        let dst_octets = self.dst_addr.octets();
+        state += NetworkEndian::read_u16(&dst_octets[0..2]) as u32;
+        state += NetworkEndian::read_u16(&dst_octets[2..4]) as u32;
+
+        // 3) 1 byte of zeros and the protocol number (1 byte)
+        state += NetworkEndian::read_u16(&[0, self.protocol as u8]) as u32;
+
+        // 4) Upper-layer length (2 bytes)
+        state += len as u32;
+        state
     }
 
     pub fn parse(buf: Bytes) -> Result<(Self, Bytes), Fail> {
+        Self::parse_impl(buf, false)
+    }
+
+    /// Parses the start of a datagram quoted in an ICMPv4 error message, which usually leaves
+    /// out most of the payload.
+    pub fn parse_truncated(buf: Bytes) -> Result<(Self, Bytes), Fail> {
+        Self::parse_impl(buf, true)
+    }
+
+    fn parse_impl(buf: Bytes, truncated: bool) -> Result<(Self, Bytes), Fail> {
         if buf.len() < IPV4_HEADER2_SIZE {
             return Err(Fail::Malformed {
                 details: "Datagram too small",
             });
         }
-        let (hdr_buf, mut payload_buf) = buf.split(IPV4_HEADER2_SIZE);
-
-        let version = hdr_buf[0] >> 4;
+        let version = buf[0] >> 4;
         if version != IPV4_VERSION {
             return Err(Fail::Unsupported {
                 details: "Unsupported IP version",
             });
         }
 
-        let ihl = hdr_buf[0] & 0xF;
+        let ihl = buf[0] & 0xF;
         if ihl < IPV4_IHL_NO_OPTIONS {
             return Err(Fail::Malformed {
                 details: "IPv4 IHL is too small",
             });
         }
-        if ihl > IPV4_IHL_NO_OPTIONS {
-            return Err(Fail::Unsupported {
-                details: "IPv4 options are unsupported",
+        let header_len = ihl as usize * 4;
+        if buf.len() < header_len {
+            return Err(Fail::Malformed {
+                details: "Datagram too small for IPv4 options",
             });
         }
+        let (hdr_buf, mut payload_buf) = buf.split(header_len);
 
         let dscp = hdr_buf[1] >> 2;
         let ecn = hdr_buf[1] & 3;
@@ -141,32 +319,30 @@ impl Ipv4Header {
         let total_length = NetworkEndian::read_u16(&hdr_buf[2..4]) as usize;
 
         // The TOTALLEN is definitely malformed if it doesn't have room for our header.
-        if total_length < IPV4_HEADER2_SIZE {
+        if total_length < header_len {
             return Err(Fail::Malformed {
                 details: "IPv4 TOTALLEN smaller than header",
             });
         }
-        if total_length - IPV4_HEADER2_SIZE > payload_buf.len() {
-            return Err(Fail::Malformed {
-                details: "IPv4 TOTALLEN greater than header + payload",
-            });
+        if total_length - header_len > payload_buf.len() {
+            if !truncated {
+                return Err(Fail::Malformed {
+                    details: "IPv4 TOTALLEN greater than header + payload",
+                });
+            }
+        } else {
+            // NB (sujayakar, 11/6/2020): I've noticed that Ethernet transmission is liable to add
+            // padding zeros for small payloads, so we can't assert that the Ethernet payload we
+            // receives exactly matches the header's TOTALLEN. Therefore, we may need to truncate
+            // off padding bytes when they don't line up.
+            let (payload, _padding) = payload_buf.split(total_length - header_len);
+            payload_buf = payload;
         }
-        // NB (sujayakar, 11/6/2020): I've noticed that Ethernet transmission is liable to add
-        // padding zeros for small payloads, so we can't assert that the Ethernet payload we
-        // receives exactly matches the header's TOTALLEN. Therefore, we may need to truncate off
-        // padding bytes when they don't line up.
-        let (payload, _padding) = payload_buf.split(total_length - IPV4_HEADER2_SIZE);
-        payload_buf = payload;
 
         let identification = NetworkEndian::read_u16(&hdr_buf[4..6]);
         let flags = (NetworkEndian::read_u16(&hdr_buf[6..8]) >> 13) as u8;
 
         let fragment_offset = NetworkEndian::read_u16(&hdr_buf[6..8]) & 0x1fff;
-        if fragment_offset != 0 {
-            return Err(Fail::Unsupported {
-                details: "IPv4 fragmentation is unsupported",
-            });
-        }
 
         let time_to_live = hdr_buf[8];
         let protocol = Ipv4Protocol2::try_from(hdr_buf[9])?;
@@ -186,6 +362,8 @@ impl Ipv4Header {
         let src_addr = Ipv4Addr::from(NetworkEndian::read_u32(&hdr_buf[12..16]));
         let dst_addr = Ipv4Addr::from(NetworkEndian::read_u32(&hdr_buf[16..20]));
 
+        let options = Ipv4HeaderOption::parse(&hdr_buf[IPV4_HEADER2_SIZE..])?;
+
         let header = Self {
             dscp,
             ecn,
@@ -196,15 +374,22 @@ impl Ipv4Header {
             protocol,
             src_addr,
             dst_addr,
+            options,
         };
         Ok((header, payload_buf))
     }
 
     pub fn serialize(&self, buf: &mut [u8], payload_len: usize) {
-        let buf: &mut [u8; IPV4_HEADER2_SIZE] = buf.try_into().unwrap();
-        buf[0] = (IPV4_VERSION << 4) | IPV4_IHL_NO_OPTIONS;
+        let options_size = self.options_size();
+        assert!(
+            options_size <= IPV4_MAX_OPTIONS_SIZE,
+            "IPv4 options too large"
+        );
+        let header_len = IPV4_HEADER2_SIZE + options_size;
+        let buf = &mut buf[..header_len];
+        buf[0] = (IPV4_VERSION << 4) | (header_len / 4) as u8;
         buf[1] = (self.dscp << 2) | (self.ecn & 3);
-        NetworkEndian::write_u16(&mut buf[2..4], (IPV4_HEADER2_SIZE + payload_len) as u16);
+        NetworkEndian::write_u16(&mut buf[2..4], (header_len + payload_len) as u16);
         NetworkEndian::write_u16(&mut buf[4..6], self.identification);
         NetworkEndian::write_u16(
             &mut buf[6..8],
@@ -217,6 +402,16 @@ impl Ipv4Header {
         buf[12..16].copy_from_slice(&self.src_addr.octets());
         buf[16..20].copy_from_slice(&self.dst_addr.octets());
 
+        let mut cur_pos = IPV4_HEADER2_SIZE;
+        for option in &self.options {
+            let option_size = option.compute_size();
+            option.serialize(&mut buf[cur_pos..(cur_pos + option_size)]);
+            cur_pos += option_size;
+        }
+        for byte in &mut buf[cur_pos..] {
+            *byte = IPV4_OPTION_END_OF_LIST;
+        }
+
         let checksum = ipv4_checksum(buf);
         NetworkEndian::write_u16(&mut buf[10..12], checksum);
     }
@@ -0,0 +1,115 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::datagram::{
+    Ipv4Header,
+    Ipv4Protocol2,
+};
+use crate::{
+    protocols::{
+        ethernet2::frame::{
+            EtherType2,
+            Ethernet2Header,
+        },
+        ip,
+        ipv6::datagram::{
+            Ipv6Header,
+            Ipv6NextHeader,
+        },
+    },
+    sync::Bytes,
+};
+use byteorder::{
+    ByteOrder,
+    NetworkEndian,
+};
+use crc::{
+    crc32,
+    Hasher32,
+};
+use std::{
+    convert::TryFrom,
+    hash::Hasher,
+    net::IpAddr,
+};
+
+/// Identifies one of several engines that share a NIC (and its addresses), each of which sees
+/// the flows whose 4-tuple hashes to its queue.
+///
+/// This is only a software model of flow steering for running several engines in one process
+/// under test: `flow_hash` isn't the Toeplitz hash a NIC's RSS uses, and nothing replicates ARP
+/// or broadcast frames to every queue on real hardware. The DPDK LibOS sticks to a single queue
+/// and never sets it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FlowSteering {
+    pub queue: usize,
+    pub num_queues: usize,
+}
+
+impl FlowSteering {
+    pub fn new(queue: usize, num_queues: usize) -> Self {
+        assert!(queue < num_queues);
+        Self { queue, num_queues }
+    }
+
+    pub fn owns(&self, local: &ip::Endpoint, remote: &ip::Endpoint) -> bool {
+        flow_queue(local, remote, self.num_queues) == self.queue
+    }
+}
+
+/// Hashes a flow's 4-tuple. The hash is symmetric, so both directions of a flow agree.
+pub fn flow_hash(a: &ip::Endpoint, b: &ip::Endpoint) -> u32 {
+    let key = |e: &ip::Endpoint| -> (IpAddr, u16) { (e.addr, e.port.into()) };
+    let (lo, hi) = if key(a) <= key(b) { (a, b) } else { (b, a) };
+    let mut hash = crc32::Digest::new(crc32::IEEE);
+    for e in &[lo, hi] {
+        match e.addr {
+            IpAddr::V4(addr) => hash.write_u32(addr.into()),
+            IpAddr::V6(addr) => hash.write_u128(addr.into()),
+        }
+        hash.write_u16(e.port.into());
+    }
+    hash.sum32()
+}
+
+pub fn flow_queue(a: &ip::Endpoint, b: &ip::Endpoint, num_queues: usize) -> usize {
+    flow_hash(a, b) as usize % num_queues
+}
+
+/// Picks the queue that should receive an incoming frame. Returns `None` for frames that every
+/// queue needs to see, like ARP traffic, broadcasts and multicasts such as Neighbor Discovery.
+pub fn steer_frame(frame: Bytes, num_queues: usize) -> Option<usize> {
+    let (eth_hdr, payload) = Ethernet2Header::parse(frame).ok()?;
+    if eth_hdr.dst_addr.is_multicast() {
+        return None;
+    }
+    let (src_addr, dst_addr, payload): (IpAddr, IpAddr, _) = match eth_hdr.ether_type {
+        EtherType2::Arp => return None,
+        EtherType2::Ipv4 => {
+            let (ip_hdr, payload) = Ipv4Header::parse(payload).ok()?;
+            match ip_hdr.protocol {
+                Ipv4Protocol2::Tcp | Ipv4Protocol2::Udp => (),
+                _ => return Some(0),
+            }
+            (ip_hdr.src_addr.into(), ip_hdr.dst_addr.into(), payload)
+        },
+        EtherType2::Ipv6 => {
+            let (ip_hdr, payload) = Ipv6Header::parse(payload).ok()?;
+            match ip_hdr.next_header {
+                Ipv6NextHeader::Tcp | Ipv6NextHeader::Udp => (),
+                // Neighbor Discovery has to reach every queue, since they each keep their own
+                // neighbor cache.
+                Ipv6NextHeader::Icmpv6 => return None,
+            }
+            (ip_hdr.src_addr.into(), ip_hdr.dst_addr.into(), payload)
+        },
+    };
+    if payload.len() < 4 {
+        return Some(0);
+    }
+    let src_port = ip::Port::try_from(NetworkEndian::read_u16(&payload[0..2])).ok()?;
+    let dst_port = ip::Port::try_from(NetworkEndian::read_u16(&payload[2..4])).ok()?;
+    let src = ip::Endpoint::new(src_addr, src_port);
+    let dst = ip::Endpoint::new(dst_addr, dst_port);
+    Some(flow_queue(&src, &dst, num_queues))
+}
@@ -0,0 +1,172 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+#[cfg(test)]
+mod tests;
+
+use super::datagram::{
+    Ipv4Header,
+    IPV4_FLAG_DONT_FRAGMENT,
+    IPV4_FLAG_MORE_FRAGMENTS,
+};
+use crate::{
+    fail::Fail,
+    protocols::{
+        ethernet2::frame::{
+            EtherType2,
+            Ethernet2Header,
+            MIN_PAYLOAD_SIZE,
+        },
+        ip,
+    },
+    runtime::{
+        PacketBuf,
+        Runtime,
+    },
+    sync::{
+        Bytes,
+        BytesMut,
+    },
+};
+use std::cmp;
+
+const MAX_DATAGRAM_SIZE: usize = u16::MAX as usize;
+
+struct Ipv4Fragment {
+    ethernet2_hdr: Ethernet2Header,
+    ipv4_hdr: Ipv4Header,
+    data: Bytes,
+}
+
+impl PacketBuf for Ipv4Fragment {
+    fn compute_size(&self) -> usize {
+        let size =
+            self.ethernet2_hdr.compute_size() + self.ipv4_hdr.compute_size() + self.data.len();
+        cmp::max(size, MIN_PAYLOAD_SIZE)
+    }
+
+    fn serialize(&self, buf: &mut [u8]) {
+        let eth_hdr_size = self.ethernet2_hdr.compute_size();
+        let ipv4_hdr_size = self.ipv4_hdr.compute_size();
+        let mut cur_pos = 0;
+
+        self.ethernet2_hdr
+            .serialize(&mut buf[cur_pos..(cur_pos + eth_hdr_size)]);
+        cur_pos += eth_hdr_size;
+
+        self.ipv4_hdr.serialize(
+            &mut buf[cur_pos..(cur_pos + ipv4_hdr_size)],
+            self.data.len(),
+        );
+        cur_pos += ipv4_hdr_size;
+
+        buf[cur_pos..(cur_pos + self.data.len())].copy_from_slice(&self.data[..]);
+        cur_pos += self.data.len();
+
+        for byte in &mut buf[cur_pos..] {
+            *byte = 0;
+        }
+    }
+}
+
+/// Splits outgoing datagrams that don't fit in the MTU into fragments, which keep the
+/// identification the sender gave the datagram.
+#[derive(Clone)]
+pub struct Fragmenter<RT: Runtime> {
+    rt: RT,
+}
+
+impl<RT: Runtime> Fragmenter<RT> {
+    pub fn new(rt: RT) -> Self {
+        Self { rt }
+    }
+
+    /// Checks that a datagram with `payload_len` bytes after `ip_hdr` can be sent.
+    pub fn check(&self, ip_hdr: &ip::Header, payload_len: usize) -> Result<(), Fail> {
+        let mtu = self.rt.ipv4_options().mtu;
+        let ipv4_hdr = match ip_hdr {
+            ip::Header::V4(hdr) => hdr,
+            // We don't fragment IPv6 datagrams, so they have to fit in the link MTU.
+            ip::Header::V6(hdr) => {
+                if hdr.compute_size() + payload_len > mtu {
+                    return Err(Fail::MessageTooLong {
+                        details: "IPv6 datagram exceeds MTU",
+                    });
+                }
+                return Ok(());
+            },
+        };
+        if ipv4_hdr.compute_size() + payload_len > MAX_DATAGRAM_SIZE {
+            return Err(Fail::MessageTooLong {
+                details: "Payload exceeds maximum IPv4 datagram size",
+            });
+        }
+        if ipv4_hdr.flags & IPV4_FLAG_DONT_FRAGMENT != 0
+            && ipv4_hdr.compute_size() + payload_len > mtu
+        {
+            return Err(Fail::MessageTooLong {
+                details: "Datagram exceeds MTU with DF set",
+            });
+        }
+        Ok(())
+    }
+
+    pub fn transmit(&self, pkt: impl PacketBuf) -> Result<(), Fail> {
+        let mtu = self.rt.ipv4_options().mtu;
+        let eth_hdr_size = self.rt.ethernet2_options().header_size();
+        let size = pkt.compute_size();
+        if size <= eth_hdr_size + mtu {
+            self.rt.transmit(pkt);
+            return Ok(());
+        }
+        if size > eth_hdr_size + MAX_DATAGRAM_SIZE {
+            return Err(Fail::MessageTooLong {
+                details: "Payload exceeds maximum IPv4 datagram size",
+            });
+        }
+
+        // Oversized datagrams are rare, so just serialize the whole thing and slice it up.
+        let mut buf = BytesMut::zeroed(size);
+        pkt.serialize(&mut buf[..]);
+        let (ethernet2_hdr, datagram) = Ethernet2Header::parse(buf.freeze())?;
+        if ethernet2_hdr.ether_type != EtherType2::Ipv4 {
+            return Err(Fail::MessageTooLong {
+                details: "IPv6 datagram exceeds MTU",
+            });
+        }
+        let (ipv4_hdr, mut payload) = Ipv4Header::parse(datagram)?;
+        if ipv4_hdr.flags & IPV4_FLAG_DONT_FRAGMENT != 0 {
+            return Err(Fail::MessageTooLong {
+                details: "Datagram exceeds MTU with DF set",
+            });
+        }
+
+        let mut offset = 0;
+        while !payload.is_empty() {
+            let mut fragment_hdr = ipv4_hdr.clone();
+            // Only options with the copied flag are repeated after the first fragment.
+            if offset > 0 {
+                fragment_hdr.options.retain(|o| o.is_copied());
+            }
+            // Every fragment but the last must carry a multiple of 8 bytes.
+            let max_fragment_size = (mtu - fragment_hdr.compute_size()) & !7;
+            let (data, rest) = if payload.len() > max_fragment_size {
+                payload.split(max_fragment_size)
+            } else {
+                (payload, Bytes::empty())
+            };
+            fragment_hdr.fragment_offset = (offset / 8) as u16;
+            if !rest.is_empty() {
+                fragment_hdr.flags |= IPV4_FLAG_MORE_FRAGMENTS;
+            }
+            offset += data.len();
+            self.rt.transmit(Ipv4Fragment {
+                ethernet2_hdr: ethernet2_hdr.clone(),
+                ipv4_hdr: fragment_hdr,
+                data,
+            });
+            payload = rest;
+        }
+        Ok(())
+    }
+}
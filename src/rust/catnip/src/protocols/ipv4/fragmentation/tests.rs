@@ -0,0 +1,107 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::*;
+use crate::{
+    engine::Protocol,
+    protocols::{
+        ip,
+        ipv4,
+    },
+    socket_options::SocketOption,
+    test_helpers,
+};
+use futures::task::noop_waker_ref;
+use must_let::must_let;
+use std::{
+    convert::TryFrom,
+    future::Future,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+    time::Instant,
+};
+
+fn payload(len: usize) -> Bytes {
+    let data = (0..len).map(|i| i as u8).collect::<Vec<_>>();
+    BytesMut::from(&data[..]).freeze()
+}
+
+#[test]
+fn oversized_udp_datagram() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    let mut alice = test_helpers::new_alice(now);
+    let mut bob = test_helpers::new_bob(now);
+
+    let alice_addr = ip::Endpoint::new(test_helpers::ALICE_IPV4, ip::Port::try_from(80).unwrap());
+    let bob_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, ip::Port::try_from(80).unwrap());
+
+    let bob_fd = bob.socket(Protocol::Udp);
+    bob.bind(bob_fd, bob_addr).unwrap();
+    let mut pop_future = bob.udp_pop(bob_fd);
+
+    let alice_fd = alice.socket(Protocol::Udp);
+    alice.bind(alice_fd, alice_addr).unwrap();
+    let _connect = alice.connect(alice_fd, bob_addr);
+
+    // 4000 bytes of data plus the UDP header take three fragments with a 1500 byte MTU.
+    let buf = payload(4000);
+    alice.udp_push(alice_fd, buf.clone()).unwrap();
+    alice.rt().poll_scheduler();
+
+    let mut identification = None;
+    for i in 0..3 {
+        let frame = alice.rt().pop_frame();
+        assert!(frame.len() <= ETHERNET2_HEADER2_SIZE + alice.rt().ipv4_options().mtu);
+
+        let (_, ipv4_datagram) = Ethernet2Header::parse(frame.clone()).unwrap();
+        let (ipv4_hdr, _) = Ipv4Header::parse(ipv4_datagram).unwrap();
+        assert!(ipv4_hdr.is_fragment());
+        assert_eq!(ipv4_hdr.flags & IPV4_FLAG_MORE_FRAGMENTS != 0, i < 2);
+        assert_eq!(
+            *identification.get_or_insert(ipv4_hdr.identification),
+            ipv4_hdr.identification
+        );
+
+        must_let!(let Poll::Pending = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+        bob.receive(frame).unwrap();
+    }
+    must_let!(let Poll::Ready(Ok((Some(remote), received))) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    assert_eq!(remote, alice_addr);
+    assert_eq!(received, buf);
+
+    // The next datagram to Bob gets a fresh identification.
+    alice.udp_push(alice_fd, payload(2000)).unwrap();
+    let (_, ipv4_datagram) = Ethernet2Header::parse(alice.rt().pop_frame()).unwrap();
+    let (ipv4_hdr, _) = Ipv4Header::parse(ipv4_datagram).unwrap();
+    assert_ne!(Some(ipv4_hdr.identification), identification);
+}
+
+#[test]
+fn dont_fragment() {
+    let now = Instant::now();
+    let mut alice = test_helpers::new_alice(now);
+
+    let alice_addr = ip::Endpoint::new(test_helpers::ALICE_IPV4, ip::Port::try_from(80).unwrap());
+    let bob_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, ip::Port::try_from(80).unwrap());
+
+    let alice_fd = alice.socket(Protocol::Udp);
+    alice.bind(alice_fd, alice_addr).unwrap();
+    let _connect = alice.connect(alice_fd, bob_addr);
+    alice
+        .setsockopt(alice_fd, SocketOption::DontFragment(true))
+        .unwrap();
+
+    must_let!(let Err(Fail::MessageTooLong { .. }) = alice.udp_push(alice_fd, payload(2000)));
+
+    // Datagrams that fit in the MTU still go out, with DF set.
+    alice.udp_push(alice_fd, payload(1000)).unwrap();
+    let (_, ipv4_datagram) = Ethernet2Header::parse(alice.rt().pop_frame()).unwrap();
+    let (ipv4_hdr, _) = Ipv4Header::parse(ipv4_datagram).unwrap();
+    assert!(ipv4_hdr.flags & IPV4_FLAG_DONT_FRAGMENT != 0);
+    assert!(!ipv4_hdr.is_fragment());
+}
@@ -3,8 +3,21 @@
 
 // mod checksum;
 pub mod datagram;
-mod endpoint;
+pub mod flow;
+mod fragmentation;
+mod options;
 mod peer;
+mod reassembly;
+pub mod routing;
 
-pub use endpoint::Ipv4Endpoint as Endpoint;
+#[cfg(test)]
+mod tests;
+
+pub use flow::FlowSteering;
+pub use fragmentation::Fragmenter;
+pub use options::Ipv4Options as Options;
 pub use peer::Ipv4Peer as Peer;
+pub use routing::{
+    Ipv4Route as Route,
+    RoutingTable,
+};
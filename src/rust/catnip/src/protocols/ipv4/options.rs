@@ -0,0 +1,137 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::{
+    flow::FlowSteering,
+    routing::{
+        netmask_prefix_len,
+        Ipv4Route,
+    },
+};
+use crate::protocols::igmp;
+use std::{
+    net::Ipv4Addr,
+    time::Duration,
+};
+
+const DEFAULT_MTU: usize = 1500;
+// RFC 791: Every internet module must be able to forward a datagram of 68 octets without further
+// fragmentation.
+const MIN_MTU: usize = 68;
+
+#[derive(Clone, Debug)]
+pub struct Ipv4Options {
+    // Only set by tests that run several engines on one simulated NIC; see `FlowSteering`.
+    pub flow_steering: Option<FlowSteering>,
+    pub mtu: usize,
+    pub netmask: Option<Ipv4Addr>,
+    pub secondary_addrs: Vec<(Ipv4Addr, u8)>,
+    pub default_gateway: Option<Ipv4Addr>,
+    pub routes: Vec<Ipv4Route>,
+    pub reassembly_timeout: Duration,
+    pub reassembly_max_datagrams: usize,
+    pub reassembly_max_bytes: usize,
+    // Get the primary address, its subnet and the default gateway from a DHCP server.
+    pub dhcp: bool,
+
+    pub igmp_version: igmp::Version,
+    // How many times unsolicited reports are sent, and the most time between them.
+    pub igmp_robustness: usize,
+    pub igmp_unsolicited_report_interval: Duration,
+}
+
+impl Default for Ipv4Options {
+    fn default() -> Self {
+        Ipv4Options {
+            flow_steering: None,
+            mtu: DEFAULT_MTU,
+            netmask: None,
+            secondary_addrs: vec![],
+            default_gateway: None,
+            routes: vec![],
+            reassembly_timeout: Duration::from_secs(30),
+            reassembly_max_datagrams: 64,
+            reassembly_max_bytes: 4 * 1024 * 1024,
+            dhcp: false,
+            // Defaults from RFC 3376 section 8.
+            igmp_version: igmp::Version::V3,
+            igmp_robustness: 2,
+            igmp_unsolicited_report_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+impl Ipv4Options {
+    pub fn flow_steering(mut self, value: FlowSteering) -> Self {
+        self.flow_steering = Some(value);
+        self
+    }
+
+    pub fn mtu(mut self, value: usize) -> Self {
+        assert!(value >= MIN_MTU);
+        self.mtu = value;
+        self
+    }
+
+    pub fn netmask(mut self, value: Ipv4Addr) -> Self {
+        assert!(netmask_prefix_len(value).is_some());
+        self.netmask = Some(value);
+        self
+    }
+
+    /// Adds another local address on a subnet of `prefix_len` bits. Use 32 for a virtual IP on
+    /// the primary address's subnet.
+    pub fn secondary_addr(mut self, addr: Ipv4Addr, prefix_len: u8) -> Self {
+        assert!(!addr.is_unspecified() && !addr.is_broadcast() && prefix_len <= 32);
+        self.secondary_addrs.push((addr, prefix_len));
+        self
+    }
+
+    pub fn default_gateway(mut self, value: Ipv4Addr) -> Self {
+        self.default_gateway = Some(value);
+        self
+    }
+
+    pub fn route(mut self, value: Ipv4Route) -> Self {
+        self.routes.push(value);
+        self
+    }
+
+    pub fn reassembly_timeout(mut self, value: Duration) -> Self {
+        assert!(value > Duration::new(0, 0));
+        self.reassembly_timeout = value;
+        self
+    }
+
+    pub fn reassembly_max_datagrams(mut self, value: usize) -> Self {
+        self.reassembly_max_datagrams = value;
+        self
+    }
+
+    pub fn reassembly_max_bytes(mut self, value: usize) -> Self {
+        self.reassembly_max_bytes = value;
+        self
+    }
+
+    pub fn dhcp(mut self, value: bool) -> Self {
+        self.dhcp = value;
+        self
+    }
+
+    pub fn igmp_version(mut self, value: igmp::Version) -> Self {
+        self.igmp_version = value;
+        self
+    }
+
+    pub fn igmp_robustness(mut self, value: usize) -> Self {
+        assert!(value > 0);
+        self.igmp_robustness = value;
+        self
+    }
+
+    pub fn igmp_unsolicited_report_interval(mut self, value: Duration) -> Self {
+        assert!(value > Duration::new(0, 0));
+        self.igmp_unsolicited_report_interval = value;
+        self
+    }
+}
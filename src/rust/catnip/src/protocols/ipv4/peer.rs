@@ -1,9 +1,14 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
-use super::datagram::{
-    Ipv4Header,
-    Ipv4Protocol2,
+use super::{
+    datagram::{
+        Ipv4Header,
+        Ipv4Protocol2,
+    },
+    fragmentation::Fragmenter,
+    reassembly::Reassembler,
+    routing::RoutingTable,
 };
 #[cfg(test)]
 use crate::file_table::FileDescriptor;
@@ -11,58 +16,164 @@ use crate::{
     fail::Fail,
     file_table::FileTable,
     protocols::{
-        arp,
+        dhcp,
         icmpv4,
+        icmpv4::datagram::{
+            DestinationUnreachable,
+            ICMPV4_PORT_UNREACHABLE,
+        },
+        igmp,
+        ip,
         tcp,
         udp,
     },
     runtime::Runtime,
     sync::Bytes,
 };
+use byteorder::{
+    ByteOrder,
+    NetworkEndian,
+};
 use std::{
+    cell::RefCell,
+    convert::TryFrom,
     future::Future,
     net::Ipv4Addr,
+    num::Wrapping,
+    rc::Rc,
     time::Duration,
 };
 
 pub struct Ipv4Peer<RT: Runtime> {
     rt: RT,
+    routes: Rc<RefCell<RoutingTable>>,
+    reassembler: Reassembler,
     icmpv4: icmpv4::Peer<RT>,
+    igmp: igmp::Peer<RT>,
+    pub dhcp: Option<dhcp::Client<RT>>,
     pub tcp: tcp::Peer<RT>,
     pub udp: udp::Peer<RT>,
 }
 
 impl<RT: Runtime> Ipv4Peer<RT> {
-    pub fn new(rt: RT, arp: arp::Peer<RT>, file_table: FileTable) -> Ipv4Peer<RT> {
-        let udp = udp::Peer::new(rt.clone(), arp.clone(), file_table.clone());
-        let icmpv4 = icmpv4::Peer::new(rt.clone(), arp.clone());
-        let tcp = tcp::Peer::new(rt.clone(), arp, file_table);
+    pub fn new(rt: RT, network: ip::Network<RT>, file_table: FileTable) -> Ipv4Peer<RT> {
+        let routes = network.ipv4_routes.clone();
+        let igmp = network.igmp.clone();
+        let fragmenter = Fragmenter::new(rt.clone());
+        let dhcp = if rt.ipv4_options().dhcp {
+            Some(dhcp::Client::new(rt.clone(), network.clone()))
+        } else {
+            None
+        };
+        let udp = udp::Peer::new(
+            rt.clone(),
+            network.clone(),
+            fragmenter.clone(),
+            file_table.clone(),
+        );
+        let icmpv4 = icmpv4::Peer::new(rt.clone(), network.clone(), fragmenter);
+        let tcp = tcp::Peer::new(rt.clone(), network, file_table);
+        let reassembler = Reassembler::new(rt.now(), &rt.ipv4_options());
         Ipv4Peer {
             rt,
+            routes,
+            reassembler,
             udp,
             icmpv4,
+            igmp,
+            dhcp,
             tcp,
         }
     }
 
     pub fn receive(&mut self, buf: Bytes) -> Result<(), Fail> {
         let (header, payload) = Ipv4Header::parse(buf)?;
-        if header.dst_addr != self.rt.local_ipv4_addr() && !header.dst_addr.is_broadcast() {
+        let dst_addr = header.dst_addr;
+        if !self.routes.borrow().is_local(dst_addr)
+            && !dst_addr.is_broadcast()
+            && !self.igmp.is_member(dst_addr)
+        {
             return Err(Fail::Misdelivered {});
         }
+        let (header, payload) = if header.is_fragment() {
+            self.reassembler.advance_clock(self.rt.now());
+            match self.reassembler.receive(header, payload)? {
+                Some(datagram) => datagram,
+                None => return Ok(()),
+            }
+        } else {
+            (header, payload)
+        };
         match header.protocol {
-            Ipv4Protocol2::Icmpv4 => self.icmpv4.receive(&header, payload),
-            Ipv4Protocol2::Tcp => self.tcp.receive(&header, payload),
-            Ipv4Protocol2::Udp => self.udp.receive(&header, payload),
+            Ipv4Protocol2::Icmpv4 => match self.icmpv4.receive(&header, payload)? {
+                Some(error) => self.receive_unreachable(error),
+                None => Ok(()),
+            },
+            Ipv4Protocol2::Igmp => self.igmp.receive(payload),
+            Ipv4Protocol2::Tcp => self.tcp.receive(&ip::Header::V4(header), payload),
+            Ipv4Protocol2::Udp => {
+                if let Some(ref dhcp) = self.dhcp {
+                    if dhcp::is_client_datagram(&payload[..]) {
+                        return dhcp.receive(&ip::Header::V4(header), payload);
+                    }
+                }
+                let r = self
+                    .udp
+                    .receive(&ip::Header::V4(header.clone()), payload.clone());
+                if let Err(Fail::ConnectionRefused {}) = r {
+                    // Nothing is bound to the port, so let the sender know.
+                    self.icmpv4.send_destination_unreachable(
+                        ICMPV4_PORT_UNREACHABLE,
+                        &header,
+                        payload,
+                    )?;
+                }
+                r
+            },
+        }
+    }
+
+    /// Hands an ICMPv4 Destination Unreachable error to the socket that sent the datagram.
+    fn receive_unreachable(&mut self, error: DestinationUnreachable) -> Result<(), Fail> {
+        // Both TCP and UDP start with the source and destination ports.
+        let src_port = ip::Port::try_from(NetworkEndian::read_u16(&error.data[0..2]))?;
+        let dst_port = ip::Port::try_from(NetworkEndian::read_u16(&error.data[2..4]))?;
+        let local = ip::Endpoint::new(error.ipv4_hdr.src_addr, src_port);
+        let remote = ip::Endpoint::new(error.ipv4_hdr.dst_addr, dst_port);
+        match error.ipv4_hdr.protocol {
+            // TCP keeps retrying through errors that may be transient.
+            Ipv4Protocol2::Tcp if error.is_hard_error() => {
+                let seq_num = Wrapping(NetworkEndian::read_u32(&error.data[4..8]));
+                self.tcp
+                    .receive_unreachable(local, remote, seq_num, error.to_fail())
+            },
+            Ipv4Protocol2::Udp => self.udp.receive_unreachable(local, remote, error.to_fail()),
+            _ => Err(Fail::Ignored {
+                details: "Unhandled ICMPv4 Destination Unreachable",
+            }),
         }
     }
 
+    pub fn dhcp_lease(&self) -> Option<dhcp::Lease> {
+        self.dhcp.as_ref().and_then(|dhcp| dhcp.lease())
+    }
+
     pub fn ping(
         &self,
         dest_ipv4_addr: Ipv4Addr,
+        payload_size: usize,
         timeout: Option<Duration>,
     ) -> impl Future<Output = Result<Duration, Fail>> {
-        self.icmpv4.ping(dest_ipv4_addr, timeout)
+        self.icmpv4.ping(dest_ipv4_addr, payload_size, timeout)
+    }
+
+    pub fn traceroute(
+        &self,
+        dest_ipv4_addr: Ipv4Addr,
+        max_hops: u8,
+        timeout: Option<Duration>,
+    ) -> impl Future<Output = Result<Vec<Option<icmpv4::TracerouteHop>>, Fail>> {
+        self.icmpv4.traceroute(dest_ipv4_addr, max_hops, timeout)
     }
 }
 
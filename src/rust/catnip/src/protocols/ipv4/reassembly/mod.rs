@@ -0,0 +1,240 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+#[cfg(test)]
+mod tests;
+
+use super::{
+    datagram::{
+        Ipv4Header,
+        Ipv4Protocol2,
+        IPV4_FLAG_MORE_FRAGMENTS,
+        IPV4_HEADER2_SIZE,
+    },
+    options::Ipv4Options,
+};
+use crate::{
+    collections::HashTtlCache,
+    fail::Fail,
+    sync::{
+        Bytes,
+        BytesMut,
+    },
+};
+use std::{
+    net::Ipv4Addr,
+    time::Instant,
+};
+
+const MAX_PAYLOAD_SIZE: usize = u16::MAX as usize - IPV4_HEADER2_SIZE;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct FragmentKey {
+    src_addr: Ipv4Addr,
+    dst_addr: Ipv4Addr,
+    protocol: Ipv4Protocol2,
+    identification: u16,
+}
+
+#[derive(Clone)]
+struct PartialDatagram {
+    // The header of the first fragment becomes the header of the reassembled datagram.
+    header: Option<Ipv4Header>,
+    // Sorted by offset and never overlapping.
+    fragments: Vec<(usize, Bytes)>,
+    // Only known once the last fragment has arrived.
+    total_len: Option<usize>,
+    buffered: usize,
+}
+
+impl PartialDatagram {
+    fn new() -> Self {
+        Self {
+            header: None,
+            fragments: vec![],
+            total_len: None,
+            buffered: 0,
+        }
+    }
+
+    /// Returns the number of bytes added, which is zero for an exact duplicate. Any other overlap
+    /// between fragments fails the whole datagram (see RFC 5722 for the IPv6 equivalent).
+    fn add(&mut self, header: Ipv4Header, payload: Bytes) -> Result<usize, Fail> {
+        let offset = header.fragment_offset as usize * 8;
+        let end = offset + payload.len();
+
+        if header.flags & IPV4_FLAG_MORE_FRAGMENTS == 0 {
+            if self.total_len.map(|len| len != end).unwrap_or(false) {
+                return Err(Fail::Malformed {
+                    details: "Conflicting IPv4 datagram lengths",
+                });
+            }
+            if let Some((last_offset, last)) = self.fragments.last() {
+                if last_offset + last.len() > end {
+                    return Err(Fail::Malformed {
+                        details: "IPv4 fragment past end of datagram",
+                    });
+                }
+            }
+            self.total_len = Some(end);
+        } else if self.total_len.map(|len| end > len).unwrap_or(false) {
+            return Err(Fail::Malformed {
+                details: "IPv4 fragment past end of datagram",
+            });
+        }
+
+        let ix = self
+            .fragments
+            .iter()
+            .position(|(o, _)| *o >= offset)
+            .unwrap_or_else(|| self.fragments.len());
+        if let Some((next_offset, next)) = self.fragments.get(ix) {
+            if *next_offset == offset && next.len() == payload.len() {
+                return Ok(0);
+            }
+            if *next_offset < end {
+                return Err(Fail::Malformed {
+                    details: "Overlapping IPv4 fragments",
+                });
+            }
+        }
+        if ix > 0 {
+            let (prev_offset, prev) = &self.fragments[ix - 1];
+            if prev_offset + prev.len() > offset {
+                return Err(Fail::Malformed {
+                    details: "Overlapping IPv4 fragments",
+                });
+            }
+        }
+
+        let len = payload.len();
+        if offset == 0 {
+            self.header = Some(header);
+        }
+        self.fragments.insert(ix, (offset, payload));
+        self.buffered += len;
+        Ok(len)
+    }
+
+    fn is_complete(&self) -> bool {
+        // Fragments never overlap and never extend past the end, so they cover the whole datagram
+        // once their lengths add up.
+        self.total_len == Some(self.buffered)
+    }
+
+    fn assemble(self) -> (Ipv4Header, Bytes) {
+        let mut header = self
+            .header
+            .expect("Complete datagram without a first fragment");
+        header.flags &= !IPV4_FLAG_MORE_FRAGMENTS;
+        header.fragment_offset = 0;
+
+        let mut buf = BytesMut::zeroed(self.buffered);
+        for (offset, fragment) in self.fragments {
+            buf[offset..(offset + fragment.len())].copy_from_slice(&fragment[..]);
+        }
+        (header, buf.freeze())
+    }
+}
+
+/// Reassembles fragmented datagrams, keyed by (source, destination, protocol, identification).
+/// Incomplete datagrams are dropped once they've been around for longer than the reassembly
+/// timeout.
+pub struct Reassembler {
+    datagrams: HashTtlCache<FragmentKey, PartialDatagram>,
+    num_datagrams: usize,
+    buffered: usize,
+
+    max_datagrams: usize,
+    max_bytes: usize,
+}
+
+impl Reassembler {
+    pub fn new(now: Instant, options: &Ipv4Options) -> Self {
+        Self {
+            datagrams: HashTtlCache::new(now, Some(options.reassembly_timeout)),
+            num_datagrams: 0,
+            buffered: 0,
+            max_datagrams: options.reassembly_max_datagrams,
+            max_bytes: options.reassembly_max_bytes,
+        }
+    }
+
+    pub fn buffered(&self) -> usize {
+        self.buffered
+    }
+
+    pub fn advance_clock(&mut self, now: Instant) {
+        self.datagrams.advance_clock(now);
+        for (key, datagram) in self.datagrams.try_evict(usize::max_value()) {
+            warn!("Timed out reassembling {:?}", key);
+            self.num_datagrams -= 1;
+            self.buffered -= datagram.buffered;
+        }
+    }
+
+    /// Returns the reassembled datagram once `payload` completes it.
+    pub fn receive(
+        &mut self,
+        header: Ipv4Header,
+        payload: Bytes,
+    ) -> Result<Option<(Ipv4Header, Bytes)>, Fail> {
+        if !header.is_fragment() {
+            return Ok(Some((header, payload)));
+        }
+        let offset = header.fragment_offset as usize * 8;
+        if header.flags & IPV4_FLAG_MORE_FRAGMENTS != 0
+            && (payload.is_empty() || payload.len() % 8 != 0)
+        {
+            return Err(Fail::Malformed {
+                details: "IPv4 fragment length isn't a multiple of 8",
+            });
+        }
+        if offset + payload.len() > MAX_PAYLOAD_SIZE {
+            return Err(Fail::Malformed {
+                details: "IPv4 fragment past maximum datagram size",
+            });
+        }
+        if self.buffered + payload.len() > self.max_bytes {
+            return Err(Fail::ResourceExhausted {
+                details: "IPv4 reassembly buffer full",
+            });
+        }
+
+        let key = FragmentKey {
+            src_addr: header.src_addr,
+            dst_addr: header.dst_addr,
+            protocol: header.protocol,
+            identification: header.identification,
+        };
+        if self.datagrams.get(&key).is_none() {
+            if self.num_datagrams >= self.max_datagrams {
+                return Err(Fail::ResourceExhausted {
+                    details: "Too many IPv4 datagrams being reassembled",
+                });
+            }
+            self.datagrams.insert(key, PartialDatagram::new());
+            self.num_datagrams += 1;
+        }
+
+        let datagram = self.datagrams.get_mut(&key).unwrap();
+        match datagram.add(header, payload) {
+            Ok(len) => self.buffered += len,
+            Err(e) => {
+                self.remove(&key);
+                return Err(e);
+            },
+        }
+        if !datagram.is_complete() {
+            return Ok(None);
+        }
+        Ok(self.remove(&key).map(PartialDatagram::assemble))
+    }
+
+    fn remove(&mut self, key: &FragmentKey) -> Option<PartialDatagram> {
+        let datagram = self.datagrams.remove(key)?;
+        self.num_datagrams -= 1;
+        self.buffered -= datagram.buffered;
+        Some(datagram)
+    }
+}
@@ -0,0 +1,212 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::*;
+use crate::{
+    engine::Protocol,
+    protocols::{
+        ethernet2::frame::{
+            Ethernet2Header,
+            ETHERNET2_HEADER2_SIZE,
+        },
+        ip,
+        ipv4,
+    },
+    runtime::Runtime,
+    test_helpers,
+};
+use futures::task::noop_waker_ref;
+use must_let::must_let;
+use std::{
+    convert::TryFrom,
+    future::Future,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+    time::Duration,
+};
+
+fn fragment(id: u16, offset: usize, more: bool, payload: &[u8]) -> (Ipv4Header, Bytes) {
+    let mut header = Ipv4Header::new(
+        test_helpers::ALICE_IPV4,
+        test_helpers::BOB_IPV4,
+        Ipv4Protocol2::Udp,
+    );
+    header.identification = id;
+    header.fragment_offset = (offset / 8) as u16;
+    if more {
+        header.flags |= IPV4_FLAG_MORE_FRAGMENTS;
+    }
+    (header, BytesMut::from(payload).freeze())
+}
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| i as u8).collect()
+}
+
+#[test]
+fn out_of_order() {
+    let now = Instant::now();
+    let mut reassembler = Reassembler::new(now, &Ipv4Options::default());
+    let data = payload(24);
+
+    let (h, p) = fragment(1, 16, false, &data[16..]);
+    must_let!(let Ok(None) = reassembler.receive(h, p));
+    let (h, p) = fragment(1, 0, true, &data[..8]);
+    must_let!(let Ok(None) = reassembler.receive(h, p));
+    assert_eq!(reassembler.buffered(), 16);
+
+    let (h, p) = fragment(1, 8, true, &data[8..16]);
+    must_let!(let Ok(Some((header, reassembled))) = reassembler.receive(h, p));
+    assert!(!header.is_fragment());
+    assert_eq!(&reassembled[..], &data[..]);
+    assert_eq!(reassembler.buffered(), 0);
+}
+
+#[test]
+fn interleaved_datagrams() {
+    let now = Instant::now();
+    let mut reassembler = Reassembler::new(now, &Ipv4Options::default());
+    let data = payload(16);
+
+    let (h, p) = fragment(1, 8, false, &data[8..]);
+    must_let!(let Ok(None) = reassembler.receive(h, p));
+    let (h, p) = fragment(2, 0, true, &data[..8]);
+    must_let!(let Ok(None) = reassembler.receive(h, p));
+    let (h, p) = fragment(2, 8, false, &data[8..]);
+    must_let!(let Ok(Some((header, _))) = reassembler.receive(h, p));
+    assert_eq!(header.identification, 2);
+    assert_eq!(reassembler.buffered(), 8);
+}
+
+#[test]
+fn duplicate_and_overlapping_fragments() {
+    let now = Instant::now();
+    let mut reassembler = Reassembler::new(now, &Ipv4Options::default());
+    let data = payload(24);
+
+    // Exact duplicates are ignored.
+    let (h, p) = fragment(1, 0, true, &data[..16]);
+    must_let!(let Ok(None) = reassembler.receive(h, p));
+    let (h, p) = fragment(1, 0, true, &data[..16]);
+    must_let!(let Ok(None) = reassembler.receive(h, p));
+    assert_eq!(reassembler.buffered(), 16);
+
+    // Any other overlap discards the whole datagram.
+    let (h, p) = fragment(1, 8, false, &data[8..]);
+    must_let!(let Err(Fail::Malformed { .. }) = reassembler.receive(h, p));
+    assert_eq!(reassembler.buffered(), 0);
+
+    let (h, p) = fragment(1, 16, false, &data[16..]);
+    must_let!(let Ok(None) = reassembler.receive(h, p));
+}
+
+#[test]
+fn malformed_fragments() {
+    let now = Instant::now();
+    let mut reassembler = Reassembler::new(now, &Ipv4Options::default());
+    let data = payload(24);
+
+    // Every fragment but the last must be a multiple of 8 bytes.
+    let (h, p) = fragment(1, 0, true, &data[..12]);
+    must_let!(let Err(Fail::Malformed { .. }) = reassembler.receive(h, p));
+
+    // Fragments can't extend past the last fragment.
+    let (h, p) = fragment(2, 8, false, &data[8..16]);
+    must_let!(let Ok(None) = reassembler.receive(h, p));
+    let (h, p) = fragment(2, 16, true, &data[16..]);
+    must_let!(let Err(Fail::Malformed { .. }) = reassembler.receive(h, p));
+}
+
+#[test]
+fn timeout() {
+    let mut now = Instant::now();
+    let options = Ipv4Options::default().reassembly_timeout(Duration::from_secs(1));
+    let mut reassembler = Reassembler::new(now, &options);
+    let data = payload(16);
+
+    let (h, p) = fragment(1, 0, true, &data[..8]);
+    must_let!(let Ok(None) = reassembler.receive(h, p));
+
+    now += Duration::from_secs(1);
+    reassembler.advance_clock(now);
+    assert_eq!(reassembler.buffered(), 0);
+
+    // The rest of the datagram shows up too late.
+    let (h, p) = fragment(1, 8, false, &data[8..]);
+    must_let!(let Ok(None) = reassembler.receive(h, p));
+}
+
+#[test]
+fn memory_limits() {
+    let now = Instant::now();
+    let options = Ipv4Options::default()
+        .reassembly_max_datagrams(1)
+        .reassembly_max_bytes(16);
+    let mut reassembler = Reassembler::new(now, &options);
+    let data = payload(32);
+
+    let (h, p) = fragment(1, 0, true, &data[..8]);
+    must_let!(let Ok(None) = reassembler.receive(h, p));
+    let (h, p) = fragment(2, 0, true, &data[..8]);
+    must_let!(let Err(Fail::ResourceExhausted { .. }) = reassembler.receive(h, p));
+    let (h, p) = fragment(1, 8, true, &data[8..24]);
+    must_let!(let Err(Fail::ResourceExhausted { .. }) = reassembler.receive(h, p));
+    assert_eq!(reassembler.buffered(), 8);
+}
+
+#[test]
+fn fragmented_udp_datagram() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    let mut alice = test_helpers::new_alice(now);
+    let mut bob = test_helpers::new_bob(now);
+
+    let alice_addr = ip::Endpoint::new(test_helpers::ALICE_IPV4, ip::Port::try_from(80).unwrap());
+    let bob_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, ip::Port::try_from(80).unwrap());
+
+    let bob_fd = bob.socket(Protocol::Udp);
+    bob.bind(bob_fd, bob_addr).unwrap();
+    let mut pop_future = bob.udp_pop(bob_fd);
+
+    let alice_fd = alice.socket(Protocol::Udp);
+    alice.bind(alice_fd, alice_addr).unwrap();
+    let buf = BytesMut::from(&payload(100)[..]).freeze();
+    let _push = alice.pushto(alice_fd, buf.clone(), bob_addr);
+    alice.rt().poll_scheduler();
+
+    // Split Alice's datagram into three fragments and hand them to Bob back to front.
+    let (ethernet2_hdr, ipv4_datagram) = Ethernet2Header::parse(alice.rt().pop_frame()).unwrap();
+    let (ipv4_hdr, ipv4_payload) = Ipv4Header::parse(ipv4_datagram).unwrap();
+    let mut frames = vec![];
+    for offset in (0..ipv4_payload.len()).step_by(48) {
+        let end = std::cmp::min(offset + 48, ipv4_payload.len());
+        let mut header = ipv4_hdr.clone();
+        header.identification = 7;
+        header.fragment_offset = (offset / 8) as u16;
+        if end < ipv4_payload.len() {
+            header.flags |= IPV4_FLAG_MORE_FRAGMENTS;
+        }
+        let chunk = &ipv4_payload[offset..end];
+        let mut frame = BytesMut::zeroed(ETHERNET2_HEADER2_SIZE + IPV4_HEADER2_SIZE + chunk.len());
+        ethernet2_hdr.serialize(&mut frame[..ETHERNET2_HEADER2_SIZE]);
+        header.serialize(
+            &mut frame[ETHERNET2_HEADER2_SIZE..(ETHERNET2_HEADER2_SIZE + IPV4_HEADER2_SIZE)],
+            chunk.len(),
+        );
+        frame[(ETHERNET2_HEADER2_SIZE + IPV4_HEADER2_SIZE)..].copy_from_slice(chunk);
+        frames.push(frame.freeze());
+    }
+    assert_eq!(frames.len(), 3);
+
+    for frame in frames.into_iter().rev() {
+        must_let!(let Poll::Pending = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+        bob.receive(frame).unwrap();
+    }
+    must_let!(let Poll::Ready(Ok((Some(remote), received))) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    assert_eq!(remote, alice_addr);
+    assert_eq!(received, buf);
+}
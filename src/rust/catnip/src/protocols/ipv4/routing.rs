@@ -0,0 +1,148 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::options::Ipv4Options;
+use crate::{
+    fail::Fail,
+    protocols::ethernet2::MacAddress,
+};
+use std::net::Ipv4Addr;
+
+/// A static route to every address that shares the first `prefix_len` bits of `destination`.
+/// Routes without a gateway are directly connected.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Ipv4Route {
+    pub destination: Ipv4Addr,
+    pub prefix_len: u8,
+    pub gateway: Option<Ipv4Addr>,
+}
+
+impl Ipv4Route {
+    pub fn new(destination: Ipv4Addr, prefix_len: u8, gateway: Option<Ipv4Addr>) -> Self {
+        assert!(prefix_len <= 32);
+        Self {
+            destination,
+            prefix_len,
+            gateway,
+        }
+    }
+
+    fn mask(&self) -> u32 {
+        prefix_mask(self.prefix_len)
+    }
+
+    fn matches(&self, addr: Ipv4Addr) -> bool {
+        u32::from(addr) & self.mask() == u32::from(self.destination) & self.mask()
+    }
+}
+
+fn prefix_mask(prefix_len: u8) -> u32 {
+    match prefix_len {
+        0 => 0,
+        n => !0u32 << (32 - n as u32),
+    }
+}
+
+/// Returns the prefix length of `netmask`, or `None` if its bits aren't contiguous.
+pub fn netmask_prefix_len(netmask: Ipv4Addr) -> Option<u8> {
+    let mask = u32::from(netmask);
+    let prefix_len = mask.leading_ones() as u8;
+    if prefix_mask(prefix_len) == mask {
+        Some(prefix_len)
+    } else {
+        None
+    }
+}
+
+/// The Ethernet address a multicast datagram to `addr` is sent to (RFC 1112 section 6.4).
+pub fn multicast_link_addr(addr: Ipv4Addr) -> MacAddress {
+    let octets = addr.octets();
+    MacAddress::new([0x01, 0x00, 0x5e, octets[1] & 0x7f, octets[2], octets[3]])
+}
+
+/// The host's local addresses along with the routes used to reach everything else.
+#[derive(Clone, Debug)]
+pub struct RoutingTable {
+    // Each local address with the prefix length of its subnet, primary address first.
+    local_addrs: Vec<Ipv4Route>,
+    routes: Vec<Ipv4Route>,
+}
+
+impl RoutingTable {
+    pub fn new(local_addr: Ipv4Addr, options: &Ipv4Options) -> Self {
+        let mut routes = options.routes.clone();
+        let primary_prefix_len = match options.netmask {
+            Some(netmask) => netmask_prefix_len(netmask).expect("Invalid netmask"),
+            None => 0,
+        };
+        if options.netmask.is_some() {
+            routes.push(Ipv4Route::new(local_addr, primary_prefix_len, None));
+        }
+        match options.default_gateway {
+            Some(gateway) => routes.push(Ipv4Route::new(Ipv4Addr::UNSPECIFIED, 0, Some(gateway))),
+            // Without any routing configuration, treat every destination as directly connected.
+            None if routes.is_empty() => {
+                routes.push(Ipv4Route::new(Ipv4Addr::UNSPECIFIED, 0, None))
+            },
+            None => (),
+        }
+
+        let mut local_addrs = vec![Ipv4Route::new(local_addr, primary_prefix_len, None)];
+        for &(addr, prefix_len) in &options.secondary_addrs {
+            if local_addrs.iter().any(|l| l.destination == addr) {
+                continue;
+            }
+            let subnet = Ipv4Route::new(addr, prefix_len, None);
+            // A secondary address on its own subnet makes that subnet directly reachable.
+            if prefix_len < 32 {
+                routes.push(subnet);
+            }
+            local_addrs.push(subnet);
+        }
+        Self {
+            local_addrs,
+            routes,
+        }
+    }
+
+    pub fn is_local(&self, addr: Ipv4Addr) -> bool {
+        self.local_addrs.iter().any(|l| l.destination == addr)
+    }
+
+    pub fn local_addrs(&self) -> impl Iterator<Item = Ipv4Addr> + '_ {
+        self.local_addrs.iter().map(|l| l.destination)
+    }
+
+    /// Picks the address whose link address a datagram to `dst_addr` should be sent to, using the
+    /// longest matching prefix.
+    pub fn next_hop(&self, dst_addr: Ipv4Addr) -> Result<Ipv4Addr, Fail> {
+        if dst_addr.is_broadcast() || dst_addr.is_multicast() {
+            return Ok(dst_addr);
+        }
+        let route = self
+            .routes
+            .iter()
+            .filter(|r| r.matches(dst_addr))
+            .max_by_key(|r| r.prefix_len)
+            .ok_or(Fail::NoRoute {})?;
+        Ok(route.gateway.unwrap_or(dst_addr))
+    }
+
+    /// Picks the local address to send from when a socket doesn't specify one: the address whose
+    /// subnet most specifically contains the next hop, preferring the primary address on ties.
+    pub fn source_addr(&self, dst_addr: Ipv4Addr) -> Ipv4Addr {
+        if self.is_local(dst_addr) {
+            return dst_addr;
+        }
+        let next_hop = self.next_hop(dst_addr).unwrap_or(dst_addr);
+        let mut best = &self.local_addrs[0];
+        for local in &self.local_addrs[1..] {
+            if local.matches(next_hop)
+                && (!best.matches(next_hop) || local.prefix_len > best.prefix_len)
+            {
+                best = local;
+            }
+        }
+        best.destination
+    }
+}
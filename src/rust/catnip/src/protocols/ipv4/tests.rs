@@ -0,0 +1,789 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::{
+    datagram::{
+        Ipv4Header,
+        Ipv4HeaderOption,
+        Ipv4Protocol2,
+        IPV4_HEADER2_SIZE,
+    },
+    routing::{
+        netmask_prefix_len,
+        Ipv4Route,
+        RoutingTable,
+    },
+};
+use crate::{
+    engine::{
+        Engine,
+        Protocol,
+    },
+    fail::Fail,
+    protocols::{
+        ethernet2::{
+            frame::{
+                EtherType2,
+                Ethernet2Header,
+                VlanTags,
+                ETHERNET2_HEADER2_SIZE,
+            },
+            MacAddress,
+        },
+        icmpv4::{
+            self,
+            datagram::{
+                Icmpv4Header,
+                Icmpv4Message,
+                Icmpv4Type2,
+                ICMPV4_PORT_UNREACHABLE,
+            },
+        },
+        ip,
+        ipv4,
+        udp::datagram::{
+            UdpDatagram,
+            UdpHeader,
+        },
+    },
+    runtime::{
+        PacketBuf,
+        Runtime,
+    },
+    socket_options::SocketOption,
+    sync::{
+        Bytes,
+        BytesMut,
+    },
+    test_helpers::{
+        self,
+        TestRuntime,
+    },
+};
+use futures::{
+    task::noop_waker_ref,
+    FutureExt,
+};
+use must_let::must_let;
+use std::{
+    convert::TryFrom,
+    future::Future,
+    net::Ipv4Addr,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+#[test]
+fn header_options_round_trip() {
+    let mut header = Ipv4Header::new(
+        test_helpers::ALICE_IPV4,
+        test_helpers::BOB_IPV4,
+        Ipv4Protocol2::Udp,
+    );
+    header.options.push(Ipv4HeaderOption::RouterAlert(0));
+    header.options.push(Ipv4HeaderOption::record_route(2));
+    header.options.push(Ipv4HeaderOption::Other {
+        kind: 0x44,
+        data: vec![1, 2],
+    });
+
+    // 4 + 11 + 4 bytes of options, padded to 20.
+    assert_eq!(header.compute_size(), IPV4_HEADER2_SIZE + 20);
+    let payload = [0xab; 10];
+    let mut buf = BytesMut::zeroed(header.compute_size() + payload.len());
+    header.serialize(&mut buf[..header.compute_size()], payload.len());
+    buf[header.compute_size()..].copy_from_slice(&payload[..]);
+    assert_eq!(buf[0] & 0xf, 10);
+
+    let (parsed, parsed_payload) = Ipv4Header::parse(buf.freeze()).unwrap();
+    assert_eq!(parsed.options, header.options);
+    assert_eq!(&parsed_payload[..], &payload[..]);
+    must_let!(let Ipv4HeaderOption::RecordRoute { pointer: 4, route } = &parsed.options[1]);
+    assert_eq!(route, &vec![Ipv4Addr::UNSPECIFIED; 2]);
+    assert!(parsed.options[0].is_copied());
+    assert!(!parsed.options[1].is_copied());
+}
+
+#[test]
+fn udp_datagram_with_router_alert() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    let mut alice = test_helpers::new_alice(now);
+    let mut bob = test_helpers::new_bob(now);
+
+    let alice_addr = ip::Endpoint::new(test_helpers::ALICE_IPV4, ip::Port::try_from(80).unwrap());
+    let bob_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, ip::Port::try_from(80).unwrap());
+
+    let bob_fd = bob.socket(Protocol::Udp);
+    bob.bind(bob_fd, bob_addr).unwrap();
+    let mut pop_future = bob.udp_pop(bob_fd);
+
+    let alice_fd = alice.socket(Protocol::Udp);
+    alice.bind(alice_fd, alice_addr).unwrap();
+    let buf = BytesMut::from(&b"hello"[..]).freeze();
+    let _push = alice.pushto(alice_fd, buf.clone(), bob_addr);
+    alice.rt().poll_scheduler();
+
+    // Rewrite Alice's datagram with a Router Alert option before handing it to Bob.
+    let (ethernet2_hdr, ipv4_datagram) = Ethernet2Header::parse(alice.rt().pop_frame()).unwrap();
+    let (mut ipv4_hdr, ipv4_payload) = Ipv4Header::parse(ipv4_datagram).unwrap();
+    ipv4_hdr.options.push(Ipv4HeaderOption::RouterAlert(0));
+    let ipv4_hdr_size = ipv4_hdr.compute_size();
+    let mut frame = BytesMut::zeroed(ETHERNET2_HEADER2_SIZE + ipv4_hdr_size + ipv4_payload.len());
+    ethernet2_hdr.serialize(&mut frame[..ETHERNET2_HEADER2_SIZE]);
+    ipv4_hdr.serialize(
+        &mut frame[ETHERNET2_HEADER2_SIZE..(ETHERNET2_HEADER2_SIZE + ipv4_hdr_size)],
+        ipv4_payload.len(),
+    );
+    frame[(ETHERNET2_HEADER2_SIZE + ipv4_hdr_size)..].copy_from_slice(&ipv4_payload[..]);
+
+    bob.receive(frame.freeze()).unwrap();
+    must_let!(let Poll::Ready(Ok((Some(remote), received))) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    assert_eq!(remote, alice_addr);
+    assert_eq!(received, buf);
+}
+
+#[test]
+fn netmask() {
+    assert_eq!(
+        netmask_prefix_len(Ipv4Addr::new(255, 255, 255, 0)),
+        Some(24)
+    );
+    assert_eq!(
+        netmask_prefix_len(Ipv4Addr::new(255, 255, 255, 255)),
+        Some(32)
+    );
+    assert_eq!(netmask_prefix_len(Ipv4Addr::UNSPECIFIED), Some(0));
+    assert_eq!(netmask_prefix_len(Ipv4Addr::new(255, 0, 255, 0)), None);
+}
+
+#[test]
+fn longest_prefix_match() {
+    let local = Ipv4Addr::new(10, 0, 0, 2);
+    let gateway = Ipv4Addr::new(10, 0, 0, 1);
+    let options = ipv4::Options::default()
+        .netmask(Ipv4Addr::new(255, 255, 255, 0))
+        .default_gateway(gateway)
+        .route(Ipv4Route::new(
+            Ipv4Addr::new(172, 16, 0, 0),
+            12,
+            Some(Ipv4Addr::new(10, 0, 0, 250)),
+        ))
+        .route(Ipv4Route::new(
+            Ipv4Addr::new(172, 16, 5, 0),
+            24,
+            Some(Ipv4Addr::new(10, 0, 0, 251)),
+        ));
+    let table = RoutingTable::new(local, &options);
+
+    // On the local subnet.
+    let neighbor = Ipv4Addr::new(10, 0, 0, 77);
+    assert_eq!(table.next_hop(neighbor).unwrap(), neighbor);
+    // The most specific static route wins.
+    assert_eq!(
+        table.next_hop(Ipv4Addr::new(172, 16, 5, 9)).unwrap(),
+        Ipv4Addr::new(10, 0, 0, 251)
+    );
+    assert_eq!(
+        table.next_hop(Ipv4Addr::new(172, 17, 0, 1)).unwrap(),
+        Ipv4Addr::new(10, 0, 0, 250)
+    );
+    // Everything else goes through the default gateway.
+    assert_eq!(table.next_hop(Ipv4Addr::new(8, 8, 8, 8)).unwrap(), gateway);
+    assert_eq!(
+        table.next_hop(Ipv4Addr::BROADCAST).unwrap(),
+        Ipv4Addr::BROADCAST
+    );
+}
+
+#[test]
+fn no_route() {
+    let local = Ipv4Addr::new(10, 0, 0, 2);
+
+    // Without any configuration, everything is directly connected.
+    let table = RoutingTable::new(local, &ipv4::Options::default());
+    let remote = Ipv4Addr::new(8, 8, 8, 8);
+    assert_eq!(table.next_hop(remote).unwrap(), remote);
+
+    let options = ipv4::Options::default().netmask(Ipv4Addr::new(255, 255, 255, 0));
+    let table = RoutingTable::new(local, &options);
+    must_let!(let Err(Fail::NoRoute {}) = table.next_hop(remote));
+}
+
+#[test]
+fn udp_through_default_gateway() {
+    let now = Instant::now();
+    // Carrie acts as Alice's gateway.
+    let options = ipv4::Options::default()
+        .netmask(Ipv4Addr::new(255, 255, 255, 0))
+        .default_gateway(test_helpers::CARRIE_IPV4);
+    let mut alice = test_helpers::new_alice_with_ipv4_options(now, options);
+
+    let alice_addr = ip::Endpoint::new(test_helpers::ALICE_IPV4, ip::Port::try_from(80).unwrap());
+    let remote_addr =
+        ip::Endpoint::new(Ipv4Addr::new(10, 1, 2, 3), ip::Port::try_from(80).unwrap());
+
+    let alice_fd = alice.socket(Protocol::Udp);
+    alice.bind(alice_fd, alice_addr).unwrap();
+    let _push = alice.pushto(
+        alice_fd,
+        BytesMut::from(&b"hello"[..]).freeze(),
+        remote_addr,
+    );
+    alice.rt().poll_scheduler();
+
+    let (ethernet2_hdr, ipv4_datagram) = Ethernet2Header::parse(alice.rt().pop_frame()).unwrap();
+    assert_eq!(ethernet2_hdr.dst_addr, test_helpers::CARRIE_MAC);
+    let (ipv4_hdr, _) = Ipv4Header::parse(ipv4_datagram).unwrap();
+    assert_eq!(ipv4_hdr.dst_addr, remote_addr.addr);
+}
+
+#[test]
+fn arp_query_without_route() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+    let options = ipv4::Options::default().netmask(Ipv4Addr::new(255, 255, 255, 0));
+    let alice = test_helpers::new_alice_with_ipv4_options(now, options);
+
+    let mut query = alice.arp_query(Ipv4Addr::new(10, 1, 2, 3)).boxed_local();
+    must_let!(let Poll::Ready(Err(Fail::NoRoute {})) = Future::poll(query.as_mut(), &mut ctx));
+}
+
+#[test]
+fn source_address_selection() {
+    let primary = Ipv4Addr::new(10, 0, 0, 2);
+    let secondary = Ipv4Addr::new(172, 16, 0, 5);
+    let vip = Ipv4Addr::new(10, 0, 0, 50);
+    let options = ipv4::Options::default()
+        .netmask(Ipv4Addr::new(255, 255, 255, 0))
+        .default_gateway(Ipv4Addr::new(10, 0, 0, 1))
+        .secondary_addr(secondary, 24)
+        .secondary_addr(vip, 32);
+    let table = RoutingTable::new(primary, &options);
+
+    assert!(table.is_local(primary));
+    assert!(table.is_local(secondary));
+    assert!(table.is_local(vip));
+    assert!(!table.is_local(Ipv4Addr::new(10, 0, 0, 3)));
+    assert_eq!(
+        table.local_addrs().collect::<Vec<_>>(),
+        vec![primary, secondary, vip]
+    );
+
+    // The secondary address's subnet is directly connected and sourced from that address.
+    let neighbor = Ipv4Addr::new(172, 16, 0, 9);
+    assert_eq!(table.next_hop(neighbor).unwrap(), neighbor);
+    assert_eq!(table.source_addr(neighbor), secondary);
+    // Everything else comes from the primary address, including traffic through the gateway.
+    assert_eq!(table.source_addr(Ipv4Addr::new(10, 0, 0, 77)), primary);
+    assert_eq!(table.source_addr(Ipv4Addr::new(8, 8, 8, 8)), primary);
+    assert_eq!(table.source_addr(vip), vip);
+}
+
+#[test]
+fn udp_to_secondary_address() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+    let vip = Ipv4Addr::new(192, 168, 1, 50);
+    let options = ipv4::Options::default().secondary_addr(vip, 32);
+    let mut alice = test_helpers::new_alice(now);
+    let mut bob = test_helpers::new_bob_with_ipv4_options(now, options);
+
+    let port = ip::Port::try_from(80).unwrap();
+    let alice_addr = ip::Endpoint::new(test_helpers::ALICE_IPV4, port);
+    let vip_addr = ip::Endpoint::new(vip, port);
+
+    // Bob can only bind to his own addresses.
+    let fd = bob.socket(Protocol::Udp);
+    must_let!(let Err(Fail::AddressNotAvailable {}) = bob.bind(fd, ip::Endpoint::new(test_helpers::CARRIE_IPV4, port)));
+
+    let bob_fd = bob.socket(Protocol::Udp);
+    bob.bind(bob_fd, vip_addr).unwrap();
+    let mut pop_future = bob.udp_pop(bob_fd);
+
+    let alice_fd = alice.socket(Protocol::Udp);
+    alice.bind(alice_fd, alice_addr).unwrap();
+    let buf = BytesMut::from(&b"hello"[..]).freeze();
+    let _push = alice.pushto(alice_fd, buf.clone(), vip_addr);
+    alice.rt().poll_scheduler();
+
+    // Bob answers ARP for the secondary address.
+    bob.receive(alice.rt().pop_frame()).unwrap();
+    alice.receive(bob.rt().pop_frame()).unwrap();
+    alice.rt().poll_scheduler();
+
+    bob.receive(alice.rt().pop_frame()).unwrap();
+    must_let!(let Poll::Ready(Ok((Some(remote), received))) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    assert_eq!(remote, alice_addr);
+    assert_eq!(received, buf);
+
+    // Replies from the bound socket come from the secondary address.
+    let _push = bob.pushto(bob_fd, buf.clone(), alice_addr);
+    let (_, ipv4_datagram) = Ethernet2Header::parse(bob.rt().pop_frame()).unwrap();
+    let (ipv4_hdr, _) = Ipv4Header::parse(ipv4_datagram).unwrap();
+    assert_eq!(ipv4_hdr.src_addr, vip);
+}
+
+#[test]
+fn udp_port_unreachable() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    let mut alice = test_helpers::new_alice(now);
+    let mut bob = test_helpers::new_bob(now);
+
+    let port = ip::Port::try_from(80).unwrap();
+    let alice_addr = ip::Endpoint::new(test_helpers::ALICE_IPV4, port);
+    let bob_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, port);
+
+    let alice_fd = alice.socket(Protocol::Udp);
+    alice.bind(alice_fd, alice_addr).unwrap();
+    let _connect = alice.connect(alice_fd, bob_addr);
+    alice
+        .udp_push(alice_fd, BytesMut::from(&b"hello"[..]).freeze())
+        .unwrap();
+
+    // Nothing is bound on Bob's side, so he answers with an ICMPv4 error.
+    must_let!(let Err(Fail::ConnectionRefused {}) = bob.receive(alice.rt().pop_frame()));
+    bob.rt().poll_scheduler();
+    let error = bob.rt().pop_frame();
+    let (_, ipv4_datagram) = Ethernet2Header::parse(error.clone()).unwrap();
+    let (ipv4_hdr, _) = Ipv4Header::parse(ipv4_datagram).unwrap();
+    assert_eq!(ipv4_hdr.protocol, Ipv4Protocol2::Icmpv4);
+    assert_eq!(ipv4_hdr.src_addr, test_helpers::BOB_IPV4);
+
+    // Alice's connected socket reports the error on its next pop.
+    alice.receive(error).unwrap();
+    let mut pop_future = alice.udp_pop(alice_fd);
+    must_let!(let Poll::Ready(Err(Fail::ConnectionRefused {})) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+}
+
+#[test]
+fn udp_port_unreachable_rate_limit() {
+    let now = Instant::now();
+
+    let mut alice = test_helpers::new_alice(now);
+    let mut bob = test_helpers::new_bob(now);
+
+    let port = ip::Port::try_from(80).unwrap();
+    let bob_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, port);
+    let alice_fd = alice.socket(Protocol::Udp);
+    alice
+        .bind(alice_fd, ip::Endpoint::new(test_helpers::ALICE_IPV4, port))
+        .unwrap();
+    let _connect = alice.connect(alice_fd, bob_addr);
+    let mut send = |bob: &mut Engine<TestRuntime>| {
+        alice
+            .udp_push(alice_fd, BytesMut::from(&b"hello"[..]).freeze())
+            .unwrap();
+        bob.receive(alice.rt().pop_frame())
+    };
+
+    // Bob answers a burst of datagrams to a closed port, and then stops until the bucket refills.
+    for _ in 0..50 {
+        must_let!(let Err(Fail::ConnectionRefused {}) = send(&mut bob));
+        bob.rt().pop_frame();
+    }
+    must_let!(let Err(Fail::Ignored { .. }) = send(&mut bob));
+    assert!(bob.rt().try_pop_frame().is_none());
+
+    bob.rt().advance_clock(now + Duration::from_millis(1));
+    must_let!(let Err(Fail::ConnectionRefused {}) = send(&mut bob));
+    bob.rt().pop_frame();
+    must_let!(let Err(Fail::Ignored { .. }) = send(&mut bob));
+    assert!(bob.rt().try_pop_frame().is_none());
+}
+
+#[test]
+fn udp_port_unreachable_to_unresolved_host() {
+    let now = Instant::now();
+    let mut bob = test_helpers::new_bob(now);
+
+    // A datagram from a host Bob can't reach without ARP doesn't get an error, or start a query.
+    let port = ip::Port::try_from(80).unwrap();
+    let datagram = UdpDatagram {
+        ethernet2_hdr: Ethernet2Header {
+            dst_addr: test_helpers::BOB_MAC,
+            src_addr: MacAddress::new([0x02, 0, 0, 0, 0, 0x09]),
+            vlan_tags: VlanTags::new(),
+            ether_type: EtherType2::Ipv4,
+        },
+        ip_hdr: ip::Header::V4(Ipv4Header::new(
+            Ipv4Addr::new(192, 168, 1, 9),
+            test_helpers::BOB_IPV4,
+            Ipv4Protocol2::Udp,
+        )),
+        udp_hdr: UdpHeader {
+            src_port: Some(port),
+            dst_port: port,
+        },
+        data: BytesMut::from(&b"hello"[..]).freeze(),
+    };
+    let mut frame = BytesMut::zeroed(datagram.compute_size());
+    datagram.serialize(&mut frame[..]);
+    must_let!(let Err(Fail::Ignored { .. }) = bob.receive(frame.freeze()));
+    bob.rt().poll_scheduler();
+    assert!(bob.rt().try_pop_frame().is_none());
+}
+
+#[test]
+fn udp_receive_buffer_limits() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    let mut alice = test_helpers::new_alice(now);
+    let mut bob = test_helpers::new_bob(now);
+
+    let port = ip::Port::try_from(80).unwrap();
+    let alice_addr = ip::Endpoint::new(test_helpers::ALICE_IPV4, port);
+    let bob_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, port);
+
+    let alice_fd = alice.socket(Protocol::Udp);
+    alice.bind(alice_fd, alice_addr).unwrap();
+    let _connect = alice.connect(alice_fd, bob_addr);
+    let bob_fd = bob.socket(Protocol::Udp);
+    bob.bind(bob_fd, bob_addr).unwrap();
+    must_let!(let Err(Fail::Invalid { .. }) = bob.setsockopt(bob_fd, SocketOption::ReceiveDatagramLimit(Some(0))));
+    bob.setsockopt(bob_fd, SocketOption::ReceiveDatagramLimit(Some(3)))
+        .unwrap();
+    bob.setsockopt(bob_fd, SocketOption::ReceiveBufferSize(Some(250)))
+        .unwrap();
+
+    let send = |alice: &mut test_helpers::TestEngine, bob: &mut test_helpers::TestEngine, len| {
+        let buf = BytesMut::from(&vec![0u8; len][..]).freeze();
+        alice.udp_push(alice_fd, buf).unwrap();
+        bob.receive(alice.rt().pop_frame())
+    };
+
+    // Datagrams past the byte limit are dropped, and so are ones past the datagram limit.
+    send(&mut alice, &mut bob, 100).unwrap();
+    send(&mut alice, &mut bob, 100).unwrap();
+    must_let!(let Err(Fail::ResourceExhausted { .. }) = send(&mut alice, &mut bob, 100));
+    send(&mut alice, &mut bob, 50).unwrap();
+    must_let!(let Err(Fail::ResourceExhausted { .. }) = send(&mut alice, &mut bob, 1));
+
+    let stats = bob.udp_socket_stats(bob_fd).unwrap();
+    assert_eq!(stats.datagrams_received, 3);
+    assert_eq!(stats.bytes_received, 250);
+    assert_eq!(stats.datagrams_dropped, 2);
+    assert_eq!(stats.bytes_dropped, 101);
+
+    // Popping makes room again.
+    let mut pop_future = bob.udp_pop(bob_fd);
+    must_let!(let Poll::Ready(Ok((_, buf))) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    assert_eq!(buf.len(), 100);
+    send(&mut alice, &mut bob, 100).unwrap();
+    assert_eq!(bob.udp_socket_stats(bob_fd).unwrap().datagrams_received, 4);
+}
+
+#[test]
+fn udp_connected_and_autobound_sockets() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    let mut alice = test_helpers::new_alice(now);
+    let mut bob = test_helpers::new_bob(now);
+    let mut carrie = test_helpers::new_carrie(now);
+
+    let port = ip::Port::try_from(80).unwrap();
+    let bob_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, port);
+    let bob_fd = bob.socket(Protocol::Udp);
+    bob.bind(bob_fd, bob_addr).unwrap();
+    let buf = BytesMut::from(&b"hello"[..]).freeze();
+
+    // Alice's socket can't pop until it's bound, which its first send does.
+    let alice_fd = alice.socket(Protocol::Udp);
+    let mut pop_future = alice.udp_pop(alice_fd);
+    must_let!(let Poll::Ready(Err(Fail::Invalid { .. })) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    let _push = alice.pushto(alice_fd, buf.clone(), bob_addr);
+    bob.receive(alice.rt().pop_frame()).unwrap();
+    let mut pop_future = bob.udp_pop(bob_fd);
+    must_let!(let Poll::Ready(Ok((Some(alice_addr), _))) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    assert_eq!(alice_addr.addr, test_helpers::ALICE_IPV4);
+    assert!(alice_addr.port.is_private());
+
+    let _push = bob.pushto(bob_fd, buf.clone(), alice_addr);
+    alice.receive(bob.rt().pop_frame()).unwrap();
+    let mut pop_future = alice.udp_pop(alice_fd);
+    must_let!(let Poll::Ready(Ok((Some(remote), _))) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    assert_eq!(remote, bob_addr);
+
+    // Once Bob's socket is connected to Alice, it ignores Carrie.
+    let _connect = bob.connect(bob_fd, alice_addr);
+    let carrie_fd = carrie.socket(Protocol::Udp);
+    let _push = carrie.pushto(carrie_fd, buf.clone(), bob_addr);
+    must_let!(let Err(Fail::Ignored { .. }) = bob.receive(carrie.rt().pop_frame()));
+
+    // A socket bound to the wildcard address gets her datagrams instead.
+    let wildcard_fd = bob.socket(Protocol::Udp);
+    bob.bind(wildcard_fd, ip::Endpoint::new(Ipv4Addr::UNSPECIFIED, port))
+        .unwrap();
+    let _push = carrie.pushto(carrie_fd, buf.clone(), bob_addr);
+    bob.receive(carrie.rt().pop_frame()).unwrap();
+    let _push = alice.pushto(alice_fd, buf, bob_addr);
+    bob.receive(alice.rt().pop_frame()).unwrap();
+    let stats = bob.udp_socket_stats(wildcard_fd).unwrap();
+    assert_eq!(stats.datagrams_received, 1);
+    let stats = bob.udp_socket_stats(bob_fd).unwrap();
+    assert_eq!(stats.datagrams_received, 2);
+}
+
+#[test]
+fn tcp_connect_port_unreachable() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    let mut alice = test_helpers::new_alice(now);
+    let bob_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, ip::Port::try_from(80).unwrap());
+    let alice_fd = alice.tcp_socket();
+    let mut connect_future = alice.tcp_connect(alice_fd, bob_addr);
+    alice.rt().poll_scheduler();
+
+    // Answer the SYN with a port unreachable error quoting its first 8 bytes.
+    let (_, syn) = Ethernet2Header::parse(alice.rt().pop_frame()).unwrap();
+    let (syn_hdr, _) = Ipv4Header::parse(syn.clone()).unwrap();
+    let (quote, _) = syn.split(syn_hdr.compute_size() + 8);
+    let msg = Icmpv4Message {
+        ethernet2_hdr: Ethernet2Header {
+            dst_addr: test_helpers::ALICE_MAC,
+            src_addr: test_helpers::BOB_MAC,
+            vlan_tags: VlanTags::new(),
+            ether_type: EtherType2::Ipv4,
+        },
+        ipv4_hdr: Ipv4Header::new(
+            test_helpers::BOB_IPV4,
+            test_helpers::ALICE_IPV4,
+            Ipv4Protocol2::Icmpv4,
+        ),
+        icmpv4_hdr: Icmpv4Header {
+            icmpv4_type: Icmpv4Type2::DestinationUnreachable,
+            code: ICMPV4_PORT_UNREACHABLE,
+        },
+        data: quote,
+    };
+    let mut frame = BytesMut::zeroed(msg.compute_size());
+    msg.serialize(&mut frame[..]);
+
+    alice.receive(frame.freeze()).unwrap();
+    must_let!(let Poll::Ready(Err(Fail::ConnectionRefused {})) = Future::poll(Pin::new(&mut connect_future), &mut ctx));
+}
+
+#[test]
+fn ping() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    let mut alice = test_helpers::new_alice(now);
+    let mut bob = test_helpers::new_bob(now);
+
+    let mut ping_future = alice.ping(test_helpers::BOB_IPV4, 100, None).boxed_local();
+    assert!(Future::poll(Pin::new(&mut ping_future), &mut ctx).is_pending());
+
+    let request = alice.rt().pop_frame();
+    bob.receive(request).unwrap();
+    bob.rt().poll_scheduler();
+    let reply = bob.rt().pop_frame();
+    let (_, ipv4_datagram) = Ethernet2Header::parse(reply.clone()).unwrap();
+    let (_, icmpv4_datagram) = Ipv4Header::parse(ipv4_datagram).unwrap();
+    let (icmpv4_hdr, data) = Icmpv4Header::parse(icmpv4_datagram).unwrap();
+    must_let!(let Icmpv4Type2::EchoReply { .. } = icmpv4_hdr.icmpv4_type);
+    assert_eq!(data.len(), 100);
+
+    alice.receive(reply).unwrap();
+    must_let!(let Poll::Ready(Ok(..)) = Future::poll(Pin::new(&mut ping_future), &mut ctx));
+}
+
+fn echo_request(src_link_addr: MacAddress, src_addr: Ipv4Addr, seq_num: u16) -> Bytes {
+    let msg = Icmpv4Message {
+        ethernet2_hdr: Ethernet2Header {
+            dst_addr: test_helpers::BOB_MAC,
+            src_addr: src_link_addr,
+            vlan_tags: VlanTags::new(),
+            ether_type: EtherType2::Ipv4,
+        },
+        ipv4_hdr: Ipv4Header::new(src_addr, test_helpers::BOB_IPV4, Ipv4Protocol2::Icmpv4),
+        icmpv4_hdr: Icmpv4Header {
+            icmpv4_type: Icmpv4Type2::EchoRequest { id: 1, seq_num },
+            code: 0,
+        },
+        data: Bytes::empty(),
+    };
+    let mut frame = BytesMut::zeroed(msg.compute_size());
+    msg.serialize(&mut frame[..]);
+    frame.freeze()
+}
+
+#[test]
+fn ping_replies_to_unresolved_hosts_dont_wait_in_line() {
+    let now = Instant::now();
+    let mut bob = test_helpers::new_bob(now);
+    let stranger_mac = MacAddress::new([0x02, 0, 0, 0, 0, 0x09]);
+    let stranger_addr = Ipv4Addr::new(192, 168, 1, 9);
+
+    // Bob doesn't know the stranger's link address, so his reply waits for ARP...
+    bob.receive(echo_request(stranger_mac, stranger_addr, 0))
+        .unwrap();
+    // ...without holding up the reply to Alice.
+    bob.receive(echo_request(
+        test_helpers::ALICE_MAC,
+        test_helpers::ALICE_IPV4,
+        1,
+    ))
+    .unwrap();
+    let (eth_hdr, ipv4_datagram) = Ethernet2Header::parse(bob.rt().pop_frame()).unwrap();
+    assert_eq!(eth_hdr.dst_addr, test_helpers::ALICE_MAC);
+    let (ipv4_hdr, _) = Ipv4Header::parse(ipv4_datagram).unwrap();
+    assert_eq!(ipv4_hdr.dst_addr, test_helpers::ALICE_IPV4);
+
+    bob.rt().poll_scheduler();
+    let (eth_hdr, _) = Ethernet2Header::parse(bob.rt().pop_frame()).unwrap();
+    assert_eq!(eth_hdr.ether_type, EtherType2::Arp);
+    assert!(bob.rt().try_pop_frame().is_none());
+}
+
+#[test]
+fn ping_dropped_before_reply() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    let mut alice = test_helpers::new_alice(now);
+    let mut bob = test_helpers::new_bob(now);
+
+    // Alice forgets about a ping she stopped waiting for, so its reply changes nothing...
+    let mut ping_future = alice.ping(test_helpers::BOB_IPV4, 0, None).boxed_local();
+    assert!(Future::poll(Pin::new(&mut ping_future), &mut ctx).is_pending());
+    drop(ping_future);
+    bob.receive(alice.rt().pop_frame()).unwrap();
+    alice.receive(bob.rt().pop_frame()).unwrap();
+
+    // ...and the next one still gets its own.
+    let mut ping_future = alice.ping(test_helpers::BOB_IPV4, 0, None).boxed_local();
+    assert!(Future::poll(Pin::new(&mut ping_future), &mut ctx).is_pending());
+    bob.receive(alice.rt().pop_frame()).unwrap();
+    alice.receive(bob.rt().pop_frame()).unwrap();
+    must_let!(let Poll::Ready(Ok(..)) = Future::poll(Pin::new(&mut ping_future), &mut ctx));
+}
+
+#[test]
+fn ping_too_large() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    let mut alice = test_helpers::new_alice(now);
+
+    // Payloads that can't fit in an IPv4 datagram fail without sending anything.
+    let payload_size = icmpv4::MAX_PING_PAYLOAD_SIZE + 1;
+    let mut ping_future = alice
+        .ping(test_helpers::BOB_IPV4, payload_size, None)
+        .boxed_local();
+    must_let!(let Poll::Ready(Err(Fail::OutOfRange { .. })) = Future::poll(Pin::new(&mut ping_future), &mut ctx));
+    assert!(alice.rt().try_pop_frame().is_none());
+}
+
+#[test]
+fn traceroute() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    let mut alice = test_helpers::new_alice(now);
+    let mut bob = test_helpers::new_bob(now);
+    let router_addr = Ipv4Addr::new(192, 168, 1, 254);
+
+    let mut traceroute_future = alice
+        .traceroute(test_helpers::BOB_IPV4, 4, None)
+        .boxed_local();
+    assert!(Future::poll(Pin::new(&mut traceroute_future), &mut ctx).is_pending());
+
+    // Pretend a router dropped the first probe.
+    let (_, probe) = Ethernet2Header::parse(alice.rt().pop_frame()).unwrap();
+    let (probe_hdr, _) = Ipv4Header::parse(probe.clone()).unwrap();
+    assert_eq!(probe_hdr.time_to_live, 1);
+    let (quote, _) = probe.split(probe_hdr.compute_size() + 8);
+    let msg = Icmpv4Message {
+        ethernet2_hdr: Ethernet2Header {
+            dst_addr: test_helpers::ALICE_MAC,
+            src_addr: test_helpers::BOB_MAC,
+            vlan_tags: VlanTags::new(),
+            ether_type: EtherType2::Ipv4,
+        },
+        ipv4_hdr: Ipv4Header::new(router_addr, test_helpers::ALICE_IPV4, Ipv4Protocol2::Icmpv4),
+        icmpv4_hdr: Icmpv4Header {
+            icmpv4_type: Icmpv4Type2::TimeExceeded,
+            code: 0,
+        },
+        data: quote,
+    };
+    let mut frame = BytesMut::zeroed(msg.compute_size());
+    msg.serialize(&mut frame[..]);
+    alice.receive(frame.freeze()).unwrap();
+    assert!(Future::poll(Pin::new(&mut traceroute_future), &mut ctx).is_pending());
+
+    // The second probe makes it to Bob.
+    let probe = alice.rt().pop_frame();
+    let (_, ipv4_datagram) = Ethernet2Header::parse(probe.clone()).unwrap();
+    let (probe_hdr, _) = Ipv4Header::parse(ipv4_datagram).unwrap();
+    assert_eq!(probe_hdr.time_to_live, 2);
+    bob.receive(probe).unwrap();
+    bob.rt().poll_scheduler();
+    alice.receive(bob.rt().pop_frame()).unwrap();
+
+    must_let!(let Poll::Ready(Ok(hops)) = Future::poll(Pin::new(&mut traceroute_future), &mut ctx));
+    let hops: Vec<_> = hops.into_iter().map(|hop| hop.unwrap().addr).collect();
+    assert_eq!(hops, vec![router_addr, test_helpers::BOB_IPV4]);
+}
+
+#[test]
+fn default_ttl_and_identification() {
+    let now = Instant::now();
+    let mut alice = test_helpers::new_alice(now);
+
+    let port = ip::Port::try_from(80).unwrap();
+    let alice_addr = ip::Endpoint::new(test_helpers::ALICE_IPV4, port);
+    let bob_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, port);
+
+    let alice_fd = alice.socket(Protocol::Udp);
+    alice.bind(alice_fd, alice_addr).unwrap();
+    let _connect = alice.connect(alice_fd, bob_addr);
+
+    let send = |alice: &mut test_helpers::TestEngine| {
+        let buf = BytesMut::from(&b"hello"[..]).freeze();
+        alice.udp_push(alice_fd, buf).unwrap();
+        let (_, ipv4_datagram) = Ethernet2Header::parse(alice.rt().pop_frame()).unwrap();
+        let (ipv4_hdr, _) = Ipv4Header::parse(ipv4_datagram).unwrap();
+        ipv4_hdr
+    };
+    let first = send(&mut alice);
+    let second = send(&mut alice);
+    assert_eq!(first.time_to_live, ipv4::datagram::DEFAULT_IPV4_TTL);
+    assert_eq!(first.dscp, 0);
+    assert_ne!(first.identification, second.identification);
+
+    // Per-socket settings override the defaults.
+    alice
+        .setsockopt(alice_fd, SocketOption::TimeToLive(Some(3)))
+        .unwrap();
+    alice
+        .setsockopt(alice_fd, SocketOption::Dscp(0x2e))
+        .unwrap();
+    let third = send(&mut alice);
+    assert_eq!(third.time_to_live, 3);
+    assert_eq!(third.dscp, 0x2e);
+
+    // A destination we keep sending to keeps its counter past the reassembly timeout.
+    let timeout = alice.rt().ipv4_options().reassembly_timeout;
+    let mut previous = third.identification;
+    for i in 1..=3 {
+        alice.rt().advance_clock(now + timeout * 2 * i / 3);
+        let next = send(&mut alice);
+        assert_eq!(next.identification, previous.wrapping_add(1));
+        previous = next.identification;
+    }
+}
@@ -0,0 +1,153 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use crate::{
+    fail::Fail,
+    sync::Bytes,
+};
+use byteorder::{
+    ByteOrder,
+    NetworkEndian,
+};
+use num_traits::FromPrimitive;
+use std::{
+    convert::{
+        TryFrom,
+        TryInto,
+    },
+    net::Ipv6Addr,
+};
+
+pub const IPV6_HEADER_SIZE: usize = 40;
+pub const IPV6_VERSION: u8 = 6;
+
+// RFC 8200 recommends using the value from the IANA registry, which is 64.
+pub const DEFAULT_IPV6_HOP_LIMIT: u8 = 64;
+
+#[repr(u8)]
+#[derive(FromPrimitive, Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Ipv6NextHeader {
+    Tcp = 0x06,
+    Udp = 0x11,
+    Icmpv6 = 0x3a,
+}
+
+impl TryFrom<u8> for Ipv6NextHeader {
+    type Error = Fail;
+
+    fn try_from(n: u8) -> Result<Self, Fail> {
+        match FromPrimitive::from_u8(n) {
+            Some(n) => Ok(n),
+            None => Err(Fail::Unsupported {
+                details: "Unsupported IPv6 next header",
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Ipv6Header {
+    pub traffic_class: u8,
+    // Only the low 20 bits are used.
+    pub flow_label: u32,
+
+    // Omit the payload length since it's generated on serialization.
+    // pub payload_length: u16,
+    pub next_header: Ipv6NextHeader,
+    pub hop_limit: u8,
+
+    pub src_addr: Ipv6Addr,
+    pub dst_addr: Ipv6Addr,
+}
+
+impl Ipv6Header {
+    pub fn new(src_addr: Ipv6Addr, dst_addr: Ipv6Addr, next_header: Ipv6NextHeader) -> Self {
+        Self {
+            traffic_class: 0,
+            flow_label: 0,
+            next_header,
+            hop_limit: DEFAULT_IPV6_HOP_LIMIT,
+            src_addr,
+            dst_addr,
+        }
+    }
+
+    pub fn compute_size(&self) -> usize {
+        IPV6_HEADER_SIZE
+    }
+
+    /// Sums the 16-bit words of the pseudo header that upper-layer checksums cover (RFC 8200
+    /// section 8.1), for `len` bytes of upper-layer data.
+    pub fn pseudo_header_sum(&self, len: usize) -> u32 {
+        let mut state = 0u32;
+        for segment in self.src_addr.segments().iter() {
+            state += *segment as u32;
+        }
+        for segment in self.dst_addr.segments().iter() {
+            state += *segment as u32;
+        }
+        state += (len >> 16) as u32;
+        state += (len & 0xffff) as u32;
+        state += self.next_header as u32;
+        state
+    }
+
+    pub fn parse(buf: Bytes) -> Result<(Self, Bytes), Fail> {
+        if buf.len() < IPV6_HEADER_SIZE {
+            return Err(Fail::Malformed {
+                details: "Datagram too small",
+            });
+        }
+        let (hdr_buf, payload_buf) = buf.split(IPV6_HEADER_SIZE);
+        let hdr_buf: &[u8; IPV6_HEADER_SIZE] = (&hdr_buf[..]).try_into().unwrap();
+
+        let version = hdr_buf[0] >> 4;
+        if version != IPV6_VERSION {
+            return Err(Fail::Unsupported {
+                details: "Unsupported IP version",
+            });
+        }
+        let traffic_class = (NetworkEndian::read_u16(&hdr_buf[0..2]) >> 4) as u8;
+        let flow_label = NetworkEndian::read_u32(&hdr_buf[0..4]) & 0xfffff;
+
+        let payload_length = NetworkEndian::read_u16(&hdr_buf[4..6]) as usize;
+        if payload_length > payload_buf.len() {
+            return Err(Fail::Malformed {
+                details: "IPv6 payload length greater than payload",
+            });
+        }
+        // As with IPv4, small frames may carry Ethernet padding after the payload.
+        let (payload_buf, _padding) = payload_buf.split(payload_length);
+
+        let next_header = Ipv6NextHeader::try_from(hdr_buf[6])?;
+        let hop_limit = hdr_buf[7];
+
+        let src_octets: [u8; 16] = hdr_buf[8..24].try_into().unwrap();
+        let dst_octets: [u8; 16] = hdr_buf[24..40].try_into().unwrap();
+
+        let header = Self {
+            traffic_class,
+            flow_label,
+            next_header,
+            hop_limit,
+            src_addr: Ipv6Addr::from(src_octets),
+            dst_addr: Ipv6Addr::from(dst_octets),
+        };
+        Ok((header, payload_buf))
+    }
+
+    pub fn serialize(&self, buf: &mut [u8], payload_len: usize) {
+        let buf: &mut [u8; IPV6_HEADER_SIZE] = (&mut buf[..IPV6_HEADER_SIZE]).try_into().unwrap();
+        NetworkEndian::write_u32(
+            &mut buf[0..4],
+            (IPV6_VERSION as u32) << 28
+                | (self.traffic_class as u32) << 20
+                | self.flow_label & 0xfffff,
+        );
+        NetworkEndian::write_u16(&mut buf[4..6], payload_len as u16);
+        buf[6] = self.next_header as u8;
+        buf[7] = self.hop_limit;
+        buf[8..24].copy_from_slice(&self.src_addr.octets());
+        buf[24..40].copy_from_slice(&self.dst_addr.octets());
+    }
+}
@@ -0,0 +1,14 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+pub mod datagram;
+mod options;
+mod peer;
+pub mod routing;
+
+#[cfg(test)]
+mod tests;
+
+pub use options::Ipv6Options as Options;
+pub use peer::Ipv6Peer as Peer;
+pub use routing::RoutingTable;
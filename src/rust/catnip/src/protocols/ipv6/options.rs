@@ -0,0 +1,68 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::routing::is_link_local;
+use std::{
+    net::Ipv6Addr,
+    time::Duration,
+};
+
+#[derive(Clone, Debug)]
+pub struct Ipv6Options {
+    // Addresses besides the link-local one, each with the prefix length of its subnet.
+    pub addrs: Vec<(Ipv6Addr, u8)>,
+    pub default_gateway: Option<Ipv6Addr>,
+
+    pub neighbor_cache_ttl: Duration,
+    pub solicitation_timeout: Duration,
+    pub solicitation_retries: usize,
+}
+
+impl Default for Ipv6Options {
+    fn default() -> Self {
+        // Timers are from RFC 4861 section 10.
+        Ipv6Options {
+            addrs: vec![],
+            default_gateway: None,
+            neighbor_cache_ttl: Duration::from_secs(30),
+            solicitation_timeout: Duration::from_secs(1),
+            solicitation_retries: 3,
+        }
+    }
+}
+
+impl Ipv6Options {
+    pub fn addr(mut self, addr: Ipv6Addr, prefix_len: u8) -> Self {
+        assert!(
+            !addr.is_unspecified()
+                && !addr.is_multicast()
+                && !is_link_local(addr)
+                && prefix_len <= 128
+        );
+        self.addrs.push((addr, prefix_len));
+        self
+    }
+
+    pub fn default_gateway(mut self, value: Ipv6Addr) -> Self {
+        self.default_gateway = Some(value);
+        self
+    }
+
+    pub fn neighbor_cache_ttl(mut self, value: Duration) -> Self {
+        assert!(value > Duration::new(0, 0));
+        self.neighbor_cache_ttl = value;
+        self
+    }
+
+    pub fn solicitation_timeout(mut self, value: Duration) -> Self {
+        assert!(value > Duration::new(0, 0));
+        self.solicitation_timeout = value;
+        self
+    }
+
+    pub fn solicitation_retries(mut self, value: usize) -> Self {
+        assert!(value > 0);
+        self.solicitation_retries = value;
+        self
+    }
+}
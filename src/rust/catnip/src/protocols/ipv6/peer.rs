@@ -0,0 +1,76 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::{
+    datagram::{
+        Ipv6Header,
+        Ipv6NextHeader,
+    },
+    routing::RoutingTable,
+};
+#[cfg(test)]
+use crate::protocols::ethernet2::MacAddress;
+use crate::{
+    fail::Fail,
+    protocols::{
+        icmpv6,
+        ip,
+        tcp,
+        udp,
+    },
+    runtime::Runtime,
+    sync::Bytes,
+};
+#[cfg(test)]
+use hashbrown::HashMap;
+#[cfg(test)]
+use std::net::Ipv6Addr;
+use std::rc::Rc;
+
+/// Delivers IPv6 datagrams to ICMPv6 and to the TCP and UDP peers shared with IPv4.
+pub struct Ipv6Peer<RT: Runtime> {
+    routes: Rc<RoutingTable>,
+    icmpv6: icmpv6::Peer<RT>,
+    tcp: tcp::Peer<RT>,
+    udp: udp::Peer<RT>,
+}
+
+impl<RT: Runtime> Ipv6Peer<RT> {
+    pub fn new(
+        routes: Rc<RoutingTable>,
+        icmpv6: icmpv6::Peer<RT>,
+        tcp: tcp::Peer<RT>,
+        udp: udp::Peer<RT>,
+    ) -> Ipv6Peer<RT> {
+        Ipv6Peer {
+            routes,
+            icmpv6,
+            tcp,
+            udp,
+        }
+    }
+
+    pub fn receive(&mut self, buf: Bytes) -> Result<(), Fail> {
+        let (header, payload) = Ipv6Header::parse(buf)?;
+        let dst_addr = header.dst_addr;
+        if !self.routes.is_local(dst_addr) && !self.routes.is_subscribed(dst_addr) {
+            return Err(Fail::Misdelivered {});
+        }
+        match header.next_header {
+            Ipv6NextHeader::Icmpv6 => self.icmpv6.receive(&header, payload),
+            // Only ICMPv6 is meaningful for the multicast groups we're in.
+            _ if dst_addr.is_multicast() => Err(Fail::Ignored {
+                details: "Multicast transport datagram",
+            }),
+            Ipv6NextHeader::Tcp => self.tcp.receive(&ip::Header::V6(header), payload),
+            Ipv6NextHeader::Udp => self.udp.receive(&ip::Header::V6(header), payload),
+        }
+    }
+}
+
+#[cfg(test)]
+impl<RT: Runtime> Ipv6Peer<RT> {
+    pub fn export_neighbor_cache(&self) -> HashMap<Ipv6Addr, MacAddress> {
+        self.icmpv6.export_cache()
+    }
+}
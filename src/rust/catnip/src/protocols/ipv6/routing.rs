@@ -0,0 +1,148 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::options::Ipv6Options;
+use crate::protocols::ethernet2::MacAddress;
+use std::net::Ipv6Addr;
+
+pub const ALL_NODES_ADDR: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
+fn prefix_mask(prefix_len: u8) -> u128 {
+    match prefix_len {
+        0 => 0,
+        n => !0u128 << (128 - n as u32),
+    }
+}
+
+fn prefix_matches(prefix: Ipv6Addr, prefix_len: u8, addr: Ipv6Addr) -> bool {
+    let mask = prefix_mask(prefix_len);
+    u128::from(addr) & mask == u128::from(prefix) & mask
+}
+
+/// Whether `addr` is in fe80::/10.
+pub fn is_link_local(addr: Ipv6Addr) -> bool {
+    addr.segments()[0] & 0xffc0 == 0xfe80
+}
+
+/// The link-local address with an interface identifier derived from `link_addr` (RFC 4291
+/// appendix A).
+pub fn link_local_addr(link_addr: MacAddress) -> Ipv6Addr {
+    let mac = link_addr.octets();
+    Ipv6Addr::from([
+        0xfe,
+        0x80,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        mac[0] ^ 0x02,
+        mac[1],
+        mac[2],
+        0xff,
+        0xfe,
+        mac[3],
+        mac[4],
+        mac[5],
+    ])
+}
+
+/// The multicast group that Neighbor Solicitations for `addr` are sent to (RFC 4291 section
+/// 2.7.1).
+pub fn solicited_node_addr(addr: Ipv6Addr) -> Ipv6Addr {
+    let octets = addr.octets();
+    Ipv6Addr::from([
+        0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xff, octets[13], octets[14], octets[15],
+    ])
+}
+
+/// The Ethernet address a multicast datagram to `addr` is sent to (RFC 2464 section 7).
+pub fn multicast_link_addr(addr: Ipv6Addr) -> MacAddress {
+    let octets = addr.octets();
+    MacAddress::new([0x33, 0x33, octets[12], octets[13], octets[14], octets[15]])
+}
+
+/// The host's IPv6 addresses and how to reach other hosts. Besides the configured addresses,
+/// every host has a link-local address derived from its link address.
+#[derive(Clone, Debug)]
+pub struct RoutingTable {
+    // Each local address with the prefix length of its subnet, link-local address first.
+    local_addrs: Vec<(Ipv6Addr, u8)>,
+    default_gateway: Option<Ipv6Addr>,
+}
+
+impl RoutingTable {
+    pub fn new(link_addr: MacAddress, options: &Ipv6Options) -> Self {
+        let mut local_addrs = vec![(link_local_addr(link_addr), 64)];
+        for &(addr, prefix_len) in &options.addrs {
+            if local_addrs.iter().all(|&(a, _)| a != addr) {
+                local_addrs.push((addr, prefix_len));
+            }
+        }
+        Self {
+            local_addrs,
+            default_gateway: options.default_gateway,
+        }
+    }
+
+    pub fn link_local_addr(&self) -> Ipv6Addr {
+        self.local_addrs[0].0
+    }
+
+    /// The first configured address, or the link-local one if there aren't any.
+    pub fn primary_addr(&self) -> Ipv6Addr {
+        self.local_addrs.get(1).unwrap_or(&self.local_addrs[0]).0
+    }
+
+    pub fn is_local(&self, addr: Ipv6Addr) -> bool {
+        self.local_addrs.iter().any(|&(a, _)| a == addr)
+    }
+
+    pub fn local_addrs(&self) -> impl Iterator<Item = Ipv6Addr> + '_ {
+        self.local_addrs.iter().map(|&(a, _)| a)
+    }
+
+    /// Whether datagrams sent to the multicast group `addr` are for us. We only listen to the
+    /// all-nodes group and the solicited-node groups of our addresses.
+    pub fn is_subscribed(&self, addr: Ipv6Addr) -> bool {
+        addr == ALL_NODES_ADDR || self.local_addrs().any(|a| solicited_node_addr(a) == addr)
+    }
+
+    /// Picks the address whose link address a datagram to `dst_addr` should be sent to.
+    /// Destinations outside our subnets go to the default gateway, or are assumed to be on-link
+    /// if there isn't one.
+    pub fn next_hop(&self, dst_addr: Ipv6Addr) -> Ipv6Addr {
+        if dst_addr.is_multicast() || is_link_local(dst_addr) {
+            return dst_addr;
+        }
+        let on_link = self.local_addrs[1..]
+            .iter()
+            .any(|&(a, prefix_len)| prefix_matches(a, prefix_len, dst_addr));
+        if on_link {
+            return dst_addr;
+        }
+        self.default_gateway.unwrap_or(dst_addr)
+    }
+
+    /// Picks the local address to send from when a socket doesn't specify one: the link-local
+    /// address for link-scoped destinations, otherwise the address whose subnet most specifically
+    /// contains the destination, falling back to the first configured address.
+    pub fn source_addr(&self, dst_addr: Ipv6Addr) -> Ipv6Addr {
+        if self.is_local(dst_addr) {
+            return dst_addr;
+        }
+        let globals = &self.local_addrs[1..];
+        if is_link_local(dst_addr) || dst_addr.is_multicast() || globals.is_empty() {
+            return self.link_local_addr();
+        }
+        // Reverse so that `max_by_key` prefers the earlier address on ties.
+        globals
+            .iter()
+            .rev()
+            .filter(|&&(a, prefix_len)| prefix_matches(a, prefix_len, dst_addr))
+            .max_by_key(|&&(_, prefix_len)| prefix_len)
+            .unwrap_or(&globals[0])
+            .0
+    }
+}
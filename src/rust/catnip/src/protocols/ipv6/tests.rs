@@ -0,0 +1,156 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::{
+    datagram::{
+        Ipv6Header,
+        Ipv6NextHeader,
+        IPV6_HEADER_SIZE,
+    },
+    routing::{
+        link_local_addr,
+        solicited_node_addr,
+        RoutingTable,
+    },
+};
+use crate::{
+    engine::Protocol,
+    protocols::{
+        ethernet2::frame::{
+            EtherType2,
+            Ethernet2Header,
+        },
+        ip,
+        ipv6,
+    },
+    sync::BytesMut,
+    test_helpers,
+};
+use futures::task::noop_waker_ref;
+use must_let::must_let;
+use std::{
+    convert::TryFrom,
+    future::Future,
+    net::Ipv6Addr,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+    time::Instant,
+};
+
+#[test]
+fn header_round_trip() {
+    let mut header = Ipv6Header::new(
+        test_helpers::ALICE_IPV6,
+        test_helpers::BOB_IPV6,
+        Ipv6NextHeader::Udp,
+    );
+    header.traffic_class = 0xb8;
+    header.flow_label = 0x12345;
+
+    // Leave room for Ethernet padding, which shouldn't end up in the payload.
+    let payload = [0xab; 10];
+    let mut buf = BytesMut::zeroed(IPV6_HEADER_SIZE + payload.len() + 6);
+    header.serialize(&mut buf[..IPV6_HEADER_SIZE], payload.len());
+    buf[IPV6_HEADER_SIZE..(IPV6_HEADER_SIZE + payload.len())].copy_from_slice(&payload[..]);
+    assert_eq!(buf[0] >> 4, 6);
+
+    let (parsed, parsed_payload) = Ipv6Header::parse(buf.freeze()).unwrap();
+    assert_eq!(parsed.traffic_class, 0xb8);
+    assert_eq!(parsed.flow_label, 0x12345);
+    assert_eq!(parsed.next_header, Ipv6NextHeader::Udp);
+    assert_eq!(parsed.src_addr, test_helpers::ALICE_IPV6);
+    assert_eq!(parsed.dst_addr, test_helpers::BOB_IPV6);
+    assert_eq!(&parsed_payload[..], &payload[..]);
+}
+
+#[test]
+fn routing() {
+    let gateway = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+    let options = ipv6::Options::default()
+        .addr(test_helpers::ALICE_IPV6, 64)
+        .default_gateway(gateway);
+    let table = RoutingTable::new(test_helpers::ALICE_MAC, &options);
+
+    // The link-local address is derived from the MAC address.
+    let link_local = link_local_addr(test_helpers::ALICE_MAC);
+    assert_eq!(
+        link_local,
+        Ipv6Addr::new(0xfe80, 0, 0, 0, 0x1023, 0x45ff, 0xfe67, 0x89ab)
+    );
+    assert!(table.is_local(link_local));
+    assert!(table.is_subscribed(solicited_node_addr(test_helpers::ALICE_IPV6)));
+    assert!(!table.is_subscribed(solicited_node_addr(test_helpers::BOB_IPV6)));
+
+    // On-link destinations are reached directly, everything else through the gateway.
+    assert_eq!(
+        table.next_hop(test_helpers::BOB_IPV6),
+        test_helpers::BOB_IPV6
+    );
+    let remote = Ipv6Addr::new(0x2001, 0xdb9, 0, 0, 0, 0, 0, 1);
+    assert_eq!(table.next_hop(remote), gateway);
+    assert_eq!(table.source_addr(remote), test_helpers::ALICE_IPV6);
+    assert_eq!(table.source_addr(gateway), link_local);
+}
+
+#[test]
+fn udp_with_neighbor_discovery() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    let mut alice = test_helpers::new_alice_with_ipv6_options(
+        now,
+        ipv6::Options::default().addr(test_helpers::ALICE_IPV6, 64),
+    );
+    let mut bob = test_helpers::new_bob_with_ipv6_options(
+        now,
+        ipv6::Options::default().addr(test_helpers::BOB_IPV6, 64),
+    );
+
+    let port = ip::Port::try_from(80).unwrap();
+    let alice_addr = ip::Endpoint::new(test_helpers::ALICE_IPV6, port);
+    let bob_addr = ip::Endpoint::new(test_helpers::BOB_IPV6, port);
+
+    let bob_fd = bob.socket(Protocol::Udp);
+    bob.bind(bob_fd, bob_addr).unwrap();
+    let mut pop_future = bob.udp_pop(bob_fd);
+
+    let alice_fd = alice.socket(Protocol::Udp);
+    alice.bind(alice_fd, alice_addr).unwrap();
+    let buf = BytesMut::from(&b"hello"[..]).freeze();
+    let _push = alice.pushto(alice_fd, buf.clone(), bob_addr);
+    alice.rt().poll_scheduler();
+
+    // Alice solicits Bob's link address through his solicited-node group.
+    let solicitation = alice.rt().pop_frame();
+    let (ethernet2_hdr, _) = Ethernet2Header::parse(solicitation.clone()).unwrap();
+    assert_eq!(ethernet2_hdr.ether_type, EtherType2::Ipv6);
+    assert!(ethernet2_hdr.dst_addr.is_multicast());
+    bob.receive(solicitation).unwrap();
+    assert_eq!(
+        bob.export_neighbor_cache().get(&test_helpers::ALICE_IPV6),
+        Some(&test_helpers::ALICE_MAC)
+    );
+
+    alice.receive(bob.rt().pop_frame()).unwrap();
+    assert_eq!(
+        alice.export_neighbor_cache().get(&test_helpers::BOB_IPV6),
+        Some(&test_helpers::BOB_MAC)
+    );
+    alice.rt().poll_scheduler();
+
+    bob.receive(alice.rt().pop_frame()).unwrap();
+    must_let!(let Poll::Ready(Ok((Some(remote), received))) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    assert_eq!(remote, alice_addr);
+    assert_eq!(received, buf);
+
+    // Bob already knows Alice, so his reply goes out right away.
+    let _push = bob.pushto(bob_fd, buf.clone(), alice_addr);
+    let mut pop_future = alice.udp_pop(alice_fd);
+    alice.receive(bob.rt().pop_frame()).unwrap();
+    must_let!(let Poll::Ready(Ok((Some(remote), received))) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    assert_eq!(remote, bob_addr);
+    assert_eq!(received, buf);
+}
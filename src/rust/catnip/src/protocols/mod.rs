@@ -2,9 +2,14 @@
 // Licensed under the MIT license.
 
 pub mod arp;
+pub mod dhcp;
+pub mod dns;
 pub mod ethernet2;
 pub mod icmpv4;
+pub mod icmpv6;
+pub mod igmp;
 pub mod ip;
 pub mod ipv4;
+pub mod ipv6;
 pub mod tcp;
 pub mod udp;
@@ -9,16 +9,8 @@ use super::{
 use crate::{
     fail::Fail,
     protocols::{
-        arp,
-        ethernet2::frame::{
-            EtherType2,
-            Ethernet2Header,
-        },
-        ipv4,
-        ipv4::datagram::{
-            Ipv4Header,
-            Ipv4Protocol2,
-        },
+        ethernet2::frame::Ethernet2Header,
+        ip,
         tcp::{
             segment::{
                 TcpHeader,
@@ -30,10 +22,12 @@ use crate::{
     },
     runtime::Runtime,
     scheduler::SchedulerHandle,
+    socket_options::SocketOptions,
     sync::Bytes,
 };
 use std::{
     cell::RefCell,
+    cmp,
     convert::TryInto,
     future::Future,
     num::Wrapping,
@@ -54,11 +48,12 @@ struct ConnectResult<RT: Runtime> {
 pub struct ActiveOpenSocket<RT: Runtime> {
     local_isn: SeqNumber,
 
-    local: ipv4::Endpoint,
-    remote: ipv4::Endpoint,
+    local: ip::Endpoint,
+    remote: ip::Endpoint,
+    options: SocketOptions,
 
     rt: RT,
-    arp: arp::Peer<RT>,
+    network: ip::Network<RT>,
 
     #[allow(unused)]
     handle: SchedulerHandle,
@@ -68,10 +63,11 @@ pub struct ActiveOpenSocket<RT: Runtime> {
 impl<RT: Runtime> ActiveOpenSocket<RT> {
     pub fn new(
         local_isn: SeqNumber,
-        local: ipv4::Endpoint,
-        remote: ipv4::Endpoint,
+        local: ip::Endpoint,
+        remote: ip::Endpoint,
+        options: SocketOptions,
         rt: RT,
-        arp: arp::Peer<RT>,
+        network: ip::Network<RT>,
     ) -> Self {
         let result = ConnectResult {
             waker: None,
@@ -83,8 +79,9 @@ impl<RT: Runtime> ActiveOpenSocket<RT> {
             local_isn,
             local.clone(),
             remote.clone(),
+            options.clone(),
             rt.clone(),
-            arp.clone(),
+            network.clone(),
             result.clone(),
         );
         let handle = rt.spawn(future);
@@ -94,8 +91,9 @@ impl<RT: Runtime> ActiveOpenSocket<RT> {
             local_isn,
             local,
             remote,
+            options,
             rt,
-            arp,
+            network,
 
             handle,
             result,
@@ -113,12 +111,28 @@ impl<RT: Runtime> ActiveOpenSocket<RT> {
         }
     }
 
+    pub fn set_options(&mut self, options: SocketOptions) {
+        self.options = options;
+    }
+
     fn set_result(&mut self, result: Result<ControlBlock<RT>, Fail>) {
         let mut r = self.result.borrow_mut();
         r.waker.take().map(|w| w.wake());
         r.result.replace(result);
     }
 
+    /// Gives up on the handshake if an ICMP error says the remote won't accept our SYN.
+    pub fn receive_unreachable(&mut self, seq_num: SeqNumber, error: Fail) -> Result<(), Fail> {
+        // Only our SYN can have triggered the error, so anything else is probably forged.
+        if seq_num != self.local_isn {
+            return Err(Fail::Ignored {
+                details: "ICMP error for an unexpected sequence number",
+            });
+        }
+        self.set_result(Err(error));
+        Ok(())
+    }
+
     pub fn receive(&mut self, header: &TcpHeader) {
         if header.rst {
             self.set_result(Err(Fail::ConnectionRefused {}));
@@ -133,7 +147,7 @@ impl<RT: Runtime> ActiveOpenSocket<RT> {
 
         // Acknowledge the SYN+ACK segment.
         debug!("Received SYN+ACK: {:?}", header);
-        let remote_link_addr = match self.arp.try_query(self.remote.address()) {
+        let remote_link_addr = match self.network.try_query(self.remote.address()) {
             Some(r) => r,
             None => panic!("TODO: Clean up ARP query control flow"),
         };
@@ -143,14 +157,23 @@ impl<RT: Runtime> ActiveOpenSocket<RT> {
         tcp_hdr.ack_num = remote_seq_num;
         debug!("Sending ACK: {:?}", tcp_hdr);
 
-        let tcp_options = self.rt.tcp_options();
+        let tcp_options = self.rt.tcp_options().with_socket_options(&self.options);
+        let mut ip_hdr = self
+            .network
+            .new_header(self.local.addr, self.remote.addr, ip::Protocol::Tcp)
+            .unwrap();
+        self.options.apply_to_ip_header(&mut ip_hdr);
         let segment = TcpSegment {
             ethernet2_hdr: Ethernet2Header {
                 dst_addr: remote_link_addr,
                 src_addr: self.rt.local_link_addr(),
-                ether_type: EtherType2::Ipv4,
+                vlan_tags: self
+                    .rt
+                    .ethernet2_options()
+                    .vlan_tags(self.options.vlan_priority),
+                ether_type: ip_hdr.ether_type(),
             },
-            ipv4_hdr: Ipv4Header::new(self.local.addr, self.remote.addr, Ipv4Protocol2::Tcp),
+            ip_hdr,
             tcp_hdr,
             data: Bytes::empty(),
             tx_checksum_offload: tcp_options.tx_checksum_offload,
@@ -172,6 +195,9 @@ impl<RT: Runtime> ActiveOpenSocket<RT> {
                 _ => continue,
             }
         }
+        if let Some(local_mss) = self.options.mss {
+            mss = cmp::min(mss, local_mss);
+        }
 
         let (local_window_scale, remote_window_scale) = match remote_window_scale {
             Some(w) => (tcp_options.window_scale as u32, w),
@@ -204,32 +230,34 @@ impl<RT: Runtime> ActiveOpenSocket<RT> {
 
         let sender = Sender::new(expected_seq, tx_window_size, remote_window_scale, mss);
         let receiver = Receiver::new(remote_seq_num, rx_window_size, local_window_scale);
-        let cb = ControlBlock {
-            local: self.local.clone(),
-            remote: self.remote.clone(),
-            rt: self.rt.clone(),
-            arp: self.arp.clone(),
+        let cb = ControlBlock::new(
+            self.local.clone(),
+            self.remote.clone(),
+            self.rt.clone(),
+            self.network.clone(),
             sender,
             receiver,
-        };
+            self.options.clone(),
+        );
         self.set_result(Ok(cb));
     }
 
     fn background(
         local_isn: SeqNumber,
-        local: ipv4::Endpoint,
-        remote: ipv4::Endpoint,
+        local: ip::Endpoint,
+        remote: ip::Endpoint,
+        options: SocketOptions,
         rt: RT,
-        arp: arp::Peer<RT>,
+        network: ip::Network<RT>,
         result: Rc<RefCell<ConnectResult<RT>>>,
     ) -> impl Future<Output = ()> {
-        let tcp_options = rt.tcp_options();
+        let tcp_options = rt.tcp_options().with_socket_options(&options);
         let handshake_retries = 3usize;
         let handshake_timeout = Duration::from_secs(5);
 
         async move {
             for _ in 0..handshake_retries {
-                let remote_link_addr = match arp.query(remote.address()).await {
+                let remote_link_addr = match network.query(remote.address()).await {
                     Ok(r) => r,
                     Err(e) => {
                         warn!("ARP query failed: {:?}", e);
@@ -250,13 +278,18 @@ impl<RT: Runtime> ActiveOpenSocket<RT> {
                 info!("Advertising window scale: {}", tcp_options.window_scale);
 
                 debug!("Sending SYN {:?}", tcp_hdr);
+                let mut ip_hdr = network
+                    .new_header(local.addr, remote.addr, ip::Protocol::Tcp)
+                    .unwrap();
+                options.apply_to_ip_header(&mut ip_hdr);
                 let segment = TcpSegment {
                     ethernet2_hdr: Ethernet2Header {
                         dst_addr: remote_link_addr,
                         src_addr: rt.local_link_addr(),
-                        ether_type: EtherType2::Ipv4,
+                        vlan_tags: rt.ethernet2_options().vlan_tags(options.vlan_priority),
+                        ether_type: ip_hdr.ether_type(),
                     },
-                    ipv4_hdr: Ipv4Header::new(local.addr, remote.addr, Ipv4Protocol2::Tcp),
+                    ip_hdr,
                     tcp_hdr,
                     data: Bytes::empty(),
                     tx_checksum_offload: tcp_options.tx_checksum_offload,
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 // from [TCP/IP Illustrated](https://learning.oreilly.com/library/view/tcpip-illustrated-volume/9780132808200/ch13.html):
 // > if no MSS option is provided, a default value of 536 bytes is used.
 pub const FALLBACK_MSS: usize = 536;
@@ -7,3 +9,8 @@ pub const MAX_MSS: usize = u16::max_value() as usize;
 
 // TODO: does this need to be determined through MTU discovery?
 pub const DEFAULT_MSS: usize = 1450;
+
+// Once a connection has been idle for its keepalive time, we probe it this often and give up
+// after this many probes go unanswered, like Linux's defaults.
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(75);
+pub const KEEPALIVE_PROBES: usize = 9;
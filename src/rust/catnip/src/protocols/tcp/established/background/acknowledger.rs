@@ -36,7 +36,7 @@ pub async fn acknowledger<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fa
                 let recv_seq_no = cb.receiver.recv_seq_no.get();
                 assert!(cb.receiver.ack_seq_no.get() < recv_seq_no);
 
-                let remote_link_addr = cb.arp.query(cb.remote.address()).await?;
+                let remote_link_addr = cb.network.query(cb.remote.address()).await?;
 
                 let mut header = cb.tcp_header();
                 header.ack = true;
@@ -33,7 +33,7 @@ async fn rx_ack_sender<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fail>
 
         // Send ACK segment
         cb.receiver.state.set(ReceiverState::AckdFin);
-        let remote_link_addr = cb.arp.query(cb.remote.address()).await?;
+        let remote_link_addr = cb.network.query(cb.remote.address()).await?;
         let mut header = cb.tcp_header();
         header.ack = true;
         header.ack_num = recv_seq + Wrapping(1);
@@ -61,7 +61,7 @@ async fn tx_fin_sender<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fail>
                 }
 
                 // TODO: When do we retransmit this?
-                let remote_link_addr = cb.arp.query(cb.remote.address()).await?;
+                let remote_link_addr = cb.network.query(cb.remote.address()).await?;
                 let mut header = cb.tcp_header();
                 header.seq_num = sent_seq + Wrapping(1);
                 header.fin = true;
@@ -70,8 +70,9 @@ async fn tx_fin_sender<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fail>
                 cb.sender.state.set(SenderState::SentFin);
             },
             SenderState::Reset => {
-                let remote_link_addr = cb.arp.query(cb.remote.address()).await?;
+                let remote_link_addr = cb.network.query(cb.remote.address()).await?;
                 let mut header = cb.tcp_header();
+                header.seq_num = cb.sender.sent_seq_no.get();
                 header.rst = true;
                 cb.emit(header, Bytes::empty(), remote_link_addr);
                 return Err(Fail::ConnectionAborted {});
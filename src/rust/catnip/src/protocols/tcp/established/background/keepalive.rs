@@ -0,0 +1,59 @@
+use super::super::state::ControlBlock;
+use crate::{
+    fail::Fail,
+    protocols::tcp::constants::{
+        KEEPALIVE_INTERVAL,
+        KEEPALIVE_PROBES,
+    },
+    runtime::Runtime,
+    sync::Bytes,
+};
+use futures::FutureExt;
+use std::{
+    num::Wrapping,
+    rc::Rc,
+};
+
+pub async fn keepalive<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fail> {
+    'top: loop {
+        let (idle, idle_changed) = cb.keepalive.watch();
+        futures::pin_mut!(idle_changed);
+
+        let idle = match idle {
+            Some(idle) => idle,
+            None => {
+                idle_changed.await;
+                continue 'top;
+            },
+        };
+
+        // Hearing anything from the remote restarts the idle timer.
+        let (last_received, received) = cb.last_received.watch();
+        futures::pin_mut!(received);
+
+        let mut probe_at = last_received + idle;
+        let mut probes_sent = 0;
+        loop {
+            let probe_future = cb.rt.wait_until(probe_at).fuse();
+            futures::pin_mut!(probe_future);
+            futures::select_biased! {
+                _ = idle_changed => continue 'top,
+                _ = received => continue 'top,
+                _ = probe_future => (),
+            }
+            if probes_sent == KEEPALIVE_PROBES {
+                return Err(Fail::Timeout {});
+            }
+
+            // RFC 1122 section 4.2.3.6: The probe carries a sequence number one less than the next
+            // one we'll send, so the remote has to answer it with an ACK.
+            let remote_link_addr = cb.network.query(cb.remote.address()).await?;
+            let mut header = cb.tcp_header();
+            header.seq_num = cb.sender.sent_seq_no.get() - Wrapping(1);
+            cb.emit(header, Bytes::empty(), remote_link_addr);
+
+            probes_sent += 1;
+            probe_at = cb.rt.now() + KEEPALIVE_INTERVAL;
+        }
+    }
+}
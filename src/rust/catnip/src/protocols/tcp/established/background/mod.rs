@@ -1,11 +1,13 @@
 mod acknowledger;
 mod closer;
+mod keepalive;
 mod retransmitter;
 mod sender;
 
 use self::{
     acknowledger::acknowledger,
     closer::closer,
+    keepalive::keepalive,
     retransmitter::retransmitter,
     sender::sender,
 };
@@ -43,6 +45,9 @@ pub fn background<RT: Runtime>(
         let sender = sender(cb.clone()).fuse();
         futures::pin_mut!(sender);
 
+        let keepalive = keepalive(cb.clone()).fuse();
+        futures::pin_mut!(keepalive);
+
         let closer = closer(cb).fuse();
         futures::pin_mut!(closer);
 
@@ -50,6 +55,7 @@ pub fn background<RT: Runtime>(
             r = acknowledger => r,
             r = retransmitter => r,
             r = sender => r,
+            r = keepalive => r,
             r = closer => r,
         };
         error!("Connection (fd {}) terminated: {:?}", fd, r);
@@ -26,7 +26,7 @@ pub async fn retransmitter<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, F
             _ = rtx_deadline_changed => continue,
             _ = rtx_future => {
                 // Our retransmission timer fired, so we need to resend a packet.
-                let remote_link_addr = cb.arp.query(cb.remote.address()).await?;
+                let remote_link_addr = cb.network.query(cb.remote.address()).await?;
 
                 let mut unacked_queue = cb.sender.unacked_queue.borrow_mut();
                 let mut rto = cb.sender.rto.borrow_mut();
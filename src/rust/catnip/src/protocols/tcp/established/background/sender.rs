@@ -39,7 +39,7 @@ pub async fn sender<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fail> {
         // If we don't have any window size at all, we need to transition to PERSIST state and
         // repeatedly send window probes until window opens up.
         if win_sz == 0 {
-            let remote_link_addr = cb.arp.query(cb.remote.address()).await?;
+            let remote_link_addr = cb.network.query(cb.remote.address()).await?;
             let buf = cb
                 .sender
                 .pop_one_unsent_byte()
@@ -91,7 +91,7 @@ pub async fn sender<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fail> {
 
         // TODO: Nagle's algorithm
         // TODO: Silly window syndrome
-        let remote_link_addr = cb.arp.query(cb.remote.address()).await?;
+        let remote_link_addr = cb.network.query(cb.remote.address()).await?;
 
         // Form an outgoing packet.
         let max_size = cmp::min((win_sz - sent_data) as usize, cb.sender.mss);
@@ -3,13 +3,16 @@ pub mod state;
 
 use self::{
     background::background,
-    state::ControlBlock,
+    state::{
+        sender::SenderState,
+        ControlBlock,
+    },
 };
 use crate::{
     fail::Fail,
     file_table::FileDescriptor,
     protocols::{
-        ipv4,
+        ip,
         tcp::segment::TcpHeader,
     },
     runtime::Runtime,
@@ -19,7 +22,9 @@ use crate::{
         UnboundedSender,
     },
 };
+use futures::FutureExt;
 use std::{
+    future::Future,
     rc::Rc,
     task::{
         Context,
@@ -73,6 +78,42 @@ impl<RT: Runtime> EstablishedSocket<RT> {
         self.cb.close()
     }
 
+    pub fn abort(&self) -> Result<(), Fail> {
+        self.cb.abort()
+    }
+
+    /// Resolves once all queued data has been acknowledged by the remote peer. If that doesn't
+    /// happen within `timeout`, the connection is reset and the future fails.
+    pub fn linger(&self, timeout: Duration) -> impl Future<Output = Result<(), Fail>> {
+        let cb = self.cb.clone();
+        async move {
+            let timer = cb.rt.wait(timeout).fuse();
+            futures::pin_mut!(timer);
+            loop {
+                let (sender_st, sender_st_changed) = cb.sender.state.watch();
+                futures::pin_mut!(sender_st_changed);
+                if sender_st == SenderState::Reset {
+                    return Err(Fail::ConnectionAborted {});
+                }
+
+                let (base_seq, base_seq_changed) = cb.sender.base_seq_no.watch();
+                futures::pin_mut!(base_seq_changed);
+                if base_seq == cb.sender.unsent_seq_no.get() {
+                    return Ok(());
+                }
+
+                futures::select_biased! {
+                    _ = sender_st_changed => continue,
+                    _ = base_seq_changed => continue,
+                    _ = timer => {
+                        let _ = cb.abort();
+                        return Err(Fail::Timeout {});
+                    },
+                }
+            }
+        }
+    }
+
     pub fn remote_mss(&self) -> usize {
         self.cb.remote_mss()
     }
@@ -81,7 +122,7 @@ impl<RT: Runtime> EstablishedSocket<RT> {
         self.cb.current_rto()
     }
 
-    pub fn endpoints(&self) -> (ipv4::Endpoint, ipv4::Endpoint) {
+    pub fn endpoints(&self) -> (ip::Endpoint, ip::Endpoint) {
         (self.cb.local.clone(), self.cb.remote.clone())
     }
 }
@@ -7,46 +7,88 @@ use self::{
     sender::Sender,
 };
 use crate::{
+    collections::watched::WatchedValue,
     fail::Fail,
     protocols::{
-        arp,
         ethernet2::{
-            frame::{
-                EtherType2,
-                Ethernet2Header,
-            },
+            frame::Ethernet2Header,
             MacAddress,
         },
-        ipv4,
-        ipv4::datagram::{
-            Ipv4Header,
-            Ipv4Protocol2,
-        },
+        ip,
         tcp::segment::{
             TcpHeader,
             TcpSegment,
         },
     },
     runtime::Runtime,
+    socket_options::SocketOptions,
     sync::Bytes,
 };
-use std::time::Duration;
+use std::{
+    cell::RefCell,
+    num::Wrapping,
+    time::{
+        Duration,
+        Instant,
+    },
+};
 
 pub struct ControlBlock<RT: Runtime> {
-    pub local: ipv4::Endpoint,
-    pub remote: ipv4::Endpoint,
+    pub local: ip::Endpoint,
+    pub remote: ip::Endpoint,
 
     pub rt: RT,
-    pub arp: arp::Peer<RT>,
+    pub network: ip::Network<RT>,
 
     pub sender: Sender,
     pub receiver: Receiver,
+
+    pub options: RefCell<SocketOptions>,
+    // Copied out of `options` so the keepalive timer can watch it.
+    pub keepalive: WatchedValue<Option<Duration>>,
+    pub last_received: WatchedValue<Instant>,
 }
 
 impl<RT: Runtime> ControlBlock<RT> {
+    pub fn new(
+        local: ip::Endpoint,
+        remote: ip::Endpoint,
+        rt: RT,
+        network: ip::Network<RT>,
+        sender: Sender,
+        receiver: Receiver,
+        options: SocketOptions,
+    ) -> Self {
+        let now = rt.now();
+        Self {
+            local,
+            remote,
+            rt,
+            network,
+            sender,
+            receiver,
+            keepalive: WatchedValue::new(options.keepalive),
+            last_received: WatchedValue::new(now),
+            options: RefCell::new(options),
+        }
+    }
+
+    pub fn set_options(&self, options: SocketOptions) {
+        self.keepalive.set(options.keepalive);
+        *self.options.borrow_mut() = options;
+    }
+
     pub fn receive(&self, header: &TcpHeader, data: Bytes) {
         debug!("Receiving {} bytes + {:?}", data.len(), header);
         let now = self.rt.now();
+        self.last_received.set(now);
+
+        // RFC 1122 section 4.2.3.6: Keepalive probes repeat the byte before the one we expect
+        // next, and we answer them with an ACK.
+        let is_probe = data.is_empty()
+            && !(header.syn || header.fin || header.rst)
+            && header.seq_num + Wrapping(1) == self.receiver.recv_seq_no.get();
+
         if header.syn {
             warn!("Ignoring duplicate SYN on established connection");
         }
@@ -57,9 +99,14 @@ impl<RT: Runtime> ControlBlock<RT> {
             self.receiver.receive_fin();
         }
         if header.ack {
+            let base_seq_no = self.sender.base_seq_no.get();
             if let Err(e) = self.sender.remote_ack(header.ack_num, now) {
                 warn!("Ignoring remote ack for {:?}: {:?}", header, e);
             }
+            // Acks for new data show that the remote is still reachable.
+            if self.sender.base_seq_no.get() != base_seq_no {
+                self.network.confirm_reachable(self.remote.address());
+            }
         }
         if let Err(e) = self.sender.update_remote_window(header.window_size as u16) {
             warn!("Invalid window size update for {:?}: {:?}", header, e);
@@ -68,6 +115,10 @@ impl<RT: Runtime> ControlBlock<RT> {
             if let Err(e) = self.receiver.receive_data(header.seq_num, data, now) {
                 warn!("Ignoring remote data for {:?}: {:?}", header, e);
             }
+        } else if is_probe {
+            if let Some(remote_link_addr) = self.network.try_query(self.remote.address()) {
+                self.emit(self.tcp_header(), Bytes::empty(), remote_link_addr);
+            }
         }
     }
 
@@ -75,6 +126,10 @@ impl<RT: Runtime> ControlBlock<RT> {
         self.sender.close()
     }
 
+    pub fn abort(&self) -> Result<(), Fail> {
+        self.sender.abort()
+    }
+
     pub fn tcp_header(&self) -> TcpHeader {
         let mut header = TcpHeader::new(self.local.port, self.remote.port);
         header.window_size = self.receiver.hdr_window_size();
@@ -90,13 +145,22 @@ impl<RT: Runtime> ControlBlock<RT> {
             self.receiver.ack_sent(header.ack_num);
         }
         debug!("Sending {} bytes + {:?}", data.len(), header);
+        let mut ip_hdr = self
+            .network
+            .new_header(self.local.addr, self.remote.addr, ip::Protocol::Tcp)
+            .unwrap();
+        self.options.borrow().apply_to_ip_header(&mut ip_hdr);
         let segment = TcpSegment {
             ethernet2_hdr: Ethernet2Header {
                 dst_addr: remote_link_addr,
                 src_addr: self.rt.local_link_addr(),
-                ether_type: EtherType2::Ipv4,
+                vlan_tags: self
+                    .rt
+                    .ethernet2_options()
+                    .vlan_tags(self.options.borrow().vlan_priority),
+                ether_type: ip_hdr.ether_type(),
             },
-            ipv4_hdr: Ipv4Header::new(self.local.addr, self.remote.addr, Ipv4Protocol2::Tcp),
+            ip_hdr,
             tcp_hdr: header,
             data,
             tx_checksum_offload: self.rt.tcp_options().tx_checksum_offload,
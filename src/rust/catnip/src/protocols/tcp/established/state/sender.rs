@@ -114,9 +114,18 @@ impl Sender {
         let sent_seq = self.sent_seq_no.get();
         let Wrapping(sent_data) = sent_seq - base_seq;
 
+        if let Some(limit) = cb.options.borrow().send_buffer_size {
+            let Wrapping(buffered) = self.unsent_seq_no.get() - base_seq;
+            if buffered as usize + buf_len as usize > limit {
+                return Err(Fail::ResourceExhausted {
+                    details: "Send buffer full",
+                });
+            }
+        }
+
         // Fast path: Try to send the data immediately.
         if win_sz > 0 && win_sz >= sent_data + buf_len {
-            if let Some(remote_link_addr) = cb.arp.try_query(cb.remote.address()) {
+            if let Some(remote_link_addr) = cb.network.try_query(cb.remote.address()) {
                 let mut header = cb.tcp_header();
                 header.seq_num = sent_seq;
                 cb.emit(header, buf.clone(), remote_link_addr);
@@ -152,6 +161,19 @@ impl Sender {
         Ok(())
     }
 
+    /// Discards any data that hasn't been sent yet and resets the connection.
+    pub fn abort(&self) -> Result<(), Fail> {
+        if self.state.get() == SenderState::Reset {
+            return Err(Fail::Ignored {
+                details: "Sender already reset",
+            });
+        }
+        self.unsent_queue.borrow_mut().clear();
+        self.unsent_seq_no.set(self.sent_seq_no.get());
+        self.state.set(SenderState::Reset);
+        Ok(())
+    }
+
     pub fn receive_rst(&self) {
         self.state.set(SenderState::Reset);
     }
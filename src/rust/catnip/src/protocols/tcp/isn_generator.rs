@@ -2,7 +2,7 @@
 // Licensed under the MIT license.
 
 use crate::protocols::{
-    ipv4,
+    ip,
     tcp::SeqNumber,
 };
 use crc::{
@@ -11,6 +11,7 @@ use crc::{
 };
 use std::{
     hash::Hasher,
+    net::IpAddr,
     num::Wrapping,
 };
 
@@ -27,11 +28,11 @@ impl IsnGenerator {
         }
     }
 
-    pub fn generate(&mut self, local: &ipv4::Endpoint, remote: &ipv4::Endpoint) -> SeqNumber {
+    pub fn generate(&mut self, local: &ip::Endpoint, remote: &ip::Endpoint) -> SeqNumber {
         let mut hash = crc32::Digest::new(crc32::IEEE);
-        hash.write_u32(remote.address().into());
+        write_addr(&mut hash, remote.address());
         hash.write_u16(remote.port().into());
-        hash.write_u32(local.address().into());
+        write_addr(&mut hash, local.address());
         hash.write_u16(local.port().into());
         hash.write_u32(self.nonce);
         let hash = hash.sum32();
@@ -40,3 +41,10 @@ impl IsnGenerator {
         isn
     }
 }
+
+fn write_addr(hash: &mut crc32::Digest, addr: IpAddr) {
+    match addr {
+        IpAddr::V4(addr) => hash.write_u32(addr.into()),
+        IpAddr::V6(addr) => hash.write_u128(addr.into()),
+    }
+}
@@ -16,6 +16,10 @@ use std::num::Wrapping;
 pub type SeqNumber = Wrapping<u32>;
 
 pub use self::{
-    options::TcpOptions as Options,
+    options::{
+        BacklogOverflowPolicy,
+        TcpOptions as Options,
+    },
+    passive_open::ListenerStats,
     peer::Peer,
 };
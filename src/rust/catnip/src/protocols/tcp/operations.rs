@@ -26,6 +26,7 @@ use std::{
 
 pub enum TcpOperation<RT: Runtime> {
     Accept(ResultFuture<AcceptFuture<RT>>),
+    Close(ResultFuture<CloseFuture>),
     Connect(ResultFuture<ConnectFuture<RT>>),
     Pop(ResultFuture<PopFuture<RT>>),
     Push(ResultFuture<PushFuture<RT>>),
@@ -37,6 +38,12 @@ impl<RT: Runtime> From<AcceptFuture<RT>> for TcpOperation<RT> {
     }
 }
 
+impl<RT: Runtime> From<CloseFuture> for TcpOperation<RT> {
+    fn from(f: CloseFuture) -> Self {
+        TcpOperation::Close(ResultFuture::new(f))
+    }
+}
+
 impl<RT: Runtime> From<ConnectFuture<RT>> for TcpOperation<RT> {
     fn from(f: ConnectFuture<RT>) -> Self {
         TcpOperation::Connect(ResultFuture::new(f))
@@ -61,6 +68,7 @@ impl<RT: Runtime> Future for TcpOperation<RT> {
     fn poll(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<()> {
         match self.get_mut() {
             TcpOperation::Accept(ref mut f) => Future::poll(Pin::new(f), ctx),
+            TcpOperation::Close(ref mut f) => Future::poll(Pin::new(f), ctx),
             TcpOperation::Connect(ref mut f) => Future::poll(Pin::new(f), ctx),
             TcpOperation::Push(ref mut f) => Future::poll(Pin::new(f), ctx),
             TcpOperation::Pop(ref mut f) => Future::poll(Pin::new(f), ctx),
@@ -91,6 +99,15 @@ impl<RT: Runtime> TcpOperation<RT> {
                 done: Some(Err(e)),
             }) => (future.fd, OperationResult::Failed(e)),
 
+            Close(ResultFuture {
+                future,
+                done: Some(Ok(())),
+            }) => (future.fd, OperationResult::Close),
+            Close(ResultFuture {
+                future,
+                done: Some(Err(e)),
+            }) => (future.fd, OperationResult::Failed(e)),
+
             Push(ResultFuture {
                 future,
                 done: Some(Ok(())),
@@ -169,6 +186,31 @@ impl<RT: Runtime> Future for AcceptFuture<RT> {
     }
 }
 
+impl<RT: Runtime> Drop for AcceptFuture<RT> {
+    fn drop(&mut self) {
+        self.inner.borrow_mut().accept_dropped(self.fd);
+    }
+}
+
+pub struct CloseFuture {
+    pub fd: FileDescriptor,
+    pub future: Pin<Box<dyn Future<Output = Result<(), Fail>>>>,
+}
+
+impl fmt::Debug for CloseFuture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CloseFuture({})", self.fd)
+    }
+}
+
+impl Future for CloseFuture {
+    type Output = Result<(), Fail>;
+
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Self::Output> {
+        Future::poll(self.get_mut().future.as_mut(), ctx)
+    }
+}
+
 pub struct PushFuture<RT: Runtime> {
     pub fd: FileDescriptor,
     pub err: Option<Fail>,
@@ -1,15 +1,34 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
-use crate::protocols::tcp::constants::{
-    DEFAULT_MSS,
-    MAX_MSS,
-    MIN_MSS,
+use crate::{
+    protocols::tcp::constants::{
+        DEFAULT_MSS,
+        MAX_MSS,
+        MIN_MSS,
+    },
+    socket_options::SocketOptions,
 };
-use std::time::Duration;
+use std::{
+    cmp,
+    time::Duration,
+};
+
+/// What a listening socket does with a completed handshake when its accept queue is full.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BacklogOverflowPolicy {
+    /// Drop the final ACK and keep the handshake inflight, so the peer's retransmission can
+    /// complete it once the application has drained the queue.
+    DropAck,
+    /// Abort the handshake with a RST.
+    Reset,
+    /// Queue the connection anyway, exceeding the backlog.
+    Accept,
+}
 
 #[derive(Clone, Debug)]
 pub struct TcpOptions {
     pub advertised_mss: usize,
+    pub backlog_overflow: BacklogOverflowPolicy,
     pub handshake_retries: usize,
     pub handshake_timeout: Duration,
     pub receive_window_size: u16,
@@ -24,6 +43,7 @@ impl Default for TcpOptions {
     fn default() -> Self {
         TcpOptions {
             advertised_mss: DEFAULT_MSS,
+            backlog_overflow: BacklogOverflowPolicy::DropAck,
             handshake_retries: 5,
             handshake_timeout: Duration::from_secs(3),
             receive_window_size: 0xffff,
@@ -44,6 +64,11 @@ impl TcpOptions {
         self
     }
 
+    pub fn backlog_overflow(mut self, value: BacklogOverflowPolicy) -> Self {
+        self.backlog_overflow = value;
+        self
+    }
+
     pub fn handshake_retries(mut self, value: usize) -> Self {
         assert!(value > 0);
         self.handshake_retries = value;
@@ -72,4 +97,16 @@ impl TcpOptions {
         self.trailing_ack_delay = value;
         self
     }
+
+    /// Overrides the stack-wide defaults with a socket's MSS and receive buffer size.
+    pub fn with_socket_options(mut self, options: &SocketOptions) -> Self {
+        if let Some(mss) = options.mss {
+            self.advertised_mss = mss;
+        }
+        if let Some(size) = options.receive_buffer_size {
+            let window_size = cmp::max(size >> self.window_scale, 1);
+            self.receive_window_size = cmp::min(window_size, 0xffff) as u16;
+        }
+        self
+    }
 }
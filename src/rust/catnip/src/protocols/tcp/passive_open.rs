@@ -6,20 +6,13 @@ use super::{
         ControlBlock,
     },
     isn_generator::IsnGenerator,
+    options::BacklogOverflowPolicy,
 };
 use crate::{
     fail::Fail,
     protocols::{
-        arp,
-        ethernet2::frame::{
-            EtherType2,
-            Ethernet2Header,
-        },
-        ipv4,
-        ipv4::datagram::{
-            Ipv4Header,
-            Ipv4Protocol2,
-        },
+        ethernet2::frame::Ethernet2Header,
+        ip,
         tcp::{
             segment::{
                 TcpHeader,
@@ -31,6 +24,7 @@ use crate::{
     },
     runtime::Runtime,
     scheduler::SchedulerHandle,
+    socket_options::SocketOptions,
     sync::Bytes,
 };
 use hashbrown::{
@@ -39,6 +33,7 @@ use hashbrown::{
 };
 use std::{
     cell::RefCell,
+    cmp,
     collections::VecDeque,
     convert::TryInto,
     future::Future,
@@ -65,7 +60,7 @@ struct InflightAccept {
 
 struct ReadySockets<RT: Runtime> {
     ready: VecDeque<Result<ControlBlock<RT>, Fail>>,
-    endpoints: HashSet<ipv4::Endpoint>,
+    endpoints: HashSet<ip::Endpoint>,
     waker: Option<Waker>,
 }
 
@@ -100,20 +95,37 @@ impl<RT: Runtime> ReadySockets<RT> {
     }
 }
 
+/// Counters for connections a listening socket turned away.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ListenerStats {
+    /// SYNs refused because `max_backlog` handshakes were already inflight.
+    pub syn_overflows: usize,
+    /// Completed handshakes that found the accept queue full.
+    pub accept_overflows: usize,
+}
+
 pub struct PassiveSocket<RT: Runtime> {
-    inflight: HashMap<ipv4::Endpoint, InflightAccept>,
+    inflight: HashMap<ip::Endpoint, InflightAccept>,
     ready: Rc<RefCell<ReadySockets<RT>>>,
 
     max_backlog: usize,
     isn_generator: IsnGenerator,
+    stats: ListenerStats,
+    options: SocketOptions,
 
-    local: ipv4::Endpoint,
+    local: ip::Endpoint,
     rt: RT,
-    arp: arp::Peer<RT>,
+    network: ip::Network<RT>,
 }
 
 impl<RT: Runtime> PassiveSocket<RT> {
-    pub fn new(local: ipv4::Endpoint, max_backlog: usize, rt: RT, arp: arp::Peer<RT>) -> Self {
+    pub fn new(
+        local: ip::Endpoint,
+        max_backlog: usize,
+        options: SocketOptions,
+        rt: RT,
+        network: ip::Network<RT>,
+    ) -> Self {
         let ready = ReadySockets {
             ready: VecDeque::new(),
             endpoints: HashSet::new(),
@@ -126,9 +138,11 @@ impl<RT: Runtime> PassiveSocket<RT> {
             ready,
             max_backlog,
             isn_generator: IsnGenerator::new(nonce),
+            stats: ListenerStats::default(),
+            options,
             local,
             rt,
-            arp,
+            network,
         }
     }
 
@@ -136,8 +150,49 @@ impl<RT: Runtime> PassiveSocket<RT> {
         self.ready.borrow_mut().poll(ctx)
     }
 
-    pub fn receive(&mut self, ip_header: &Ipv4Header, header: &TcpHeader) -> Result<(), Fail> {
-        let remote = ipv4::Endpoint::new(ip_header.src_addr, header.src_port);
+    pub fn stats(&self) -> ListenerStats {
+        self.stats
+    }
+
+    /// Updates the options inherited by connections accepted from now on.
+    pub fn set_options(&mut self, options: SocketOptions) {
+        self.options = options;
+    }
+
+    /// Stops accepting connections: inflight handshakes and connections still waiting in the
+    /// accept queue are reset, and a pending accept is woken so it can observe the close.
+    pub fn close(&mut self) {
+        let inflight: Vec<_> = self.inflight.drain().collect();
+        for (remote, accept) in inflight {
+            if let Err(e) = self.send_rst(&remote, accept.local_isn + Wrapping(1)) {
+                warn!("Failed to reset handshake with {:?}: {:?}", remote, e);
+            }
+        }
+
+        let mut ready = self.ready.borrow_mut();
+        ready.endpoints.clear();
+        for r in ready.ready.drain(..) {
+            let cb = match r {
+                Ok(cb) => cb,
+                Err(..) => continue,
+            };
+            let remote_link_addr = match cb.network.try_query(cb.remote.address()) {
+                Some(r) => r,
+                None => {
+                    warn!("Failed to reset unaccepted connection with {:?}", cb.remote);
+                    continue;
+                },
+            };
+            let mut header = cb.tcp_header();
+            header.rst = true;
+            header.seq_num = cb.sender.sent_seq_no.get();
+            cb.emit(header, Bytes::empty(), remote_link_addr);
+        }
+        ready.waker.take().map(|w| w.wake());
+    }
+
+    pub fn receive(&mut self, ip_header: &ip::Header, header: &TcpHeader) -> Result<(), Fail> {
+        let remote = ip::Endpoint::new(ip_header.src_addr(), header.src_port);
         if self.ready.borrow().endpoints.contains(&remote) {
             // TODO: What should we do if a packet shows up for a connection that hasn't been
             // `accept`ed yet?
@@ -168,7 +223,25 @@ impl<RT: Runtime> PassiveSocket<RT> {
                 });
             }
 
-            let tcp_options = self.rt.tcp_options();
+            let tcp_options = self.rt.tcp_options().with_socket_options(&self.options);
+            if self.ready.borrow().len() >= self.max_backlog {
+                self.stats.accept_overflows += 1;
+                match tcp_options.backlog_overflow {
+                    BacklogOverflowPolicy::DropAck => {
+                        return Err(Fail::ResourceExhausted {
+                            details: "Accept queue full",
+                        });
+                    },
+                    BacklogOverflowPolicy::Reset => {
+                        self.inflight.remove(&remote);
+                        self.send_rst(&remote, local_isn + Wrapping(1))?;
+                        return Err(Fail::ResourceExhausted {
+                            details: "Accept queue full",
+                        });
+                    },
+                    BacklogOverflowPolicy::Accept => (),
+                }
+            }
             let (local_window_scale, remote_window_scale) = match remote_window_scale {
                 Some(w) => (tcp_options.window_scale as u32, w),
                 None => (0, 0),
@@ -202,14 +275,15 @@ impl<RT: Runtime> PassiveSocket<RT> {
                 local_window_scale,
             );
             self.inflight.remove(&remote);
-            let cb = ControlBlock {
-                local: self.local.clone(),
-                remote: remote.clone(),
-                rt: self.rt.clone(),
-                arp: self.arp.clone(),
+            let cb = ControlBlock::new(
+                self.local.clone(),
+                remote.clone(),
+                self.rt.clone(),
+                self.network.clone(),
                 sender,
                 receiver,
-            };
+                self.options.clone(),
+            );
             self.ready.borrow_mut().push_ok(cb);
             return Ok(());
         }
@@ -221,8 +295,10 @@ impl<RT: Runtime> PassiveSocket<RT> {
             });
         }
         debug!("Received SYN: {:?}", header);
-        if inflight_len + self.ready.borrow().len() >= self.max_backlog {
-            // TODO: Should we send a RST here?
+        // The accept queue is checked when the handshake completes, so here we only bound the
+        // number of handshakes in flight.
+        if inflight_len >= self.max_backlog {
+            self.stats.syn_overflows += 1;
             return Err(Fail::ConnectionRefused {});
         }
         let local_isn = self.isn_generator.generate(&self.local, &remote);
@@ -232,8 +308,9 @@ impl<RT: Runtime> PassiveSocket<RT> {
             remote_isn,
             self.local,
             remote.clone(),
+            self.options.clone(),
             self.rt.clone(),
-            self.arp.clone(),
+            self.network.clone(),
             self.ready.clone(),
         );
         let handle = self.rt.spawn(future);
@@ -253,6 +330,9 @@ impl<RT: Runtime> PassiveSocket<RT> {
                 _ => continue,
             }
         }
+        if let Some(local_mss) = self.options.mss {
+            mss = cmp::min(mss, local_mss);
+        }
         let accept = InflightAccept {
             local_isn,
             remote_isn,
@@ -265,22 +345,59 @@ impl<RT: Runtime> PassiveSocket<RT> {
         Ok(())
     }
 
+    fn send_rst(&self, remote: &ip::Endpoint, seq_num: SeqNumber) -> Result<(), Fail> {
+        let remote_link_addr =
+            self.network
+                .try_query(remote.addr)
+                .ok_or_else(|| Fail::ResourceNotFound {
+                    details: "RST destination not in ARP cache",
+                })?;
+
+        let mut tcp_hdr = TcpHeader::new(self.local.port, remote.port);
+        tcp_hdr.rst = true;
+        tcp_hdr.seq_num = seq_num;
+        debug!("Sending RST: {:?}", tcp_hdr);
+
+        let mut ip_hdr =
+            self.network
+                .new_header(self.local.addr, remote.addr, ip::Protocol::Tcp)?;
+        self.options.apply_to_ip_header(&mut ip_hdr);
+        let segment = TcpSegment {
+            ethernet2_hdr: Ethernet2Header {
+                dst_addr: remote_link_addr,
+                src_addr: self.rt.local_link_addr(),
+                vlan_tags: self
+                    .rt
+                    .ethernet2_options()
+                    .vlan_tags(self.options.vlan_priority),
+                ether_type: ip_hdr.ether_type(),
+            },
+            ip_hdr,
+            tcp_hdr,
+            data: Bytes::empty(),
+            tx_checksum_offload: self.rt.tcp_options().tx_checksum_offload,
+        };
+        self.rt.transmit(segment);
+        Ok(())
+    }
+
     fn background(
         local_isn: SeqNumber,
         remote_isn: SeqNumber,
-        local: ipv4::Endpoint,
-        remote: ipv4::Endpoint,
+        local: ip::Endpoint,
+        remote: ip::Endpoint,
+        options: SocketOptions,
         rt: RT,
-        arp: arp::Peer<RT>,
+        network: ip::Network<RT>,
         ready: Rc<RefCell<ReadySockets<RT>>>,
     ) -> impl Future<Output = ()> {
-        let tcp_options = rt.tcp_options();
+        let tcp_options = rt.tcp_options().with_socket_options(&options);
         let handshake_retries = 3usize;
         let handshake_timeout = Duration::from_secs(5);
 
         async move {
             for _ in 0..handshake_retries {
-                let remote_link_addr = match arp.query(remote.address()).await {
+                let remote_link_addr = match network.query(remote.address()).await {
                     Ok(r) => r,
                     Err(e) => {
                         warn!("ARP query failed: {:?}", e);
@@ -302,13 +419,18 @@ impl<RT: Runtime> PassiveSocket<RT> {
                 info!("Advertising window scale: {}", tcp_options.window_scale);
 
                 debug!("Sending SYN+ACK: {:?}", tcp_hdr);
+                let mut ip_hdr = network
+                    .new_header(local.addr, remote.addr, ip::Protocol::Tcp)
+                    .unwrap();
+                options.apply_to_ip_header(&mut ip_hdr);
                 let segment = TcpSegment {
                     ethernet2_hdr: Ethernet2Header {
                         dst_addr: remote_link_addr,
                         src_addr: rt.local_link_addr(),
-                        ether_type: EtherType2::Ipv4,
+                        vlan_tags: rt.ethernet2_options().vlan_tags(options.vlan_priority),
+                        ether_type: ip_hdr.ether_type(),
                     },
-                    ipv4_hdr: Ipv4Header::new(local.addr, remote.addr, Ipv4Protocol2::Tcp),
+                    ip_hdr,
                     tcp_hdr,
                     data: Bytes::empty(),
                     tx_checksum_offload: tcp_options.tx_checksum_offload,
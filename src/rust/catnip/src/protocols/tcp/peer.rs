@@ -2,7 +2,10 @@ use super::{
     active_open::ActiveOpenSocket,
     established::EstablishedSocket,
     isn_generator::IsnGenerator,
-    passive_open::PassiveSocket,
+    passive_open::{
+        ListenerStats,
+        PassiveSocket,
+    },
 };
 use crate::{
     fail::Fail,
@@ -12,21 +15,13 @@ use crate::{
         FileTable,
     },
     protocols::{
-        arp,
-        ethernet2::frame::{
-            EtherType2,
-            Ethernet2Header,
-        },
+        ethernet2::frame::Ethernet2Header,
         ip,
         ip::port::EphemeralPorts,
-        ipv4,
-        ipv4::datagram::{
-            Ipv4Header,
-            Ipv4Protocol2,
-        },
         tcp::{
             operations::{
                 AcceptFuture,
+                CloseFuture,
                 ConnectFuture,
                 ConnectFutureState,
                 PopFuture,
@@ -36,20 +31,28 @@ use crate::{
                 TcpHeader,
                 TcpSegment,
             },
+            SeqNumber,
         },
     },
     runtime::Runtime,
     scheduler::SchedulerHandle,
+    socket_options::{
+        SocketOption,
+        SocketOptionName,
+        SocketOptions,
+    },
     sync::{
         Bytes,
         UnboundedReceiver,
         UnboundedSender,
     },
 };
+use futures::future;
 use futures_intrusive::channel::shared::generic_channel;
 use hashbrown::HashMap;
 use std::{
     cell::RefCell,
+    net::IpAddr,
     rc::Rc,
     task::{
         Context,
@@ -58,14 +61,20 @@ use std::{
     time::Duration,
 };
 
+#[derive(Clone)]
 pub struct Peer<RT: Runtime> {
     pub(super) inner: Rc<RefCell<Inner<RT>>>,
 }
 
 impl<RT: Runtime> Peer<RT> {
-    pub fn new(rt: RT, arp: arp::Peer<RT>, file_table: FileTable) -> Self {
+    pub fn new(rt: RT, network: ip::Network<RT>, file_table: FileTable) -> Self {
         let (tx, rx) = generic_channel(16);
-        let inner = Rc::new(RefCell::new(Inner::new(rt.clone(), arp, file_table, tx)));
+        let inner = Rc::new(RefCell::new(Inner::new(
+            rt.clone(),
+            network,
+            file_table,
+            tx,
+        )));
         let bg_handle = rt.spawn(Self::background(rx, inner.clone()));
         inner.borrow_mut().dead_socket_handle = Some(bg_handle);
         Self { inner }
@@ -96,6 +105,8 @@ impl<RT: Runtime> Peer<RT> {
                     )
                 });
 
+            inner.options.remove(&fd);
+
             // TODO: Assert we've been properly closed here.
             // TODO: Recycle this FD.
             info!("Cleaning up dead socket for FD {}", fd);
@@ -110,16 +121,72 @@ impl<RT: Runtime> Peer<RT> {
             .sockets
             .insert(fd, Socket::Inactive { local: None })
             .is_none());
+        assert!(inner.options.insert(fd, SocketOptions::default()).is_none());
         fd
     }
 
-    pub fn bind(&self, fd: FileDescriptor, addr: ipv4::Endpoint) -> Result<(), Fail> {
+    pub fn setsockopt(&self, fd: FileDescriptor, option: SocketOption) -> Result<(), Fail> {
+        let mut inner_ = self.inner.borrow_mut();
+        let inner = &mut *inner_;
+        let options = inner
+            .options
+            .get_mut(&fd)
+            .ok_or(Fail::Malformed { details: "Bad FD" })?;
+        options.set(option)?;
+
+        // Push the new values down to wherever the socket's state currently lives.
+        match inner.sockets.get(&fd) {
+            Some(Socket::Listening { local }) => {
+                if let Some(s) = inner.passive.get_mut(local) {
+                    s.set_options(options.clone());
+                }
+            },
+            Some(Socket::Connecting { local, remote }) => {
+                if let Some(s) = inner.connecting.get_mut(&(*local, *remote)) {
+                    s.set_options(options.clone());
+                }
+            },
+            Some(Socket::Established { local, remote }) => {
+                if let Some(s) = inner.established.get(&(*local, *remote)) {
+                    s.cb.set_options(options.clone());
+                }
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+
+    pub fn getsockopt(
+        &self,
+        fd: FileDescriptor,
+        name: SocketOptionName,
+    ) -> Result<SocketOption, Fail> {
+        let inner = self.inner.borrow();
+        let options = inner
+            .options
+            .get(&fd)
+            .ok_or(Fail::Malformed { details: "Bad FD" })?;
+        Ok(options.get(name))
+    }
+
+    /// Binding to the unspecified address binds to our primary address of the same family, since
+    /// a listener only accepts connections to the one it's bound to.
+    pub fn bind(&self, fd: FileDescriptor, mut addr: ip::Endpoint) -> Result<(), Fail> {
         let mut inner = self.inner.borrow_mut();
         if addr.port() >= ip::Port::first_private_port() {
             return Err(Fail::Malformed {
                 details: "Port number in private port range",
             });
         }
+        if addr.addr.is_unspecified() {
+            addr.addr = match addr.addr {
+                IpAddr::V4(..) => inner.rt.local_ipv4_addr().into(),
+                IpAddr::V6(..) => inner.network.ipv6_routes.primary_addr().into(),
+            };
+        }
+        if !inner.network.is_local(addr.addr) {
+            return Err(Fail::AddressNotAvailable {});
+        }
         match inner.sockets.get_mut(&fd) {
             Some(Socket::Inactive { ref mut local }) => {
                 *local = Some(addr);
@@ -131,10 +198,27 @@ impl<RT: Runtime> Peer<RT> {
         }
     }
 
-    pub fn receive(&self, ip_header: &Ipv4Header, buf: Bytes) -> Result<(), Fail> {
+    pub fn receive(&self, ip_header: &ip::Header, buf: Bytes) -> Result<(), Fail> {
         self.inner.borrow_mut().receive(ip_header, buf)
     }
 
+    /// Handles an ICMP error about a segment we sent from `local` to `remote`.
+    pub fn receive_unreachable(
+        &self,
+        local: ip::Endpoint,
+        remote: ip::Endpoint,
+        seq_num: SeqNumber,
+        error: Fail,
+    ) -> Result<(), Fail> {
+        let mut inner = self.inner.borrow_mut();
+        match inner.connecting.get_mut(&(local, remote)) {
+            Some(socket) => socket.receive_unreachable(seq_num, error),
+            None => Err(Fail::Ignored {
+                details: "ICMP error for a connection that isn't opening",
+            }),
+        }
+    }
+
     pub fn listen(&self, fd: FileDescriptor, backlog: usize) -> Result<(), Fail> {
         let mut inner = self.inner.borrow_mut();
         let local = match inner.sockets.get_mut(&fd) {
@@ -152,7 +236,14 @@ impl<RT: Runtime> Peer<RT> {
             });
         }
 
-        let socket = PassiveSocket::new(local, backlog, inner.rt.clone(), inner.arp.clone());
+        let options = inner.options.get(&fd).cloned().unwrap_or_default();
+        let socket = PassiveSocket::new(
+            local,
+            backlog,
+            options,
+            inner.rt.clone(),
+            inner.network.clone(),
+        );
         assert!(inner.passive.insert(local.clone(), socket).is_none());
         inner.sockets.insert(fd, Socket::Listening { local });
         Ok(())
@@ -168,6 +259,7 @@ impl<RT: Runtime> Peer<RT> {
 
         let local = match inner.sockets.get(&fd) {
             Some(Socket::Listening { local }) => local,
+            Some(Socket::Closed) => return Poll::Ready(Err(Fail::ConnectionAborted {})),
             Some(..) => {
                 return Poll::Ready(Err(Fail::Malformed {
                     details: "Socket not listening",
@@ -185,6 +277,8 @@ impl<RT: Runtime> Peer<RT> {
             Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
         };
         let fd = inner.file_table.alloc(File::TcpSocket);
+        let options = cb.options.borrow().clone();
+        assert!(inner.options.insert(fd, options).is_none());
         let established = EstablishedSocket::new(cb, fd, inner.dead_socket_tx.clone());
         let key = (established.cb.local.clone(), established.cb.remote.clone());
 
@@ -198,27 +292,65 @@ impl<RT: Runtime> Peer<RT> {
         Poll::Ready(Ok(fd))
     }
 
+    pub fn listener_stats(&self, fd: FileDescriptor) -> Result<ListenerStats, Fail> {
+        let inner = self.inner.borrow();
+        let local = match inner.sockets.get(&fd) {
+            Some(Socket::Listening { local }) => local,
+            Some(..) => {
+                return Err(Fail::Malformed {
+                    details: "Socket not listening",
+                })
+            },
+            None => return Err(Fail::Malformed { details: "Bad FD" }),
+        };
+        let passive = inner
+            .passive
+            .get(local)
+            .expect("sockets/local inconsistency");
+        Ok(passive.stats())
+    }
+
     pub fn accept(&self, fd: FileDescriptor) -> AcceptFuture<RT> {
+        *self
+            .inner
+            .borrow_mut()
+            .pending_accepts
+            .entry(fd)
+            .or_insert(0) += 1;
         AcceptFuture {
             fd,
             inner: self.inner.clone(),
         }
     }
 
-    pub fn connect(&self, fd: FileDescriptor, remote: ipv4::Endpoint) -> ConnectFuture<RT> {
+    pub fn connect(&self, fd: FileDescriptor, remote: ip::Endpoint) -> ConnectFuture<RT> {
         let mut inner = self.inner.borrow_mut();
 
         let r = try {
-            match inner.sockets.get_mut(&fd) {
-                Some(Socket::Inactive { .. }) => (),
+            // Send from the bound address if there is one.
+            let local_addr = match inner.sockets.get(&fd) {
+                Some(Socket::Inactive { local: Some(local) }) => local.addr,
+                Some(Socket::Inactive { local: None }) => inner.network.source_addr(remote.addr),
                 _ => Err(Fail::Malformed {
                     details: "Invalid file descriptor",
                 })?,
+            };
+            if local_addr.is_ipv4() != remote.addr.is_ipv4() {
+                Err(Fail::AddressFamilyNotSupported {})?;
+            }
+            if inner.network.is_lost(local_addr) {
+                Err(Fail::AddressInUse {})?;
             }
 
             // TODO: We need to free these!
-            let local_port = inner.ephemeral_ports.alloc()?;
-            let local = ipv4::Endpoint::new(inner.rt.local_ipv4_addr(), local_port);
+            let local_port = match inner.rt.ipv4_options().flow_steering {
+                // Pick a port whose replies get steered back to this engine.
+                Some(steering) => inner.ephemeral_ports.alloc_where(|port| {
+                    steering.owns(&ip::Endpoint::new(local_addr, port), &remote)
+                })?,
+                None => inner.ephemeral_ports.alloc()?,
+            };
+            let local = ip::Endpoint::new(local_addr, local_port);
 
             let socket = Socket::Connecting {
                 local: local.clone(),
@@ -228,12 +360,14 @@ impl<RT: Runtime> Peer<RT> {
 
             let local_isn = inner.isn_generator.generate(&local, &remote);
             let key = (local.clone(), remote.clone());
+            let options = inner.options.get(&fd).cloned().unwrap_or_default();
             let socket = ActiveOpenSocket::new(
                 local_isn,
                 local,
                 remote,
+                options,
                 inner.rt.clone(),
-                inner.arp.clone(),
+                inner.network.clone(),
             );
             assert!(inner.connecting.insert(key, socket).is_none());
             fd
@@ -347,11 +481,15 @@ impl<RT: Runtime> Peer<RT> {
     }
 
     pub fn close(&self, fd: FileDescriptor) -> Result<(), Fail> {
-        let inner = self.inner.borrow_mut();
+        let mut inner = self.inner.borrow_mut();
         match inner.sockets.get(&fd) {
             Some(Socket::Established { local, remote }) => {
                 let key = (local.clone(), remote.clone());
                 match inner.established.get(&key) {
+                    // A zero linger timeout aborts the connection instead of shutting it down.
+                    Some(ref s) if s.cb.options.borrow().linger == Some(Duration::new(0, 0)) => {
+                        s.abort()?
+                    },
                     Some(ref s) => s.close()?,
                     None => {
                         return Err(Fail::Malformed {
@@ -360,8 +498,32 @@ impl<RT: Runtime> Peer<RT> {
                     },
                 }
             },
-            Some(..) => {
-                // TODO: Implement close for listening sockets.
+            Some(Socket::Listening { local }) => {
+                let local = *local;
+                let mut socket = inner
+                    .passive
+                    .remove(&local)
+                    .expect("sockets/local inconsistency");
+                socket.close();
+                inner.options.remove(&fd);
+
+                // Pending accepts still refer to the listener by its FD, so we can only give it
+                // back once they've all observed the close.
+                if inner.pending_accepts.contains_key(&fd) {
+                    inner.sockets.insert(fd, Socket::Closed);
+                } else {
+                    inner.sockets.remove(&fd);
+                    inner.file_table.free(fd);
+                }
+            },
+            Some(Socket::Closed) => return Err(Fail::Malformed { details: "Bad FD" }),
+            Some(Socket::Inactive { .. }) => {
+                inner.sockets.remove(&fd);
+                inner.options.remove(&fd);
+                inner.file_table.free(fd);
+            },
+            Some(Socket::Connecting { .. }) => {
+                // TODO: Implement close for connecting sockets.
                 unimplemented!();
             },
             None => return Err(Fail::Malformed { details: "Bad FD" }),
@@ -369,6 +531,40 @@ impl<RT: Runtime> Peer<RT> {
         Ok(())
     }
 
+    /// Like `close`, but for sockets with a nonzero linger timeout the returned future only
+    /// completes once all queued data has been acknowledged or the timeout expires.
+    pub fn async_close(&self, fd: FileDescriptor) -> CloseFuture {
+        if let Err(e) = self.close(fd) {
+            return CloseFuture {
+                fd,
+                future: Box::pin(future::err::<(), Fail>(e)),
+            };
+        }
+        let inner = self.inner.borrow();
+        let lingering = match inner.sockets.get(&fd) {
+            Some(Socket::Established { local, remote }) => {
+                match inner.established.get(&(*local, *remote)) {
+                    Some(s) => match s.cb.options.borrow().linger {
+                        Some(timeout) if timeout > Duration::new(0, 0) => Some(s.linger(timeout)),
+                        _ => None,
+                    },
+                    None => None,
+                }
+            },
+            _ => None,
+        };
+        match lingering {
+            Some(f) => CloseFuture {
+                fd,
+                future: Box::pin(f),
+            },
+            None => CloseFuture {
+                fd,
+                future: Box::pin(future::ok::<(), Fail>(())),
+            },
+        }
+    }
+
     pub fn remote_mss(&self, fd: FileDescriptor) -> Result<usize, Fail> {
         let inner = self.inner.borrow();
         let key = match inner.sockets.get(&fd) {
@@ -411,7 +607,7 @@ impl<RT: Runtime> Peer<RT> {
         }
     }
 
-    pub fn endpoints(&self, fd: FileDescriptor) -> Result<(ipv4::Endpoint, ipv4::Endpoint), Fail> {
+    pub fn endpoints(&self, fd: FileDescriptor) -> Result<(ip::Endpoint, ip::Endpoint), Fail> {
         let inner = self.inner.borrow();
         let key = match inner.sockets.get(&fd) {
             Some(Socket::Established { local, remote }) => (*local, *remote),
@@ -435,19 +631,21 @@ impl<RT: Runtime> Peer<RT> {
 
 enum Socket {
     Inactive {
-        local: Option<ipv4::Endpoint>,
+        local: Option<ip::Endpoint>,
     },
     Listening {
-        local: ipv4::Endpoint,
+        local: ip::Endpoint,
     },
     Connecting {
-        local: ipv4::Endpoint,
-        remote: ipv4::Endpoint,
+        local: ip::Endpoint,
+        remote: ip::Endpoint,
     },
     Established {
-        local: ipv4::Endpoint,
-        remote: ipv4::Endpoint,
+        local: ip::Endpoint,
+        remote: ip::Endpoint,
     },
+    /// A closed listener whose FD is held until its pending accepts complete.
+    Closed,
 }
 
 pub struct Inner<RT: Runtime> {
@@ -458,13 +656,16 @@ pub struct Inner<RT: Runtime> {
 
     // FD -> local port
     sockets: HashMap<FileDescriptor, Socket>,
+    options: HashMap<FileDescriptor, SocketOptions>,
+    // FD -> number of outstanding `AcceptFuture`s
+    pending_accepts: HashMap<FileDescriptor, usize>,
 
-    passive: HashMap<ipv4::Endpoint, PassiveSocket<RT>>,
-    connecting: HashMap<(ipv4::Endpoint, ipv4::Endpoint), ActiveOpenSocket<RT>>,
-    established: HashMap<(ipv4::Endpoint, ipv4::Endpoint), EstablishedSocket<RT>>,
+    passive: HashMap<ip::Endpoint, PassiveSocket<RT>>,
+    connecting: HashMap<(ip::Endpoint, ip::Endpoint), ActiveOpenSocket<RT>>,
+    established: HashMap<(ip::Endpoint, ip::Endpoint), EstablishedSocket<RT>>,
 
     rt: RT,
-    arp: arp::Peer<RT>,
+    network: ip::Network<RT>,
 
     dead_socket_tx: UnboundedSender<FileDescriptor>,
     dead_socket_handle: Option<SchedulerHandle>,
@@ -473,7 +674,7 @@ pub struct Inner<RT: Runtime> {
 impl<RT: Runtime> Inner<RT> {
     fn new(
         rt: RT,
-        arp: arp::Peer<RT>,
+        network: ip::Network<RT>,
         file_table: FileTable,
         dead_socket_tx: UnboundedSender<FileDescriptor>,
     ) -> Self {
@@ -482,24 +683,47 @@ impl<RT: Runtime> Inner<RT> {
             file_table,
             ephemeral_ports: EphemeralPorts::new(&rt),
             sockets: HashMap::new(),
+            options: HashMap::new(),
+            pending_accepts: HashMap::new(),
             passive: HashMap::new(),
             connecting: HashMap::new(),
             established: HashMap::new(),
             rt,
-            arp,
+            network,
             dead_socket_tx,
             dead_socket_handle: None,
         }
     }
 
-    fn receive(&mut self, ip_hdr: &Ipv4Header, buf: Bytes) -> Result<(), Fail> {
+    /// Called when an `AcceptFuture` goes away, releasing its listener's FD if it was the last
+    /// one holding a closed listener open.
+    pub(super) fn accept_dropped(&mut self, fd: FileDescriptor) {
+        let pending = self
+            .pending_accepts
+            .get_mut(&fd)
+            .expect("Dropped untracked accept");
+        *pending -= 1;
+        if *pending > 0 {
+            return;
+        }
+        self.pending_accepts.remove(&fd);
+        if let Some(Socket::Closed) = self.sockets.get(&fd) {
+            self.sockets.remove(&fd);
+            self.file_table.free(fd);
+        }
+    }
+
+    fn receive(&mut self, ip_hdr: &ip::Header, buf: Bytes) -> Result<(), Fail> {
         let tcp_options = self.rt.tcp_options();
         let (tcp_hdr, data) = TcpHeader::parse(ip_hdr, buf, tcp_options.rx_checksum_offload)?;
-        let local = ipv4::Endpoint::new(ip_hdr.dst_addr, tcp_hdr.dst_port);
-        let remote = ipv4::Endpoint::new(ip_hdr.src_addr, tcp_hdr.src_port);
+        let local = ip::Endpoint::new(ip_hdr.dst_addr(), tcp_hdr.dst_port);
+        let remote = ip::Endpoint::new(ip_hdr.src_addr(), tcp_hdr.src_port);
 
-        if remote.addr.is_broadcast() || remote.addr.is_multicast() || remote.addr.is_unspecified()
-        {
+        let is_broadcast = match remote.addr {
+            IpAddr::V4(addr) => addr.is_broadcast(),
+            IpAddr::V6(..) => false,
+        };
+        if is_broadcast || remote.addr.is_multicast() || remote.addr.is_unspecified() {
             return Err(Fail::Malformed {
                 details: "Invalid address type",
             });
@@ -524,10 +748,10 @@ impl<RT: Runtime> Inner<RT> {
         Ok(())
     }
 
-    fn send_rst(&mut self, local: &ipv4::Endpoint, remote: &ipv4::Endpoint) -> Result<(), Fail> {
+    fn send_rst(&mut self, local: &ip::Endpoint, remote: &ip::Endpoint) -> Result<(), Fail> {
         // TODO: Make this work pending on ARP resolution if needed.
         let remote_link_addr =
-            self.arp
+            self.network
                 .try_query(remote.addr)
                 .ok_or_else(|| Fail::ResourceNotFound {
                     details: "RST destination not in ARP cache",
@@ -536,13 +760,17 @@ impl<RT: Runtime> Inner<RT> {
         let mut tcp_hdr = TcpHeader::new(local.port, remote.port);
         tcp_hdr.rst = true;
 
+        let ip_hdr = self
+            .network
+            .new_header(local.addr, remote.addr, ip::Protocol::Tcp)?;
         let segment = TcpSegment {
             ethernet2_hdr: Ethernet2Header {
                 dst_addr: remote_link_addr,
                 src_addr: self.rt.local_link_addr(),
-                ether_type: EtherType2::Ipv4,
+                vlan_tags: self.rt.ethernet2_options().vlan_tags(None),
+                ether_type: ip_hdr.ether_type(),
             },
-            ipv4_hdr: Ipv4Header::new(local.addr, remote.addr, Ipv4Protocol2::Tcp),
+            ip_hdr,
             tcp_hdr,
             data: Bytes::empty(),
             tx_checksum_offload: self.rt.tcp_options().tx_checksum_offload,
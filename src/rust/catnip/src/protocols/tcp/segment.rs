@@ -8,10 +8,6 @@ use crate::{
             MIN_PAYLOAD_SIZE,
         },
         ip,
-        ipv4::datagram::{
-            Ipv4Header,
-            Ipv4Protocol2,
-        },
         tcp::SeqNumber,
     },
     runtime::PacketBuf,
@@ -38,7 +34,7 @@ const MAX_TCP_OPTIONS: usize = 5;
 
 pub struct TcpSegment {
     pub ethernet2_hdr: Ethernet2Header,
-    pub ipv4_hdr: Ipv4Header,
+    pub ip_hdr: ip::Header,
     pub tcp_hdr: TcpHeader,
     pub data: Bytes,
 
@@ -48,7 +44,7 @@ pub struct TcpSegment {
 impl PacketBuf for TcpSegment {
     fn compute_size(&self) -> usize {
         let size = self.ethernet2_hdr.compute_size()
-            + self.ipv4_hdr.compute_size()
+            + self.ip_hdr.compute_size()
             + self.tcp_hdr.compute_size()
             + self.data.len();
 
@@ -58,7 +54,7 @@ impl PacketBuf for TcpSegment {
 
     fn serialize(&self, buf: &mut [u8]) {
         let eth_hdr_size = self.ethernet2_hdr.compute_size();
-        let ipv4_hdr_size = self.ipv4_hdr.compute_size();
+        let ip_hdr_size = self.ip_hdr.compute_size();
         let tcp_hdr_size = self.tcp_hdr.compute_size();
         let mut cur_pos = 0;
 
@@ -66,16 +62,14 @@ impl PacketBuf for TcpSegment {
             .serialize(&mut buf[cur_pos..(cur_pos + eth_hdr_size)]);
         cur_pos += eth_hdr_size;
 
-        let ipv4_payload_len = tcp_hdr_size + self.data.len();
-        self.ipv4_hdr.serialize(
-            &mut buf[cur_pos..(cur_pos + ipv4_hdr_size)],
-            ipv4_payload_len,
-        );
-        cur_pos += ipv4_hdr_size;
+        let ip_payload_len = tcp_hdr_size + self.data.len();
+        self.ip_hdr
+            .serialize(&mut buf[cur_pos..(cur_pos + ip_hdr_size)], ip_payload_len);
+        cur_pos += ip_hdr_size;
 
         self.tcp_hdr.serialize(
             &mut buf[cur_pos..(cur_pos + tcp_hdr_size)],
-            &self.ipv4_hdr,
+            &self.ip_hdr,
             &self.data[..],
             self.tx_checksum_offload,
         );
@@ -237,7 +231,7 @@ impl TcpHeader {
     }
 
     pub fn parse(
-        ipv4_header: &Ipv4Header,
+        ip_header: &ip::Header,
         buf: Bytes,
         rx_checksum_offload: bool,
     ) -> Result<(Self, Bytes), Fail> {
@@ -285,7 +279,7 @@ impl TcpHeader {
 
         if !rx_checksum_offload {
             let checksum = NetworkEndian::read_u16(&hdr_buf[16..18]);
-            if checksum != tcp_checksum(ipv4_header, &hdr_buf[..], &data_buf[..]) {
+            if checksum != tcp_checksum(ip_header, &hdr_buf[..], &data_buf[..]) {
                 return Err(Fail::Malformed {
                     details: "TCP checksum mismatch",
                 });
@@ -409,7 +403,7 @@ impl TcpHeader {
     pub fn serialize(
         &self,
         buf: &mut [u8],
-        ipv4_hdr: &Ipv4Header,
+        ip_hdr: &ip::Header,
         data: &[u8],
         tx_checksum_offload: bool,
     ) {
@@ -473,7 +467,7 @@ impl TcpHeader {
 
         // Alright, we've fully filled out the header, time to compute the checksum.
         if !tx_checksum_offload {
-            let checksum = tcp_checksum(ipv4_hdr, &buf[..], data);
+            let checksum = tcp_checksum(ip_hdr, &buf[..], data);
             NetworkEndian::write_u16(&mut buf[16..18], checksum);
         } else {
             NetworkEndian::write_u16(&mut buf[16..18], 0u16);
@@ -504,25 +498,11 @@ impl TcpHeader {
     }
 }
 
-fn tcp_checksum(ipv4_header: &Ipv4Header, header: &[u8], data: &[u8]) -> u16 {
+fn tcp_checksum(ip_header: &ip::Header, header: &[u8], data: &[u8]) -> u16 {
     let mut state = 0xffffu32;
 
-    // First, fold in a "pseudo-IP" header of...
-    // 1) Source address (4 bytes)
-    let src_octets = ipv4_header.src_addr.octets();
-    state += NetworkEndian::read_u16(&src_octets[0..2]) as u32;
-    state += NetworkEndian::read_u16(&src_octets[2..4]) as u32;
-
-    // 2) Destination address (4 bytes)
-    let dst_octets = ipv4_header.dst_addr.octets();
-    state += NetworkEndian::read_u16(&dst_octets[0..2]) as u32;
-    state += NetworkEndian::read_u16(&dst_octets[2..4]) as u32;
-
-    // 3) 1 byte of zeros and TCP protocol number (1 byte)
-    state += NetworkEndian::read_u16(&[0, Ipv4Protocol2::Tcp as u8]) as u32;
-
-    // 4) TCP segment length (2 bytes)
-    state += (header.len() + data.len()) as u32;
+    // First, fold in a "pseudo-IP" header with the addresses, protocol and TCP segment length.
+    state += ip_header.pseudo_header_sum(header.len() + data.len());
 
     let fixed_header: &[u8; MIN_TCP_HEADER2_SIZE] =
         header[..MIN_TCP_HEADER2_SIZE].try_into().unwrap();
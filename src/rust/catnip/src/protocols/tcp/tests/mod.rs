@@ -1,9 +1,25 @@
 use crate::{
+    fail::Fail,
     protocols::{
+        ethernet2::frame::Ethernet2Header,
         ip,
-        ipv4,
+        ipv4::{
+            self,
+            datagram::Ipv4Header,
+        },
+        tcp::{
+            constants::{
+                KEEPALIVE_INTERVAL,
+                KEEPALIVE_PROBES,
+            },
+            segment::TcpHeader,
+        },
     },
     runtime::Runtime,
+    socket_options::{
+        SocketOption,
+        SocketOptionName,
+    },
     sync::BytesMut,
     test_helpers,
 };
@@ -12,6 +28,10 @@ use must_let::must_let;
 use std::{
     convert::TryFrom,
     future::Future,
+    net::{
+        Ipv4Addr,
+        Ipv6Addr,
+    },
     pin::Pin,
     task::{
         Context,
@@ -33,7 +53,7 @@ fn test_connect() {
 
     // Establish the connection between the two peers.
     let listen_port = ip::Port::try_from(80).unwrap();
-    let listen_addr = ipv4::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+    let listen_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
 
     let listen_fd = bob.tcp_socket();
     bob.tcp_bind(listen_fd, listen_addr).unwrap();
@@ -94,3 +114,465 @@ fn test_connect() {
     bob.receive(alice.rt().pop_frame()).unwrap();
     bob.rt().poll_scheduler();
 }
+
+#[test]
+fn test_bind_unspecified() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    let mut alice = test_helpers::new_alice(now);
+    let mut bob = test_helpers::new_bob(now);
+    let listen_port = ip::Port::try_from(80).unwrap();
+
+    // Listening on the unspecified address listens on Bob's own.
+    let listen_fd = bob.tcp_socket();
+    bob.tcp_bind(
+        listen_fd,
+        ip::Endpoint::new(Ipv4Addr::UNSPECIFIED, listen_port),
+    )
+    .unwrap();
+    bob.tcp_listen(listen_fd, 1).unwrap();
+    let mut accept_future = bob.tcp_accept(listen_fd);
+
+    let alice_fd = alice.tcp_socket();
+    let listen_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+    let mut connect_future = alice.tcp_connect(alice_fd, listen_addr);
+    alice.rt().poll_scheduler();
+    bob.receive(alice.rt().pop_frame()).unwrap();
+    bob.rt().poll_scheduler();
+    alice.receive(bob.rt().pop_frame()).unwrap();
+    alice.rt().poll_scheduler();
+    bob.receive(alice.rt().pop_frame()).unwrap();
+    must_let!(let Poll::Ready(Ok(..)) = Future::poll(Pin::new(&mut accept_future), &mut ctx));
+    must_let!(let Poll::Ready(Ok(())) = Future::poll(Pin::new(&mut connect_future), &mut ctx));
+
+    // The same goes for IPv6.
+    let listen_fd = bob.tcp_socket();
+    bob.tcp_bind(
+        listen_fd,
+        ip::Endpoint::new(Ipv6Addr::UNSPECIFIED, listen_port),
+    )
+    .unwrap();
+    bob.tcp_listen(listen_fd, 1).unwrap();
+}
+
+#[test]
+fn test_accept_queue_overflow() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    let mut alice = test_helpers::new_alice(now);
+    let mut bob = test_helpers::new_bob(now);
+    let mut carrie = test_helpers::new_carrie(now);
+
+    let listen_port = ip::Port::try_from(80).unwrap();
+    let listen_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+
+    let listen_fd = bob.tcp_socket();
+    bob.tcp_bind(listen_fd, listen_addr).unwrap();
+    bob.tcp_listen(listen_fd, 1).unwrap();
+
+    // Alice's connection fills up Bob's accept queue.
+    let alice_fd = alice.tcp_socket();
+    let _alice_connect = alice.tcp_connect(alice_fd, listen_addr);
+    alice.rt().poll_scheduler();
+    bob.receive(alice.rt().pop_frame()).unwrap();
+    bob.rt().poll_scheduler();
+    alice.receive(bob.rt().pop_frame()).unwrap();
+    alice.rt().poll_scheduler();
+    bob.receive(alice.rt().pop_frame()).unwrap();
+
+    // Carrie's handshake completes, but her ACK is dropped since nothing has been accepted yet.
+    let carrie_fd = carrie.tcp_socket();
+    let _carrie_connect = carrie.tcp_connect(carrie_fd, listen_addr);
+    carrie.rt().poll_scheduler();
+    bob.receive(carrie.rt().pop_frame()).unwrap();
+    bob.rt().poll_scheduler();
+    carrie.receive(bob.rt().pop_frame()).unwrap();
+    carrie.rt().poll_scheduler();
+    let carrie_ack = carrie.rt().pop_frame();
+    must_let!(let Err(Fail::ResourceExhausted { .. }) = bob.receive(carrie_ack.clone()));
+
+    let stats = bob.tcp_listener_stats(listen_fd).unwrap();
+    assert_eq!(stats.accept_overflows, 1);
+    assert_eq!(stats.syn_overflows, 0);
+
+    // Once Alice's connection is accepted, a retransmitted ACK from Carrie gets through.
+    let mut accept_future = bob.tcp_accept(listen_fd);
+    must_let!(let Poll::Ready(Ok(..)) = Future::poll(Pin::new(&mut accept_future), &mut ctx));
+    bob.receive(carrie_ack).unwrap();
+    let mut accept_future = bob.tcp_accept(listen_fd);
+    must_let!(let Poll::Ready(Ok(..)) = Future::poll(Pin::new(&mut accept_future), &mut ctx));
+}
+
+#[test]
+fn test_close_listener() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    let mut alice = test_helpers::new_alice(now);
+    let mut bob = test_helpers::new_bob(now);
+    let mut carrie = test_helpers::new_carrie(now);
+
+    let listen_port = ip::Port::try_from(80).unwrap();
+    let listen_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+
+    let listen_fd = bob.tcp_socket();
+    bob.tcp_bind(listen_fd, listen_addr).unwrap();
+    bob.tcp_listen(listen_fd, 2).unwrap();
+    let mut accept_future = bob.tcp_accept(listen_fd);
+
+    // Alice completes her handshake, but the connection is never accepted.
+    let alice_fd = alice.tcp_socket();
+    let _alice_connect = alice.tcp_connect(alice_fd, listen_addr);
+    alice.rt().poll_scheduler();
+    bob.receive(alice.rt().pop_frame()).unwrap();
+    bob.rt().poll_scheduler();
+    alice.receive(bob.rt().pop_frame()).unwrap();
+    alice.rt().poll_scheduler();
+    bob.receive(alice.rt().pop_frame()).unwrap();
+
+    // Carrie's handshake is still inflight: her SYN+ACK gets lost.
+    let carrie_fd = carrie.tcp_socket();
+    let mut carrie_connect = carrie.tcp_connect(carrie_fd, listen_addr);
+    carrie.rt().poll_scheduler();
+    bob.receive(carrie.rt().pop_frame()).unwrap();
+    bob.rt().poll_scheduler();
+    bob.rt().pop_frame();
+
+    // Closing the listener resets both connections and fails the pending accept.
+    bob.close(listen_fd).unwrap();
+    carrie.receive(bob.rt().pop_frame()).unwrap();
+    alice.receive(bob.rt().pop_frame()).unwrap();
+    must_let!(let Poll::Ready(Err(Fail::ConnectionAborted {})) = Future::poll(Pin::new(&mut accept_future), &mut ctx));
+    must_let!(let Poll::Ready(Err(Fail::ConnectionRefused {})) = Future::poll(Pin::new(&mut carrie_connect), &mut ctx));
+
+    // The endpoint can be reused right away, but the listener's FD is held until its accept is
+    // gone, so a new listener can't pick up a stale accept.
+    let new_listen_fd = bob.tcp_socket();
+    assert_ne!(new_listen_fd, listen_fd);
+    bob.tcp_bind(new_listen_fd, listen_addr).unwrap();
+    bob.tcp_listen(new_listen_fd, 2).unwrap();
+    must_let!(let Poll::Ready(Err(Fail::ConnectionAborted {})) = Future::poll(Pin::new(&mut accept_future), &mut ctx));
+    must_let!(let Err(Fail::Malformed { .. }) = bob.close(listen_fd));
+
+    drop(accept_future);
+    assert_eq!(bob.tcp_socket(), listen_fd);
+}
+
+#[test]
+fn test_shared_listener() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+    let num_queues = 2;
+    let num_connections = 16;
+
+    let mut alice = test_helpers::new_alice(now);
+    let mut bobs = test_helpers::new_bob_queues(now, num_queues);
+
+    // Every engine listens on the same endpoint.
+    let listen_port = ip::Port::try_from(80).unwrap();
+    let listen_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+    let mut listen_fds = vec![];
+    for bob in &mut bobs {
+        let fd = bob.tcp_socket();
+        bob.tcp_bind(fd, listen_addr).unwrap();
+        bob.tcp_listen(fd, num_connections).unwrap();
+        listen_fds.push(fd);
+    }
+
+    // Bob's simulated NIC hands each of Alice's frames to the engine its 4-tuple hashes to.
+    let mut accepted = vec![0; num_queues];
+    for _ in 0..num_connections {
+        let alice_fd = alice.tcp_socket();
+        let _connect_future = alice.tcp_connect(alice_fd, listen_addr);
+        alice.rt().poll_scheduler();
+        let syn = alice.rt().pop_frame();
+        let queue = ipv4::flow::steer_frame(syn.clone(), num_queues).unwrap();
+        bobs[queue].receive(syn).unwrap();
+
+        bobs[queue].rt().poll_scheduler();
+        alice.receive(bobs[queue].rt().pop_frame()).unwrap();
+        alice.rt().poll_scheduler();
+        let ack = alice.rt().pop_frame();
+        assert_eq!(
+            ipv4::flow::steer_frame(ack.clone(), num_queues),
+            Some(queue)
+        );
+        bobs[queue].receive(ack).unwrap();
+
+        let mut accept_future = bobs[queue].tcp_accept(listen_fds[queue]);
+        must_let!(let Poll::Ready(Ok(..)) = Future::poll(Pin::new(&mut accept_future), &mut ctx));
+        accepted[queue] += 1;
+    }
+    assert!(accepted.iter().all(|&n| n > 0));
+
+    // Active opens pick a local port whose replies come back to the same engine.
+    let alice_listen_addr = ip::Endpoint::new(test_helpers::ALICE_IPV4, listen_port);
+    let alice_listen_fd = alice.tcp_socket();
+    alice.tcp_bind(alice_listen_fd, alice_listen_addr).unwrap();
+    alice.tcp_listen(alice_listen_fd, num_queues).unwrap();
+    for (queue, bob) in bobs.iter_mut().enumerate() {
+        let bob_fd = bob.tcp_socket();
+        let _connect_future = bob.tcp_connect(bob_fd, alice_listen_addr);
+        bob.rt().poll_scheduler();
+        alice.receive(bob.rt().pop_frame()).unwrap();
+        alice.rt().poll_scheduler();
+        let syn_ack = alice.rt().pop_frame();
+        assert_eq!(ipv4::flow::steer_frame(syn_ack, num_queues), Some(queue));
+    }
+}
+
+#[test]
+fn test_socket_options() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    let mut alice = test_helpers::new_alice(now);
+    let mut bob = test_helpers::new_bob(now);
+
+    let listen_port = ip::Port::try_from(80).unwrap();
+    let listen_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+
+    let listen_fd = bob.tcp_socket();
+    bob.setsockopt(listen_fd, SocketOption::TimeToLive(Some(17)))
+        .unwrap();
+    bob.setsockopt(listen_fd, SocketOption::Dscp(0x2e)).unwrap();
+    must_let!(let Err(Fail::OutOfRange { .. }) = bob.setsockopt(listen_fd, SocketOption::Dscp(0x40)));
+    must_let!(let Ok(SocketOption::TimeToLive(Some(17))) = bob.getsockopt(listen_fd, SocketOptionName::TimeToLive));
+    bob.tcp_bind(listen_fd, listen_addr).unwrap();
+    bob.tcp_listen(listen_fd, 1).unwrap();
+    let mut accept_future = bob.tcp_accept(listen_fd);
+
+    let alice_fd = alice.tcp_socket();
+    let _connect_future = alice.tcp_connect(alice_fd, listen_addr);
+    alice.rt().poll_scheduler();
+    bob.receive(alice.rt().pop_frame()).unwrap();
+
+    // The SYN+ACK carries the listener's TTL and DSCP.
+    bob.rt().poll_scheduler();
+    let syn_ack = bob.rt().pop_frame();
+    let (_, payload) = Ethernet2Header::parse(syn_ack.clone()).unwrap();
+    let (ipv4_hdr, _) = Ipv4Header::parse(payload).unwrap();
+    assert_eq!(ipv4_hdr.time_to_live, 17);
+    assert_eq!(ipv4_hdr.dscp, 0x2e);
+    alice.receive(syn_ack).unwrap();
+    alice.rt().poll_scheduler();
+    bob.receive(alice.rt().pop_frame()).unwrap();
+
+    // Accepted sockets inherit the listener's options.
+    must_let!(let Poll::Ready(Ok(bob_fd)) = Future::poll(Pin::new(&mut accept_future), &mut ctx));
+    must_let!(let Ok(SocketOption::Dscp(0x2e)) = bob.getsockopt(bob_fd, SocketOptionName::Dscp));
+}
+
+#[test]
+fn test_linger() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    let mut alice = test_helpers::new_alice(now);
+    let mut bob = test_helpers::new_bob(now);
+
+    let listen_port = ip::Port::try_from(80).unwrap();
+    let listen_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+
+    let listen_fd = bob.tcp_socket();
+    bob.tcp_bind(listen_fd, listen_addr).unwrap();
+    bob.tcp_listen(listen_fd, 1).unwrap();
+    let mut accept_future = bob.tcp_accept(listen_fd);
+
+    let alice_fd = alice.tcp_socket();
+    let mut connect_future = alice.tcp_connect(alice_fd, listen_addr);
+    alice.rt().poll_scheduler();
+    bob.receive(alice.rt().pop_frame()).unwrap();
+    bob.rt().poll_scheduler();
+    alice.receive(bob.rt().pop_frame()).unwrap();
+    alice.rt().poll_scheduler();
+    bob.receive(alice.rt().pop_frame()).unwrap();
+
+    must_let!(let Poll::Ready(Ok(..)) = Future::poll(Pin::new(&mut accept_future), &mut ctx));
+    must_let!(let Poll::Ready(Ok(())) = Future::poll(Pin::new(&mut connect_future), &mut ctx));
+
+    let linger = SocketOption::Linger(Some(Duration::from_secs(10)));
+    alice.setsockopt(alice_fd, linger).unwrap();
+
+    let buf = BytesMut::from(&vec![0x5a; 32][..]).freeze();
+    let mut write_future = alice.tcp_push(alice_fd, buf);
+    must_let!(let Poll::Ready(Ok(())) = Future::poll(Pin::new(&mut write_future), &mut ctx));
+    alice.rt().poll_scheduler();
+    bob.receive(alice.rt().pop_frame()).unwrap();
+
+    // The close doesn't complete until Bob has acknowledged Alice's data.
+    let mut close_future = alice.tcp_async_close(alice_fd);
+    must_let!(let Poll::Pending = Future::poll(Pin::new(&mut close_future), &mut ctx));
+    alice.rt().poll_scheduler();
+    bob.receive(alice.rt().pop_frame()).unwrap();
+    must_let!(let Poll::Pending = Future::poll(Pin::new(&mut close_future), &mut ctx));
+
+    bob.rt().poll_scheduler();
+    now += Duration::from_secs(5);
+    bob.rt().advance_clock(now);
+    bob.rt().poll_scheduler();
+    alice.receive(bob.rt().pop_frame()).unwrap();
+    alice.receive(bob.rt().pop_frame()).unwrap();
+    must_let!(let Poll::Ready(Ok(())) = Future::poll(Pin::new(&mut close_future), &mut ctx));
+}
+
+#[test]
+fn test_abortive_close() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    let mut alice = test_helpers::new_alice(now);
+    let mut bob = test_helpers::new_bob(now);
+
+    let listen_port = ip::Port::try_from(80).unwrap();
+    let listen_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+
+    let listen_fd = bob.tcp_socket();
+    bob.tcp_bind(listen_fd, listen_addr).unwrap();
+    bob.tcp_listen(listen_fd, 1).unwrap();
+    let mut accept_future = bob.tcp_accept(listen_fd);
+
+    let alice_fd = alice.tcp_socket();
+    let mut connect_future = alice.tcp_connect(alice_fd, listen_addr);
+    alice.rt().poll_scheduler();
+    bob.receive(alice.rt().pop_frame()).unwrap();
+    bob.rt().poll_scheduler();
+    alice.receive(bob.rt().pop_frame()).unwrap();
+    alice.rt().poll_scheduler();
+    bob.receive(alice.rt().pop_frame()).unwrap();
+
+    must_let!(let Poll::Ready(Ok(..)) = Future::poll(Pin::new(&mut accept_future), &mut ctx));
+    must_let!(let Poll::Ready(Ok(())) = Future::poll(Pin::new(&mut connect_future), &mut ctx));
+
+    // With a zero linger timeout, closing sends a RST instead of a FIN.
+    alice
+        .setsockopt(alice_fd, SocketOption::Linger(Some(Duration::from_secs(0))))
+        .unwrap();
+    let mut close_future = alice.tcp_async_close(alice_fd);
+    must_let!(let Poll::Ready(Ok(())) = Future::poll(Pin::new(&mut close_future), &mut ctx));
+    alice.rt().poll_scheduler();
+
+    let (_, payload) = Ethernet2Header::parse(alice.rt().pop_frame()).unwrap();
+    let (ipv4_hdr, payload) = Ipv4Header::parse(payload).unwrap();
+    let (tcp_hdr, _) = TcpHeader::parse(&ip::Header::V4(ipv4_hdr), payload, false).unwrap();
+    assert!(tcp_hdr.rst);
+    assert!(!tcp_hdr.fin);
+}
+
+#[test]
+fn test_keepalive() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    let mut alice = test_helpers::new_alice(now);
+    let mut bob = test_helpers::new_bob(now);
+
+    let listen_port = ip::Port::try_from(80).unwrap();
+    let listen_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+
+    let listen_fd = bob.tcp_socket();
+    bob.tcp_bind(listen_fd, listen_addr).unwrap();
+    bob.tcp_listen(listen_fd, 1).unwrap();
+    let mut accept_future = bob.tcp_accept(listen_fd);
+
+    let alice_fd = alice.tcp_socket();
+    let mut connect_future = alice.tcp_connect(alice_fd, listen_addr);
+    alice.rt().poll_scheduler();
+    bob.receive(alice.rt().pop_frame()).unwrap();
+    bob.rt().poll_scheduler();
+    alice.receive(bob.rt().pop_frame()).unwrap();
+    alice.rt().poll_scheduler();
+    bob.receive(alice.rt().pop_frame()).unwrap();
+
+    must_let!(let Poll::Ready(Ok(..)) = Future::poll(Pin::new(&mut accept_future), &mut ctx));
+    must_let!(let Poll::Ready(Ok(())) = Future::poll(Pin::new(&mut connect_future), &mut ctx));
+
+    // We don't support Nagle's algorithm, so turning off `TCP_NODELAY` fails.
+    must_let!(let Err(Fail::Unsupported { .. }) = alice.setsockopt(alice_fd, SocketOption::NoDelay(false)));
+    must_let!(let Ok(SocketOption::NoDelay(true)) = alice.getsockopt(alice_fd, SocketOptionName::NoDelay));
+
+    let idle = Duration::from_secs(10);
+    alice
+        .setsockopt(alice_fd, SocketOption::KeepAlive(Some(idle)))
+        .unwrap();
+    alice.rt().poll_scheduler();
+    assert!(alice.rt().try_pop_frame().is_none());
+
+    // Once the connection has been idle long enough, Alice probes Bob, who answers with an ACK.
+    now += idle;
+    alice.rt().advance_clock(now);
+    alice.rt().poll_scheduler();
+    let probe = alice.rt().pop_frame();
+    let (_, payload) = Ethernet2Header::parse(probe.clone()).unwrap();
+    let (ipv4_hdr, payload) = Ipv4Header::parse(payload).unwrap();
+    let (tcp_hdr, data) = TcpHeader::parse(&ip::Header::V4(ipv4_hdr), payload, false).unwrap();
+    assert!(tcp_hdr.ack);
+    assert_eq!(data.len(), 0);
+    bob.receive(probe).unwrap();
+    alice.receive(bob.rt().pop_frame()).unwrap();
+    alice.rt().poll_scheduler();
+    assert!(alice.rt().try_pop_frame().is_none());
+
+    // If Bob stops answering, Alice gives up after her last probe goes unanswered.
+    now += idle;
+    alice.rt().advance_clock(now);
+    alice.rt().poll_scheduler();
+    alice.rt().pop_frame();
+    for _ in 1..KEEPALIVE_PROBES {
+        now += KEEPALIVE_INTERVAL;
+        alice.rt().advance_clock(now);
+        alice.rt().poll_scheduler();
+        alice.rt().pop_frame();
+    }
+    now += KEEPALIVE_INTERVAL;
+    alice.rt().advance_clock(now);
+    alice.rt().poll_scheduler();
+    assert!(alice.rt().try_pop_frame().is_none());
+    now += KEEPALIVE_INTERVAL;
+    alice.rt().advance_clock(now);
+    alice.rt().poll_scheduler();
+    assert!(alice.rt().try_pop_frame().is_none());
+}
+
+#[test]
+fn test_send_buffer_size() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    let mut alice = test_helpers::new_alice(now);
+    let mut bob = test_helpers::new_bob(now);
+
+    let listen_port = ip::Port::try_from(80).unwrap();
+    let listen_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+
+    let listen_fd = bob.tcp_socket();
+    bob.tcp_bind(listen_fd, listen_addr).unwrap();
+    bob.tcp_listen(listen_fd, 1).unwrap();
+    let mut accept_future = bob.tcp_accept(listen_fd);
+
+    let alice_fd = alice.tcp_socket();
+    let mut connect_future = alice.tcp_connect(alice_fd, listen_addr);
+    alice.rt().poll_scheduler();
+    bob.receive(alice.rt().pop_frame()).unwrap();
+    bob.rt().poll_scheduler();
+    alice.receive(bob.rt().pop_frame()).unwrap();
+    alice.rt().poll_scheduler();
+    bob.receive(alice.rt().pop_frame()).unwrap();
+
+    must_let!(let Poll::Ready(Ok(..)) = Future::poll(Pin::new(&mut accept_future), &mut ctx));
+    must_let!(let Poll::Ready(Ok(())) = Future::poll(Pin::new(&mut connect_future), &mut ctx));
+
+    // Unacknowledged data counts against the send buffer until Bob acknowledges it.
+    alice
+        .setsockopt(alice_fd, SocketOption::SendBufferSize(Some(64)))
+        .unwrap();
+    let buf = BytesMut::from(&vec![0x5a; 32][..]).freeze();
+    for _ in 0..2 {
+        let mut write_future = alice.tcp_push(alice_fd, buf.clone());
+        must_let!(let Poll::Ready(Ok(())) = Future::poll(Pin::new(&mut write_future), &mut ctx));
+    }
+    let mut write_future = alice.tcp_push(alice_fd, buf);
+    must_let!(let Poll::Ready(Err(Fail::ResourceExhausted { .. })) = Future::poll(Pin::new(&mut write_future), &mut ctx));
+}
@@ -6,10 +6,6 @@ use crate::{
             MIN_PAYLOAD_SIZE,
         },
         ip,
-        ipv4::datagram::{
-            Ipv4Header,
-            Ipv4Protocol2,
-        },
     },
     runtime::PacketBuf,
     sync::Bytes,
@@ -38,7 +34,7 @@ pub struct UdpHeader {
 
 pub struct UdpDatagram {
     pub ethernet2_hdr: Ethernet2Header,
-    pub ipv4_hdr: Ipv4Header,
+    pub ip_hdr: ip::Header,
     pub udp_hdr: UdpHeader,
     pub data: Bytes,
 }
@@ -46,7 +42,7 @@ pub struct UdpDatagram {
 impl PacketBuf for UdpDatagram {
     fn compute_size(&self) -> usize {
         let size = self.ethernet2_hdr.compute_size()
-            + self.ipv4_hdr.compute_size()
+            + self.ip_hdr.compute_size()
             + self.udp_hdr.compute_size()
             + self.data.len();
 
@@ -56,7 +52,7 @@ impl PacketBuf for UdpDatagram {
 
     fn serialize(&self, buf: &mut [u8]) {
         let eth_hdr_size = self.ethernet2_hdr.compute_size();
-        let ipv4_hdr_size = self.ipv4_hdr.compute_size();
+        let ip_hdr_size = self.ip_hdr.compute_size();
         let udp_hdr_size = self.udp_hdr.compute_size();
         let mut cur_pos = 0;
 
@@ -64,16 +60,14 @@ impl PacketBuf for UdpDatagram {
             .serialize(&mut buf[cur_pos..(cur_pos + eth_hdr_size)]);
         cur_pos += eth_hdr_size;
 
-        let ipv4_payload_len = udp_hdr_size + self.data.len();
-        self.ipv4_hdr.serialize(
-            &mut buf[cur_pos..(cur_pos + ipv4_hdr_size)],
-            ipv4_payload_len,
-        );
-        cur_pos += ipv4_hdr_size;
+        let ip_payload_len = udp_hdr_size + self.data.len();
+        self.ip_hdr
+            .serialize(&mut buf[cur_pos..(cur_pos + ip_hdr_size)], ip_payload_len);
+        cur_pos += ip_hdr_size;
 
         self.udp_hdr.serialize(
             &mut buf[cur_pos..(cur_pos + udp_hdr_size)],
-            &self.ipv4_hdr,
+            &self.ip_hdr,
             &self.data[..],
         );
         cur_pos += udp_hdr_size;
@@ -93,7 +87,7 @@ impl UdpHeader {
         UDP_HEADER2_SIZE
     }
 
-    pub fn parse(ipv4_header: &Ipv4Header, buf: Bytes) -> Result<(Self, Bytes), Fail> {
+    pub fn parse(ip_header: &ip::Header, buf: Bytes) -> Result<(Self, Bytes), Fail> {
         if buf.len() < UDP_HEADER2_SIZE {
             return Err(Fail::Malformed {
                 details: "UDP segment too small",
@@ -112,7 +106,15 @@ impl UdpHeader {
         }
 
         let checksum = NetworkEndian::read_u16(&hdr_buf[6..8]);
-        if checksum != 0 && checksum != udp_checksum(&ipv4_header, &hdr_buf[..], &data_buf[..]) {
+        // RFC 8200 section 8.1: The checksum is only optional over IPv4.
+        if checksum == 0 {
+            if let ip::Header::V6(..) = ip_header {
+                return Err(Fail::Malformed {
+                    details: "UDP checksum missing",
+                });
+            }
+        }
+        if checksum != 0 && checksum != udp_checksum(ip_header, &hdr_buf[..], &data_buf[..]) {
             return Err(Fail::Malformed {
                 details: "UDP checksum mismatch",
             });
@@ -122,7 +124,7 @@ impl UdpHeader {
         Ok((header, data_buf))
     }
 
-    fn serialize(&self, buf: &mut [u8], ipv4_hdr: &Ipv4Header, data: &[u8]) {
+    fn serialize(&self, buf: &mut [u8], ip_hdr: &ip::Header, data: &[u8]) {
         let fixed_buf: &mut [u8; UDP_HEADER2_SIZE] =
             (&mut buf[..UDP_HEADER2_SIZE]).try_into().unwrap();
 
@@ -133,30 +135,16 @@ impl UdpHeader {
         NetworkEndian::write_u16(&mut fixed_buf[2..4], self.dst_port.into());
         NetworkEndian::write_u16(&mut fixed_buf[4..6], (UDP_HEADER2_SIZE + data.len()) as u16);
 
-        let checksum = udp_checksum(ipv4_hdr, &fixed_buf[..], data);
+        let checksum = udp_checksum(ip_hdr, &fixed_buf[..], data);
         NetworkEndian::write_u16(&mut fixed_buf[6..8], checksum);
     }
 }
 
-fn udp_checksum(ipv4_header: &Ipv4Header, header: &[u8], data: &[u8]) -> u16 {
+fn udp_checksum(ip_header: &ip::Header, header: &[u8], data: &[u8]) -> u16 {
     let mut state = 0xffffu32;
 
-    // First, hash an IPv4 "psuedo header" consisting of...
-    // 1) Source address (4 bytes)
-    let src_octets = ipv4_header.src_addr.octets();
-    state += NetworkEndian::read_u16(&src_octets[0..2]) as u32;
-    state += NetworkEndian::read_u16(&src_octets[2..4]) as u32;
-
-    // 2) Destination address (4 bytes)
-    let dst_octets = ipv4_header.dst_addr.octets();
-    state += NetworkEndian::read_u16(&dst_octets[0..2]) as u32;
-    state += NetworkEndian::read_u16(&dst_octets[2..4]) as u32;
-
-    // 3) 1 byte of zeros and UDP protocol number (1 byte)
-    state += NetworkEndian::read_u16(&[0, Ipv4Protocol2::Udp as u8]) as u32;
-
-    // 4) UDP segment length (2 bytes)
-    state += (header.len() + data.len()) as u32;
+    // First, hash the IP "psuedo header" with the addresses, protocol and UDP segment length.
+    state += ip_header.pseudo_header_sum(header.len() + data.len());
 
     // Then, include the UDP header.
     let fixed_header: &[u8; UDP_HEADER2_SIZE] = header.try_into().unwrap();
@@ -4,6 +4,7 @@
 use super::datagram::{
     UdpDatagram,
     UdpHeader,
+    UDP_HEADER2_SIZE,
 };
 use crate::{
     fail::Fail,
@@ -18,30 +19,39 @@ use crate::{
     },
     protocols::{
         arp,
-        ethernet2::frame::{
-            EtherType2,
-            Ethernet2Header,
+        ethernet2::frame::Ethernet2Header,
+        ip::{
+            self,
+            port::EphemeralPorts,
         },
         ipv4,
-        ipv4::datagram::{
-            Ipv4Header,
-            Ipv4Protocol2,
-        },
     },
     runtime::Runtime,
     scheduler::SchedulerHandle,
-    sync::{
-        Bytes,
-        UnboundedReceiver,
-        UnboundedSender,
+    socket_options::{
+        SocketOption,
+        SocketOptionName,
+        SocketOptions,
     },
+    sync::Bytes,
+};
+use futures::channel::oneshot::{
+    channel,
+    Receiver,
 };
-use futures_intrusive::channel::shared::generic_channel;
 use hashbrown::HashMap;
 use std::{
-    cell::RefCell,
+    cell::{
+        Cell,
+        RefCell,
+    },
     collections::VecDeque,
     future::Future,
+    net::{
+        IpAddr,
+        Ipv4Addr,
+        Ipv6Addr,
+    },
     pin::Pin,
     rc::Rc,
     task::{
@@ -51,90 +61,94 @@ use std::{
     },
 };
 
+// How many bytes a socket may have waiting for link addresses, unless its send buffer size says
+// otherwise.
+const DEFAULT_SEND_QUEUE_SIZE: usize = 64 * 1024;
+
+// How much a socket holds on to until it's popped, unless its options say otherwise.
+const DEFAULT_RECEIVE_BUFFER_SIZE: usize = 256 * 1024;
+const DEFAULT_RECEIVE_DATAGRAM_LIMIT: usize = 1024;
+
+/// Counters for the datagrams that arrived at a socket.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SocketStats {
+    pub datagrams_received: usize,
+    pub bytes_received: usize,
+    /// Datagrams that found the socket's receive buffer full.
+    pub datagrams_dropped: usize,
+    pub bytes_dropped: usize,
+}
+
+#[derive(Clone)]
 pub struct UdpPeer<RT: Runtime> {
     inner: Rc<RefCell<Inner<RT>>>,
 }
 
 struct Listener {
-    buf: VecDeque<(Option<ipv4::Endpoint>, Bytes)>,
+    fd: FileDescriptor,
+    buf: VecDeque<(Option<ip::Endpoint>, Bytes)>,
+    buf_bytes: usize,
+    stats: SocketStats,
+    // An ICMP error for the connected remote, reported by the next pop.
+    error: Option<Fail>,
     waker: Option<Waker>,
 }
 
 #[derive(Debug)]
 struct Socket {
     // `bind(2)` fixes a local address
-    local: Option<ipv4::Endpoint>,
+    local: Option<ip::Endpoint>,
     // `connect(2)` fixes a remote address
-    remote: Option<ipv4::Endpoint>,
+    remote: Option<ip::Endpoint>,
+    options: SocketOptions,
+    // Multicast groups joined through the socket, left when it closes.
+    groups: Vec<Ipv4Addr>,
+    // Bytes of the datagrams waiting for their destination's link address.
+    queued: Rc<Cell<usize>>,
+    // Whether the local port came from `ephemeral_ports` on the first send.
+    autobound: bool,
 }
 
-type OutgoingReq = (Option<ipv4::Endpoint>, ipv4::Endpoint, Bytes);
-type OutgoingSender = UnboundedSender<OutgoingReq>;
-type OutgoingReceiver = UnboundedReceiver<OutgoingReq>;
-
 struct Inner<RT: Runtime> {
     #[allow(unused)]
     rt: RT,
-    #[allow(unused)]
-    arp: arp::Peer<RT>,
+    network: ip::Network<RT>,
+    fragmenter: ipv4::Fragmenter<RT>,
     file_table: FileTable,
 
     sockets: HashMap<FileDescriptor, Socket>,
-    bound: HashMap<ipv4::Endpoint, Rc<RefCell<Listener>>>,
+    bound: HashMap<ip::Endpoint, Rc<RefCell<Listener>>>,
+    ephemeral_ports: EphemeralPorts,
 
-    outgoing: OutgoingSender,
-    #[allow(unused)]
-    handle: SchedulerHandle,
+    // IPv6 datagrams waiting for Neighbor Discovery. IPv4 ones wait in ARP instead.
+    resolving: Rc<RefCell<HashMap<u64, SchedulerHandle>>>,
+    next_resolving_id: Cell<u64>,
 }
 
 impl<RT: Runtime> UdpPeer<RT> {
-    pub fn new(rt: RT, arp: arp::Peer<RT>, file_table: FileTable) -> Self {
-        let (tx, rx) = generic_channel(16);
-        let future = Self::background(rt.clone(), arp.clone(), rx);
-        let handle = rt.spawn(future);
+    pub fn new(
+        rt: RT,
+        network: ip::Network<RT>,
+        fragmenter: ipv4::Fragmenter<RT>,
+        file_table: FileTable,
+    ) -> Self {
+        let ephemeral_ports = EphemeralPorts::new(&rt);
         let inner = Inner {
             rt,
-            arp,
+            network,
+            fragmenter,
             file_table,
             sockets: HashMap::new(),
             bound: HashMap::new(),
-            outgoing: tx,
-            handle,
+            ephemeral_ports,
+            resolving: Rc::new(RefCell::new(HashMap::new())),
+            next_resolving_id: Cell::new(0),
         };
         Self {
             inner: Rc::new(RefCell::new(inner)),
         }
     }
 
-    async fn background(rt: RT, arp: arp::Peer<RT>, rx: OutgoingReceiver) {
-        while let Some((local, remote, buf)) = rx.receive().await {
-            let r: Result<_, Fail> = try {
-                let link_addr = arp.query(remote.addr).await?;
-                let datagram = UdpDatagram {
-                    ethernet2_hdr: Ethernet2Header {
-                        dst_addr: link_addr,
-                        src_addr: rt.local_link_addr(),
-                        ether_type: EtherType2::Ipv4,
-                    },
-                    ipv4_hdr: Ipv4Header::new(
-                        rt.local_ipv4_addr(),
-                        remote.addr,
-                        Ipv4Protocol2::Udp,
-                    ),
-                    udp_hdr: UdpHeader {
-                        src_port: local.map(|l| l.port),
-                        dst_port: remote.port,
-                    },
-                    data: buf,
-                };
-                rt.transmit(datagram);
-            };
-            if let Err(e) = r {
-                warn!("Failed to send UDP message: {:?}", e);
-            }
-        }
-    }
-
     pub fn accept(&self) -> Fail {
         Fail::Malformed {
             details: "Operation not supported",
@@ -147,13 +161,63 @@ impl<RT: Runtime> UdpPeer<RT> {
         let socket = Socket {
             local: None,
             remote: None,
+            options: SocketOptions::default(),
+            groups: vec![],
+            queued: Rc::new(Cell::new(0)),
+            autobound: false,
         };
         assert!(inner.sockets.insert(fd, socket).is_none());
         fd
     }
 
-    pub fn bind(&self, fd: FileDescriptor, addr: ipv4::Endpoint) -> Result<(), Fail> {
+    pub fn setsockopt(&self, fd: FileDescriptor, option: SocketOption) -> Result<(), Fail> {
+        Self::check_option(option.name())?;
+        let mut inner = self.inner.borrow_mut();
+        match inner.sockets.get_mut(&fd) {
+            Some(s) => s.options.set(option),
+            None => Err(Fail::Malformed {
+                details: "Invalid file descriptor on setsockopt",
+            }),
+        }
+    }
+
+    pub fn getsockopt(
+        &self,
+        fd: FileDescriptor,
+        name: SocketOptionName,
+    ) -> Result<SocketOption, Fail> {
+        Self::check_option(name)?;
+        let inner = self.inner.borrow();
+        match inner.sockets.get(&fd) {
+            Some(s) => Ok(s.options.get(name)),
+            None => Err(Fail::Malformed {
+                details: "Invalid file descriptor on getsockopt",
+            }),
+        }
+    }
+
+    fn check_option(name: SocketOptionName) -> Result<(), Fail> {
+        match name {
+            SocketOptionName::NoDelay | SocketOptionName::MaxSegmentSize => {
+                Err(Fail::Unsupported {
+                    details: "TCP option on UDP socket",
+                })
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Binding to a multicast group receives the datagrams sent to it, once some socket joins
+    /// the group. Binding to the unspecified address receives the port's datagrams that no other
+    /// socket is bound to, including broadcasts.
+    pub fn bind(&self, fd: FileDescriptor, addr: ip::Endpoint) -> Result<(), Fail> {
         let mut inner = self.inner.borrow_mut();
+        if !inner.network.is_local(addr.addr)
+            && !addr.addr.is_multicast()
+            && !addr.addr.is_unspecified()
+        {
+            return Err(Fail::AddressNotAvailable {});
+        }
         if inner.bound.contains_key(&addr) {
             return Err(Fail::Malformed {
                 details: "Port already listening",
@@ -169,18 +233,11 @@ impl<RT: Runtime> UdpPeer<RT> {
                 })
             },
         }
-        let listener = Listener {
-            buf: VecDeque::new(),
-            waker: None,
-        };
-        assert!(inner
-            .bound
-            .insert(addr, Rc::new(RefCell::new(listener)))
-            .is_none());
+        assert!(inner.bound.insert(addr, Listener::new(fd)).is_none());
         Ok(())
     }
 
-    pub fn connect(&self, fd: FileDescriptor, addr: ipv4::Endpoint) -> Result<(), Fail> {
+    pub fn connect(&self, fd: FileDescriptor, addr: ip::Endpoint) -> Result<(), Fail> {
         let mut inner = self.inner.borrow_mut();
         match inner.sockets.get_mut(&fd) {
             Some(Socket { ref mut remote, .. }) if remote.is_none() => {
@@ -193,51 +250,163 @@ impl<RT: Runtime> UdpPeer<RT> {
         }
     }
 
-    pub fn receive(&self, ipv4_header: &Ipv4Header, buf: Bytes) -> Result<(), Fail> {
-        let (hdr, data) = UdpHeader::parse(ipv4_header, buf)?;
-        let local = ipv4::Endpoint::new(ipv4_header.dst_addr, hdr.dst_port);
-        let remote = hdr
-            .src_port
-            .map(|p| ipv4::Endpoint::new(ipv4_header.src_addr, p));
+    pub fn join_multicast_group(&self, fd: FileDescriptor, group: Ipv4Addr) -> Result<(), Fail> {
+        let mut inner = self.inner.borrow_mut();
+        let inner = &mut *inner;
+        let socket = inner.sockets.get_mut(&fd).ok_or(Fail::ResourceNotFound {
+            details: "Invalid file descriptor on join",
+        })?;
+        if socket.groups.contains(&group) {
+            return Err(Fail::ResourceBusy {
+                details: "Multicast group already joined",
+            });
+        }
+        inner.network.igmp.join(group)?;
+        socket.groups.push(group);
+        Ok(())
+    }
 
-        // TODO: Send ICMPv4 error in this condition.
+    pub fn leave_multicast_group(&self, fd: FileDescriptor, group: Ipv4Addr) -> Result<(), Fail> {
         let mut inner = self.inner.borrow_mut();
-        let listener = inner.bound.get_mut(&local).ok_or_else(|| Fail::Malformed {
-            details: "Port not bound",
+        let inner = &mut *inner;
+        let socket = inner.sockets.get_mut(&fd).ok_or(Fail::ResourceNotFound {
+            details: "Invalid file descriptor on leave",
         })?;
+        match socket.groups.iter().position(|&g| g == group) {
+            Some(i) => socket.groups.swap_remove(i),
+            None => return Err(Fail::AddressNotAvailable {}),
+        };
+        inner.network.igmp.leave(group)
+    }
+
+    pub fn receive(&self, ip_header: &ip::Header, buf: Bytes) -> Result<(), Fail> {
+        let (hdr, data) = UdpHeader::parse(ip_header, buf)?;
+        let local = ip::Endpoint::new(ip_header.dst_addr(), hdr.dst_port);
+        let remote = hdr
+            .src_port
+            .map(|p| ip::Endpoint::new(ip_header.src_addr(), p));
+
+        // The caller answers with an ICMP port unreachable error.
+        let inner = self.inner.borrow();
+        let wildcard = ip::Endpoint::new(wildcard_addr(local.addr), local.port);
+        let mut listeners = vec![local, wildcard]
+            .into_iter()
+            .filter_map(|addr| inner.bound.get(&addr))
+            .peekable();
+        if listeners.peek().is_none() {
+            return Err(Fail::ConnectionRefused {});
+        }
+        // Connected sockets only take datagrams from their peer, leaving the rest to any socket
+        // bound to the wildcard address.
+        let listener = listeners
+            .find(|l| match inner.sockets[&l.borrow().fd].remote {
+                Some(peer) => remote == Some(peer),
+                None => true,
+            })
+            .ok_or(Fail::Ignored {
+                details: "UDP datagram from outside the connected peer",
+            })?;
         let mut l = listener.borrow_mut();
+
+        // Drop the datagram if the socket is already holding on to too much. It gets at least
+        // one, however small its buffer is.
+        let options = &inner.sockets[&l.fd].options;
+        let max_bytes = options
+            .receive_buffer_size
+            .unwrap_or(DEFAULT_RECEIVE_BUFFER_SIZE);
+        let max_datagrams = options
+            .receive_datagram_limit
+            .unwrap_or(DEFAULT_RECEIVE_DATAGRAM_LIMIT);
+        if !l.buf.is_empty()
+            && (l.buf.len() >= max_datagrams || l.buf_bytes + data.len() > max_bytes)
+        {
+            l.stats.datagrams_dropped += 1;
+            l.stats.bytes_dropped += data.len();
+            return Err(Fail::ResourceExhausted {
+                details: "UDP receive buffer full",
+            });
+        }
+        l.stats.datagrams_received += 1;
+        l.stats.bytes_received += data.len();
+        l.buf_bytes += data.len();
         l.buf.push_back((remote, data));
         l.waker.take().map(|w| w.wake());
         Ok(())
     }
 
-    pub fn push(&self, fd: FileDescriptor, buf: Bytes) -> Result<(), Fail> {
+    /// Sockets that aren't bound haven't received anything yet.
+    pub fn stats(&self, fd: FileDescriptor) -> Result<SocketStats, Fail> {
         let inner = self.inner.borrow();
-        let (local, remote) = match inner.sockets.get(&fd) {
+        let socket = inner.sockets.get(&fd).ok_or(Fail::ResourceNotFound {
+            details: "Invalid file descriptor on stats",
+        })?;
+        let stats = socket
+            .local
+            .and_then(|local| inner.bound.get(&local))
+            .map(|l| l.borrow().stats)
+            .unwrap_or_default();
+        Ok(stats)
+    }
+
+    /// Handles an ICMP error about a datagram we sent from `local` to `remote`. Like other
+    /// stacks, we only report it to a socket connected to `remote`.
+    pub fn receive_unreachable(
+        &self,
+        local: ip::Endpoint,
+        remote: ip::Endpoint,
+        error: Fail,
+    ) -> Result<(), Fail> {
+        let inner = self.inner.borrow();
+        let wildcard = ip::Endpoint::new(wildcard_addr(local.addr), local.port);
+        let listener = inner
+            .sockets
+            .values()
+            .find(|s| {
+                s.remote == Some(remote) && (s.local == Some(local) || s.local == Some(wildcard))
+            })
+            .and_then(|s| inner.bound.get(&s.local?))
+            .ok_or(Fail::Ignored {
+                details: "ICMP error for an unconnected socket",
+            })?;
+        let mut l = listener.borrow_mut();
+        l.error = Some(error);
+        l.waker.take().map(|w| w.wake());
+        Ok(())
+    }
+
+    /// Fails right away if the datagram can't be sent or queued. Otherwise the future completes
+    /// once the datagram has been transmitted, or when it couldn't be.
+    pub fn push(&self, fd: FileDescriptor, buf: Bytes) -> Result<PushFuture, Fail> {
+        let mut inner = self.inner.borrow_mut();
+        let remote = match inner.sockets.get(&fd) {
             Some(Socket {
-                local,
                 remote: Some(remote),
-            }) => (*local, *remote),
+                ..
+            }) => *remote,
             _ => {
                 return Err(Fail::Malformed {
                     details: "Invalid file descriptor on push",
                 })
             },
         };
-        inner.send_datagram(buf, local, remote)
+        inner.autobind(fd, remote)?;
+        inner.send_datagram(fd, &inner.sockets[&fd], buf, remote)
     }
 
-    pub fn pushto(&self, fd: FileDescriptor, buf: Bytes, to: ipv4::Endpoint) -> Result<(), Fail> {
-        let inner = self.inner.borrow();
-        let local = match inner.sockets.get(&fd) {
-            Some(Socket { local, .. }) => *local,
-            _ => {
-                return Err(Fail::Malformed {
-                    details: "Invalid file descriptor on pushto",
-                })
-            },
-        };
-        inner.send_datagram(buf, local, to)
+    pub fn pushto(
+        &self,
+        fd: FileDescriptor,
+        buf: Bytes,
+        to: ip::Endpoint,
+    ) -> Result<PushFuture, Fail> {
+        let mut inner = self.inner.borrow_mut();
+        if !inner.sockets.contains_key(&fd) {
+            return Err(Fail::Malformed {
+                details: "Invalid file descriptor on pushto",
+            });
+        }
+        inner.autobind(fd, to)?;
+        inner.send_datagram(fd, &inner.sockets[&fd], buf, to)
     }
 
     pub fn pop(&self, fd: FileDescriptor) -> PopFuture {
@@ -245,8 +414,13 @@ impl<RT: Runtime> UdpPeer<RT> {
         let listener = match inner.sockets.get(&fd) {
             Some(Socket {
                 local: Some(local), ..
-            }) => Ok(inner.bound.get(&local).unwrap().clone()),
-            _ => Err(Fail::Malformed {
+            }) => inner.bound.get(&local).cloned().ok_or(Fail::Malformed {
+                details: "Socket not bound",
+            }),
+            Some(..) => Err(Fail::Invalid {
+                details: "Pop on unbound socket",
+            }),
+            None => Err(Fail::Malformed {
                 details: "Invalid file descriptor",
             }),
         };
@@ -265,45 +439,161 @@ impl<RT: Runtime> UdpPeer<RT> {
         };
         if let Some(local) = socket.local {
             assert!(inner.bound.remove(&local).is_some());
+            if socket.autobound {
+                inner.ephemeral_ports.free(local.port);
+            }
         }
         inner.file_table.free(fd);
+        for group in socket.groups {
+            inner.network.igmp.leave(group)?;
+        }
         Ok(())
     }
 }
 
 impl<RT: Runtime> Inner<RT> {
+    /// Binds an unbound socket to the wildcard address and an ephemeral port before it first
+    /// sends to `remote`, so it can get replies.
+    fn autobind(&mut self, fd: FileDescriptor, remote: ip::Endpoint) -> Result<(), Fail> {
+        match self.sockets.get(&fd) {
+            Some(Socket { local: None, .. }) => (),
+            _ => return Ok(()),
+        }
+        let addr = wildcard_addr(remote.addr);
+        let bound = &self.bound;
+        let port = match self.rt.ipv4_options().flow_steering {
+            // Pick a port whose replies get steered back to this engine.
+            Some(steering) => {
+                let local_addr = self.network.source_addr(remote.addr);
+                self.ephemeral_ports.alloc_where(|port| {
+                    !bound.keys().any(|e| e.port == port)
+                        && steering.owns(&ip::Endpoint::new(local_addr, port), &remote)
+                })?
+            },
+            None => self
+                .ephemeral_ports
+                .alloc_where(|port| !bound.keys().any(|e| e.port == port))?,
+        };
+        let local = ip::Endpoint::new(addr, port);
+        let socket = self.sockets.get_mut(&fd).unwrap();
+        socket.local = Some(local);
+        socket.autobound = true;
+        assert!(self.bound.insert(local, Listener::new(fd)).is_none());
+        Ok(())
+    }
+
     fn send_datagram(
         &self,
+        fd: FileDescriptor,
+        socket: &Socket,
         buf: Bytes,
-        local: Option<ipv4::Endpoint>,
-        remote: ipv4::Endpoint,
-    ) -> Result<(), Fail> {
-        // First, try to send the packet immediately.
-        if let Some(link_addr) = self.arp.try_query(remote.addr) {
+        remote: ip::Endpoint,
+    ) -> Result<PushFuture, Fail> {
+        let local = socket.local;
+        let options = &socket.options;
+        // Send from the bound address if there is one, unless it's a multicast group or the
+        // unspecified address.
+        let src_addr = match local {
+            Some(local) if !local.addr.is_multicast() && !local.addr.is_unspecified() => local.addr,
+            _ => self.network.source_addr(remote.addr),
+        };
+        if self.network.is_lost(src_addr) {
+            return Err(Fail::AddressInUse {});
+        }
+        let mut ip_hdr = self
+            .network
+            .new_header(src_addr, remote.addr, ip::Protocol::Udp)?;
+        options.apply_to_ip_header(&mut ip_hdr);
+        let vlan_tags = self.rt.ethernet2_options().vlan_tags(options.vlan_priority);
+        self.fragmenter
+            .check(&ip_hdr, UDP_HEADER2_SIZE + buf.len())?;
+
+        let len = buf.len();
+        let fragmenter = self.fragmenter.clone();
+        let src_link_addr = self.rt.local_link_addr();
+        let transmit = move |link_addr| {
             let datagram = UdpDatagram {
                 ethernet2_hdr: Ethernet2Header {
                     dst_addr: link_addr,
-                    src_addr: self.rt.local_link_addr(),
-                    ether_type: EtherType2::Ipv4,
+                    src_addr: src_link_addr,
+                    vlan_tags,
+                    ether_type: ip_hdr.ether_type(),
                 },
-                ipv4_hdr: Ipv4Header::new(
-                    self.rt.local_ipv4_addr(),
-                    remote.addr,
-                    Ipv4Protocol2::Udp,
-                ),
+                ip_hdr,
                 udp_hdr: UdpHeader {
                     src_port: local.map(|l| l.port),
                     dst_port: remote.port,
                 },
                 data: buf,
             };
-            self.rt.transmit(datagram);
+            fragmenter.transmit(datagram)
+        };
+        if let Some(link_addr) = self.network.try_query(remote.addr) {
+            transmit(link_addr)?;
+            return Ok(PushFuture::done(fd, Ok(())));
+        }
+
+        // Datagrams to neighbors we don't know yet wait without holding up the others, as long
+        // as the socket has room for them.
+        let limit = options.send_buffer_size.unwrap_or(DEFAULT_SEND_QUEUE_SIZE);
+        if socket.queued.get() + len > limit {
+            return Err(Fail::ResourceExhausted {
+                details: "Send queue full",
+            });
         }
-        // Otherwise defer to the async path.
-        else {
-            self.outgoing.try_send((local, remote, buf)).unwrap();
+        socket.queued.set(socket.queued.get() + len);
+        let queued = socket.queued.clone();
+        let (tx, rx) = channel();
+        let send: arp::PendingFrame = Box::new(move |link_addr| {
+            queued.set(queued.get() - len);
+            let r = link_addr.and_then(transmit);
+            let _ = tx.send(r.clone());
+            r
+        });
+        match remote.addr {
+            IpAddr::V4(ipv4_addr) => self.network.arp.transmit_when_resolved(ipv4_addr, send)?,
+            IpAddr::V6(ipv6_addr) => self.resolve_ipv6(ipv6_addr, send),
         }
-        Ok(())
+        Ok(PushFuture {
+            fd,
+            result: None,
+            sent: Some(rx),
+        })
+    }
+
+    fn resolve_ipv6(&self, ipv6_addr: Ipv6Addr, send: arp::PendingFrame) {
+        let id = self.next_resolving_id.get();
+        self.next_resolving_id.set(id + 1);
+        let resolving = self.resolving.clone();
+        let query = self.network.icmpv6.query(ipv6_addr);
+        let handle = self.rt.spawn(async move {
+            if let Err(e) = send(query.await) {
+                warn!("Failed to send UDP message: {:?}", e);
+            }
+            resolving.borrow_mut().remove(&id);
+        });
+        self.resolving.borrow_mut().insert(id, handle);
+    }
+}
+
+impl Listener {
+    fn new(fd: FileDescriptor) -> Rc<RefCell<Self>> {
+        let listener = Self {
+            fd,
+            buf: VecDeque::new(),
+            buf_bytes: 0,
+            stats: SocketStats::default(),
+            error: None,
+            waker: None,
+        };
+        Rc::new(RefCell::new(listener))
+    }
+}
+
+fn wildcard_addr(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V4(..) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(..) => Ipv6Addr::UNSPECIFIED.into(),
     }
 }
 
@@ -313,7 +603,7 @@ pub struct PopFuture {
 }
 
 impl Future for PopFuture {
-    type Output = Result<(Option<ipv4::Endpoint>, Bytes), Fail>;
+    type Output = Result<(Option<ip::Endpoint>, Bytes), Fail>;
 
     fn poll(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Self::Output> {
         let self_ = self.get_mut();
@@ -321,9 +611,12 @@ impl Future for PopFuture {
             Err(ref e) => Poll::Ready(Err(e.clone())),
             Ok(ref l) => {
                 let mut listener = l.borrow_mut();
-                match listener.buf.pop_front() {
-                    Some(r) => return Poll::Ready(Ok(r)),
-                    None => (),
+                if let Some(e) = listener.error.take() {
+                    return Poll::Ready(Err(e));
+                }
+                if let Some((remote, buf)) = listener.buf.pop_front() {
+                    listener.buf_bytes -= buf.len();
+                    return Poll::Ready(Ok((remote, buf)));
                 }
                 let waker = ctx.waker();
                 listener.waker = Some(waker.clone());
@@ -333,10 +626,46 @@ impl Future for PopFuture {
     }
 }
 
+pub struct PushFuture {
+    pub fd: FileDescriptor,
+    // Set when we knew how the push went right away.
+    result: Option<Result<(), Fail>>,
+    sent: Option<Receiver<Result<(), Fail>>>,
+}
+
+impl PushFuture {
+    pub fn done(fd: FileDescriptor, result: Result<(), Fail>) -> Self {
+        Self {
+            fd,
+            result: Some(result),
+            sent: None,
+        }
+    }
+}
+
+impl Future for PushFuture {
+    type Output = Result<(), Fail>;
+
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Self::Output> {
+        let self_ = self.get_mut();
+        if let Some(r) = self_.result.take() {
+            return Poll::Ready(r);
+        }
+        let sent = self_.sent.as_mut().expect("Polled after completion");
+        match Future::poll(Pin::new(sent), ctx) {
+            Poll::Ready(Ok(r)) => Poll::Ready(r),
+            // The datagram went away along with whoever was going to send it.
+            Poll::Ready(Err(..)) => Poll::Ready(Err(Fail::ConnectionAborted {})),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
 pub enum UdpOperation {
     Accept(FileDescriptor, Fail),
+    Close(FileDescriptor, Result<(), Fail>),
     Connect(FileDescriptor, Result<(), Fail>),
-    Push(FileDescriptor, Result<(), Fail>),
+    Push(ResultFuture<PushFuture>),
     Pop(ResultFuture<PopFuture>),
 }
 
@@ -345,9 +674,10 @@ impl Future for UdpOperation {
 
     fn poll(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<()> {
         match self.get_mut() {
-            UdpOperation::Accept(..) | UdpOperation::Connect(..) | UdpOperation::Push(..) => {
+            UdpOperation::Accept(..) | UdpOperation::Close(..) | UdpOperation::Connect(..) => {
                 Poll::Ready(())
             },
+            UdpOperation::Push(ref mut f) => Future::poll(Pin::new(f), ctx),
             UdpOperation::Pop(ref mut f) => Future::poll(Pin::new(f), ctx),
         }
     }
@@ -356,11 +686,19 @@ impl Future for UdpOperation {
 impl UdpOperation {
     pub fn expect_result(self) -> (FileDescriptor, OperationResult) {
         match self {
-            UdpOperation::Push(fd, Err(e))
+            UdpOperation::Close(fd, Err(e))
             | UdpOperation::Connect(fd, Err(e))
             | UdpOperation::Accept(fd, e) => (fd, OperationResult::Failed(e)),
+            UdpOperation::Close(fd, Ok(())) => (fd, OperationResult::Close),
             UdpOperation::Connect(fd, Ok(())) => (fd, OperationResult::Connect),
-            UdpOperation::Push(fd, Ok(())) => (fd, OperationResult::Push),
+            UdpOperation::Push(ResultFuture {
+                future,
+                done: Some(Ok(())),
+            }) => (future.fd, OperationResult::Push),
+            UdpOperation::Push(ResultFuture {
+                future,
+                done: Some(Err(e)),
+            }) => (future.fd, OperationResult::Failed(e)),
 
             UdpOperation::Pop(ResultFuture {
                 future,
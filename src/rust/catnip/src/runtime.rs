@@ -3,7 +3,13 @@
 use crate::{
     protocols::{
         arp,
-        ethernet2::MacAddress,
+        dns,
+        ethernet2::{
+            self,
+            MacAddress,
+        },
+        ipv4,
+        ipv6,
         tcp,
     },
     scheduler::{
@@ -38,8 +44,13 @@ pub trait Runtime: Clone + Unpin + 'static {
 
     fn local_link_addr(&self) -> MacAddress;
     fn local_ipv4_addr(&self) -> Ipv4Addr;
+    fn set_local_ipv4_addr(&self, addr: Ipv4Addr);
+    fn ethernet2_options(&self) -> ethernet2::Options;
     fn arp_options(&self) -> arp::Options;
+    fn ipv4_options(&self) -> ipv4::Options;
+    fn ipv6_options(&self) -> ipv6::Options;
     fn tcp_options(&self) -> tcp::Options;
+    fn dns_options(&self) -> dns::Options;
 
     type WaitFuture: Future<Output = ()>;
     fn wait(&self, duration: Duration) -> Self::WaitFuture;
@@ -10,6 +10,8 @@ use crate::{
         WAKER_PAGE_SIZE,
     },
     protocols::{
+        dns::operations::DnsOperation,
+        icmpv4::operations::Icmpv4Operation,
         tcp::operations::TcpOperation,
         udp::peer::UdpOperation,
     },
@@ -35,6 +37,8 @@ pub enum Operation<RT: Runtime> {
     // These are all stored inline to prevent hitting the allocator on insertion/removal.
     Tcp(TcpOperation<RT>),
     Udp(UdpOperation),
+    Icmpv4(Icmpv4Operation),
+    Dns(DnsOperation),
 
     // These are expected to have long lifetimes and be large enough to justify another allocation.
     Background(Pin<Box<dyn Future<Output = ()>>>),
@@ -47,6 +51,8 @@ impl<RT: Runtime> Future for Operation<RT> {
         match self.get_mut() {
             Operation::Tcp(ref mut f) => Future::poll(Pin::new(f), ctx),
             Operation::Udp(ref mut f) => Future::poll(Pin::new(f), ctx),
+            Operation::Icmpv4(ref mut f) => Future::poll(Pin::new(f), ctx),
+            Operation::Dns(ref mut f) => Future::poll(Pin::new(f), ctx),
             Operation::Background(ref mut f) => Future::poll(Pin::new(f), ctx),
         }
     }
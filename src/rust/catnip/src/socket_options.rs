@@ -0,0 +1,241 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use crate::{
+    fail::Fail,
+    protocols::{
+        ip,
+        ipv4::datagram::IPV4_FLAG_DONT_FRAGMENT,
+        tcp::constants::{
+            MAX_MSS,
+            MIN_MSS,
+        },
+    },
+};
+use std::time::Duration;
+
+/// Idle time before keepalive probes start when enabled without an explicit interval.
+pub const DEFAULT_KEEPALIVE_IDLE: Duration = Duration::from_secs(7200);
+
+/// Multicast datagrams stay on the local network unless the socket says otherwise.
+pub const DEFAULT_MULTICAST_TTL: u8 = 1;
+
+const MAX_DSCP: u8 = 0x3f;
+const MAX_VLAN_PRIORITY: u8 = 7;
+
+/// Per-socket overrides of the stack-wide defaults. `None` means the default from the runtime's
+/// options applies.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SocketOptions {
+    pub keepalive: Option<Duration>,
+    pub send_buffer_size: Option<usize>,
+    pub receive_buffer_size: Option<usize>,
+    // How many datagrams a UDP socket holds on to until they're popped.
+    pub receive_datagram_limit: Option<usize>,
+    pub linger: Option<Duration>,
+    pub mss: Option<usize>,
+    pub ttl: Option<u8>,
+    pub multicast_ttl: Option<u8>,
+    pub dscp: u8,
+    pub dont_fragment: bool,
+    // The 802.1Q priority code point for frames on a VLAN.
+    pub vlan_priority: Option<u8>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SocketOptionName {
+    NoDelay,
+    KeepAlive,
+    SendBufferSize,
+    ReceiveBufferSize,
+    ReceiveDatagramLimit,
+    Linger,
+    MaxSegmentSize,
+    TimeToLive,
+    MulticastTimeToLive,
+    Dscp,
+    DontFragment,
+    VlanPriority,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SocketOption {
+    NoDelay(bool),
+    KeepAlive(Option<Duration>),
+    SendBufferSize(Option<usize>),
+    ReceiveBufferSize(Option<usize>),
+    ReceiveDatagramLimit(Option<usize>),
+    Linger(Option<Duration>),
+    MaxSegmentSize(Option<usize>),
+    TimeToLive(Option<u8>),
+    MulticastTimeToLive(Option<u8>),
+    Dscp(u8),
+    DontFragment(bool),
+    VlanPriority(Option<u8>),
+}
+
+impl SocketOption {
+    pub fn name(&self) -> SocketOptionName {
+        match self {
+            SocketOption::NoDelay(..) => SocketOptionName::NoDelay,
+            SocketOption::KeepAlive(..) => SocketOptionName::KeepAlive,
+            SocketOption::SendBufferSize(..) => SocketOptionName::SendBufferSize,
+            SocketOption::ReceiveBufferSize(..) => SocketOptionName::ReceiveBufferSize,
+            SocketOption::ReceiveDatagramLimit(..) => SocketOptionName::ReceiveDatagramLimit,
+            SocketOption::Linger(..) => SocketOptionName::Linger,
+            SocketOption::MaxSegmentSize(..) => SocketOptionName::MaxSegmentSize,
+            SocketOption::TimeToLive(..) => SocketOptionName::TimeToLive,
+            SocketOption::MulticastTimeToLive(..) => SocketOptionName::MulticastTimeToLive,
+            SocketOption::Dscp(..) => SocketOptionName::Dscp,
+            SocketOption::DontFragment(..) => SocketOptionName::DontFragment,
+            SocketOption::VlanPriority(..) => SocketOptionName::VlanPriority,
+        }
+    }
+}
+
+impl SocketOptions {
+    pub fn set(&mut self, option: SocketOption) -> Result<(), Fail> {
+        match option {
+            // We never hold back small segments, so every TCP socket is effectively
+            // `TCP_NODELAY`.
+            SocketOption::NoDelay(true) => (),
+            SocketOption::NoDelay(false) => {
+                return Err(Fail::Unsupported {
+                    details: "Nagle's algorithm",
+                })
+            },
+            SocketOption::KeepAlive(value) => {
+                if value == Some(Duration::new(0, 0)) {
+                    return Err(Fail::Invalid {
+                        details: "Keepalive interval must be positive",
+                    });
+                }
+                self.keepalive = value;
+            },
+            SocketOption::SendBufferSize(value) => {
+                if value == Some(0) {
+                    return Err(Fail::Invalid {
+                        details: "Send buffer size must be positive",
+                    });
+                }
+                self.send_buffer_size = value;
+            },
+            SocketOption::ReceiveBufferSize(value) => {
+                if value == Some(0) {
+                    return Err(Fail::Invalid {
+                        details: "Receive buffer size must be positive",
+                    });
+                }
+                self.receive_buffer_size = value;
+            },
+            SocketOption::ReceiveDatagramLimit(value) => {
+                if value == Some(0) {
+                    return Err(Fail::Invalid {
+                        details: "Receive datagram limit must be positive",
+                    });
+                }
+                self.receive_datagram_limit = value;
+            },
+            SocketOption::Linger(value) => self.linger = value,
+            SocketOption::MaxSegmentSize(value) => {
+                if let Some(mss) = value {
+                    if mss < MIN_MSS || mss > MAX_MSS {
+                        return Err(Fail::OutOfRange {
+                            details: "MSS out of range",
+                        });
+                    }
+                }
+                self.mss = value;
+            },
+            SocketOption::TimeToLive(value) => {
+                if value == Some(0) {
+                    return Err(Fail::OutOfRange {
+                        details: "TTL must be positive",
+                    });
+                }
+                self.ttl = value;
+            },
+            SocketOption::MulticastTimeToLive(value) => {
+                if value == Some(0) {
+                    return Err(Fail::OutOfRange {
+                        details: "TTL must be positive",
+                    });
+                }
+                self.multicast_ttl = value;
+            },
+            SocketOption::Dscp(value) => {
+                if value > MAX_DSCP {
+                    return Err(Fail::OutOfRange {
+                        details: "DSCP out of range",
+                    });
+                }
+                self.dscp = value;
+            },
+            SocketOption::DontFragment(value) => self.dont_fragment = value,
+            SocketOption::VlanPriority(value) => {
+                if let Some(pcp) = value {
+                    if pcp > MAX_VLAN_PRIORITY {
+                        return Err(Fail::OutOfRange {
+                            details: "VLAN priority out of range",
+                        });
+                    }
+                }
+                self.vlan_priority = value;
+            },
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: SocketOptionName) -> SocketOption {
+        match name {
+            SocketOptionName::NoDelay => SocketOption::NoDelay(true),
+            SocketOptionName::KeepAlive => SocketOption::KeepAlive(self.keepalive),
+            SocketOptionName::SendBufferSize => SocketOption::SendBufferSize(self.send_buffer_size),
+            SocketOptionName::ReceiveBufferSize => {
+                SocketOption::ReceiveBufferSize(self.receive_buffer_size)
+            },
+            SocketOptionName::ReceiveDatagramLimit => {
+                SocketOption::ReceiveDatagramLimit(self.receive_datagram_limit)
+            },
+            SocketOptionName::Linger => SocketOption::Linger(self.linger),
+            SocketOptionName::MaxSegmentSize => SocketOption::MaxSegmentSize(self.mss),
+            SocketOptionName::TimeToLive => SocketOption::TimeToLive(self.ttl),
+            SocketOptionName::MulticastTimeToLive => {
+                SocketOption::MulticastTimeToLive(self.multicast_ttl)
+            },
+            SocketOptionName::Dscp => SocketOption::Dscp(self.dscp),
+            SocketOptionName::DontFragment => SocketOption::DontFragment(self.dont_fragment),
+            SocketOptionName::VlanPriority => SocketOption::VlanPriority(self.vlan_priority),
+        }
+    }
+
+    /// Stamps the socket's TTL, DSCP and DF bit onto an outgoing datagram. IPv6 uses the TTL as
+    /// its hop limit and has no DF bit, since routers never fragment IPv6 datagrams.
+    pub fn apply_to_ip_header(&self, header: &mut ip::Header) {
+        let ttl = if header.dst_addr().is_multicast() {
+            Some(self.multicast_ttl.unwrap_or(DEFAULT_MULTICAST_TTL))
+        } else {
+            self.ttl
+        };
+        match header {
+            ip::Header::V4(header) => {
+                if let Some(ttl) = ttl {
+                    header.time_to_live = ttl;
+                }
+                header.dscp = self.dscp;
+                if self.dont_fragment {
+                    header.flags |= IPV4_FLAG_DONT_FRAGMENT;
+                } else {
+                    header.flags &= !IPV4_FLAG_DONT_FRAGMENT;
+                }
+            },
+            ip::Header::V6(header) => {
+                if let Some(ttl) = ttl {
+                    header.hop_limit = ttl;
+                }
+                // The DSCP takes up the upper six bits of the traffic class.
+                header.traffic_class = (self.dscp << 2) | (header.traffic_class & 3);
+            },
+        }
+    }
+}
@@ -5,7 +5,13 @@ use crate::{
     engine::Engine,
     protocols::{
         arp,
-        ethernet2::MacAddress,
+        dns,
+        ethernet2::{
+            self,
+            MacAddress,
+        },
+        ipv4,
+        ipv6,
         tcp,
     },
     runtime::{
@@ -41,7 +47,10 @@ use std::{
     cell::RefCell,
     collections::VecDeque,
     future::Future,
-    net::Ipv4Addr,
+    net::{
+        Ipv4Addr,
+        Ipv6Addr,
+    },
     rc::Rc,
     time::{
         Duration,
@@ -56,6 +65,8 @@ pub const BOB_MAC: MacAddress = MacAddress::new([0xab, 0x89, 0x67, 0x45, 0x23, 0
 pub const BOB_IPV4: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 2);
 pub const CARRIE_MAC: MacAddress = MacAddress::new([0xef, 0xcd, 0xab, 0x89, 0x67, 0x45]);
 pub const CARRIE_IPV4: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 3);
+pub const ALICE_IPV6: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+pub const BOB_IPV6: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2);
 
 pub type TestEngine = Engine<TestRuntime>;
 
@@ -76,6 +87,8 @@ impl TestRuntime {
         arp_options.retry_count = 2;
         arp_options.cache_ttl = Duration::from_secs(600);
         arp_options.request_timeout = Duration::from_secs(1);
+        arp_options.probe_count = 0;
+        arp_options.announce_count = 0;
         arp_options.initial_values.insert(ALICE_MAC, ALICE_IPV4);
         arp_options.initial_values.insert(BOB_MAC, BOB_IPV4);
         arp_options.initial_values.insert(CARRIE_MAC, CARRIE_IPV4);
@@ -94,6 +107,10 @@ impl TestRuntime {
             ipv4_addr,
             tcp_options,
             arp_options,
+            ethernet2_options: ethernet2::Options::default(),
+            ipv4_options: ipv4::Options::default(),
+            ipv6_options: ipv6::Options::default(),
+            dns_options: dns::Options::default(),
         };
         Self {
             inner: Rc::new(RefCell::new(inner)),
@@ -105,6 +122,10 @@ impl TestRuntime {
         self.inner.borrow_mut().outgoing.pop_front().unwrap()
     }
 
+    pub fn try_pop_frame(&self) -> Option<Bytes> {
+        self.inner.borrow_mut().outgoing.pop_front()
+    }
+
     pub fn push_frame(&self, buf: Bytes) {
         self.inner.borrow_mut().incoming.push_back(buf);
     }
@@ -127,6 +148,10 @@ struct Inner {
     ipv4_addr: Ipv4Addr,
     tcp_options: tcp::Options,
     arp_options: arp::Options,
+    ethernet2_options: ethernet2::Options,
+    ipv4_options: ipv4::Options,
+    ipv6_options: ipv6::Options,
+    dns_options: dns::Options,
 }
 
 impl Runtime for TestRuntime {
@@ -155,6 +180,10 @@ impl Runtime for TestRuntime {
         self.inner.borrow().ipv4_addr.clone()
     }
 
+    fn set_local_ipv4_addr(&self, addr: Ipv4Addr) {
+        self.inner.borrow_mut().ipv4_addr = addr;
+    }
+
     fn tcp_options(&self) -> tcp::Options {
         self.inner.borrow().tcp_options.clone()
     }
@@ -163,6 +192,22 @@ impl Runtime for TestRuntime {
         self.inner.borrow().arp_options.clone()
     }
 
+    fn ethernet2_options(&self) -> ethernet2::Options {
+        self.inner.borrow().ethernet2_options.clone()
+    }
+
+    fn ipv4_options(&self) -> ipv4::Options {
+        self.inner.borrow().ipv4_options.clone()
+    }
+
+    fn ipv6_options(&self) -> ipv6::Options {
+        self.inner.borrow().ipv6_options.clone()
+    }
+
+    fn dns_options(&self) -> dns::Options {
+        self.inner.borrow().dns_options.clone()
+    }
+
     fn advance_clock(&self, now: Instant) {
         self.inner.borrow_mut().timer.0.advance_clock(now);
     }
@@ -209,12 +254,79 @@ pub fn new_alice(now: Instant) -> Engine<TestRuntime> {
     Engine::new(rt).unwrap()
 }
 
+pub fn new_alice_with_ipv4_options(now: Instant, options: ipv4::Options) -> Engine<TestRuntime> {
+    let rt = TestRuntime::new("alice", now, ALICE_MAC, ALICE_IPV4);
+    rt.inner.borrow_mut().ipv4_options = options;
+    Engine::new(rt).unwrap()
+}
+
 pub fn new_bob(now: Instant) -> Engine<TestRuntime> {
     let rt = TestRuntime::new("bob", now, BOB_MAC, BOB_IPV4);
     Engine::new(rt).unwrap()
 }
 
+pub fn new_bob_with_ipv4_options(now: Instant, options: ipv4::Options) -> Engine<TestRuntime> {
+    let rt = TestRuntime::new("bob", now, BOB_MAC, BOB_IPV4);
+    rt.inner.borrow_mut().ipv4_options = options;
+    Engine::new(rt).unwrap()
+}
+
+pub fn new_alice_with_ipv6_options(now: Instant, options: ipv6::Options) -> Engine<TestRuntime> {
+    let rt = TestRuntime::new("alice", now, ALICE_MAC, ALICE_IPV4);
+    rt.inner.borrow_mut().ipv6_options = options;
+    Engine::new(rt).unwrap()
+}
+
+pub fn new_bob_with_ipv6_options(now: Instant, options: ipv6::Options) -> Engine<TestRuntime> {
+    let rt = TestRuntime::new("bob", now, BOB_MAC, BOB_IPV4);
+    rt.inner.borrow_mut().ipv6_options = options;
+    Engine::new(rt).unwrap()
+}
+
+pub fn new_alice_with_arp_options(now: Instant, options: arp::Options) -> Engine<TestRuntime> {
+    let rt = TestRuntime::new("alice", now, ALICE_MAC, ALICE_IPV4);
+    rt.inner.borrow_mut().arp_options = options;
+    Engine::new(rt).unwrap()
+}
+
+pub fn new_alice_with_dns_options(now: Instant, options: dns::Options) -> Engine<TestRuntime> {
+    let rt = TestRuntime::new("alice", now, ALICE_MAC, ALICE_IPV4);
+    rt.inner.borrow_mut().dns_options = options;
+    Engine::new(rt).unwrap()
+}
+
+pub fn new_alice_with_ethernet2_options(
+    now: Instant,
+    options: ethernet2::Options,
+) -> Engine<TestRuntime> {
+    let rt = TestRuntime::new("alice", now, ALICE_MAC, ALICE_IPV4);
+    rt.inner.borrow_mut().ethernet2_options = options;
+    Engine::new(rt).unwrap()
+}
+
+pub fn new_bob_with_ethernet2_options(
+    now: Instant,
+    options: ethernet2::Options,
+) -> Engine<TestRuntime> {
+    let rt = TestRuntime::new("bob", now, BOB_MAC, BOB_IPV4);
+    rt.inner.borrow_mut().ethernet2_options = options;
+    Engine::new(rt).unwrap()
+}
+
 pub fn new_carrie(now: Instant) -> Engine<TestRuntime> {
     let rt = TestRuntime::new("carrie", now, CARRIE_MAC, CARRIE_IPV4);
     Engine::new(rt).unwrap()
 }
+
+/// Creates `num_queues` engines sharing Bob's addresses, as if each one polled its own queue of
+/// a single NIC. Use `ipv4::flow::steer_frame` to pick which of them gets an incoming frame.
+pub fn new_bob_queues(now: Instant, num_queues: usize) -> Vec<Engine<TestRuntime>> {
+    (0..num_queues)
+        .map(|queue| {
+            let rt = TestRuntime::new("bob", now, BOB_MAC, BOB_IPV4);
+            rt.inner.borrow_mut().ipv4_options =
+                ipv4::Options::default().flow_steering(ipv4::FlowSteering::new(queue, num_queues));
+            Engine::new(rt).unwrap()
+        })
+        .collect()
+}
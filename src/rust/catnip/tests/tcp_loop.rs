@@ -2,10 +2,7 @@
 
 use catnip::{
     file_table::FileDescriptor,
-    protocols::{
-        ip,
-        ipv4,
-    },
+    protocols::ip,
     sync::{
         Bytes,
         BytesMut,
@@ -81,7 +78,7 @@ fn tcp_loop() {
 
     // Establish the connection between the two peers.
     let listen_port = ip::Port::try_from(80).unwrap();
-    let listen_addr = ipv4::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+    let listen_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
 
     let listen_fd = bob.tcp_socket();
     bob.tcp_bind(listen_fd, listen_addr).unwrap();
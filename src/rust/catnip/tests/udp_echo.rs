@@ -9,9 +9,14 @@ use catnip::{
     libos::LibOS,
     protocols::{
         arp,
-        ethernet2::MacAddress,
+        dns,
+        ethernet2::{
+            self,
+            MacAddress,
+        },
         ip,
         ipv4,
+        ipv6,
         tcp,
     },
     runtime::{
@@ -23,6 +28,7 @@ use catnip::{
         Scheduler,
         SchedulerHandle,
     },
+    socket_options::SocketOption,
     sync::{
         Bytes,
         BytesMut,
@@ -32,6 +38,7 @@ use catnip::{
         ALICE_MAC,
         BOB_IPV4,
         BOB_MAC,
+        CARRIE_IPV4,
     },
     timer::{
         Timer,
@@ -84,6 +91,8 @@ impl TestRuntime {
         arp_options.retry_count = 2;
         arp_options.cache_ttl = Duration::from_secs(600);
         arp_options.request_timeout = Duration::from_secs(1);
+        arp_options.probe_count = 0;
+        arp_options.announce_count = 0;
         arp_options.initial_values.insert(ALICE_MAC, ALICE_IPV4);
         arp_options.initial_values.insert(BOB_MAC, BOB_IPV4);
 
@@ -96,6 +105,7 @@ impl TestRuntime {
             ipv4_addr,
             tcp_options: tcp::Options::default(),
             arp_options,
+            ipv4_options: ipv4::Options::default(),
         };
         Self {
             inner: Rc::new(RefCell::new(inner)),
@@ -114,6 +124,7 @@ struct Inner {
     ipv4_addr: Ipv4Addr,
     tcp_options: tcp::Options,
     arp_options: arp::Options,
+    ipv4_options: ipv4::Options,
 }
 
 impl Runtime for TestRuntime {
@@ -148,6 +159,10 @@ impl Runtime for TestRuntime {
         self.inner.borrow().ipv4_addr.clone()
     }
 
+    fn set_local_ipv4_addr(&self, addr: Ipv4Addr) {
+        self.inner.borrow_mut().ipv4_addr = addr;
+    }
+
     fn tcp_options(&self) -> tcp::Options {
         self.inner.borrow().tcp_options.clone()
     }
@@ -156,6 +171,22 @@ impl Runtime for TestRuntime {
         self.inner.borrow().arp_options.clone()
     }
 
+    fn ethernet2_options(&self) -> ethernet2::Options {
+        ethernet2::Options::default()
+    }
+
+    fn ipv4_options(&self) -> ipv4::Options {
+        self.inner.borrow().ipv4_options.clone()
+    }
+
+    fn ipv6_options(&self) -> ipv6::Options {
+        ipv6::Options::default()
+    }
+
+    fn dns_options(&self) -> dns::Options {
+        dns::Options::default()
+    }
+
     fn advance_clock(&self, now: Instant) {
         self.inner.borrow_mut().timer.0.advance_clock(now);
     }
@@ -197,6 +228,137 @@ impl Runtime for TestRuntime {
     }
 }
 
+#[test]
+fn udp_pop_unbound() {
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let alice_rt = TestRuntime::new(Instant::now(), ALICE_MAC, ALICE_IPV4, rx, tx);
+    let mut alice = LibOS::new(alice_rt).unwrap();
+
+    // The pop fails instead of taking the process down with it.
+    let alice_fd = alice.socket(libc::AF_INET, libc::SOCK_DGRAM, 0).unwrap();
+    let qt = alice.pop(alice_fd);
+    let qr = alice.wait(qt);
+    assert_eq!(qr.qr_opcode, dmtr_opcode_t::DMTR_OPC_FAILED);
+    assert_eq!(qr.qr_qd, alice_fd as i32);
+    assert_eq!(unsafe { qr.qr_value.failed.error }, libc::EINVAL);
+}
+
+#[test]
+fn udp_push_queue_full() {
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let alice_rt = TestRuntime::new(Instant::now(), ALICE_MAC, ALICE_IPV4, rx, tx);
+    let mut alice = LibOS::new(alice_rt).unwrap();
+
+    // Nobody answers for Carrie, so the first datagram waits and leaves no room for the second.
+    let alice_fd = alice.socket(libc::AF_INET, libc::SOCK_DGRAM, 0).unwrap();
+    alice
+        .setsockopt(alice_fd, SocketOption::SendBufferSize(Some(16)))
+        .unwrap();
+    let carrie_addr = ip::Endpoint::new(CARRIE_IPV4, ip::Port::try_from(80).unwrap());
+    let sga = dmtr_sgarray_t::from(&[0u8; 10][..]);
+    let first = alice.pushto(alice_fd, &sga, carrie_addr);
+    let qt = alice.pushto(alice_fd, &sga, carrie_addr);
+    let qr = alice.wait(qt);
+    assert_eq!(qr.qr_opcode, dmtr_opcode_t::DMTR_OPC_FAILED);
+    assert_eq!(unsafe { qr.qr_value.failed.error }, libc::ENOMEM);
+    assert!(alice.poll(first).is_none());
+    sga.free();
+}
+
+#[test]
+fn connection_refused() {
+    let (forward_tx, forward_rx) = crossbeam_channel::unbounded();
+    let (backward_tx, backward_rx) = crossbeam_channel::unbounded();
+
+    let now = Instant::now();
+    let port = ip::Port::try_from(80).unwrap();
+    let alice_addr = ip::Endpoint::new(ALICE_IPV4, port);
+    let bob_addr = ip::Endpoint::new(BOB_IPV4, port);
+    let done_addr = ip::Endpoint::new(BOB_IPV4, ip::Port::try_from(81).unwrap());
+
+    // Bob has nothing on port 80, and answers there until Alice is done.
+    let server = thread::spawn(move || {
+        let bob_rt = TestRuntime::new(now, BOB_MAC, BOB_IPV4, forward_rx, backward_tx);
+        let mut bob = LibOS::new(bob_rt).unwrap();
+        let bob_fd = bob.socket(libc::AF_INET, libc::SOCK_DGRAM, 0).unwrap();
+        bob.bind(bob_fd, done_addr).unwrap();
+        let qt = bob.pop(bob_fd);
+        assert_eq!(bob.wait(qt).qr_opcode, dmtr_opcode_t::DMTR_OPC_POP);
+    });
+
+    let alice_rt = TestRuntime::new(now, ALICE_MAC, ALICE_IPV4, backward_rx, forward_tx);
+    let mut alice = LibOS::new(alice_rt).unwrap();
+    let sga = dmtr_sgarray_t::from(&b"hello"[..]);
+
+    // Bob's ICMP error shows up in the connected UDP socket's next pop.
+    let udp_fd = alice.socket(libc::AF_INET, libc::SOCK_DGRAM, 0).unwrap();
+    alice.bind(udp_fd, alice_addr).unwrap();
+    let qt = alice.connect(udp_fd, bob_addr);
+    assert_eq!(alice.wait(qt).qr_opcode, dmtr_opcode_t::DMTR_OPC_CONNECT);
+    let qt = alice.push(udp_fd, &sga);
+    assert_eq!(alice.wait(qt).qr_opcode, dmtr_opcode_t::DMTR_OPC_PUSH);
+    let qt = alice.pop(udp_fd);
+    let qr = alice.wait(qt);
+    assert_eq!(qr.qr_opcode, dmtr_opcode_t::DMTR_OPC_FAILED);
+    assert_eq!(unsafe { qr.qr_value.failed.error }, libc::ECONNREFUSED);
+
+    // So does his RST in a TCP connect.
+    let tcp_fd = alice.socket(libc::AF_INET, libc::SOCK_STREAM, 0).unwrap();
+    let qt = alice.connect(tcp_fd, bob_addr);
+    let qr = alice.wait(qt);
+    assert_eq!(qr.qr_opcode, dmtr_opcode_t::DMTR_OPC_FAILED);
+    assert_eq!(qr.qr_qd, tcp_fd as i32);
+    assert_eq!(unsafe { qr.qr_value.failed.error }, libc::ECONNREFUSED);
+
+    let qt = alice.pushto(udp_fd, &sga, done_addr);
+    assert_eq!(alice.wait(qt).qr_opcode, dmtr_opcode_t::DMTR_OPC_PUSH);
+    server.join().unwrap();
+    sga.free();
+}
+
+#[test]
+fn traceroute() {
+    let (forward_tx, forward_rx) = crossbeam_channel::unbounded();
+    let (backward_tx, backward_rx) = crossbeam_channel::unbounded();
+
+    let now = Instant::now();
+    let done_addr = ip::Endpoint::new(BOB_IPV4, ip::Port::try_from(81).unwrap());
+
+    // Bob answers pings until Alice is done.
+    let server = thread::spawn(move || {
+        let bob_rt = TestRuntime::new(now, BOB_MAC, BOB_IPV4, forward_rx, backward_tx);
+        let mut bob = LibOS::new(bob_rt).unwrap();
+        let bob_fd = bob.socket(libc::AF_INET, libc::SOCK_DGRAM, 0).unwrap();
+        bob.bind(bob_fd, done_addr).unwrap();
+        let qt = bob.pop(bob_fd);
+        assert_eq!(bob.wait(qt).qr_opcode, dmtr_opcode_t::DMTR_OPC_POP);
+    });
+
+    let alice_rt = TestRuntime::new(now, ALICE_MAC, ALICE_IPV4, backward_rx, forward_tx);
+    let mut alice = LibOS::new(alice_rt).unwrap();
+
+    // The result only counts the hops, which go to the caller.
+    let hops = Rc::new(RefCell::new(vec![]));
+    let hops_ = hops.clone();
+    let qt = alice.traceroute(BOB_IPV4, 4, None, move |h| {
+        hops_.borrow_mut().extend_from_slice(h)
+    });
+    let qr = alice.wait(qt);
+    assert_eq!(qr.qr_opcode, dmtr_opcode_t::DMTR_OPC_TRACEROUTE);
+    assert_eq!(unsafe { qr.qr_value.trace.error }, 0);
+    assert_eq!(unsafe { qr.qr_value.trace.num_hops }, 1);
+    let hops = hops.borrow();
+    assert_eq!(hops.len(), 1);
+    assert_eq!(hops[0].as_ref().unwrap().addr, BOB_IPV4);
+
+    let udp_fd = alice.socket(libc::AF_INET, libc::SOCK_DGRAM, 0).unwrap();
+    let sga = dmtr_sgarray_t::from(&b"done"[..]);
+    let qt = alice.pushto(udp_fd, &sga, done_addr);
+    assert_eq!(alice.wait(qt).qr_opcode, dmtr_opcode_t::DMTR_OPC_PUSH);
+    server.join().unwrap();
+    sga.free();
+}
+
 #[test]
 // #[cfg(not(feature = "threadunsafe"))]
 fn udp_echo() {
@@ -205,8 +367,8 @@ fn udp_echo() {
 
     let now = Instant::now();
     let port = ip::Port::try_from(80).unwrap();
-    let alice_addr = ipv4::Endpoint::new(ALICE_IPV4, port);
-    let bob_addr = ipv4::Endpoint::new(BOB_IPV4, port);
+    let alice_addr = ip::Endpoint::new(ALICE_IPV4, port);
+    let bob_addr = ip::Endpoint::new(BOB_IPV4, port);
 
     let num_iters: usize = env::var("NUM_ITERS")
         .map(|s| s.parse().unwrap())
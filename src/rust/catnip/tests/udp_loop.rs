@@ -2,10 +2,7 @@
 
 use catnip::{
     engine::Protocol,
-    protocols::{
-        ip,
-        ipv4,
-    },
+    protocols::ip,
     sync::BytesMut,
     test_helpers,
 };
@@ -37,8 +34,8 @@ fn udp_loop() {
     let mut bob = test_helpers::new_bob(now);
 
     let port = ip::Port::try_from(80).unwrap();
-    let alice_addr = ipv4::Endpoint::new(test_helpers::ALICE_IPV4, port);
-    let bob_addr = ipv4::Endpoint::new(test_helpers::BOB_IPV4, port);
+    let alice_addr = ip::Endpoint::new(test_helpers::ALICE_IPV4, port);
+    let bob_addr = ip::Endpoint::new(test_helpers::BOB_IPV4, port);
 
     let alice_fd = alice.socket(Protocol::Udp);
     let _ = alice.bind(alice_fd, alice_addr);
@@ -42,7 +42,6 @@ use crate::{
         RTE_MBUF_DEFAULT_BUF_SIZE,
         RTE_PKTMBUF_HEADROOM,
     },
-    runtime::DPDKRuntime,
 };
 use anyhow::{
     bail,
@@ -50,11 +49,9 @@ use anyhow::{
     Error,
 };
 use catnip::protocols::ethernet2::MacAddress;
-use hashbrown::HashMap;
 use std::{
     ffi::CString,
     mem::MaybeUninit,
-    net::Ipv4Addr,
     ptr,
     time::Duration,
 };
@@ -70,14 +67,20 @@ macro_rules! expect_zero {
     }};
 }
 
+/// The DPDK port we send and receive on, with a single RX/TX queue pair.
+#[derive(Clone, Copy)]
+pub struct DPDKPort {
+    pub port_id: u16,
+    pub mempool: *mut rte_mempool,
+    pub link_addr: MacAddress,
+    pub mtu: u16,
+}
+
 pub fn initialize_dpdk(
-    local_ipv4_addr: Ipv4Addr,
     eal_init_args: &[CString],
-    arp_table: HashMap<MacAddress, Ipv4Addr>,
-    disable_arp: bool,
     use_jumbo_frames: bool,
     mtu: u16,
-) -> Result<DPDKRuntime, Error> {
+) -> Result<DPDKPort, Error> {
     std::env::set_var("MLX5_SHUT_UP_BF", "1");
     let eal_init_refs = eal_init_args
         .iter()
@@ -144,14 +147,12 @@ pub fn initialize_dpdk(
         Err(format_err!("Invalid mac address"))?;
     }
 
-    Ok(DPDKRuntime::new(
-        local_link_addr,
-        local_ipv4_addr,
+    Ok(DPDKPort {
         port_id,
-        mbuf_pool,
-        arp_table,
-        disable_arp,
-    ))
+        mempool: mbuf_pool,
+        link_addr: local_link_addr,
+        mtu,
+    })
 }
 
 fn initialize_dpdk_port(
@@ -5,18 +5,40 @@
 use catnip::{
     file_table::FileDescriptor,
     interop::{
+        dmtr_arp_entry_t,
         dmtr_qresult_t,
         dmtr_qtoken_t,
+        dmtr_resolve_addr_t,
         dmtr_sgarray_t,
+        dmtr_traceroute_hop_t,
     },
     libos::LibOS,
     logging,
     protocols::{
-        ethernet2::MacAddress,
+        dns,
+        ethernet2::{
+            self,
+            MacAddress,
+        },
+        icmpv4::{
+            TracerouteHop,
+            MAX_PING_PAYLOAD_SIZE,
+        },
+        igmp,
         ip,
-        ipv4,
+        ipv4::{
+            self,
+            datagram::DEFAULT_IPV4_TTL,
+        },
+        ipv6,
     },
     runtime::Runtime,
+    socket_options::{
+        SocketOption,
+        SocketOptionName,
+        DEFAULT_KEEPALIVE_IDLE,
+        DEFAULT_MULTICAST_TTL,
+    },
 };
 use clap::{
     App,
@@ -26,6 +48,7 @@ use hashbrown::HashMap;
 use libc::{
     c_char,
     c_int,
+    c_void,
     sockaddr,
     socklen_t,
 };
@@ -39,8 +62,14 @@ use std::{
     fs::File,
     io::Read,
     mem,
-    net::Ipv4Addr,
+    net::{
+        IpAddr,
+        Ipv4Addr,
+        Ipv6Addr,
+    },
+    ptr,
     slice,
+    time::Duration,
 };
 use yaml_rust::{
     Yaml,
@@ -57,6 +86,11 @@ use anyhow::{
     Error,
 };
 
+// How long `dmtr_init` waits for a DHCP server before giving up.
+const DHCP_TIMEOUT: Duration = Duration::from_secs(30);
+// How long `dmtr_init` waits for duplicate address detection, which takes up to 7 seconds.
+const ARP_PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+
 thread_local! {
     static LIBOS: RefCell<Option<LibOS<DPDKRuntime>>> = RefCell::new(None);
 }
@@ -72,6 +106,41 @@ pub extern "C" fn catnip_libos_noop() {
     println!("hey there!");
 }
 
+/// Parses `a.b.c.d/len`, where a missing prefix length means a single host.
+fn parse_ipv4_prefix(s: &str) -> Result<(Ipv4Addr, u8), Error> {
+    let mut parts = s.splitn(2, '/');
+    let addr: Ipv4Addr = parts.next().unwrap().parse()?;
+    let prefix_len: u8 = match parts.next() {
+        Some(p) => p.parse()?,
+        None => 32,
+    };
+    if prefix_len > 32 {
+        Err(format_err!("Invalid IPv4 prefix {}", s))?;
+    }
+    Ok((addr, prefix_len))
+}
+
+fn parse_vlan_id(vlan_id: i64) -> Result<u16, Error> {
+    // VLAN IDs 0 and 4095 are reserved.
+    if vlan_id <= 0 || vlan_id >= 4095 {
+        Err(format_err!("Invalid VLAN ID {}", vlan_id))?;
+    }
+    Ok(vlan_id as u16)
+}
+
+fn parse_ipv6_prefix(s: &str) -> Result<(Ipv6Addr, u8), Error> {
+    let mut parts = s.splitn(2, '/');
+    let addr: Ipv6Addr = parts.next().unwrap().parse()?;
+    let prefix_len: u8 = match parts.next() {
+        Some(p) => p.parse()?,
+        None => 128,
+    };
+    if prefix_len > 128 {
+        Err(format_err!("Invalid IPv6 prefix {}", s))?;
+    }
+    Ok((addr, prefix_len))
+}
+
 #[no_mangle]
 pub extern "C" fn dmtr_init(argc: c_int, argv: *mut *mut c_char) -> c_int {
     let r: Result<_, Error> = try {
@@ -124,11 +193,14 @@ pub extern "C" fn dmtr_init(argc: c_int, argv: *mut *mut c_char) -> c_int {
             _ => Err(format_err!("Wrong number of config objects"))?,
         };
 
-        let local_ipv4_addr: Ipv4Addr = config_obj["catnip"]["my_ipv4_addr"]
-            .as_str()
-            .ok_or_else(|| format_err!("Couldn't find my_ipv4_addr in config"))?
-            .parse()?;
-        if local_ipv4_addr.is_unspecified() || local_ipv4_addr.is_broadcast() {
+        // With DHCP the address comes from the lease instead.
+        let dhcp = config_obj["catnip"]["dhcp"].as_bool().unwrap_or(false);
+        let local_ipv4_addr: Ipv4Addr = match config_obj["catnip"]["my_ipv4_addr"].as_str() {
+            Some(addr) => addr.parse()?,
+            None if dhcp => Ipv4Addr::UNSPECIFIED,
+            None => Err(format_err!("Couldn't find my_ipv4_addr in config"))?,
+        };
+        if !dhcp && (local_ipv4_addr.is_unspecified() || local_ipv4_addr.is_broadcast()) {
             Err(format_err!("Invalid IPv4 address"))?;
         }
 
@@ -154,6 +226,99 @@ pub extern "C" fn dmtr_init(argc: c_int, argv: *mut *mut c_char) -> c_int {
             println!("ARP disabled: {:?}", disable_arp);
         }
 
+        let mut ethernet2_options = ethernet2::Options::default();
+        if let Some(vlan_id) = config_obj["catnip"]["vlan_id"].as_i64() {
+            ethernet2_options = ethernet2_options.vlan_id(parse_vlan_id(vlan_id)?);
+        }
+        if let Some(vlan_id) = config_obj["catnip"]["outer_vlan_id"].as_i64() {
+            ethernet2_options = ethernet2_options.outer_vlan_id(parse_vlan_id(vlan_id)?);
+        }
+        if let Some(pcp) = config_obj["catnip"]["vlan_pcp"].as_i64() {
+            if pcp < 0 || pcp > 7 {
+                Err(format_err!("Invalid VLAN priority {}", pcp))?;
+            }
+            ethernet2_options = ethernet2_options.pcp(pcp as u8);
+        }
+
+        let mut ipv4_options = ipv4::Options::default().dhcp(dhcp);
+        if let Some(netmask) = config_obj["catnip"]["netmask"].as_str() {
+            let netmask: Ipv4Addr = netmask.parse()?;
+            if ipv4::routing::netmask_prefix_len(netmask).is_none() {
+                Err(format_err!("Invalid netmask {}", netmask))?;
+            }
+            ipv4_options = ipv4_options.netmask(netmask);
+        }
+        if let Some(gateway) = config_obj["catnip"]["default_gateway"].as_str() {
+            ipv4_options = ipv4_options.default_gateway(gateway.parse()?);
+        }
+        if let Some(routes) = config_obj["catnip"]["routes"].as_vec() {
+            for route_obj in routes {
+                let destination = route_obj["destination"]
+                    .as_str()
+                    .ok_or_else(|| format_err!("Couldn't find route destination in config"))?;
+                let (addr, prefix_len) = parse_ipv4_prefix(destination)?;
+                let gateway = match route_obj["gateway"].as_str() {
+                    Some(g) => Some(g.parse()?),
+                    None => None,
+                };
+                ipv4_options = ipv4_options.route(ipv4::Route::new(addr, prefix_len, gateway));
+            }
+        }
+        if let Some(addrs) = config_obj["catnip"]["secondary_ipv4_addrs"].as_vec() {
+            for addr_obj in addrs {
+                let addr_str = addr_obj
+                    .as_str()
+                    .ok_or_else(|| format_err!("Malformed secondary_ipv4_addrs in config"))?;
+                let (addr, prefix_len) = parse_ipv4_prefix(addr_str)?;
+                if addr.is_unspecified() || addr.is_broadcast() {
+                    Err(format_err!("Invalid IPv4 address {}", addr))?;
+                }
+                ipv4_options = ipv4_options.secondary_addr(addr, prefix_len);
+            }
+        }
+        if let Some(version) = config_obj["catnip"]["igmp_version"].as_i64() {
+            let version = match version {
+                2 => igmp::Version::V2,
+                3 => igmp::Version::V3,
+                _ => Err(format_err!("Unsupported IGMP version {}", version))?,
+            };
+            ipv4_options = ipv4_options.igmp_version(version);
+        }
+
+        let mut ipv6_options = ipv6::Options::default();
+        if let Some(addrs) = config_obj["catnip"]["ipv6_addrs"].as_vec() {
+            for addr_obj in addrs {
+                let addr_str = addr_obj
+                    .as_str()
+                    .ok_or_else(|| format_err!("Malformed ipv6_addrs in config"))?;
+                let (addr, prefix_len) = parse_ipv6_prefix(addr_str)?;
+                if addr.is_unspecified()
+                    || addr.is_multicast()
+                    || ipv6::routing::is_link_local(addr)
+                {
+                    Err(format_err!("Invalid IPv6 address {}", addr))?;
+                }
+                ipv6_options = ipv6_options.addr(addr, prefix_len);
+            }
+        }
+        if let Some(gateway) = config_obj["catnip"]["ipv6_default_gateway"].as_str() {
+            ipv6_options = ipv6_options.default_gateway(gateway.parse()?);
+        }
+
+        let mut dns_options = dns::Options::default();
+        if let Some(servers) = config_obj["catnip"]["dns_servers"].as_vec() {
+            for server_obj in servers {
+                let addr: IpAddr = server_obj
+                    .as_str()
+                    .ok_or_else(|| format_err!("Malformed dns_servers in config"))?
+                    .parse()?;
+                if addr.is_unspecified() || addr.is_multicast() {
+                    Err(format_err!("Invalid DNS server {}", addr))?;
+                }
+                dns_options = dns_options.server(addr);
+            }
+        }
+
         let eal_init_args = match config_obj["dpdk"]["eal_init"] {
             Yaml::Array(ref arr) => arr
                 .iter()
@@ -168,16 +333,29 @@ pub extern "C" fn dmtr_init(argc: c_int, argv: *mut *mut c_char) -> c_int {
 
         let use_jumbo_frames = true;
         let mtu = 9216;
-        let runtime = self::dpdk::initialize_dpdk(
+        let port = self::dpdk::initialize_dpdk(&eal_init_args, use_jumbo_frames, mtu)?;
+        let runtime = DPDKRuntime::new(
+            port.link_addr,
             local_ipv4_addr,
-            &eal_init_args,
+            port.port_id,
+            port.mempool,
+            port.mtu,
             arp_table,
             disable_arp,
-            use_jumbo_frames,
-            mtu,
-        )?;
+            ethernet2_options,
+            ipv4_options,
+            ipv6_options,
+            dns_options,
+        );
         logging::initialize();
-        LibOS::new(runtime)?
+        let mut libos = LibOS::new(runtime)?;
+        if dhcp {
+            let lease = libos.wait_for_dhcp_lease(DHCP_TIMEOUT)?;
+            println!("DHCP lease: {:?}", lease);
+        } else {
+            libos.wait_for_ipv4_addr(ARP_PROBE_TIMEOUT)?;
+        }
+        libos
     };
     let libos = match r {
         Ok(libos) => libos,
@@ -215,30 +393,41 @@ pub extern "C" fn dmtr_socket(
     })
 }
 
+/// Reads a `sockaddr_in` or `sockaddr_in6`, telling them apart by `size`.
+fn parse_sockaddr(saddr: *const sockaddr, size: socklen_t) -> Result<ip::Endpoint, c_int> {
+    let (addr, port) = if size as usize == mem::size_of::<libc::sockaddr_in>() {
+        let saddr_in = unsafe { *(saddr as *const libc::sockaddr_in) };
+        let addr = Ipv4Addr::from(u32::from_be_bytes(saddr_in.sin_addr.s_addr.to_le_bytes()));
+        (IpAddr::V4(addr), saddr_in.sin_port)
+    } else if size as usize == mem::size_of::<libc::sockaddr_in6>() {
+        let saddr_in6 = unsafe { *(saddr as *const libc::sockaddr_in6) };
+        (
+            IpAddr::V6(Ipv6Addr::from(saddr_in6.sin6_addr.s6_addr)),
+            saddr_in6.sin6_port,
+        )
+    } else {
+        return Err(libc::EINVAL);
+    };
+    let port = ip::Port::try_from(u16::from_be(port)).unwrap();
+    Ok(ip::Endpoint::new(addr, port))
+}
+
 #[no_mangle]
 pub extern "C" fn dmtr_bind(qd: c_int, saddr: *const sockaddr, size: socklen_t) -> c_int {
     if saddr.is_null() {
         return libc::EINVAL;
     }
-    if size as usize != mem::size_of::<libc::sockaddr_in>() {
-        return libc::EINVAL;
-    }
-    let saddr_in = unsafe { *mem::transmute::<*const sockaddr, *const libc::sockaddr_in>(saddr) };
-    let mut addr = Ipv4Addr::from(u32::from_be_bytes(saddr_in.sin_addr.s_addr.to_le_bytes()));
-    let port = ip::Port::try_from(u16::from_be(saddr_in.sin_port)).unwrap();
+    let endpoint = match parse_sockaddr(saddr, size) {
+        Ok(endpoint) => endpoint,
+        Err(e) => return e,
+    };
 
-    with_libos(|libos| {
-        if addr.is_unspecified() {
-            addr = libos.rt().local_ipv4_addr();
-        }
-        let endpoint = ipv4::Endpoint::new(addr, port);
-        match libos.bind(qd as FileDescriptor, endpoint) {
-            Ok(..) => 0,
-            Err(e) => {
-                eprintln!("dmtr_bind failed: {:?}", e);
-                e.errno()
-            },
-        }
+    with_libos(|libos| match libos.bind(qd as FileDescriptor, endpoint) {
+        Ok(..) => 0,
+        Err(e) => {
+            eprintln!("dmtr_bind failed: {:?}", e);
+            e.errno()
+        },
     })
 }
 
@@ -273,13 +462,10 @@ pub extern "C" fn dmtr_connect(
     if saddr.is_null() {
         return libc::EINVAL;
     }
-    if size as usize != mem::size_of::<libc::sockaddr_in>() {
-        return libc::EINVAL;
-    }
-    let saddr_in = unsafe { *mem::transmute::<*const sockaddr, *const libc::sockaddr_in>(saddr) };
-    let addr = Ipv4Addr::from(u32::from_be_bytes(saddr_in.sin_addr.s_addr.to_le_bytes()));
-    let port = ip::Port::try_from(u16::from_be(saddr_in.sin_port)).unwrap();
-    let endpoint = ipv4::Endpoint::new(addr, port);
+    let endpoint = match parse_sockaddr(saddr, size) {
+        Ok(endpoint) => endpoint,
+        Err(e) => return e,
+    };
 
     with_libos(|libos| {
         unsafe { *qtok_out = libos.connect(qd as FileDescriptor, endpoint) };
@@ -298,6 +484,221 @@ pub extern "C" fn dmtr_close(qd: c_int) -> c_int {
     })
 }
 
+fn sockopt_name(level: c_int, optname: c_int) -> Option<SocketOptionName> {
+    let name = match (level, optname) {
+        (libc::SOL_SOCKET, libc::SO_KEEPALIVE) => SocketOptionName::KeepAlive,
+        (libc::SOL_SOCKET, libc::SO_SNDBUF) => SocketOptionName::SendBufferSize,
+        (libc::SOL_SOCKET, libc::SO_RCVBUF) => SocketOptionName::ReceiveBufferSize,
+        (libc::SOL_SOCKET, libc::SO_LINGER) => SocketOptionName::Linger,
+        (libc::SOL_SOCKET, libc::SO_PRIORITY) => SocketOptionName::VlanPriority,
+        (libc::IPPROTO_TCP, libc::TCP_NODELAY) => SocketOptionName::NoDelay,
+        (libc::IPPROTO_TCP, libc::TCP_MAXSEG) => SocketOptionName::MaxSegmentSize,
+        (libc::IPPROTO_TCP, libc::TCP_KEEPIDLE) => SocketOptionName::KeepAlive,
+        (libc::IPPROTO_IP, libc::IP_TTL) => SocketOptionName::TimeToLive,
+        (libc::IPPROTO_IP, libc::IP_MULTICAST_TTL) => SocketOptionName::MulticastTimeToLive,
+        (libc::IPPROTO_IP, libc::IP_TOS) => SocketOptionName::Dscp,
+        (libc::IPPROTO_IP, libc::IP_MTU_DISCOVER) => SocketOptionName::DontFragment,
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[no_mangle]
+pub extern "C" fn dmtr_setsockopt(
+    qd: c_int,
+    level: c_int,
+    optname: c_int,
+    optval: *const c_void,
+    optlen: socklen_t,
+) -> c_int {
+    if level == libc::IPPROTO_IP
+        && (optname == libc::IP_ADD_MEMBERSHIP || optname == libc::IP_DROP_MEMBERSHIP)
+    {
+        return set_membership(qd, optname, optval, optlen);
+    }
+    let name = match sockopt_name(level, optname) {
+        Some(name) => name,
+        None => return libc::ENOPROTOOPT,
+    };
+    if optval.is_null() {
+        return libc::EINVAL;
+    }
+    let option = if name == SocketOptionName::Linger {
+        if optlen as usize != mem::size_of::<libc::linger>() {
+            return libc::EINVAL;
+        }
+        let l = unsafe { ptr::read_unaligned(optval as *const libc::linger) };
+        if l.l_linger < 0 {
+            return libc::EINVAL;
+        }
+        let linger = if l.l_onoff != 0 {
+            Some(Duration::from_secs(l.l_linger as u64))
+        } else {
+            None
+        };
+        SocketOption::Linger(linger)
+    } else {
+        if optlen as usize != mem::size_of::<c_int>() {
+            return libc::EINVAL;
+        }
+        let value = unsafe { ptr::read_unaligned(optval as *const c_int) };
+        match (name, optname) {
+            (SocketOptionName::KeepAlive, libc::TCP_KEEPIDLE) if value > 0 => {
+                SocketOption::KeepAlive(Some(Duration::from_secs(value as u64)))
+            },
+            (SocketOptionName::KeepAlive, libc::SO_KEEPALIVE) => {
+                let current = with_libos(|libos| libos.getsockopt(qd as FileDescriptor, name));
+                let idle = match current {
+                    Ok(SocketOption::KeepAlive(Some(idle))) => idle,
+                    _ => DEFAULT_KEEPALIVE_IDLE,
+                };
+                SocketOption::KeepAlive(if value != 0 { Some(idle) } else { None })
+            },
+            (SocketOptionName::SendBufferSize, _) if value > 0 => {
+                SocketOption::SendBufferSize(Some(value as usize))
+            },
+            (SocketOptionName::ReceiveBufferSize, _) if value > 0 => {
+                SocketOption::ReceiveBufferSize(Some(value as usize))
+            },
+            (SocketOptionName::NoDelay, _) => SocketOption::NoDelay(value != 0),
+            (SocketOptionName::MaxSegmentSize, _) if value >= 0 => {
+                SocketOption::MaxSegmentSize(if value == 0 {
+                    None
+                } else {
+                    Some(value as usize)
+                })
+            },
+            // Like Linux, a TTL of -1 restores the stack default.
+            (SocketOptionName::TimeToLive, _) if value == -1 => SocketOption::TimeToLive(None),
+            (SocketOptionName::TimeToLive, _) if value > 0 && value <= 255 => {
+                SocketOption::TimeToLive(Some(value as u8))
+            },
+            (SocketOptionName::MulticastTimeToLive, _) if value == -1 => {
+                SocketOption::MulticastTimeToLive(None)
+            },
+            (SocketOptionName::MulticastTimeToLive, _) if value > 0 && value <= 255 => {
+                SocketOption::MulticastTimeToLive(Some(value as u8))
+            },
+            (SocketOptionName::Dscp, _) if value >= 0 && value <= 255 => {
+                SocketOption::Dscp((value as u8) >> 2)
+            },
+            // We don't do path MTU discovery, so only the DF bit itself is configurable.
+            (SocketOptionName::DontFragment, _) if value == libc::IP_PMTUDISC_DO => {
+                SocketOption::DontFragment(true)
+            },
+            (SocketOptionName::DontFragment, _) if value == libc::IP_PMTUDISC_DONT => {
+                SocketOption::DontFragment(false)
+            },
+            // The socket priority only goes as far as the PCP of the frames' VLAN tag.
+            (SocketOptionName::VlanPriority, _) if value >= 0 && value <= 7 => {
+                SocketOption::VlanPriority(Some(value as u8))
+            },
+            _ => return libc::EINVAL,
+        }
+    };
+    with_libos(
+        |libos| match libos.setsockopt(qd as FileDescriptor, option) {
+            Ok(..) => 0,
+            Err(e) => {
+                eprintln!("dmtr_setsockopt failed: {:?}", e);
+                e.errno()
+            },
+        },
+    )
+}
+
+/// Joins or leaves a multicast group. There's only one interface, so `imr_interface` is ignored.
+fn set_membership(qd: c_int, optname: c_int, optval: *const c_void, optlen: socklen_t) -> c_int {
+    if optval.is_null() || (optlen as usize) < mem::size_of::<libc::ip_mreq>() {
+        return libc::EINVAL;
+    }
+    let mreq = unsafe { ptr::read_unaligned(optval as *const libc::ip_mreq) };
+    let group = Ipv4Addr::from(u32::from_be_bytes(mreq.imr_multiaddr.s_addr.to_le_bytes()));
+    with_libos(|libos| {
+        let r = if optname == libc::IP_ADD_MEMBERSHIP {
+            libos.join_multicast_group(qd as FileDescriptor, group)
+        } else {
+            libos.leave_multicast_group(qd as FileDescriptor, group)
+        };
+        match r {
+            Ok(..) => 0,
+            Err(e) => {
+                eprintln!("dmtr_setsockopt failed: {:?}", e);
+                e.errno()
+            },
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn dmtr_getsockopt(
+    qd: c_int,
+    level: c_int,
+    optname: c_int,
+    optval: *mut c_void,
+    optlen: *mut socklen_t,
+) -> c_int {
+    let name = match sockopt_name(level, optname) {
+        Some(name) => name,
+        None => return libc::ENOPROTOOPT,
+    };
+    if optval.is_null() || optlen.is_null() {
+        return libc::EINVAL;
+    }
+    let option = match with_libos(|libos| libos.getsockopt(qd as FileDescriptor, name)) {
+        Ok(option) => option,
+        Err(e) => {
+            eprintln!("dmtr_getsockopt failed: {:?}", e);
+            return e.errno();
+        },
+    };
+    if let SocketOption::Linger(linger) = option {
+        if unsafe { *optlen } as usize != mem::size_of::<libc::linger>() {
+            return libc::EINVAL;
+        }
+        let l = libc::linger {
+            l_onoff: linger.is_some() as c_int,
+            l_linger: linger.map(|d| d.as_secs() as c_int).unwrap_or(0),
+        };
+        unsafe { ptr::write_unaligned(optval as *mut libc::linger, l) };
+        return 0;
+    }
+    if (unsafe { *optlen } as usize) < mem::size_of::<c_int>() {
+        return libc::EINVAL;
+    }
+    let value = match option {
+        SocketOption::KeepAlive(idle) if optname == libc::TCP_KEEPIDLE => {
+            idle.unwrap_or(DEFAULT_KEEPALIVE_IDLE).as_secs() as c_int
+        },
+        SocketOption::KeepAlive(idle) => idle.is_some() as c_int,
+        SocketOption::SendBufferSize(size) => size.unwrap_or(0) as c_int,
+        SocketOption::ReceiveBufferSize(size) => size.unwrap_or(0) as c_int,
+        SocketOption::NoDelay(no_delay) => no_delay as c_int,
+        SocketOption::MaxSegmentSize(mss) => mss.unwrap_or(0) as c_int,
+        SocketOption::TimeToLive(ttl) => ttl.unwrap_or(DEFAULT_IPV4_TTL) as c_int,
+        SocketOption::MulticastTimeToLive(ttl) => ttl.unwrap_or(DEFAULT_MULTICAST_TTL) as c_int,
+        SocketOption::Dscp(dscp) => (dscp << 2) as c_int,
+        SocketOption::DontFragment(true) => libc::IP_PMTUDISC_DO,
+        SocketOption::DontFragment(false) => libc::IP_PMTUDISC_DONT,
+        SocketOption::VlanPriority(pcp) => pcp.unwrap_or(0) as c_int,
+        // There's no C option for the datagram limit.
+        SocketOption::Linger(..) | SocketOption::ReceiveDatagramLimit(..) => unreachable!(),
+    };
+    unsafe {
+        ptr::write_unaligned(optval as *mut c_int, value);
+        *optlen = mem::size_of::<c_int>() as socklen_t;
+    }
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn dmtr_async_close(qtok_out: *mut dmtr_qtoken_t, qd: c_int) -> c_int {
+    with_libos(|libos| {
+        unsafe { *qtok_out = libos.async_close(qd as FileDescriptor) };
+        0
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn dmtr_push(
     qtok_out: *mut dmtr_qtoken_t,
@@ -329,13 +730,10 @@ pub extern "C" fn dmtr_pushto(
     if saddr.is_null() {
         return libc::EINVAL;
     }
-    if size as usize != mem::size_of::<libc::sockaddr_in>() {
-        return libc::EINVAL;
-    }
-    let saddr_in = unsafe { *mem::transmute::<*const sockaddr, *const libc::sockaddr_in>(saddr) };
-    let addr = Ipv4Addr::from(u32::from_be_bytes(saddr_in.sin_addr.s_addr.to_le_bytes()));
-    let port = ip::Port::try_from(u16::from_be(saddr_in.sin_port)).unwrap();
-    let endpoint = ipv4::Endpoint::new(addr, port);
+    let endpoint = match parse_sockaddr(saddr, size) {
+        Ok(endpoint) => endpoint,
+        Err(e) => return e,
+    };
     with_libos(|libos| {
         unsafe { *qtok_out = libos.pushto(qd as FileDescriptor, sga, endpoint) };
         0
@@ -350,6 +748,176 @@ pub extern "C" fn dmtr_pop(qtok_out: *mut dmtr_qtoken_t, qd: c_int) -> c_int {
     })
 }
 
+fn parse_timeout(timeout_ms: c_int) -> Option<Duration> {
+    if timeout_ms > 0 {
+        Some(Duration::from_millis(timeout_ms as u64))
+    } else {
+        None
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn dmtr_ping(
+    qtok_out: *mut dmtr_qtoken_t,
+    dst: *const libc::in_addr,
+    payload_size: libc::size_t,
+    timeout_ms: c_int,
+) -> c_int {
+    if dst.is_null() || payload_size > MAX_PING_PAYLOAD_SIZE {
+        return libc::EINVAL;
+    }
+    let dst = Ipv4Addr::from(u32::from_be_bytes(unsafe { (*dst).s_addr }.to_le_bytes()));
+    with_libos(|libos| {
+        unsafe { *qtok_out = libos.ping(dst, payload_size, parse_timeout(timeout_ms)) };
+        0
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn dmtr_traceroute(
+    qtok_out: *mut dmtr_qtoken_t,
+    dst: *const libc::in_addr,
+    max_hops: c_int,
+    timeout_ms: c_int,
+    hops: *mut dmtr_traceroute_hop_t,
+    capacity: libc::size_t,
+) -> c_int {
+    if dst.is_null()
+        || hops.is_null()
+        || max_hops <= 0
+        || max_hops > u8::MAX as c_int
+        || max_hops as usize > capacity
+    {
+        return libc::EINVAL;
+    }
+    let dst = Ipv4Addr::from(u32::from_be_bytes(unsafe { (*dst).s_addr }.to_le_bytes()));
+    // The caller keeps `hops` around until the traceroute completes or its token is dropped.
+    let store = move |results: &[Option<TracerouteHop>]| {
+        for (i, hop) in results.iter().take(capacity).enumerate() {
+            unsafe { *hops.add(i) = dmtr_traceroute_hop_t::from(hop.clone()) };
+        }
+    };
+    with_libos(|libos| {
+        let timeout = parse_timeout(timeout_ms);
+        unsafe { *qtok_out = libos.traceroute(dst, max_hops as u8, timeout, store) };
+        0
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn dmtr_resolve(
+    qtok_out: *mut dmtr_qtoken_t,
+    name: *const c_char,
+    family: c_int,
+    timeout_ms: c_int,
+    addrs: *mut dmtr_resolve_addr_t,
+    capacity: libc::size_t,
+) -> c_int {
+    if name.is_null() || (addrs.is_null() && capacity > 0) {
+        return libc::EINVAL;
+    }
+    let name = match unsafe { CStr::from_ptr(name) }.to_str() {
+        Ok(name) => name,
+        Err(..) => return libc::EINVAL,
+    };
+    let record_types: &[dns::RecordType] = match family {
+        libc::AF_INET => &[dns::RecordType::A],
+        libc::AF_INET6 => &[dns::RecordType::Aaaa],
+        libc::AF_UNSPEC => &[dns::RecordType::A, dns::RecordType::Aaaa],
+        _ => return libc::EAFNOSUPPORT,
+    };
+    // The caller keeps `addrs` around until the query completes or its token is dropped.
+    let store = move |results: &[IpAddr]| {
+        for (i, &addr) in results.iter().take(capacity).enumerate() {
+            unsafe { *addrs.add(i) = dmtr_resolve_addr_t::from(addr) };
+        }
+    };
+    with_libos(|libos| {
+        let timeout = parse_timeout(timeout_ms);
+        unsafe { *qtok_out = libos.resolve(name, record_types, timeout, store) };
+        0
+    })
+}
+
+/// Copies up to `capacity` entries of the ARP table to `entries` and sets `count_out` to how many
+/// there are in total.
+#[no_mangle]
+pub extern "C" fn dmtr_arp_list(
+    entries: *mut dmtr_arp_entry_t,
+    capacity: libc::size_t,
+    count_out: *mut libc::size_t,
+) -> c_int {
+    if count_out.is_null() || (entries.is_null() && capacity > 0) {
+        return libc::EINVAL;
+    }
+    with_libos(|libos| {
+        let arp_entries = libos.arp_entries();
+        for (i, &entry) in arp_entries.iter().take(capacity).enumerate() {
+            unsafe { *entries.add(i) = dmtr_arp_entry_t::from(entry) };
+        }
+        unsafe { *count_out = arp_entries.len() };
+        0
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn dmtr_arp_add(addr: *const libc::in_addr, link_addr: *const u8) -> c_int {
+    if addr.is_null() || link_addr.is_null() {
+        return libc::EINVAL;
+    }
+    let addr = Ipv4Addr::from(u32::from_be_bytes(unsafe { (*addr).s_addr }.to_le_bytes()));
+    let link_addr = MacAddress::from_bytes(unsafe { slice::from_raw_parts(link_addr, 6) });
+    with_libos(|libos| match libos.add_arp_entry(addr, link_addr) {
+        Ok(..) => 0,
+        Err(e) => e.errno(),
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn dmtr_arp_delete(addr: *const libc::in_addr) -> c_int {
+    if addr.is_null() {
+        return libc::EINVAL;
+    }
+    let addr = Ipv4Addr::from(u32::from_be_bytes(unsafe { (*addr).s_addr }.to_le_bytes()));
+    with_libos(|libos| match libos.remove_arp_entry(addr) {
+        Ok(..) => 0,
+        Err(e) => e.errno(),
+    })
+}
+
+/// Reports the last host found using our IPv4 address in `addr_out` and `link_addr_out`, or
+/// returns `ENOENT` if there hasn't been one.
+#[no_mangle]
+pub extern "C" fn dmtr_ipv4_conflict(
+    addr_out: *mut libc::in_addr,
+    link_addr_out: *mut u8,
+) -> c_int {
+    if addr_out.is_null() || link_addr_out.is_null() {
+        return libc::EINVAL;
+    }
+    with_libos(|libos| match libos.ipv4_addr_conflict() {
+        Some(conflict) => {
+            let s_addr = u32::from_le_bytes(conflict.ipv4_addr.octets());
+            let link_addr = conflict.link_addr.octets();
+            unsafe {
+                *addr_out = libc::in_addr { s_addr };
+                ptr::copy_nonoverlapping(link_addr.as_ptr(), link_addr_out, link_addr.len());
+            }
+            0
+        },
+        None => libc::ENOENT,
+    })
+}
+
+/// Forgets the learned entries of the ARP table. Static ones stay.
+#[no_mangle]
+pub extern "C" fn dmtr_arp_flush() -> c_int {
+    with_libos(|libos| {
+        libos.flush_arp_cache();
+        0
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn dmtr_poll(qr_out: *mut dmtr_qresult_t, qt: dmtr_qtoken_t) -> c_int {
     with_libos(|libos| match libos.poll(qt) {
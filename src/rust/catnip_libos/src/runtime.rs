@@ -7,7 +7,13 @@ use crate::bindings::{
 use catnip::{
     protocols::{
         arp,
-        ethernet2::MacAddress,
+        dns,
+        ethernet2::{
+            self,
+            MacAddress,
+        },
+        ipv4,
+        ipv6,
         tcp,
     },
     runtime::{
@@ -96,8 +102,13 @@ impl DPDKRuntime {
         ipv4_addr: Ipv4Addr,
         dpdk_port_id: u16,
         dpdk_mempool: *mut rte_mempool,
+        mtu: u16,
         arp_table: HashMap<MacAddress, Ipv4Addr>,
         disable_arp: bool,
+        ethernet2_options: ethernet2::Options,
+        ipv4_options: ipv4::Options,
+        ipv6_options: ipv6::Options,
+        dns_options: dns::Options,
     ) -> Self {
         let mut rng = rand::thread_rng();
         let rng = SmallRng::from_rng(&mut rng).expect("Failed to initialize RNG");
@@ -121,6 +132,8 @@ impl DPDKRuntime {
         tcp_options.tx_checksum_offload = true;
         tcp_options.rx_checksum_offload = true;
 
+        let ipv4_options = ipv4_options.mtu(mtu as usize);
+
         let inner = Inner {
             timer: TimerRc(Rc::new(Timer::new(now))),
             link_addr,
@@ -128,6 +141,10 @@ impl DPDKRuntime {
             rng,
             arp_options,
             tcp_options,
+            ethernet2_options,
+            ipv4_options,
+            ipv6_options,
+            dns_options,
 
             dpdk_port_id,
             dpdk_mempool,
@@ -149,6 +166,10 @@ struct Inner {
     rng: SmallRng,
     arp_options: arp::Options,
     tcp_options: tcp::Options,
+    ethernet2_options: ethernet2::Options,
+    ipv4_options: ipv4::Options,
+    ipv6_options: ipv6::Options,
+    dns_options: dns::Options,
 
     dpdk_port_id: u16,
     dpdk_mempool: *mut rte_mempool,
@@ -240,6 +261,10 @@ impl Runtime for DPDKRuntime {
         self.inner.borrow().ipv4_addr.clone()
     }
 
+    fn set_local_ipv4_addr(&self, addr: Ipv4Addr) {
+        self.inner.borrow_mut().ipv4_addr = addr;
+    }
+
     fn tcp_options(&self) -> tcp::Options {
         self.inner.borrow().tcp_options.clone()
     }
@@ -248,6 +273,22 @@ impl Runtime for DPDKRuntime {
         self.inner.borrow().arp_options.clone()
     }
 
+    fn ethernet2_options(&self) -> ethernet2::Options {
+        self.inner.borrow().ethernet2_options.clone()
+    }
+
+    fn ipv4_options(&self) -> ipv4::Options {
+        self.inner.borrow().ipv4_options.clone()
+    }
+
+    fn ipv6_options(&self) -> ipv6::Options {
+        self.inner.borrow().ipv6_options.clone()
+    }
+
+    fn dns_options(&self) -> dns::Options {
+        self.inner.borrow().dns_options.clone()
+    }
+
     fn advance_clock(&self, now: Instant) {
         self.inner.borrow_mut().timer.0.advance_clock(now);
     }